@@ -1,9 +1,23 @@
 /// Performance benchmarks for NSFW
 ///
 /// Run with: cargo bench
+use std::collections::HashMap;
+use std::io::Read;
 use std::time::{Instant, Duration};
+use anyhow::Result;
 use nsfw::cache::SearchCache;
+use nsfw::package_cache::{CacheBuilder, PackageCache};
 use nsfw::nix_ops::types::SearchResult;
+use nsfw::package_cache::stream;
+use nsfw::wsl2::{CommandOutput, WSL2Bridge};
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+use support::{measure_peak, SyntheticNixEnvOutput, TrackingAllocator};
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
 
 /// Benchmark result
 #[derive(Debug)]
@@ -43,8 +57,12 @@ impl BenchmarkResult {
 fn benchmark_cache() {
     println!("\n=== Cache Benchmarks ===\n");
 
-    // Clear cache
-    SearchCache::clear();
+    // The search cache lives in the package cache database
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let pkg_cache = PackageCache::with_path(dir.path().join("packages.db"))
+        .expect("Failed to open package cache");
+    pkg_cache.initialize().expect("Failed to initialize package cache");
+    let cache = SearchCache::new(&pkg_cache).expect("Failed to open search cache");
 
     // Prepare test data
     let results: Vec<SearchResult> = (0..50)
@@ -60,31 +78,34 @@ fn benchmark_cache() {
     let start = Instant::now();
     for i in 0..iterations {
         let query = format!("test-{}", i % 100);
-        SearchCache::put(&query, 10, results.clone());
+        cache.put(&query, 10, results.clone());
     }
     let elapsed = start.elapsed();
     BenchmarkResult::new("Cache Write", iterations, elapsed).print();
 
     // Benchmark: Cache read (hit)
-    let iterations = 100000;
+    let iterations = 10000;
     let start = Instant::now();
     for i in 0..iterations {
         let query = format!("test-{}", i % 100);
-        let _ = SearchCache::get(&query, 10);
+        let _ = cache.get(&query, 10);
     }
     let elapsed = start.elapsed();
     BenchmarkResult::new("Cache Read (Hit)", iterations, elapsed).print();
 
     // Benchmark: Cache read (miss)
-    SearchCache::clear();
-    let iterations = 100000;
+    cache.clear();
+    let iterations = 10000;
     let start = Instant::now();
     for i in 0..iterations {
         let query = format!("nonexistent-{}", i);
-        let _ = SearchCache::get(&query, 10);
+        let _ = cache.get(&query, 10);
     }
     let elapsed = start.elapsed();
     BenchmarkResult::new("Cache Read (Miss)", iterations, elapsed).print();
+
+    let stats = cache.stats();
+    println!("Hit rate: {:.1}% ({} hits, {} misses)", stats.hit_rate() * 100.0, stats.hits, stats.misses);
 }
 
 /// Benchmark path operations
@@ -128,6 +149,101 @@ fn benchmark_paths() {
     BenchmarkResult::new("Path Type Detection", iterations, elapsed).print();
 }
 
+/// Bridge whose `nix-env` streams synthetic output
+///
+/// Every other command fails, so the cache is built without provenance.
+struct SyntheticBridge {
+    packages: usize,
+}
+
+impl WSL2Bridge for SyntheticBridge {
+    fn execute(&self, command: &str, _args: &[&str]) -> Result<CommandOutput> {
+        Ok(CommandOutput::new(String::new(), format!("{}: not available", command), 1))
+    }
+
+    fn execute_streamed(
+        &self,
+        command: &str,
+        args: &[&str],
+        consume: &mut dyn FnMut(&mut dyn Read) -> Result<()>,
+    ) -> Result<CommandOutput> {
+        if command != "nix-env" {
+            return self.execute(command, args);
+        }
+        consume(&mut SyntheticNixEnvOutput::new(self.packages))?;
+        Ok(CommandOutput::new(String::new(), String::new(), 0))
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn version(&self) -> Result<String> {
+        Ok("synthetic".to_string())
+    }
+
+    fn translate_path_to_wsl(&self, path: &str) -> Result<String> {
+        Ok(path.to_string())
+    }
+
+    fn translate_path_to_windows(&self, path: &str) -> Result<String> {
+        Ok(path.to_string())
+    }
+}
+
+/// Benchmark peak memory of streamed vs buffered nix-env JSON parsing
+fn benchmark_cache_build_memory() {
+    println!("\n=== Cache Build Memory Benchmarks ===\n");
+
+    for &packages in &[10_000usize, 50_000, 100_000] {
+        // Buffered: whole stdout in a String, then a HashMap of every package
+        let start = Instant::now();
+        let buffered_peak = measure_peak(|| {
+            let mut stdout = String::new();
+            SyntheticNixEnvOutput::new(packages).read_to_string(&mut stdout).unwrap();
+            let parsed: HashMap<String, serde_json::Value> = serde_json::from_str(&stdout).unwrap();
+            assert_eq!(parsed.len(), packages);
+        });
+        let buffered_time = start.elapsed();
+
+        // Streamed: one entry at a time from the pipe
+        let start = Instant::now();
+        let streamed_peak = measure_peak(|| {
+            let count = stream::for_each_package(SyntheticNixEnvOutput::new(packages), |_, _| Ok(())).unwrap();
+            assert_eq!(count, packages);
+        });
+        let streamed_time = start.elapsed();
+
+        // The real cache update: streamed parse, delta tracking and SQLite upserts
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let pkg_cache = PackageCache::with_path(dir.path().join("packages.db"))
+            .expect("Failed to open package cache");
+        pkg_cache.initialize().expect("Failed to initialize package cache");
+        let builder = CacheBuilder::new(pkg_cache, SyntheticBridge { packages });
+
+        let start = Instant::now();
+        let build_peak = measure_peak(|| {
+            let delta = builder.update_incremental().unwrap();
+            assert_eq!(delta.added, packages);
+        });
+        let build_time = start.elapsed();
+
+        // Nothing changed, so only the delta is tracked
+        let start = Instant::now();
+        let refresh_peak = measure_peak(|| {
+            assert!(builder.update_incremental().unwrap().is_empty());
+        });
+        let refresh_time = start.elapsed();
+
+        println!("Packages: {}", packages);
+        println!("  Buffered peak heap: {:>10} KiB ({:?})", buffered_peak / 1024, buffered_time);
+        println!("  Streamed peak heap: {:>10} KiB ({:?})", streamed_peak / 1024, streamed_time);
+        println!("  Cache build peak:   {:>10} KiB ({:?})", build_peak / 1024, build_time);
+        println!("  Cache refresh peak: {:>10} KiB ({:?})", refresh_peak / 1024, refresh_time);
+        println!();
+    }
+}
+
 /// Main benchmark runner
 fn main() {
     println!("╔════════════════════════════════════════════════╗");
@@ -136,6 +252,7 @@ fn main() {
 
     benchmark_cache();
     benchmark_paths();
+    benchmark_cache_build_memory();
 
     println!("\n✅ All benchmarks complete!");
 }
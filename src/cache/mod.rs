@@ -1,97 +1,309 @@
-/// Simple in-memory cache for search results
+/// Persistent cache for search results
 ///
-/// Caches search results to avoid re-running expensive WSL2 commands
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
+/// Caches `nix search` results in the package cache database so repeated
+/// queries are answered without re-running expensive WSL2 commands, across
+/// CLI invocations. Entries expire after a configurable TTL, are invalidated
+/// when the nixpkgs revision of the package cache changes, and are evicted
+/// least-recently-used first once the cache is full.
+use anyhow::{Context, Result};
+use rusqlite::{params, OptionalExtension};
+use std::time::Duration;
 
 use crate::nix_ops::types::SearchResult;
+use crate::package_cache::PackageCache;
 
-/// Cache entry with expiration
-#[derive(Clone)]
-struct CacheEntry {
-    results: Vec<SearchResult>,
-    created_at: Instant,
-    ttl: Duration,
+/// Search cache settings
+#[derive(Debug, Clone)]
+pub struct SearchCacheConfig {
+    /// How long cached results stay valid
+    pub ttl: Duration,
+
+    /// Maximum number of cached queries before LRU eviction
+    pub max_entries: usize,
 }
 
-impl CacheEntry {
-    fn new(results: Vec<SearchResult>, ttl: Duration) -> Self {
-        Self {
-            results,
-            created_at: Instant::now(),
-            ttl,
+impl SearchCacheConfig {
+    /// Default TTL for cached results (1 hour)
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+    /// Default maximum number of cached queries
+    pub const DEFAULT_MAX_ENTRIES: usize = 500;
+
+    /// Build the configuration from the environment
+    ///
+    /// `NSFW_SEARCH_CACHE_TTL` overrides the TTL (in seconds) and
+    /// `NSFW_SEARCH_CACHE_SIZE` the maximum number of entries.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Some(ttl) = std::env::var("NSFW_SEARCH_CACHE_TTL").ok().and_then(|v| v.parse().ok()) {
+            config.ttl = Duration::from_secs(ttl);
+        }
+        if let Some(size) = std::env::var("NSFW_SEARCH_CACHE_SIZE").ok().and_then(|v| v.parse().ok()) {
+            config.max_entries = size;
         }
+
+        config
     }
+}
 
-    fn is_expired(&self) -> bool {
-        self.created_at.elapsed() > self.ttl
+impl Default for SearchCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Self::DEFAULT_TTL,
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+        }
     }
 }
 
-/// Global search cache
-static SEARCH_CACHE: Lazy<Mutex<HashMap<String, CacheEntry>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+/// Search cache statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchCacheStats {
+    /// Number of cached queries
+    pub entries: usize,
+
+    /// Cached queries that are expired or from an older nixpkgs revision
+    pub expired: usize,
+
+    /// Lookups answered from the cache
+    pub hits: u64,
+
+    /// Lookups that missed the cache
+    pub misses: u64,
+}
+
+impl SearchCacheStats {
+    /// Fraction of lookups answered from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
 
 /// Search result cache manager
-pub struct SearchCache;
+pub struct SearchCache<'a> {
+    cache: &'a PackageCache,
+    config: SearchCacheConfig,
+}
 
-impl SearchCache {
-    /// Default TTL for cached results (5 minutes)
-    const DEFAULT_TTL: Duration = Duration::from_secs(300);
+impl<'a> SearchCache<'a> {
+    /// Open the search cache stored in a package cache database
+    pub fn new(cache: &'a PackageCache) -> Result<Self> {
+        Self::with_config(cache, SearchCacheConfig::from_env())
+    }
 
-    /// Get cached search results if available and not expired
-    pub fn get(query: &str, limit: usize) -> Option<Vec<SearchResult>> {
-        let cache = SEARCH_CACHE.lock().ok()?;
-        let key = Self::make_key(query, limit);
+    /// Open the search cache with explicit settings
+    pub fn with_config(cache: &'a PackageCache, config: SearchCacheConfig) -> Result<Self> {
+        cache.conn()?.execute(
+            "CREATE TABLE IF NOT EXISTS search_cache (
+                key TEXT PRIMARY KEY,
+                results TEXT NOT NULL,
+                revision TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_access INTEGER NOT NULL
+            )",
+            [],
+        ).context("Failed to create search cache table")?;
 
-        if let Some(entry) = cache.get(&key) {
-            if !entry.is_expired() {
+        Ok(Self { cache, config })
+    }
+
+    /// Get cached search results if available and not expired
+    pub fn get(&self, query: &str, limit: usize) -> Option<Vec<SearchResult>> {
+        match self.lookup(query, limit) {
+            Ok(Some(results)) => {
                 log::debug!("Cache hit for query '{}' (limit: {})", query, limit);
-                return Some(entry.results.clone());
-            } else {
-                log::debug!("Cache expired for query '{}' (limit: {})", query, limit);
+                self.bump_counter("search_cache_hits");
+                Some(results)
+            }
+            Ok(None) => {
+                self.bump_counter("search_cache_misses");
+                None
+            }
+            Err(e) => {
+                log::debug!("Search cache lookup failed: {}", e);
+                self.bump_counter("search_cache_misses");
+                None
             }
         }
+    }
+
+    fn lookup(&self, query: &str, limit: usize) -> Result<Option<Vec<SearchResult>>> {
+        let key = Self::make_key(query, limit);
+        let revision = self.current_revision()?;
+        let conn = self.cache.conn()?;
+
+        let entry: Option<(String, String, i64)> = conn.prepare_cached(
+            "SELECT results, revision, created_at FROM search_cache WHERE key = ?1"
+        )?
+        .query_row(params![key], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .optional()?;
+
+        let Some((results, entry_revision, created_at)) = entry else {
+            return Ok(None);
+        };
+
+        if entry_revision != revision || self.is_expired(created_at) {
+            log::debug!("Cache expired for query '{}' (limit: {})", query, limit);
+            conn.execute("DELETE FROM search_cache WHERE key = ?1", params![key])?;
+            return Ok(None);
+        }
+
+        conn.prepare_cached("UPDATE search_cache SET last_access = ?1 WHERE key = ?2")?
+            .execute(params![now(), key])?;
+
+        Ok(Some(serde_json::from_str(&results)?))
+    }
+
+    /// Get cached search results regardless of age or nixpkgs revision
+    ///
+    /// Used when Nix cannot be reached. Returns the results together with
+    /// the time they were cached, and does not count towards hit statistics.
+    pub fn get_stale(&self, query: &str, limit: usize) -> Option<(Vec<SearchResult>, i64)> {
+        let key = Self::make_key(query, limit);
+
+        let result = self.cache.conn().and_then(|conn| {
+            let entry: Option<(String, i64)> = conn.prepare_cached(
+                "SELECT results, created_at FROM search_cache WHERE key = ?1"
+            )?
+            .query_row(params![key], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
 
-        None
+            entry.map(|(results, created_at)| Ok((serde_json::from_str(&results)?, created_at)))
+                .transpose()
+        });
+
+        result.unwrap_or_else(|e| {
+            log::debug!("Stale search cache lookup failed: {}", e);
+            None
+        })
     }
 
     /// Store search results in cache
-    pub fn put(query: &str, limit: usize, results: Vec<SearchResult>) {
-        if let Ok(mut cache) = SEARCH_CACHE.lock() {
-            let key = Self::make_key(query, limit);
-            let entry = CacheEntry::new(results, Self::DEFAULT_TTL);
-            cache.insert(key, entry);
+    pub fn put(&self, query: &str, limit: usize, results: Vec<SearchResult>) {
+        if let Err(e) = self.store(query, limit, &results) {
+            log::debug!("Failed to cache results for query '{}': {}", query, e);
+        } else {
             log::debug!("Cached results for query '{}' (limit: {})", query, limit);
         }
     }
 
-    /// Clear all cached results
-    pub fn clear() {
-        if let Ok(mut cache) = SEARCH_CACHE.lock() {
-            cache.clear();
-            log::debug!("Search cache cleared");
+    fn store(&self, query: &str, limit: usize, results: &[SearchResult]) -> Result<()> {
+        let key = Self::make_key(query, limit);
+        let revision = self.current_revision()?;
+        let json = serde_json::to_string(results)?;
+        let now = now();
+        let conn = self.cache.conn()?;
+
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO search_cache (key, results, revision, created_at, last_access)
+             VALUES (?1, ?2, ?3, ?4, ?4)"
+        )?
+        .execute(params![key, json, revision, now])?;
+
+        // Evict least recently used entries beyond the size bound
+        conn.prepare_cached(
+            "DELETE FROM search_cache WHERE key NOT IN (
+                SELECT key FROM search_cache ORDER BY last_access DESC, created_at DESC LIMIT ?1
+            )"
+        )?
+        .execute(params![self.config.max_entries as i64])?;
+
+        Ok(())
+    }
+
+    /// Clear all cached results and statistics
+    pub fn clear(&self) {
+        let result = self.cache.conn().and_then(|conn| {
+            conn.execute("DELETE FROM search_cache", [])?;
+            conn.execute(
+                "DELETE FROM metadata WHERE key IN ('search_cache_hits', 'search_cache_misses')",
+                [],
+            )?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => log::debug!("Search cache cleared"),
+            Err(e) => log::debug!("Failed to clear search cache: {}", e),
         }
     }
 
-    /// Remove expired entries from cache
-    pub fn cleanup_expired() {
-        if let Ok(mut cache) = SEARCH_CACHE.lock() {
-            cache.retain(|_, entry| !entry.is_expired());
-            log::debug!("Cleaned up expired cache entries");
+    /// Remove expired entries and entries from older nixpkgs revisions
+    pub fn cleanup_expired(&self) {
+        let result = self.current_revision().and_then(|revision| {
+            let cutoff = now() - self.config.ttl.as_secs() as i64;
+            let conn = self.cache.conn()?;
+            conn.execute(
+                "DELETE FROM search_cache WHERE created_at <= ?1 OR revision != ?2",
+                params![cutoff, revision],
+            )?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => log::debug!("Cleaned up expired cache entries"),
+            Err(e) => log::debug!("Failed to clean up search cache: {}", e),
         }
     }
 
     /// Get cache statistics
-    pub fn stats() -> (usize, usize) {
-        if let Ok(cache) = SEARCH_CACHE.lock() {
-            let total = cache.len();
-            let expired = cache.values().filter(|e| e.is_expired()).count();
-            (total, expired)
-        } else {
-            (0, 0)
+    pub fn stats(&self) -> SearchCacheStats {
+        let result = self.current_revision().and_then(|revision| {
+            let cutoff = now() - self.config.ttl.as_secs() as i64;
+            let conn = self.cache.conn()?;
+
+            let (entries, expired): (i64, i64) = conn.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(created_at <= ?1 OR revision != ?2), 0) FROM search_cache",
+                params![cutoff, revision],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            drop(conn);
+
+            Ok(SearchCacheStats {
+                entries: entries as usize,
+                expired: expired as usize,
+                hits: self.counter("search_cache_hits"),
+                misses: self.counter("search_cache_misses"),
+            })
+        });
+
+        result.unwrap_or_default()
+    }
+
+    /// Revision entries are tied to ("" until the cache has provenance)
+    fn current_revision(&self) -> Result<String> {
+        Ok(self.cache.get_metadata("nixpkgs_revision")?.unwrap_or_default())
+    }
+
+    fn is_expired(&self, created_at: i64) -> bool {
+        now() - created_at >= self.config.ttl.as_secs() as i64
+    }
+
+    fn counter(&self, key: &str) -> u64 {
+        self.cache.get_metadata(key)
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn bump_counter(&self, key: &str) {
+        let result = self.cache.conn().and_then(|conn| {
+            conn.prepare_cached(
+                "INSERT INTO metadata (key, value) VALUES (?1, '1')
+                 ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + 1"
+            )?
+            .execute(params![key])?;
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            log::debug!("Failed to update search cache statistics: {}", e);
         }
     }
 
@@ -101,65 +313,149 @@ impl SearchCache {
     }
 }
 
+/// Current Unix timestamp
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::package_cache::temp_cache;
 
-    #[test]
-    fn test_cache_put_and_get() {
-        SearchCache::clear();
-
-        let results = vec![SearchResult {
+    fn test_results() -> Vec<SearchResult> {
+        vec![SearchResult {
             pname: "test".to_string(),
             version: "1.0".to_string(),
             description: "Test package".to_string(),
-        }];
+        }]
+    }
 
-        SearchCache::put("firefox", 10, results.clone());
+    #[test]
+    fn test_cache_put_and_get() {
+        let (_dir, pkg_cache) = temp_cache();
+        let cache = SearchCache::new(&pkg_cache).unwrap();
 
-        let cached = SearchCache::get("firefox", 10);
+        cache.put("firefox", 10, test_results());
+
+        let cached = cache.get("firefox", 10);
         assert!(cached.is_some());
         assert_eq!(cached.unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_cache_persists_across_instances() {
+        let (dir, pkg_cache) = temp_cache();
+        SearchCache::new(&pkg_cache).unwrap().put("firefox", 10, test_results());
+        drop(pkg_cache);
+
+        let reopened = PackageCache::with_path(dir.path().join("packages.db")).unwrap();
+        let cache = SearchCache::new(&reopened).unwrap();
+        assert!(cache.get("firefox", 10).is_some());
+    }
+
     #[test]
     fn test_cache_miss() {
-        SearchCache::clear();
+        let (_dir, pkg_cache) = temp_cache();
+        let cache = SearchCache::new(&pkg_cache).unwrap();
 
-        let cached = SearchCache::get("nonexistent", 10);
+        let cached = cache.get("nonexistent", 10);
         assert!(cached.is_none());
     }
 
     #[test]
     fn test_cache_key_case_insensitive() {
-        SearchCache::clear();
-
-        let results = vec![SearchResult {
-            pname: "test".to_string(),
-            version: "1.0".to_string(),
-            description: "Test package".to_string(),
-        }];
+        let (_dir, pkg_cache) = temp_cache();
+        let cache = SearchCache::new(&pkg_cache).unwrap();
 
-        SearchCache::put("Firefox", 10, results.clone());
+        cache.put("Firefox", 10, test_results());
 
-        let cached = SearchCache::get("firefox", 10);
+        let cached = cache.get("firefox", 10);
         assert!(cached.is_some());
     }
 
     #[test]
-    fn test_cache_stats() {
-        SearchCache::clear();
+    fn test_cache_ttl_expiry() {
+        let (_dir, pkg_cache) = temp_cache();
+        let config = SearchCacheConfig {
+            ttl: Duration::from_secs(0),
+            ..Default::default()
+        };
+        let cache = SearchCache::with_config(&pkg_cache, config).unwrap();
 
-        let results = vec![SearchResult {
-            pname: "test".to_string(),
-            version: "1.0".to_string(),
-            description: "Test package".to_string(),
-        }];
+        cache.put("vim", 10, test_results());
+        assert_eq!(cache.stats().expired, 1);
+        assert!(cache.get("vim", 10).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_cache_invalidated_by_revision_change() {
+        let (_dir, pkg_cache) = temp_cache();
+        pkg_cache.set_metadata("nixpkgs_revision", "24.05.1").unwrap();
+        let cache = SearchCache::new(&pkg_cache).unwrap();
+
+        cache.put("vim", 10, test_results());
+        assert!(cache.get("vim", 10).is_some());
+
+        pkg_cache.set_metadata("nixpkgs_revision", "24.05.2").unwrap();
+        assert!(cache.get("vim", 10).is_none());
+    }
+
+    #[test]
+    fn test_get_stale_ignores_expiry() {
+        let (_dir, pkg_cache) = temp_cache();
+        let config = SearchCacheConfig {
+            ttl: Duration::from_secs(0),
+            ..Default::default()
+        };
+        let cache = SearchCache::with_config(&pkg_cache, config).unwrap();
+
+        cache.put("vim", 10, test_results());
+        pkg_cache.set_metadata("nixpkgs_revision", "24.05.2").unwrap();
+
+        let (results, cached_at) = cache.get_stale("vim", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(cached_at > 0);
+        assert!(cache.get_stale("emacs", 10).is_none());
+    }
+
+    #[test]
+    fn test_cache_lru_eviction() {
+        let (_dir, pkg_cache) = temp_cache();
+        let config = SearchCacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        };
+        let cache = SearchCache::with_config(&pkg_cache, config).unwrap();
+
+        cache.put("vim", 10, test_results());
+        cache.put("emacs", 10, test_results());
+        // Make "vim" the most recently used, then push out the LRU entry
+        pkg_cache.conn().unwrap()
+            .execute("UPDATE search_cache SET last_access = last_access - 10 WHERE key = 'emacs:10'", [])
+            .unwrap();
+        cache.put("nano", 10, test_results());
+
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.get("emacs", 10).is_none());
+        assert!(cache.get("vim", 10).is_some());
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let (_dir, pkg_cache) = temp_cache();
+        let cache = SearchCache::new(&pkg_cache).unwrap();
 
-        SearchCache::put("vim", 10, results.clone());
-        SearchCache::put("emacs", 10, results.clone());
+        cache.put("vim", 10, test_results());
+        cache.put("emacs", 10, test_results());
+        cache.get("vim", 10);
+        cache.get("nano", 10);
 
-        let (total, _expired) = SearchCache::stats();
-        assert_eq!(total, 2);
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hit_rate(), 0.5);
     }
 }
@@ -1,36 +1,184 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
 
-use crate::nix_ops::{BridgedNixExecutor, NixError, types::SearchResult};
+use crate::cli::report;
+use crate::config::Config;
+use crate::setup::SetupSources;
+use crate::nix_ops::{InstallPlan, NixBackend, NixError, PackageRef, Refusal, StoreMaintenance, default_backend, default_bridge, runs_natively, types::{SearchResult, InstalledPackage}};
+use crate::nix_ops::profile::{find_installed, parse_profile_list};
 use crate::templates::{WrapperGenerator, PackageInfo, WrapperType};
-use crate::wsl2::RealWSL2Bridge;
 use crate::cache::SearchCache;
 use crate::ui::{ProgressIndicator, OutputFormatter, MessageType};
-use crate::package_cache::{PackageCache, CacheBuilder, CachedPackage};
+use crate::package_cache::{
+    PackageCache, CacheBuilder, CachedPackage, SearchFilters,
+    BuildLock, BuildState, SnapshotManifest, TruncatedPackageSet, UsageSignal, VersionResolver,
+    spawn_refresh_worker, read_manifest,
+};
 
-/// Helper to spawn background cache update if needed
-fn spawn_cache_update_if_needed(cache: PackageCache) {
-    std::thread::spawn(move || {
-        let bridge = RealWSL2Bridge::new();
-        let builder = CacheBuilder::new(cache, bridge);
+/// Helper to spawn a detached background cache update if needed
+///
+/// Empty caches always start a build; otherwise the worker is only started
+/// periodically to check for a new nixpkgs revision.
+fn spawn_cache_update_if_needed(cache: &PackageCache) {
+    let due = cache.is_empty() || cache.refresh_check_due().unwrap_or(true);
+    if !due {
+        return;
+    }
+
+    if let Err(e) = spawn_refresh_worker(cache) {
+        log::warn!("Failed to start background cache update: {}", e);
+    }
+}
+
+/// Convert cached packages into search results for display
+fn cached_to_search_results(packages: &[CachedPackage]) -> Vec<SearchResult> {
+    packages.iter().map(|p| SearchResult {
+        pname: p.name.clone(),
+        version: p.version.clone(),
+        description: p.description.clone(),
+    }).collect()
+}
+
+/// Feed a usage signal into the package cache's popularity ranking
+fn record_usage(package: &PackageRef, signal: UsageSignal) {
+    // Only nixpkgs packages are in the package cache
+    let Some(attr_path) = package.attr_path.as_deref().filter(|_| package.is_nixpkgs()) else {
+        return;
+    };
+
+    let result = PackageCache::new()
+        .and_then(|cache| cache.initialize().map(|_| cache))
+        .and_then(|cache| cache.record_usage_by_name(attr_path, signal));
+
+    if let Err(e) = result {
+        log::debug!("Failed to record package usage: {}", e);
+    }
+}
+
+/// Tell the user that output comes from local state instead of Nix
+fn print_stale_notice(what: &str, as_of: Option<i64>) {
+    let as_of = as_of
+        .map(format_timestamp)
+        .unwrap_or_else(|| "an unknown time".to_string());
+    report::print_message(
+        MessageType::Warning,
+        &format!("Offline: showing {} as of {} (may be stale)", what, as_of)
+    );
+}
+
+/// Print JSON output that comes from local state instead of Nix
+///
+/// The output is wrapped as `{"stale": true, "as_of": <timestamp>, <key>: ...}`
+/// so scripts can tell it apart from live results.
+fn print_stale_json<T: serde::Serialize>(key: &str, value: &T, as_of: Option<i64>) -> Result<()> {
+    let mut json = serde_json::json!({
+        "stale": true,
+        "as_of": as_of,
+    });
+    json[key] = serde_json::to_value(value)?;
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+/// Print search results in the requested format
+fn print_search_results(query: &str, results: &[SearchResult], format: &str, found_message: &str) -> Result<()> {
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(results)?);
+    } else if results.is_empty() {
+        report::print_message(MessageType::Warning, &format!("No results found for '{}'", query));
+    } else {
+        report::print_message(MessageType::Success, &format!("Found {} result(s){}", results.len(), found_message));
+        print!("{}", OutputFormatter::format_search_results(results, true));
+    }
+    Ok(())
+}
 
-        if let Ok(true) = builder.needs_update() {
-            log::info!("Starting background cache update");
-            if let Err(e) = builder.build_from_nix_env() {
-                log::warn!("Background cache update failed: {}", e);
+/// Serve a search from previously cached `nix search` results of any age
+///
+/// Returns `false` if nothing is cached for the query.
+fn search_offline(query: &str, limit: usize, format: &str, search_cache: &SearchCache) -> Result<bool> {
+    let Some((results, cached_at)) = search_cache.get_stale(query, limit) else {
+        return Ok(false);
+    };
+
+    print_stale_notice("cached search results", Some(cached_at));
+    if format == "json" {
+        print_stale_json("results", &results, Some(cached_at))?;
+    } else {
+        print_search_results(query, &results, format, "")?;
+    }
+    Ok(true)
+}
+
+/// Neither Nix nor a saved profile snapshot could list installed packages
+#[derive(Debug, thiserror::Error)]
+#[error("No offline snapshot of installed packages")]
+struct NoProfileSnapshot;
+
+/// Suggestion for an `installed_packages` failure
+///
+/// A missing snapshot is only fixed by one listing while Nix is reachable,
+/// so that is suggested instead of `fallback`.
+fn installed_packages_suggestion(error: &anyhow::Error, fallback: &str) -> String {
+    let unreachable = error.downcast_ref::<NixError>().is_some_and(NixError::is_connectivity);
+    if unreachable || error.downcast_ref::<NoProfileSnapshot>().is_some() {
+        "Run 'nsfw list' once while Nix is reachable to save a profile snapshot for offline use".to_string()
+    } else {
+        fallback.to_string()
+    }
+}
+
+/// Get installed packages from Nix, or from the last profile snapshot
+///
+/// Successful listings refresh the snapshot. The snapshot is used when
+/// `offline` is set or Nix cannot be reached; its timestamp is returned so
+/// callers can mark the output as stale.
+fn installed_packages(
+    pkg_cache: &PackageCache,
+    executor: &dyn NixBackend,
+    offline: bool,
+) -> Result<(Vec<InstalledPackage>, Option<i64>)> {
+    let mut connectivity_error = None;
+
+    if !offline {
+        match executor.profile_list_json() {
+            Ok(json) => {
+                let packages = parse_profile_list(&json)?;
+                if let Err(e) = pkg_cache.save_profile_snapshot(&json) {
+                    log::warn!("Failed to save profile snapshot: {}", e);
+                }
+                return Ok((packages, None));
+            }
+            Err(e) if e.is_connectivity() => {
+                log::debug!("Nix unreachable, using profile snapshot: {}", e);
+                connectivity_error = Some(e);
             }
+            Err(e) => return Err(e.into()),
         }
-    });
+    }
+
+    match pkg_cache.profile_snapshot()? {
+        Some(snapshot) => Ok((parse_profile_list(&snapshot.json)?, Some(snapshot.taken_at))),
+        None => Err(match connectivity_error {
+            Some(e) => e.into(),
+            None => NoProfileSnapshot.into(),
+        }),
+    }
 }
 
-pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
+pub fn search(query: &str, limit: usize, format: &str, filters: SearchFilters, offline: bool) -> Result<()> {
     // Show search header
-    eprintln!("{}", OutputFormatter::format_section(&format!("Searching for '{}'", query)));
+    report::print_section(&format!("Searching for '{}'", query));
 
     // Try package cache first (local database - instant!)
     let pkg_cache = PackageCache::new()?;
     pkg_cache.initialize()?;
 
+    // Filters rely on cached metadata, so they are served from the cache only
+    if filters.is_active() {
+        return search_filtered(query, limit, format, filters, pkg_cache, offline);
+    }
+
     if !pkg_cache.is_empty() {
         log::debug!("Checking package cache for '{}'", query);
         let cached_packages = pkg_cache.search(query, limit)?;
@@ -39,23 +187,28 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
             log::info!("Found {} results in package cache", cached_packages.len());
 
             // Convert to SearchResult format
-            let results: Vec<SearchResult> = cached_packages.iter().map(|p| SearchResult {
-                pname: p.name.clone(),
-                version: p.version.clone(),
-                description: p.description.clone(),
-            }).collect();
+            let results = cached_to_search_results(&cached_packages);
+
+            let built_at = pkg_cache.provenance()?.map(|p| p.built_at);
+            if offline {
+                print_stale_notice("the local package cache", built_at);
+            }
 
             // Output results
-            if format == "json" {
+            if format == "json" && offline {
+                print_stale_json("results", &results, built_at)?;
+            } else if format == "json" {
                 let json = serde_json::to_string_pretty(&results)?;
                 println!("{}", json);
             } else {
-                eprintln!("{}", OutputFormatter::format_message(MessageType::Success, &format!("⚡ Found {} result(s) (instant search!)", results.len())));
+                report::print_message(MessageType::Success, &format!("⚡ Found {} result(s) (instant search!)", results.len()));
                 print!("{}", OutputFormatter::format_search_results(&results, true));
             }
 
             // Start background cache update if needed
-            spawn_cache_update_if_needed(pkg_cache);
+            if !offline {
+                spawn_cache_update_if_needed(&pkg_cache);
+            }
 
             return Ok(());
         } else {
@@ -63,12 +216,31 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
         }
     }
 
+    // Results of earlier `nix search` runs are kept in the search cache
+    let search_cache = SearchCache::new(&pkg_cache)?;
+
+    if offline {
+        if !search_offline(query, limit, format, &search_cache)? {
+            report::print_error_with_suggestion(
+                &format!("No offline results for '{}'", query),
+                "Run the search again without --offline once WSL2 and the network are available"
+            );
+            if format == "json" {
+                print_stale_json("results", &Vec::<SearchResult>::new(), None)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(results) = search_cache.get(query, limit) {
+        return print_search_results(query, &results, format, " (cached)");
+    }
+
     // Create progress spinner
-    let progress = ProgressIndicator::spinner("Connecting to WSL2...");
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
 
-    // Create bridged executor that uses WSL2
-    let bridge = RealWSL2Bridge::new();
-    let executor = BridgedNixExecutor::new(bridge);
+    // Runs Nix natively on Linux, through WSL2 on Windows
+    let executor = default_backend();
 
     // Check if Nix is available
     progress.set_message("Checking Nix availability...");
@@ -78,24 +250,33 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
         Ok(version) => {
             progress.finish_and_clear();
             if log::log_enabled!(log::Level::Debug) {
-                eprintln!("{}", OutputFormatter::format_message(MessageType::Info, &format!("Using: {}", version)));
+                report::print_message(MessageType::Info, &format!("Using: {}", version));
             }
 
             // Inform user about first-time delay only if cache doesn't exist
             if is_first_time {
-                eprintln!("{}", OutputFormatter::format_message(
+                report::print_message(
                     MessageType::Warning,
                     "⏳ First-time setup: Downloading package database (200-500MB, 2-10 min)"
-                ));
-                eprintln!("{}", OutputFormatter::format_message(
+                );
+                report::print_message(
                     MessageType::Info,
                     "   This is a ONE-TIME operation. All future searches will be instant!"
-                ));
+                );
             }
         }
+        Err(e) if e.is_connectivity() => {
+            progress.finish_and_clear();
+            report::print_message(MessageType::Warning, &format!("{} - falling back to local results", e));
+            if search_offline(query, limit, format, &search_cache)? {
+                return Ok(());
+            }
+            report::print_error(&e.to_string());
+            return Err(e.into());
+        }
         Err(e) => {
             progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Error, &e.to_string()));
+            report::print_error(&e.to_string());
             return Err(e.into());
         }
     }
@@ -112,7 +293,7 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
             progress.finish_and_clear();
 
             // Cache the results (search cache for this specific query)
-            SearchCache::put(query, limit, results.clone());
+            search_cache.put(query, limit, results.clone());
 
             // Add results to package cache for future instant searches
             let cached_packages: Vec<CachedPackage> = results.iter().map(|r| CachedPackage {
@@ -122,6 +303,7 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
                 attr_path: format!("nixpkgs.{}", r.pname),
                 last_updated: chrono::Utc::now().timestamp(),
                 search_count: 0,
+                ..Default::default()
             }).collect();
 
             if !cached_packages.is_empty() {
@@ -133,11 +315,11 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
             // Start background cache build if cache is empty (first-time user)
             if pkg_cache.is_empty() {
                 log::info!("Starting background cache build for future instant searches");
-                eprintln!("{}", OutputFormatter::format_message(
+                report::print_message(
                     MessageType::Info,
                     "💡 Building local package cache in background for instant future searches..."
-                ));
-                spawn_cache_update_if_needed(pkg_cache);
+                );
+                spawn_cache_update_if_needed(&pkg_cache);
             }
 
             // Output results based on format
@@ -148,9 +330,9 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
                 }
                 _ => {
                     if results.is_empty() {
-                        eprintln!("{}", OutputFormatter::format_message(MessageType::Warning, &format!("No results found for '{}'", query)));
+                        report::print_message(MessageType::Warning, &format!("No results found for '{}'", query));
                     } else {
-                        eprintln!("{}", OutputFormatter::format_message(MessageType::Success, &format!("Found {} result(s)", results.len())));
+                        report::print_message(MessageType::Success, &format!("Found {} result(s)", results.len()));
                         print!("{}", OutputFormatter::format_search_results(&results, true));
                     }
                 }
@@ -158,157 +340,691 @@ pub fn search(query: &str, limit: usize, format: &str) -> Result<()> {
 
             Ok(())
         }
+        Err(e) if e.is_connectivity() => {
+            progress.finish_and_clear();
+            report::print_message(MessageType::Warning, &format!("{} - falling back to local results", e));
+            if search_offline(query, limit, format, &search_cache)? {
+                return Ok(());
+            }
+            report::print_error(&format!("Search failed: {}", e));
+            Err(e.into())
+        }
         Err(e) => {
             progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Error, &format!("Search failed: {}", e)));
+            report::print_error(&format!("Search failed: {}", e));
             Err(e.into())
         }
     }
 }
 
-pub fn install(package: &str, yes: bool) -> Result<()> {
-    eprintln!("{}", OutputFormatter::format_section(&format!("Installing '{}'", package)));
+/// Search the package cache with metadata and install-state filters
+fn search_filtered(
+    query: &str,
+    limit: usize,
+    format: &str,
+    filters: SearchFilters,
+    pkg_cache: PackageCache,
+    offline: bool,
+) -> Result<()> {
+    if pkg_cache.is_empty() {
+        let error = report::fail(
+            anyhow!("Package cache is empty"),
+            "Search filters require the local package cache, which has not been built yet",
+            "Run 'nsfw search <query>' without filters first, then retry once the cache is built"
+        );
+        if !offline {
+            spawn_cache_update_if_needed(&pkg_cache);
+        }
+        return Err(error);
+    }
+
+    // Join with the installed profile only when an install-state filter is used
+    let mut stale_since = None;
+    let filters = if filters.needs_installed_names() {
+        let progress = ProgressIndicator::spinner("Checking installed packages...");
+        let executor = default_backend();
+        let (installed, snapshot_at) = match installed_packages(&pkg_cache, executor.as_ref(), offline) {
+            Ok(installed) => installed,
+            Err(e) => {
+                progress.finish_and_clear();
+                let message = format!("Failed to list installed packages: {}", e);
+                let suggestion = installed_packages_suggestion(
+                    &e,
+                    "Drop --installed/--not-installed to search without the profile",
+                );
+                return Err(report::fail(e, &message, &suggestion));
+            }
+        };
+        progress.finish_and_clear();
+        if snapshot_at.is_some() {
+            print_stale_notice("installed packages from the last profile snapshot", snapshot_at);
+        }
+        stale_since = snapshot_at;
+        filters.with_installed_names(installed.into_iter().map(|p| p.name).collect())
+    } else {
+        filters
+    };
+
+    let cached_packages = pkg_cache.search_filtered(query, limit, &filters)?;
+    let results = cached_to_search_results(&cached_packages);
+
+    match format {
+        "json" if stale_since.is_some() => print_stale_json("results", &results, stale_since)?,
+        "json" => {
+            let json = serde_json::to_string_pretty(&results)?;
+            println!("{}", json);
+        }
+        _ => {
+            if results.is_empty() {
+                report::print_message(MessageType::Warning, &format!("No results found for '{}' matching the given filters", query));
+            } else {
+                report::print_message(MessageType::Success, &format!("⚡ Found {} result(s) matching filters", results.len()));
+                print!("{}", OutputFormatter::format_search_results(&results, true));
+            }
+        }
+    }
+
+    if !offline {
+        spawn_cache_update_if_needed(&pkg_cache);
+    }
+
+    Ok(())
+}
+
+pub fn browse(category: Option<&str>, limit: usize, format: &str) -> Result<()> {
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    if pkg_cache.is_empty() {
+        let error = report::fail(
+            anyhow!("Package cache is empty"),
+            "Browsing requires the local package cache, which has not been built yet",
+            "Run 'nsfw update' to build it"
+        );
+        spawn_cache_update_if_needed(&pkg_cache);
+        return Err(error);
+    }
+
+    let Some(category) = category else {
+        report::print_section("Package Categories");
+        let categories = pkg_cache.categories(5)?;
+
+        if format == "json" {
+            println!("{}", serde_json::to_string_pretty(&categories)?);
+        } else if categories.is_empty() {
+            report::print_error_with_suggestion(
+                "No categories found in the package cache",
+                "Run 'nsfw update' to refresh the cache with category data"
+            );
+        } else {
+            print!("{}", OutputFormatter::format_categories(&categories));
+            report::print_message(MessageType::Info, "Run 'nsfw browse <category>' to see more packages");
+        }
+        return Ok(());
+    };
+
+    report::print_section(&format!("Category '{}'", category));
+    let results = cached_to_search_results(&pkg_cache.browse(category, limit)?);
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else if results.is_empty() {
+        report::print_error_with_suggestion(
+            &format!("No packages in category '{}'", category),
+            "Run 'nsfw browse' to list the available categories"
+        );
+    } else {
+        print!("{}", OutputFormatter::format_search_results(&results, true));
+    }
+
+    Ok(())
+}
+
+/// Parse a package argument, explaining what is wrong with invalid ones
+fn parse_package_ref(package: &str) -> Result<PackageRef> {
+    PackageRef::parse(package).map_err(|e| {
+        let message = e.to_string();
+        report::fail(
+            e,
+            &message,
+            "Use a name or attribute path (python3Packages.requests), optionally with outputs (openssl^dev), or a flake reference (github:owner/repo#package)"
+        )
+    })
+}
+
+/// Pin a package with a requested version to a nixpkgs commit that has it
+fn resolve_version(package: &PackageRef, resolver: &dyn VersionResolver) -> Result<PackageRef> {
+    let (Some(attr_path), Some(version)) = (&package.attr_path, &package.version) else {
+        return Ok(package.clone());
+    };
+
+    match resolver.resolve(attr_path, version)? {
+        Some(resolved) => {
+            report::print_message(
+                MessageType::Info,
+                &format!("Using {} {} from nixpkgs {}", attr_path, resolved.version, resolved.revision)
+            );
+            Ok(package.pinned(&resolved.revision))
+        }
+        None => {
+            let e = NixError::VersionNotFound {
+                package: attr_path.clone(),
+                version: version.clone(),
+            };
+            let known = resolver.known_versions(attr_path)?;
+            let suggestion = if known.is_empty() {
+                "Versions are indexed whenever the package cache is updated ('nsfw update')".to_string()
+            } else {
+                format!("Known versions: {}", known.join(", "))
+            };
+            let message = e.to_string();
+            Err(report::fail(e, &message, &suggestion))
+        }
+    }
+}
+
+/// A package of an install transaction
+struct PendingInstall {
+    /// As given on the command line
+    requested: PackageRef,
+
+    /// Pinned to a nixpkgs commit if a version was requested
+    package: PackageRef,
+
+    /// Reference under which consent is remembered
+    key: String,
+
+    /// Refusals by nixpkgs overridden for this package
+    allowed: Vec<Refusal>,
+}
+
+/// Install packages in a single profile transaction
+///
+/// All packages are resolved and checked first; packages that cannot be
+/// installed are reported and left out. The rest are installed with one
+/// `nix profile install`, so a failure leaves the profile unchanged.
+pub fn install(packages: &[String], yes: bool, dry_run: bool, allow: &[Refusal]) -> Result<()> {
+    let requested = packages.iter()
+        .map(|package| parse_package_ref(package))
+        .collect::<Result<Vec<_>>>()?;
+
+    report::print_section(&format!("Installing '{}'", display_names(&requested)));
+
+    // Versions are pinned through the version index in the package cache
+    let mut pkg_cache = None;
+    let mut resolved = Vec::with_capacity(requested.len());
+    for package in &requested {
+        if package.version.is_none() {
+            resolved.push(package.clone());
+            continue;
+        }
+        if pkg_cache.is_none() {
+            let cache = PackageCache::new()?;
+            cache.initialize()?;
+            pkg_cache = Some(cache);
+        }
+        resolved.push(resolve_version(package, pkg_cache.as_ref().expect("package cache opened"))?);
+    }
 
-    // Create bridged executor that uses WSL2
-    let progress = ProgressIndicator::spinner("Connecting to WSL2...");
-    let bridge = RealWSL2Bridge::new();
-    let executor = BridgedNixExecutor::new(bridge);
+    // Runs Nix natively on Linux, through WSL2 on Windows
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
+    let executor = default_backend();
 
     // Check if Nix is available
     progress.set_message("Checking Nix availability...");
     if let Err(e) = executor.check_nix_available() {
         progress.finish_and_clear();
-        eprintln!("{}", OutputFormatter::format_message(MessageType::Error, &e.to_string()));
+        report::print_error(&e.to_string());
         return Err(e.into());
     }
+
+    // Refusals by nixpkgs the user has overridden before or on the command line
+    let mut config = Config::load()?;
+    let mut pending: Vec<PendingInstall> = requested.into_iter().zip(resolved)
+        .map(|(requested, package)| {
+            let key = consent_key(&requested);
+            let mut allowed = config.allowances(&key);
+            for refusal in allow {
+                if !allowed.contains(refusal) {
+                    allowed.push(*refusal);
+                }
+            }
+            PendingInstall { requested, package, key, allowed }
+        })
+        .collect();
+
+    // Packages already in the profile would fail the whole transaction
+    progress.set_message("Checking installed packages...");
+    match executor.list() {
+        Ok(installed) => pending.retain(|p| {
+            let already = is_installed(&installed, &p.requested);
+            if already {
+                report::print_message(MessageType::Info, &format!("Package '{}' is already installed", p.requested));
+            }
+            !already
+        }),
+        Err(e) => log::debug!("Could not list installed packages: {}", e),
+    }
     progress.finish_and_clear();
 
+    if pending.is_empty() {
+        if dry_run {
+            println!("{}", serde_json::to_string_pretty(&InstallPlan::default())?);
+        }
+        return Ok(());
+    }
+
+    // Check each package on its own, so consent given for one never covers another
+    let mut failures = Vec::new();
+    let mut plans = Vec::new();
+    let mut accepted = Vec::new();
+    for mut p in pending {
+        match check_install(executor.as_ref(), &mut p, &mut config, yes || dry_run)? {
+            Ok(plan) => {
+                plans.push(plan);
+                accepted.push(p);
+            }
+            Err(e) => {
+                report_install_failure(&p.requested, &e);
+                failures.push(e);
+            }
+        }
+    }
+
+    if accepted.is_empty() {
+        return Err(failures.remove(0).into());
+    }
+
+    let packages: Vec<PackageRef> = accepted.iter().map(|p| p.package.clone()).collect();
+    let mut allowed: Vec<Refusal> = Vec::new();
+    for refusal in accepted.iter().flat_map(|p| &p.allowed) {
+        if !allowed.contains(refusal) {
+            allowed.push(*refusal);
+        }
+    }
+    let names = display_names(&accepted.iter().map(|p| p.requested.clone()).collect::<Vec<_>>());
+
+    // One plan for the whole transaction
+    let plan = if plans.len() == 1 {
+        plans.remove(0)
+    } else {
+        let progress = ProgressIndicator::spinner("Planning installation...");
+        let plan = executor.plan_install(&packages, &allowed);
+        progress.finish_and_clear();
+        match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                let suggestion = e.suggestion().unwrap_or_else(|| "Try installing the packages one at a time".to_string());
+                let message = format!("Planning failed: {}", e);
+                return Err(report::fail(e, &message, &suggestion));
+            }
+        }
+    };
+
+    if dry_run {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return match failures.into_iter().next() {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        };
+    }
+
     // Confirm unless --yes flag
     if !yes {
+        eprint!("{}", OutputFormatter::format_install_plan(&plan));
+
         use dialoguer::Confirm;
         let confirmed = Confirm::new()
-            .with_prompt(format!("Proceed with installation of '{}'?", package))
+            .with_prompt(format!("Proceed with installation of '{}' ({})?", names, OutputFormatter::format_size_delta(plan.disk_delta)))
             .default(false)
             .interact()?;
 
         if !confirmed {
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Info, "Installation cancelled"));
+            report::print_message(MessageType::Info, "Installation cancelled");
             return Ok(());
         }
     }
 
-    // Perform installation with progress indicator
-    let progress = ProgressIndicator::spinner(&format!("Installing '{}'...", package));
-    match executor.install(package) {
+    // Install everything in one transaction
+    let progress = ProgressIndicator::spinner(&format!("Installing '{}'...", names));
+    let result = executor.install_allowing(&packages, &allowed);
+    progress.finish_and_clear();
+
+    match result {
         Ok(()) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Success, &format!("Successfully installed '{}'", package)));
-            Ok(())
+            for p in &accepted {
+                report::print_message(MessageType::Success, &format!("Successfully installed '{}'", p.requested));
+                record_usage(&p.requested, UsageSignal::Installed);
+            }
         }
         Err(NixError::AlreadyInstalled(_)) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Info, &format!("Package '{}' is already installed", package)));
-            Ok(())
+            report::print_message(MessageType::Info, &format!("Package '{}' is already installed", names));
         }
         Err(e) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_error_with_suggestion(
-                &format!("Installation failed: {}", e),
-                "Try updating your Nix channels with 'nsfw update' or check package name"
-            ));
+            let suggestion = e.suggestion().unwrap_or_else(|| {
+                "Try updating your Nix channels with 'nsfw update' or check package name".to_string()
+            });
+            let message = format!("Installation failed: {}", e);
+            let error = report::fail(e, &message, &suggestion);
+            if accepted.len() > 1 && !report::json_errors() {
+                report::print_message(MessageType::Info, "No package was installed; the profile is unchanged");
+            }
+            return Err(error);
+        }
+    }
+
+    let total = accepted.len() + failures.len();
+    match failures.into_iter().next() {
+        Some(e) => {
+            report::print_message(
+                MessageType::Warning,
+                &format!("Installed {} of {} package(s)", accepted.len(), total)
+            );
             Err(e.into())
         }
+        None => Ok(()),
     }
 }
 
-pub fn remove(package: &str, yes: bool) -> Result<()> {
-    eprintln!("{}", OutputFormatter::format_section(&format!("Removing '{}'", package)));
+/// Check that a package can be installed, asking for consent when nixpkgs
+/// refuses it
+///
+/// The outer error is a failed prompt; the inner one means the package
+/// cannot (or may not) be installed.
+fn check_install(
+    executor: &dyn NixBackend,
+    pending: &mut PendingInstall,
+    config: &mut Config,
+    non_interactive: bool,
+) -> Result<std::result::Result<InstallPlan, NixError>> {
+    loop {
+        let progress = ProgressIndicator::spinner(&format!("Checking '{}'...", pending.requested));
+        let result = executor.plan_install(std::slice::from_ref(&pending.package), &pending.allowed);
+        progress.finish_and_clear();
+
+        let e = match result {
+            Ok(plan) => return Ok(Ok(plan)),
+            Err(e) => e,
+        };
 
-    // Create bridged executor that uses WSL2
-    let progress = ProgressIndicator::spinner("Connecting to WSL2...");
-    let bridge = RealWSL2Bridge::new();
-    let executor = BridgedNixExecutor::new(bridge);
+        let Some((refusal, _)) = e.refusal().filter(|(refusal, _)| !pending.allowed.contains(refusal)) else {
+            return Ok(Err(e));
+        };
+
+        match ask_consent(&e, &pending.key, non_interactive)? {
+            Consent::Once => pending.allowed.push(refusal),
+            Consent::Always => {
+                pending.allowed.push(refusal);
+                config.allow(&pending.key, refusal);
+                config.save()?;
+                report::print_message(
+                    MessageType::Info,
+                    &format!("Remembered: '{}' may be installed although it is {}", pending.key, refusal)
+                );
+            }
+            Consent::Declined => return Ok(Err(e)),
+        }
+    }
+}
+
+/// Report a package that is left out of an install transaction
+fn report_install_failure(package: &PackageRef, e: &NixError) {
+    let suggestion = e.suggestion().unwrap_or_else(|| {
+        "Try updating your Nix channels with 'nsfw update' or check package name".to_string()
+    });
+    report::print_error_with_suggestion(&format!("Cannot install '{}': {}", package, e), &suggestion);
+}
+
+/// Comma-separated package references for messages
+fn display_names(packages: &[PackageRef]) -> String {
+    packages.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Check if a package is in the profile
+///
+/// Requests for a specific version are never considered installed, since
+/// they may replace another version.
+fn is_installed(installed: &[InstalledPackage], package: &PackageRef) -> bool {
+    if package.version.is_some() {
+        return false;
+    }
+
+    find_installed(installed, package).is_some()
+}
+
+/// Answer to installing a package nixpkgs refused
+enum Consent {
+    Once,
+    Always,
+    Declined,
+}
+
+/// Package reference under which consent is remembered
+///
+/// Consent covers all versions and outputs of a package.
+fn consent_key(package: &PackageRef) -> String {
+    PackageRef { version: None, outputs: Vec::new(), ..package.clone() }.to_string()
+}
+
+/// Explain why nixpkgs refused a package and ask whether to install it anyway
+///
+/// Never asked with `--yes`: consent has to be given explicitly, either
+/// interactively or with the `--allow-*` flags.
+fn ask_consent(refused: &NixError, key: &str, yes: bool) -> Result<Consent> {
+    let explanation = match refused {
+        NixError::UnfreeRefused { package, license } => format!(
+            "'{}' has an unfree license ({}); its terms may restrict use or redistribution", package, license
+        ),
+        NixError::InsecureRefused { package, issues } => {
+            let mut explanation = format!("'{}' is marked as insecure", package);
+            if !issues.is_empty() {
+                explanation.push_str(". Known issues:");
+                for issue in issues {
+                    explanation.push_str(&format!("\n  - {}", issue));
+                }
+            }
+            explanation
+        }
+        NixError::BrokenRefused { package } => format!(
+            "'{}' is marked as broken; it is known to fail to build or work", package
+        ),
+        _ => return Ok(Consent::Declined),
+    };
+    report::print_message(MessageType::Warning, &explanation);
+
+    if yes {
+        return Ok(Consent::Declined);
+    }
+
+    use dialoguer::Select;
+    let choices = [
+        "Install it this time".to_string(),
+        format!("Always allow installing '{}'", key),
+        "Cancel".to_string(),
+    ];
+    let choice = Select::new()
+        .with_prompt("Install it anyway?")
+        .items(&choices)
+        .default(2)
+        .interact()?;
+
+    Ok(match choice {
+        0 => Consent::Once,
+        1 => Consent::Always,
+        _ => Consent::Declined,
+    })
+}
+
+/// Remove packages in a single profile transaction
+pub fn remove(packages: &[String], yes: bool, dry_run: bool) -> Result<()> {
+    report::print_section(&format!("Removing '{}'", packages.join(", ")));
+
+    // Runs Nix natively on Linux, through WSL2 on Windows
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
+    let executor = default_backend();
 
     // Check if Nix is available
     progress.set_message("Checking Nix availability...");
     if let Err(e) = executor.check_nix_available() {
         progress.finish_and_clear();
-        eprintln!("{}", OutputFormatter::format_message(MessageType::Error, &e.to_string()));
+        report::print_error(&e.to_string());
         return Err(e.into());
     }
+
+    // Resolve every package against the profile before removing anything
+    progress.set_message("Checking installed packages...");
+    let installed = match executor.list() {
+        Ok(installed) => installed,
+        Err(e) => {
+            progress.finish_and_clear();
+            let message = format!("Failed to list packages: {}", e);
+            return Err(report::fail(
+                e,
+                &message,
+                "Ensure Nix profile is initialized"
+            ));
+        }
+    };
     progress.finish_and_clear();
 
-    // Confirm unless --yes flag
-    if !yes {
+    // Profile element names, or what the packages were installed as
+    let mut present: Vec<&str> = Vec::with_capacity(packages.len());
+    let mut missing = Vec::new();
+    for package in packages {
+        let element = match installed.iter().find(|p| p.name == *package) {
+            Some(element) => Some(element),
+            None => find_installed(&installed, &parse_package_ref(package)?),
+        };
+        match element {
+            Some(element) if !present.contains(&element.name.as_str()) => present.push(&element.name),
+            Some(_) => {}
+            None => missing.push(package.as_str()),
+        }
+    }
+    if !missing.is_empty() {
+        for name in &missing {
+            report::print_error(&format!("Package '{}' is not installed", name));
+        }
+        let error = NixError::NotInstalled(missing.join(", "));
+        let suggestion = error.suggestion().unwrap_or_default();
+        return Err(report::fail(error, "No package was removed; the profile is unchanged", &suggestion));
+    }
+    let names = present.join(", ");
+
+    // Preview what will be freed
+    if dry_run || !yes {
+        let progress = ProgressIndicator::spinner("Planning removal...");
+        let plan = executor.plan_remove(&present);
+        progress.finish_and_clear();
+
+        let prompt = match plan {
+            Ok(plan) if dry_run => {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+                return Ok(());
+            }
+            Ok(plan) => {
+                eprint!("{}", OutputFormatter::format_remove_plan(&plan));
+                format!("Proceed with removal of '{}' ({} after garbage collection)?", names, OutputFormatter::format_size_delta(plan.disk_delta))
+            }
+            Err(e) if dry_run => {
+                let suggestion = e.suggestion().unwrap_or_else(|| "Check if the package name is correct with 'nsfw list'".to_string());
+                let message = format!("Planning failed: {}", e);
+                return Err(report::fail(e, &message, &suggestion));
+            }
+            Err(e) => {
+                log::debug!("Could not plan removal: {}", e);
+                format!("Proceed with removal of '{}'?", names)
+            }
+        };
+
         use dialoguer::Confirm;
         let confirmed = Confirm::new()
-            .with_prompt(format!("Proceed with removal of '{}'?", package))
+            .with_prompt(prompt)
             .default(false)
             .interact()?;
 
         if !confirmed {
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Info, "Removal cancelled"));
+            report::print_message(MessageType::Info, "Removal cancelled");
             return Ok(());
         }
     }
 
-    // Perform removal with progress indicator
-    let progress = ProgressIndicator::spinner(&format!("Removing '{}'...", package));
-    match executor.remove(package) {
+    // Remove everything in one transaction
+    let progress = ProgressIndicator::spinner(&format!("Removing '{}'...", names));
+    let result = executor.remove_all(&present);
+    progress.finish_and_clear();
+
+    match result {
         Ok(()) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Success, &format!("Successfully removed '{}'", package)));
-            Ok(())
-        }
-        Err(NixError::NotInstalled(_)) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_message(MessageType::Warning, &format!("Package '{}' is not installed", package)));
+            for name in &present {
+                report::print_message(MessageType::Success, &format!("Successfully removed '{}'", name));
+            }
             Ok(())
         }
         Err(e) => {
-            progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_error_with_suggestion(
-                &format!("Removal failed: {}", e),
-                "Check if the package name is correct with 'nsfw list'"
-            ));
-            Err(e.into())
+            let suggestion = e.suggestion().unwrap_or_else(|| {
+                "Check if the package name is correct with 'nsfw list'".to_string()
+            });
+            let message = format!("Removal failed: {}", e);
+            let error = report::fail(e, &message, &suggestion);
+            if present.len() > 1 && !report::json_errors() {
+                report::print_message(MessageType::Info, "No package was removed; the profile is unchanged");
+            }
+            Err(error)
         }
     }
 }
 
-pub fn list(detailed: bool, format: &str) -> Result<()> {
-    eprintln!("{}", OutputFormatter::format_section("Installed Packages"));
+pub fn list(detailed: bool, format: &str, offline: bool) -> Result<()> {
+    report::print_section("Installed Packages");
 
-    // Create bridged executor that uses WSL2
-    let progress = ProgressIndicator::spinner("Connecting to WSL2...");
-    let bridge = RealWSL2Bridge::new();
-    let executor = BridgedNixExecutor::new(bridge);
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
 
-    // Check if Nix is available
-    progress.set_message("Checking Nix availability...");
-    if let Err(e) = executor.check_nix_available() {
-        progress.finish_and_clear();
-        eprintln!("{}", OutputFormatter::format_message(MessageType::Error, &e.to_string()));
-        return Err(e.into());
+    // Runs Nix natively on Linux, through WSL2 on Windows
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
+    let executor = default_backend();
+
+    // Check if Nix is available (unreachable Nix falls back to the snapshot)
+    let mut offline = offline;
+    if !offline {
+        progress.set_message("Checking Nix availability...");
+        match executor.check_nix_available() {
+            Ok(_) => {}
+            Err(e) if e.is_connectivity() => {
+                report::print_message(MessageType::Warning, &format!("{} - falling back to the last profile snapshot", e));
+                offline = true;
+            }
+            Err(e) => {
+                progress.finish_and_clear();
+                report::print_error(&e.to_string());
+                return Err(e.into());
+            }
+        }
     }
 
     // Get list of installed packages
     progress.set_message("Retrieving package list...");
-    match executor.list() {
-        Ok(packages) => {
+    match installed_packages(&pkg_cache, executor.as_ref(), offline) {
+        Ok((packages, snapshot_at)) => {
             progress.finish_and_clear();
 
+            if snapshot_at.is_some() {
+                print_stale_notice("installed packages from the last profile snapshot", snapshot_at);
+            }
+
             // Output based on format
             match format {
+                "json" if snapshot_at.is_some() => print_stale_json("packages", &packages, snapshot_at)?,
                 "json" => {
                     let json = serde_json::to_string_pretty(&packages)?;
                     println!("{}", json);
                 }
                 _ => {
                     if packages.is_empty() {
-                        eprintln!("{}", OutputFormatter::format_message(MessageType::Info, "No packages installed"));
+                        report::print_message(MessageType::Info, "No packages installed");
                     } else {
-                        eprintln!("{}", OutputFormatter::format_message(MessageType::Success, &format!("{} package(s) installed", packages.len())));
+                        report::print_message(MessageType::Success, &format!("{} package(s) installed", packages.len()));
                         print!("{}", OutputFormatter::format_installed_packages(&packages, detailed));
                     }
                 }
@@ -318,32 +1034,443 @@ pub fn list(detailed: bool, format: &str) -> Result<()> {
         }
         Err(e) => {
             progress.finish_and_clear();
-            eprintln!("{}", OutputFormatter::format_error_with_suggestion(
-                &format!("Failed to list packages: {}", e),
-                "Ensure Nix profile is initialized"
+            let message = format!("Failed to list packages: {}", e);
+            let suggestion = installed_packages_suggestion(&e, "Ensure Nix profile is initialized");
+            Err(report::fail(e, &message, &suggestion))
+        }
+    }
+}
+
+pub fn info(package: &str, format: &str, offline: bool) -> Result<()> {
+    let package_ref = parse_package_ref(package)?;
+
+    report::print_section(&format!("Package info for '{}'", package));
+
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    let executor = default_backend();
+    let progress = ProgressIndicator::spinner("Looking up package...");
+
+    // Metadata comes from the package cache, falling back to evaluating the package
+    let mut offline = offline;
+    let cached = match package_ref.attr_path.as_deref() {
+        Some(attr_path) if package_ref.is_nixpkgs() => pkg_cache.find_package(attr_path)?,
+        _ => None,
+    };
+    let details = match cached {
+        Some(cached) => {
+            if let Err(e) = pkg_cache.record_usage(&cached.attr_path, UsageSignal::Selected) {
+                log::debug!("Failed to record package usage: {}", e);
+            }
+            cached
+        }
+        None if offline => {
+            progress.finish_and_clear();
+            let e = NixError::PackageNotFound(package.to_string());
+            let message = e.to_string();
+            return Err(report::fail(
+                e,
+                &message,
+                "The package is not in the local cache; retry without --offline"
             ));
-            Err(e.into())
+        }
+        None => match executor.describe(&package_ref) {
+            Ok(described) => CachedPackage {
+                name: described.pname,
+                version: described.version,
+                description: described.description,
+                attr_path: package_ref.installable(),
+                ..Default::default()
+            },
+            Err(e @ NixError::AttributeNotFound { .. }) => {
+                progress.finish_and_clear();
+                let message = e.to_string();
+                return Err(report::fail(
+                    e,
+                    &message,
+                    &format!("Try 'nsfw search {}' to find similar packages", package_ref.name())
+                ));
+            }
+            Err(e) => {
+                progress.finish_and_clear();
+                let suggestion = if e.is_connectivity() {
+                    "The package is not in the local cache, so it cannot be looked up offline".to_string()
+                } else {
+                    e.suggestion().unwrap_or_else(|| "Check the package name with 'nsfw search'".to_string())
+                };
+                let message = e.to_string();
+                return Err(report::fail(e, &message, &suggestion));
+            }
+        },
+    };
+
+    // Install state from the profile (or its snapshot)
+    progress.set_message("Checking installed packages...");
+    let installed = match installed_packages(&pkg_cache, executor.as_ref(), offline) {
+        Ok((packages, snapshot_at)) => {
+            offline |= snapshot_at.is_some();
+            Some(packages.into_iter().find(|p| p.name == details.name || p.name == package_ref.name()))
+        }
+        Err(e) => {
+            log::debug!("Could not determine install state: {}", e);
+            None
+        }
+    };
+    progress.finish_and_clear();
+
+    let built_at = pkg_cache.provenance()?.map(|p| p.built_at);
+    if offline {
+        print_stale_notice("package details from the local cache", built_at);
+    }
+
+    // Versions that can be requested with `package@version`
+    let known_versions = match package_ref.attr_path.as_deref() {
+        Some(attr_path) if package_ref.is_nixpkgs() => pkg_cache.known_versions(attr_path)?,
+        _ => Vec::new(),
+    };
+
+    if format == "json" {
+        let json = serde_json::json!({
+            "package": details,
+            "installed": installed.as_ref().map(|p| p.is_some()),
+            "installed_version": installed.as_ref().and_then(|p| p.as_ref().map(|p| p.version.clone())),
+            "known_versions": known_versions,
+            "stale": offline,
+            "as_of": if offline { built_at } else { None },
+        });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    let mut output = String::new();
+    output.push_str(&OutputFormatter::format_field("Name", &details.name));
+    output.push_str(&OutputFormatter::format_field("Version", &details.version));
+    if !details.attr_path.is_empty() {
+        output.push_str(&OutputFormatter::format_field("Attribute", &details.attr_path));
+    }
+    if !details.description.is_empty() {
+        output.push_str(&OutputFormatter::format_field("Description", &details.description));
+    }
+    if !details.license.is_empty() {
+        output.push_str(&OutputFormatter::format_field("License", &details.license));
+    }
+    if !details.platforms.is_empty() {
+        output.push_str(&OutputFormatter::format_field("Platforms", &details.platforms));
+    }
+    if details.unfree {
+        output.push_str(&OutputFormatter::format_field("Unfree", "yes"));
+    }
+    if details.broken {
+        output.push_str(&OutputFormatter::format_field("Broken", "yes"));
+    }
+    if !known_versions.is_empty() {
+        output.push_str(&OutputFormatter::format_field("Known versions", &known_versions.join(", ")));
+    }
+    let installed = match &installed {
+        Some(Some(p)) => format!("yes ({})", p.version),
+        Some(None) => "no".to_string(),
+        None => "unknown".to_string(),
+    };
+    output.push_str(&OutputFormatter::format_field("Installed", &installed));
+    print!("{}", output);
+
+    Ok(())
+}
+
+pub fn update(force: bool) -> Result<()> {
+    report::print_section("Updating package database");
+
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    // Only one build at a time, foreground or background
+    let Some(_lock) = BuildLock::acquire(&pkg_cache)? else {
+        let message = match pkg_cache.build_state()? {
+            BuildState::Building { percent, .. } => format!("A cache build is already running ({}%)", percent),
+            _ => "A cache build is already running".to_string(),
+        };
+        report::print_message(MessageType::Info, &message);
+        return Ok(());
+    };
+
+    // Runs Nix natively on Linux, through WSL2 on Windows
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
+    let executor = default_backend();
+
+    // Check if Nix is available
+    progress.set_message("Checking Nix availability...");
+    if let Err(e) = executor.check_nix_available() {
+        progress.finish_and_clear();
+        report::print_error(&e.to_string());
+        return Err(e.into());
+    }
+
+    progress.set_message("Loading nixpkgs package set (this can take a few minutes)...");
+    let builder = CacheBuilder::new(pkg_cache, default_bridge()).force(force);
+    match builder.update_incremental() {
+        Ok(delta) => {
+            progress.finish_and_clear();
+            if delta.is_empty() {
+                report::print_message(MessageType::Success, "Package database is already up to date");
+            } else {
+                report::print_message(MessageType::Success, &format!("Package database updated: {}", delta.summary()));
+                print!("{}", OutputFormatter::format_cache_delta(&delta, 25));
+            }
+            Ok(())
+        }
+        Err(e) => {
+            progress.finish_and_clear();
+            let message = format!("Update failed: {}", e);
+            let suggestion = if e.downcast_ref::<TruncatedPackageSet>().is_some() {
+                "If nixpkgs really lost those packages, apply the update with 'nsfw update --force'"
+            } else {
+                "Check that nix-env works in WSL2 and that your nixpkgs channel is configured"
+            };
+            Err(report::fail(e, &message, suggestion))
+        }
+    }
+}
+
+pub fn cache_status(format: &str) -> Result<()> {
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    let stats = pkg_cache.stats()?;
+    let provenance = pkg_cache.provenance()?;
+    let build_state = pkg_cache.build_state()?;
+    let build_running = BuildLock::is_held(&pkg_cache);
+    let search_stats = SearchCache::new(&pkg_cache)?.stats();
+
+    if format == "json" {
+        let json = serde_json::json!({
+            "revision": provenance.as_ref().map(|p| p.revision.revision.clone()),
+            "source": provenance.as_ref().map(|p| p.revision.source),
+            "built_at": provenance.as_ref().map(|p| p.built_at),
+            "package_count": stats.total_packages,
+            "db_size": stats.db_size,
+            "db_path": pkg_cache.db_path(),
+            "last_delta": pkg_cache.last_delta()?,
+            "build": build_state,
+            "build_running": build_running,
+            "search_cache": {
+                "entries": search_stats.entries,
+                "expired": search_stats.expired,
+                "hits": search_stats.hits,
+                "misses": search_stats.misses,
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    report::print_section("Package Cache");
+
+    let status = match &build_state {
+        BuildState::Building { percent, .. } if build_running => format!("building: {}%", percent),
+        BuildState::Building { .. } => "interrupted (run 'nsfw update' to rebuild)".to_string(),
+        BuildState::Failed { error } => format!("last build failed: {}", error),
+        BuildState::Idle => "idle".to_string(),
+    };
+
+    let mut output = String::new();
+    output.push_str(&OutputFormatter::format_field("Status", &status));
+    match &provenance {
+        Some(p) => {
+            output.push_str(&OutputFormatter::format_field("Revision", &p.revision.revision));
+            output.push_str(&OutputFormatter::format_field("Source", p.revision.source.as_str()));
+            output.push_str(&OutputFormatter::format_field("Built", &format_timestamp(p.built_at)));
+        }
+        None => {
+            output.push_str(&OutputFormatter::format_field("Revision", "unknown (not built from a tracked revision)"));
+        }
+    }
+    if let Some(delta) = pkg_cache.last_delta()? {
+        output.push_str(&OutputFormatter::format_field("Last update", &delta.summary()));
+    }
+    output.push_str(&OutputFormatter::format_field("Packages", &stats.total_packages.to_string()));
+    output.push_str(&OutputFormatter::format_field("Search cache", &format!(
+        "{} entries, {} hits / {} misses ({:.0}% hit rate)",
+        search_stats.entries, search_stats.hits, search_stats.misses, search_stats.hit_rate() * 100.0
+    )));
+    output.push_str(&OutputFormatter::format_field("Database size", &OutputFormatter::format_size(stats.db_size)));
+    output.push_str(&OutputFormatter::format_field("Location", &pkg_cache.db_path().display().to_string()));
+    print!("{}", output);
+
+    Ok(())
+}
+
+pub fn cache_export(file: &Path) -> Result<()> {
+    report::print_section("Exporting package cache");
+
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    if pkg_cache.is_empty() {
+        return Err(report::fail(
+            anyhow!("Package cache is empty"),
+            "The package cache is empty",
+            "Run 'nsfw update' to build it before exporting"
+        ));
+    }
+
+    let progress = ProgressIndicator::spinner("Writing snapshot...");
+    match pkg_cache.export_snapshot(file) {
+        Ok(manifest) => {
+            progress.finish_and_clear();
+            let size = std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+            report::print_message(
+                MessageType::Success,
+                &format!("Exported {} packages to {}", manifest.package_count, file.display())
+            );
+            print!("{}", format_snapshot_manifest(&manifest, size));
+            Ok(())
+        }
+        Err(e) => {
+            progress.finish_and_clear();
+            report::print_error(&format!("Export failed: {}", e));
+            Err(e)
+        }
+    }
+}
+
+pub fn cache_import(file: &Path, yes: bool) -> Result<()> {
+    report::print_section("Importing package cache");
+
+    let manifest = match read_manifest(file) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let message = format!("Cannot import {}: {}", file.display(), e);
+            return Err(report::fail(
+                e,
+                &message,
+                "Create snapshots with 'nsfw cache export <file>'"
+            ));
+        }
+    };
+    let size = std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+    print!("{}", format_snapshot_manifest(&manifest, size));
+
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    // Never swap the database under a running build
+    let Some(_lock) = BuildLock::acquire(&pkg_cache)? else {
+        report::print_message(
+            MessageType::Warning,
+            "A cache build is running; try again when it has finished"
+        );
+        return Err(anyhow!("Package cache is being rebuilt"));
+    };
+
+    if !yes {
+        use dialoguer::Confirm;
+        let confirmed = Confirm::new()
+            .with_prompt("Replace the local package cache with this snapshot?")
+            .default(false)
+            .interact()?;
+
+        if !confirmed {
+            report::print_message(MessageType::Info, "Import cancelled");
+            return Ok(());
+        }
+    }
+
+    let progress = ProgressIndicator::spinner("Verifying and importing snapshot...");
+    match pkg_cache.import_snapshot(file) {
+        Ok(manifest) => {
+            progress.finish_and_clear();
+            report::print_message(
+                MessageType::Success,
+                &format!("Imported {} packages", manifest.package_count)
+            );
+            Ok(())
+        }
+        Err(e) => {
+            progress.finish_and_clear();
+            let message = format!("Import failed: {}", e);
+            Err(report::fail(
+                e,
+                &message,
+                "The existing cache was left unchanged; re-export the snapshot and copy it again"
+            ))
+        }
+    }
+}
+
+/// Describe a cache snapshot for display
+fn format_snapshot_manifest(manifest: &SnapshotManifest, file_size: u64) -> String {
+    let mut output = String::new();
+    match &manifest.provenance {
+        Some(p) => {
+            output.push_str(&OutputFormatter::format_field("Revision", &p.revision.revision));
+            output.push_str(&OutputFormatter::format_field("Source", p.revision.source.as_str()));
+            output.push_str(&OutputFormatter::format_field("Built", &format_timestamp(p.built_at)));
+        }
+        None => {
+            output.push_str(&OutputFormatter::format_field("Revision", "unknown"));
         }
     }
+    output.push_str(&OutputFormatter::format_field("Packages", &manifest.package_count.to_string()));
+    output.push_str(&OutputFormatter::format_field("Exported", &format!(
+        "{} by nsfw {}", format_timestamp(manifest.created_at), manifest.created_by
+    )));
+    output.push_str(&OutputFormatter::format_field("Size", &format!(
+        "{} ({} uncompressed)",
+        OutputFormatter::format_size(file_size),
+        OutputFormatter::format_size(manifest.db_size)
+    )));
+    output.push_str(&OutputFormatter::format_field("SHA-256", &manifest.sha256));
+    output
 }
 
-pub fn info(package: &str) -> Result<()> {
-    println!("ℹ️  Package info for '{}'", package);
-    println!("⚠️  Info not yet implemented (Phase 1 Day 3-4)");
+pub fn cache_reset_stats() -> Result<()> {
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+    pkg_cache.reset_popularity()?;
+
+    report::print_message(MessageType::Success, "Package popularity statistics cleared");
     Ok(())
 }
 
-pub fn update() -> Result<()> {
-    println!("🔄 Updating package database");
-    println!("⚠️  Update not yet implemented (Phase 1 Day 3-4)");
+/// Entry point of the detached background cache worker
+pub fn cache_refresh_worker() -> Result<()> {
+    let pkg_cache = PackageCache::new()?;
+    pkg_cache.initialize()?;
+
+    let Some(_lock) = BuildLock::acquire(&pkg_cache)? else {
+        log::debug!("Another cache build is running, worker exiting");
+        return Ok(());
+    };
+
+    // Throttle checks even if this one fails
+    pkg_cache.mark_refresh_checked()?;
+
+    let builder = CacheBuilder::new(pkg_cache, default_bridge());
+    if !builder.needs_update()? {
+        log::debug!("Package cache is up to date");
+        return Ok(());
+    }
+
+    log::info!("Starting background cache update");
+    builder.update_incremental()?;
     Ok(())
 }
 
+/// Format a Unix timestamp in local time
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 pub fn generate_wrapper(package: &str, package_path: &str) -> Result<()> {
+    // Wrappers are named after the package, not its set or flake
+    let package = &parse_package_ref(package)?.name();
     println!("🔧 Generating wrapper for '{}'...", package);
 
     // Determine output directory (current directory by default)
-    let output_dir = PathBuf::from(".");
+    let output_dir = Config::load()?.wrapper_dir.unwrap_or_else(|| PathBuf::from("."));
     let generator = WrapperGenerator::new(output_dir);
 
     // Validate the Nix store path
@@ -377,9 +1504,276 @@ pub fn generate_wrapper(package: &str, package_path: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn setup(auto_yes: bool, interactive: bool) -> Result<()> {
+/// Run the setup wizard
+///
+/// `sources` from the command line take precedence over the `setup` section
+/// of the configuration.
+pub fn setup(auto_yes: bool, interactive: bool, sources: SetupSources) -> Result<()> {
     use crate::setup::SetupWizard;
 
-    let wizard = SetupWizard::new(auto_yes, interactive);
+    let configured = Config::load()?.setup;
+    let sources = SetupSources {
+        distro: sources.distro.or(configured.distro),
+        distro_image: sources.distro_image.or(configured.distro_image),
+        distro_install_dir: sources.distro_install_dir.or(configured.distro_install_dir),
+        nix_installer: sources.nix_installer.or(configured.nix_installer),
+        nixpkgs: sources.nixpkgs.or(configured.nixpkgs),
+    };
+
+    let wizard = SetupWizard::new(auto_yes, interactive).with_sources(sources);
     wizard.run()
 }
+
+/// Connect to Nix for a store command, reporting failures
+fn connect_store() -> Result<Box<dyn NixBackend>> {
+    let progress = ProgressIndicator::spinner("Connecting to Nix...");
+    let executor = default_backend();
+
+    progress.set_message("Checking Nix availability...");
+    let available = executor.check_nix_available();
+    progress.finish_and_clear();
+
+    if let Err(e) = available {
+        report::print_error(&e.to_string());
+        return Err(e.into());
+    }
+    Ok(executor)
+}
+
+/// Show where the Nix store's disk space goes
+pub fn du(top: usize, format: &str) -> Result<()> {
+    report::print_section("Nix Store Usage");
+
+    let executor = connect_store()?;
+    let progress = ProgressIndicator::spinner("Measuring the Nix store...");
+    let usage = executor.store_usage(top);
+    progress.finish_and_clear();
+
+    let usage = match usage {
+        Ok(usage) => usage,
+        Err(e) => {
+            let suggestion = e.suggestion().unwrap_or_else(|| "Run with --verbose to see the failing Nix command".to_string());
+            let message = format!("Failed to measure the store: {}", e);
+            return Err(report::fail(e, &message, &suggestion));
+        }
+    };
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&usage)?);
+        return Ok(());
+    }
+
+    print!("{}", OutputFormatter::format_store_usage(&usage));
+
+    let old_generations = usage.generations.iter().filter(|g| !g.current).count();
+    if usage.reclaimable_size > 0 || old_generations > 0 {
+        report::print_message(
+            MessageType::Info,
+            "Run 'nsfw gc' to free reclaimable space, or 'nsfw gc --older-than 30d' to also delete old generations"
+        );
+    }
+
+    Ok(())
+}
+
+/// Delete old profile generations and collect garbage
+pub fn gc(older_than_days: Option<u32>, dry_run: bool, yes: bool, format: &str) -> Result<()> {
+    report::print_section("Nix Garbage Collection");
+
+    let executor = connect_store()?;
+
+    // Preview what will be deleted
+    if dry_run || !yes {
+        let progress = ProgressIndicator::spinner("Finding garbage...");
+        let preview = executor.gc_preview(older_than_days, chrono::Local::now().naive_local());
+        progress.finish_and_clear();
+
+        let preview = match preview {
+            Ok(preview) => preview,
+            Err(e) => {
+                let suggestion = e.suggestion().unwrap_or_else(|| "Run with --verbose to see the failing Nix command".to_string());
+                let message = format!("Failed to find garbage: {}", e);
+                return Err(report::fail(e, &message, &suggestion));
+            }
+        };
+
+        if dry_run && format == "json" {
+            println!("{}", serde_json::to_string_pretty(&preview)?);
+            return Ok(());
+        }
+
+        let generations = if preview.generations.is_empty() {
+            "none".to_string()
+        } else {
+            preview.generations.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+        };
+        let mut output = String::new();
+        if older_than_days.is_some() {
+            output.push_str(&OutputFormatter::format_field("Generations to delete", &generations));
+        }
+        output.push_str(&OutputFormatter::format_field("Dead store paths", &preview.dead_path_count.to_string()));
+        if older_than_days.is_some() {
+            output.push_str(&OutputFormatter::format_field("Paths only old generations use", &preview.released_path_count.to_string()));
+        }
+        output.push_str(&OutputFormatter::format_field("Space freed", &OutputFormatter::format_size(preview.reclaimable_size)));
+        print!("{}", output);
+
+        if dry_run {
+            return Ok(());
+        }
+
+        use dialoguer::Confirm;
+        let confirmed = Confirm::new()
+            .with_prompt("Proceed with garbage collection?")
+            .default(false)
+            .interact()?;
+
+        if !confirmed {
+            report::print_message(MessageType::Info, "Garbage collection cancelled");
+            return Ok(());
+        }
+    }
+
+    let progress = ProgressIndicator::spinner("Collecting garbage...");
+    let report = executor.collect_garbage(older_than_days);
+    progress.finish_and_clear();
+
+    match report {
+        Ok(report) => {
+            if format == "json" {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report::print_message(
+                    MessageType::Success,
+                    &format!(
+                        "Deleted {} generation(s) and {} store path(s), freeing {}",
+                        report.generations_removed.len(),
+                        report.paths_deleted,
+                        OutputFormatter::format_size(report.bytes_freed)
+                    )
+                );
+                if !runs_natively() && report.bytes_freed > 0 {
+                    report::print_message(
+                        MessageType::Info,
+                        "The WSL2 virtual disk does not shrink by itself; compact it with 'Optimize-VHD' or 'diskpart' to return the space to Windows"
+                    );
+                }
+            }
+            Ok(())
+        }
+        Err(e) => {
+            let suggestion = e.suggestion().unwrap_or_else(|| "Run with --verbose to see the failing Nix command".to_string());
+            let message = format!("Garbage collection failed: {}", e);
+            Err(report::fail(e, &message, &suggestion))
+        }
+    }
+}
+
+/// Check the installation for problems, optionally repairing them
+pub fn doctor(fix: bool, yes: bool, format: &str) -> Result<()> {
+    use crate::setup::doctor::{CheckStatus, Doctor};
+
+    report::print_section("NSFW Doctor");
+
+    let backend = default_backend();
+    let wrapper_dir = Config::load()?.wrapper_dir;
+    let doctor = Doctor::new(backend.as_ref(), runs_natively(), PackageCache::default_db_path()?, wrapper_dir);
+
+    let progress = ProgressIndicator::spinner("Running checks...");
+    let mut results = doctor.run();
+    progress.finish_and_clear();
+
+    let repairs: Vec<_> = results.iter()
+        .filter(|r| r.is_problem())
+        .filter_map(|r| r.repair.clone().map(|repair| (r.fix.clone(), repair)))
+        .collect();
+
+    if fix && !repairs.is_empty() {
+        if !report::json_errors() {
+            eprint!("{}", OutputFormatter::format_check_results(&results));
+            eprintln!();
+        }
+
+        let can_sudo = repairs.iter().any(|(_, repair)| repair.needs_sudo()) && doctor.can_sudo();
+
+        let mut repaired = 0;
+        for (hint, repair) in &repairs {
+            // Without a terminal sudo cannot ask for a password
+            if repair.needs_sudo() && !can_sudo {
+                if let Some(command) = repair.manual_command() {
+                    report::print_message(
+                        MessageType::Warning,
+                        &format!("{}: sudo needs a password, so run this yourself in a Linux shell:\n  {}", repair.description(), command)
+                    );
+                }
+                continue;
+            }
+
+            if !yes {
+                use dialoguer::Confirm;
+                let confirmed = Confirm::new()
+                    .with_prompt(format!("{}?", repair.description()))
+                    .default(true)
+                    .interact()?;
+                if !confirmed {
+                    continue;
+                }
+            }
+
+            match doctor.repair(repair) {
+                Ok(()) => {
+                    repaired += 1;
+                    report::print_message(MessageType::Success, &repair.description());
+                    if let Some(follow_up) = repair.follow_up().filter(|_| !report::json_errors()) {
+                        eprintln!("  {}", follow_up);
+                    }
+                }
+                Err(e) => {
+                    let suggestion = hint.clone().unwrap_or_else(|| "Run with --verbose for details".to_string());
+                    report::print_error_with_suggestion(
+                        &format!("{} failed: {:#}", repair.description(), e),
+                        &suggestion
+                    );
+                }
+            }
+        }
+
+        if repaired > 0 {
+            let progress = ProgressIndicator::spinner("Checking again...");
+            results = doctor.run();
+            progress.finish_and_clear();
+        }
+        if !report::json_errors() {
+            eprintln!();
+        }
+    }
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print!("{}", OutputFormatter::format_check_results(&results));
+    }
+
+    let failed = results.iter().filter(|r| r.status == CheckStatus::Fail).count();
+    let warned = results.iter().filter(|r| r.status == CheckStatus::Warn).count();
+    let repairable = results.iter().filter(|r| r.is_problem() && r.repair.is_some()).count();
+
+    if failed == 0 && warned == 0 {
+        report::print_message(MessageType::Success, "No problems found");
+        return Ok(());
+    }
+
+    if !fix && repairable > 0 {
+        report::print_message(
+            MessageType::Info,
+            &format!("Run 'nsfw doctor --fix' to repair {} problem(s) automatically", repairable)
+        );
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} check(s) failed", failed));
+    }
+
+    report::print_message(MessageType::Warning, &format!("{} warning(s)", warned));
+    Ok(())
+}
@@ -0,0 +1,177 @@
+/// Reporting of failed commands
+///
+/// Failures end the process with an exit code derived from the error, and
+/// are described as a JSON object on stderr when `--format json` is used.
+/// In that mode the JSON object is all that is written to stderr, so
+/// commands write headers, notices and failures through `print_section`,
+/// `print_message`, `print_error` and `fail`, which stay quiet then.
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::nix_ops::NixError;
+use crate::ui::{MessageType, OutputFormatter};
+
+/// Whether failures are reported as JSON instead of messages
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Report failures as a JSON object instead of human-readable messages
+pub fn set_json_errors(enabled: bool) {
+    JSON_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Check if failures are reported as JSON
+pub fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Show a section header, unless failures are reported as JSON
+pub fn print_section(title: &str) {
+    if !json_errors() {
+        eprintln!("{}", OutputFormatter::format_section(title));
+    }
+}
+
+/// Show a notice such as a warning or result summary (see `print_section`)
+pub fn print_message(msg_type: MessageType, message: &str) {
+    if !json_errors() {
+        eprintln!("{}", OutputFormatter::format_message(msg_type, message));
+    }
+}
+
+/// Show a failure to the user, unless failures are reported as JSON
+pub fn print_error(message: &str) {
+    if !json_errors() {
+        eprintln!("{}", OutputFormatter::format_message(MessageType::Error, message));
+    }
+}
+
+/// Show a failure and what to do about it (see `print_error`)
+pub fn print_error_with_suggestion(message: &str, suggestion: &str) {
+    if !json_errors() {
+        eprintln!("{}", OutputFormatter::format_error_with_suggestion(message, suggestion));
+    }
+}
+
+/// Show a failure with a suggestion and return the error to fail with
+///
+/// The suggestion travels with the error, so the `ErrorReport` carries the
+/// same suggestion the user would have seen.
+pub fn fail(error: impl Into<anyhow::Error>, message: &str, suggestion: &str) -> anyhow::Error {
+    print_error_with_suggestion(message, suggestion);
+    Suggested {
+        error: error.into(),
+        suggestion: suggestion.to_string(),
+    }.into()
+}
+
+/// An error together with the suggestion shown for it
+#[derive(Debug)]
+pub struct Suggested {
+    pub error: anyhow::Error,
+    pub suggestion: String,
+}
+
+impl fmt::Display for Suggested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for Suggested {}
+
+/// Code of errors that are not `NixError`s
+pub const GENERIC_ERROR_CODE: &str = "NSFW-E000";
+
+/// Exit code of errors that are not `NixError`s
+pub const GENERIC_EXIT_CODE: i32 = 1;
+
+/// A failed command, as reported to the user or a calling program
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorReport {
+    /// Stable error code (see `NixError::code`)
+    pub code: String,
+
+    /// Process exit code
+    pub exit_code: i32,
+
+    /// Human-readable description
+    pub message: String,
+
+    /// What the user can do about it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl ErrorReport {
+    /// Describe a command failure
+    ///
+    /// Uses the first `NixError` in the error's chain, so context added on
+    /// top of it does not hide the code.
+    ///
+    /// A suggestion shown with the error (see `fail`) takes precedence over
+    /// the `NixError`'s own.
+    pub fn new(error: &anyhow::Error) -> Self {
+        let suggested = error.chain().find_map(|e| e.downcast_ref::<Suggested>());
+        let nix_error = error.chain()
+            .chain(suggested.into_iter().flat_map(|s| s.error.chain()))
+            .find_map(|e| e.downcast_ref::<NixError>());
+
+        Self {
+            code: nix_error.map_or(GENERIC_ERROR_CODE, NixError::code).to_string(),
+            exit_code: nix_error.map_or(GENERIC_EXIT_CODE, NixError::exit_code),
+            message: format!("{:#}", error),
+            suggestion: suggested.map(|s| s.suggestion.clone())
+                .or_else(|| nix_error.and_then(NixError::suggestion)),
+        }
+    }
+
+    /// Single-line JSON object
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("error report is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_report_nix_error() {
+        let error: anyhow::Error = NixError::NixNotInstalled.into();
+        let report = ErrorReport::new(&error);
+        assert_eq!(report.code, "NSFW-E010");
+        assert_eq!(report.exit_code, 20);
+        assert!(report.suggestion.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["code"], "NSFW-E010");
+        assert_eq!(json["exit_code"], 20);
+    }
+
+    #[test]
+    fn test_report_wrapped_and_generic_errors() {
+        let error = Err::<(), _>(NixError::OutOfDiskSpace { path: None })
+            .context("Failed to install firefox")
+            .unwrap_err();
+        let report = ErrorReport::new(&error);
+        assert_eq!(report.code, "NSFW-E015");
+        assert_eq!(report.message, "Failed to install firefox: Out of disk space");
+
+        let error = fail(
+            NixError::InvalidPackageRef { input: "foo bar".to_string(), reason: "unexpected ' '".to_string() },
+            "Invalid package",
+            "Use a name or attribute path",
+        );
+        let report = ErrorReport::new(&error);
+        assert_eq!(report.code, "NSFW-E008");
+        assert!(report.message.contains("foo bar"));
+        assert_eq!(report.suggestion.as_deref(), Some("Use a name or attribute path"));
+
+        let report = ErrorReport::new(&anyhow::anyhow!("Package cache is empty"));
+        assert_eq!(report.code, GENERIC_ERROR_CODE);
+        assert_eq!(report.exit_code, GENERIC_EXIT_CODE);
+        assert!(!report.to_json().contains("suggestion"));
+    }
+}
@@ -0,0 +1,173 @@
+//! User configuration
+//!
+//! Settings NSFW remembers between runs, stored as JSON in
+//! `~/.config/nsfw/config.json` (`%APPDATA%\nsfw\config.json` on Windows).
+//! A missing file is the default configuration.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::nix_ops::Refusal;
+use crate::setup::SetupSources;
+
+/// Packages the user agreed to install although nixpkgs refuses them
+///
+/// Entries are package references as given to `nsfw install`, without
+/// version or outputs (e.g. `vscode`, `github:owner/repo#tool`).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedPackages {
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub unfree: BTreeSet<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub insecure: BTreeSet<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub broken: BTreeSet<String>,
+}
+
+impl AllowedPackages {
+    fn set(&self, refusal: Refusal) -> &BTreeSet<String> {
+        match refusal {
+            Refusal::Unfree => &self.unfree,
+            Refusal::Insecure => &self.insecure,
+            Refusal::Broken => &self.broken,
+        }
+    }
+
+    fn set_mut(&mut self, refusal: Refusal) -> &mut BTreeSet<String> {
+        match refusal {
+            Refusal::Unfree => &mut self.unfree,
+            Refusal::Insecure => &mut self.insecure,
+            Refusal::Broken => &mut self.broken,
+        }
+    }
+}
+
+/// NSFW user configuration
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    /// Per-package consent to unfree, insecure and broken packages
+    #[serde(default)]
+    pub allowed: AllowedPackages,
+
+    /// Directory Windows wrapper scripts are written to
+    ///
+    /// Should be on `PATH` so wrapped packages can be run by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper_dir: Option<PathBuf>,
+
+    /// Local files `nsfw setup` installs from on offline machines
+    #[serde(default, skip_serializing_if = "SetupSources::is_empty")]
+    pub setup: SetupSources,
+}
+
+impl Config {
+    /// Default location of the configuration file
+    pub fn default_path() -> Result<PathBuf> {
+        let config_dir = if cfg!(target_os = "windows") {
+            dirs::config_dir()
+                .context("Could not find config directory")?
+                .join("nsfw")
+        } else {
+            PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string()))
+                .join(".config")
+                .join("nsfw")
+        };
+
+        Ok(config_dir.join("config.json"))
+    }
+
+    /// Load the configuration from its default location
+    pub fn load() -> Result<Self> {
+        Self::load_from(&Self::default_path()?)
+    }
+
+    /// Load the configuration from a file, defaulting when it does not exist
+    pub fn load_from(path: &Path) -> Result<Self> {
+        load_json_file(path, "configuration file")
+    }
+
+    /// Save the configuration to its default location
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&Self::default_path()?)
+    }
+
+    /// Save the configuration to a file
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        save_json_file(self, path)
+    }
+
+    /// The refusals the user has overridden for a package
+    pub fn allowances(&self, package: &str) -> Vec<Refusal> {
+        Refusal::ALL.into_iter()
+            .filter(|refusal| self.allowed.set(*refusal).contains(package))
+            .collect()
+    }
+
+    /// Remember that the user allows a refused package
+    ///
+    /// Returns false if it was already allowed.
+    pub fn allow(&mut self, package: &str, refusal: Refusal) -> bool {
+        self.allowed.set_mut(refusal).insert(package.to_string())
+    }
+}
+
+/// Load a JSON settings file, defaulting when it does not exist
+///
+/// `kind` names the file in errors, e.g. "configuration file".
+pub(crate) fn load_json_file<T: DeserializeOwned + Default>(path: &Path, kind: &str) -> Result<T> {
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json)
+            .with_context(|| format!("Invalid {} {}", kind, path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Save a JSON settings file, creating its directory
+pub(crate) fn save_json_file<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let json = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_file_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load_from(&dir.path().join("config.json")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_allowances_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nsfw").join("config.json");
+
+        let mut config = Config::default();
+        assert!(config.allow("vscode", Refusal::Unfree));
+        assert!(!config.allow("vscode", Refusal::Unfree));
+        config.allow("openssl_1_1", Refusal::Insecure);
+        config.save_to(&path).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.allowances("vscode"), vec![Refusal::Unfree]);
+        assert_eq!(config.allowances("openssl_1_1"), vec![Refusal::Insecure]);
+        assert!(config.allowances("firefox").is_empty());
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("broken"));
+    }
+}
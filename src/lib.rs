@@ -16,6 +16,9 @@ pub mod cache;
 pub mod ui;
 pub mod package_cache;
 
+// User configuration
+pub mod config;
+
 // Setup wizard module
 pub mod setup;
 
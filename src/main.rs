@@ -1,8 +1,13 @@
 use clap::{Parser, Subcommand};
-use log::{info, error};
+use log::{debug, error};
+use std::path::PathBuf;
 
 // Use the library modules
 use nsfw::cli;
+use nsfw::cli::report::ErrorReport;
+use nsfw::nix_ops::Refusal;
+use nsfw::package_cache::SearchFilters;
+use nsfw::setup::{LocalFile, SetupSources};
 
 #[derive(Parser)]
 #[command(name = "nsfw")]
@@ -34,28 +39,96 @@ enum Commands {
         /// Output format (text, json)
         #[arg(short, long, default_value = "text")]
         format: String,
+
+        /// Only show packages with a free license
+        #[arg(long)]
+        free_only: bool,
+
+        /// Hide packages marked as broken
+        #[arg(long)]
+        exclude_broken: bool,
+
+        /// Only show packages that are installed
+        #[arg(long, conflicts_with = "not_installed")]
+        installed: bool,
+
+        /// Only show packages that are not installed
+        #[arg(long)]
+        not_installed: bool,
+
+        /// Only show packages with this license (e.g., mit, gpl3Only)
+        #[arg(long)]
+        license: Option<String>,
+
+        /// Only show packages available on this platform (e.g., x86_64-linux)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Only show packages under this attribute path (e.g., python3Packages)
+        #[arg(long)]
+        attr_prefix: Option<String>,
+
+        /// Answer from local state only, without contacting Nix
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Browse packages by category (e.g., python, editors, games)
+    Browse {
+        /// Category to list; shows all categories if omitted
+        category: Option<String>,
+
+        /// Maximum number of packages to show
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
     },
 
     /// Install a package
     #[command(alias = "add")]
     Install {
-        /// Package name (e.g., firefox, python3)
-        package: String,
+        /// Packages to install (e.g., firefox, python3Packages.requests, openssl^dev, github:owner/repo#pkg)
+        #[arg(required = true)]
+        packages: Vec<String>,
 
         /// Skip confirmation prompt
         #[arg(short = 'y', long)]
         yes: bool,
+
+        /// Show what would be built, downloaded and stored as JSON, without installing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Install even if the package has an unfree license
+        #[arg(long)]
+        allow_unfree: bool,
+
+        /// Install even if the package is marked as insecure
+        #[arg(long)]
+        allow_insecure: bool,
+
+        /// Install even if the package is marked as broken
+        #[arg(long)]
+        allow_broken: bool,
     },
 
     /// Remove an installed package
     #[command(alias = "uninstall")]
     Remove {
-        /// Package name to remove
-        package: String,
+        /// Names of the packages to remove
+        #[arg(required = true)]
+        packages: Vec<String>,
 
         /// Skip confirmation prompt
         #[arg(short = 'y', long)]
         yes: bool,
+
+        /// Show what would be removed and freed as JSON, without removing
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// List installed packages
@@ -68,16 +141,68 @@ enum Commands {
         /// Output format (text, json)
         #[arg(short, long, default_value = "text")]
         format: String,
+
+        /// Answer from local state only, without contacting Nix
+        #[arg(long)]
+        offline: bool,
     },
 
     /// Show information about a package
     Info {
-        /// Package name
+        /// Package name, attribute path or flake reference
         package: String,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
+
+        /// Answer from local state only, without contacting Nix
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Show the disk usage of the Nix store
+    Du {
+        /// Number of installed packages with the largest closures to show
+        #[arg(short, long, default_value_t = 10)]
+        top: usize,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+
+    /// Free disk space by collecting Nix store garbage
+    Gc {
+        /// Also delete profile generations older than this (e.g., 30d)
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<u32>,
+
+        /// Show what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Skip confirmation prompt
+        #[arg(short = 'y', long)]
+        yes: bool,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
     },
 
     /// Update the package database
-    Update {},
+    Update {
+        /// Apply the update even if it deletes most of the cached packages
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Manage the local package cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommands,
+    },
 
     /// Setup WSL2 and Nix environment (first-time setup)
     Setup {
@@ -88,6 +213,53 @@ enum Commands {
         /// Use interactive mode to choose options
         #[arg(short, long)]
         interactive: bool,
+
+        /// Name of the distribution to install or import
+        #[arg(long)]
+        distro: Option<String>,
+
+        /// Import the distribution from a local image (.wsl or rootfs tarball)
+        #[arg(long, value_name = "PATH")]
+        distro_image: Option<PathBuf>,
+
+        /// Expected SHA-256 of the distribution image
+        #[arg(long, value_name = "HEX", requires = "distro_image")]
+        distro_image_sha256: Option<String>,
+
+        /// Directory to store the imported distribution in
+        #[arg(long, value_name = "DIR", requires = "distro_image")]
+        distro_dir: Option<PathBuf>,
+
+        /// Install Nix from a local installer (release tarball or nix-installer binary)
+        #[arg(long, value_name = "PATH")]
+        nix_installer: Option<PathBuf>,
+
+        /// Expected SHA-256 of the Nix installer
+        #[arg(long, value_name = "HEX", requires = "nix_installer")]
+        nix_installer_sha256: Option<String>,
+
+        /// Register a local nixpkgs channel tarball (nixexprs.tar.xz) instead of downloading the channel
+        #[arg(long, value_name = "PATH")]
+        nixpkgs: Option<PathBuf>,
+
+        /// Expected SHA-256 of the nixpkgs channel tarball
+        #[arg(long, value_name = "HEX", requires = "nixpkgs")]
+        nixpkgs_sha256: Option<String>,
+    },
+
+    /// Check the WSL2 and Nix installation for problems
+    Doctor {
+        /// Offer to repair the problems found
+        #[arg(long)]
+        fix: bool,
+
+        /// Apply repairs without asking
+        #[arg(short = 'y', long, requires = "fix")]
+        yes: bool,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
     },
 
     /// Generate wrapper script for a package (internal)
@@ -101,45 +273,177 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Show cache provenance, size and package count
+    Status {
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+
+    /// Write the cache to a compressed snapshot file
+    Export {
+        /// Snapshot file to create
+        file: PathBuf,
+    },
+
+    /// Replace the cache with a snapshot created by 'nsfw cache export'
+    Import {
+        /// Snapshot file to import
+        file: PathBuf,
+
+        /// Skip confirmation prompt
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+
+    /// Forget the locally learned package popularity used for ranking
+    ResetStats,
+
+    /// Refresh the cache in a detached background process (internal)
+    #[command(hide = true)]
+    RefreshWorker,
+}
+
+/// Parse a `--older-than` age such as `30d`
+fn parse_age(age: &str) -> Result<u32, String> {
+    nsfw::nix_ops::store::parse_age(age).ok_or_else(|| format!("expected a number of days like '30d', got '{}'", age))
+}
+
+impl Commands {
+    /// The `--format` of the command, for commands that have one
+    fn format(&self) -> Option<&str> {
+        match self {
+            Commands::Search { format, .. }
+            | Commands::Browse { format, .. }
+            | Commands::List { format, .. }
+            | Commands::Info { format, .. }
+            | Commands::Du { format, .. }
+            | Commands::Gc { format, .. }
+            | Commands::Doctor { format, .. }
+            | Commands::Cache { action: CacheCommands::Status { format } } => Some(format),
+            // Dry runs print their plan as JSON
+            Commands::Install { dry_run: true, .. } | Commands::Remove { dry_run: true, .. } => Some("json"),
+            _ => None,
+        }
+    }
+}
+
 fn main() {
     // Parse CLI arguments
     let cli = Cli::parse();
 
-    // Initialize logging
+    let json_errors = cli.command.format() == Some("json");
+    cli::report::set_json_errors(json_errors);
+
+    // Initialize logging; JSON output keeps stderr for the error object
+    // unless logs were asked for with --verbose
     if cli.verbose {
         env_logger::Builder::from_default_env()
             .filter_level(log::LevelFilter::Debug)
             .init();
+    } else if json_errors {
+        env_logger::Builder::new()
+            .filter_level(log::LevelFilter::Off)
+            .init();
     } else {
         env_logger::Builder::from_default_env()
             .filter_level(log::LevelFilter::Info)
             .init();
     }
 
-    info!("NSFW v0.1.0 starting...");
+    debug!("NSFW v0.1.0 starting...");
 
     // Execute command
     let result = match cli.command {
-        Commands::Search { query, limit, format } => {
-            cli::commands::search(&query, limit, &format)
+        Commands::Search {
+            query,
+            limit,
+            format,
+            free_only,
+            exclude_broken,
+            installed,
+            not_installed,
+            license,
+            platform,
+            attr_prefix,
+            offline,
+        } => {
+            let filters = SearchFilters {
+                free_only,
+                exclude_broken,
+                license,
+                platform,
+                attr_prefix,
+                installed: match (installed, not_installed) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                ..Default::default()
+            };
+            cli::commands::search(&query, limit, &format, filters, offline)
+        }
+        Commands::Browse { category, limit, format } => {
+            cli::commands::browse(category.as_deref(), limit, &format)
+        }
+        Commands::Install { packages, yes, dry_run, allow_unfree, allow_insecure, allow_broken } => {
+            let allow: Vec<Refusal> = [
+                (allow_unfree, Refusal::Unfree),
+                (allow_insecure, Refusal::Insecure),
+                (allow_broken, Refusal::Broken),
+            ].into_iter().filter_map(|(flag, refusal)| flag.then_some(refusal)).collect();
+            cli::commands::install(&packages, yes, dry_run, &allow)
         }
-        Commands::Install { package, yes } => {
-            cli::commands::install(&package, yes)
+        Commands::Remove { packages, yes, dry_run } => {
+            cli::commands::remove(&packages, yes, dry_run)
         }
-        Commands::Remove { package, yes } => {
-            cli::commands::remove(&package, yes)
+        Commands::List { detailed, format, offline } => {
+            cli::commands::list(detailed, &format, offline)
         }
-        Commands::List { detailed, format } => {
-            cli::commands::list(detailed, &format)
+        Commands::Info { package, format, offline } => {
+            cli::commands::info(&package, &format, offline)
         }
-        Commands::Info { package } => {
-            cli::commands::info(&package)
+        Commands::Du { top, format } => {
+            cli::commands::du(top, &format)
         }
-        Commands::Update {} => {
-            cli::commands::update()
+        Commands::Gc { older_than, dry_run, yes, format } => {
+            cli::commands::gc(older_than, dry_run, yes, &format)
         }
-        Commands::Setup { yes, interactive } => {
-            cli::commands::setup(yes, interactive)
+        Commands::Update { force } => {
+            cli::commands::update(force)
+        }
+        Commands::Cache { action } => match action {
+            CacheCommands::Status { format } => cli::commands::cache_status(&format),
+            CacheCommands::Export { file } => cli::commands::cache_export(&file),
+            CacheCommands::Import { file, yes } => cli::commands::cache_import(&file, yes),
+            CacheCommands::ResetStats => cli::commands::cache_reset_stats(),
+            CacheCommands::RefreshWorker => cli::commands::cache_refresh_worker(),
+        },
+        Commands::Doctor { fix, yes, format } => {
+            cli::commands::doctor(fix, yes, &format)
+        }
+        Commands::Setup {
+            yes,
+            interactive,
+            distro,
+            distro_image,
+            distro_image_sha256,
+            distro_dir,
+            nix_installer,
+            nix_installer_sha256,
+            nixpkgs,
+            nixpkgs_sha256,
+        } => {
+            let sources = SetupSources {
+                distro,
+                distro_image: distro_image.map(|path| LocalFile::new(path, distro_image_sha256)),
+                distro_install_dir: distro_dir,
+                nix_installer: nix_installer.map(|path| LocalFile::new(path, nix_installer_sha256)),
+                nixpkgs: nixpkgs.map(|path| LocalFile::new(path, nixpkgs_sha256)),
+            };
+            cli::commands::setup(yes, interactive, sources)
         }
         Commands::GenerateWrapper { package, package_path } => {
             cli::commands::generate_wrapper(&package, &package_path)
@@ -148,7 +452,12 @@ fn main() {
 
     // Handle errors
     if let Err(e) = result {
-        error!("Command failed: {}", e);
-        std::process::exit(1);
+        let report = ErrorReport::new(&e);
+        if json_errors {
+            eprintln!("{}", report.to_json());
+        } else {
+            error!("Command failed [{}]: {}", report.code, report.message);
+        }
+        std::process::exit(report.exit_code);
     }
 }
@@ -0,0 +1,520 @@
+/// Common interface of the Nix executors
+///
+/// A backend only knows how to run `nix` somewhere: natively on Linux
+/// (`NixExecutor`) or inside WSL2 (`BridgedNixExecutor`). Everything built on
+/// top of that, from parsing command output to classifying failures, is
+/// shared here so both behave the same.
+use serde_json::Value;
+
+use super::errors::{NixError, Refusal};
+use super::package_ref::{FlakeRef, PackageRef};
+use super::plan::{parse_path_info, InstallPlan, PathInfo, RemovePlan};
+use super::profile::parse_profile_list;
+use super::types::{InstalledPackage, Package, SearchResult};
+use super::{BridgedNixExecutor, NixExecutor};
+use crate::wsl2::{CommandOutput, NativeBridge, RealWSL2Bridge, WSL2Bridge};
+
+/// Nix function extracting the fields of `Package` from a derivation
+pub(crate) const DESCRIBE_EXPR: &str =
+    "p: { name = p.pname or p.name; version = p.version or \"\"; description = p.meta.description or \"\"; }";
+
+/// Environment variable forcing a backend ("native" or "wsl")
+pub const BACKEND_ENV: &str = "NSFW_BACKEND";
+
+/// Flags every backend passes before the `nix` subcommand
+///
+/// Flakes and the `nix` command are enabled per invocation, so no
+/// `nix.conf` changes are needed.
+pub const NIX_GLOBAL_ARGS: [&str; 2] = ["--extra-experimental-features", "nix-command flakes"];
+
+/// Runs Nix operations on some host
+pub trait NixBackend {
+    /// Run `nix` with exactly these arguments and extra environment variables
+    ///
+    /// Backends implement this; everything else goes through
+    /// `run_nix_with_env`, which adds `NIX_GLOBAL_ARGS`.
+    fn exec_nix(&self, args: &[&str], env: &[(&str, &str)]) -> Result<CommandOutput, NixError>;
+
+    /// Run `nix` with the given arguments and extra environment variables
+    fn run_nix_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> Result<CommandOutput, NixError> {
+        let args: Vec<&str> = NIX_GLOBAL_ARGS.into_iter().chain(args.iter().copied()).collect();
+        self.exec_nix(&args, env)
+    }
+
+    /// Run `nix` with the given arguments
+    fn run_nix(&self, args: &[&str]) -> Result<CommandOutput, NixError> {
+        self.run_nix_with_env(args, &[])
+    }
+
+    /// Run one of the tools shipped with Nix (e.g. `nix-collect-garbage`)
+    fn run_tool(&self, program: &str, args: &[&str]) -> Result<CommandOutput, NixError>;
+
+    /// Check that the host Nix runs on is reachable
+    fn ensure_available(&self) -> Result<(), NixError> {
+        Ok(())
+    }
+
+    /// Translate a local flake path into one Nix can read
+    fn translate_path(&self, path: &str) -> Result<String, NixError> {
+        Ok(path.to_string())
+    }
+
+    /// Check if the Nix evaluation cache exists (first-time setup is done)
+    fn is_cache_built(&self) -> bool;
+
+    /// Check if Nix is available, returning its version
+    fn check_nix_available(&self) -> Result<String, NixError> {
+        self.ensure_available()?;
+
+        let output = self.run_nix(&["--version"])?;
+        if !output.is_success() {
+            log::debug!("nix --version failed: {}", output.stderr.trim());
+            return Err(NixError::NixNotInstalled);
+        }
+
+        Ok(output.stdout.trim().to_string())
+    }
+
+    /// Search for packages in nixpkgs
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, NixError> {
+        self.ensure_available()?;
+
+        let output = self.run_nix(&["search", "nixpkgs", query, "--json"])?;
+        if !output.is_success() {
+            // `nix search` fails when nothing matches
+            if output.stderr.contains("no results") {
+                return Ok(Vec::new());
+            }
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        parse_search_json(&output.stdout, limit)
+    }
+
+    /// Install a package
+    ///
+    /// Accepts anything `PackageRef` parses, e.g. `firefox`,
+    /// `python3Packages.requests`, `openssl^dev` or `github:owner/repo#pkg`.
+    fn install(&self, package: &str) -> Result<(), NixError> {
+        self.install_ref(&PackageRef::parse(package)?)
+    }
+
+    /// Install a parsed package reference
+    fn install_ref(&self, package: &PackageRef) -> Result<(), NixError> {
+        self.install_allowing(std::slice::from_ref(package), &[])
+    }
+
+    /// Install packages in one profile transaction
+    ///
+    /// Nix creates the new profile generation only once every package is
+    /// built, so either all packages are installed or none. `allow`
+    /// overrides nixpkgs refusals for this transaction only; nothing is
+    /// written to the Nix configuration.
+    fn install_allowing(&self, packages: &[PackageRef], allow: &[Refusal]) -> Result<(), NixError> {
+        self.ensure_available()?;
+
+        let installables = self.installables(packages)?;
+        let (flags, env) = allow_flags(allow);
+        let args: Vec<&str> = ["profile", "install"].into_iter()
+            .chain(flags)
+            .chain(installables.iter().map(String::as_str))
+            .collect();
+        let output = self.run_nix_with_env(&args, &env)?;
+
+        if !output.is_success() {
+            if output.stderr.contains("already installed") {
+                return Err(NixError::AlreadyInstalled(display_list(packages)));
+            }
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        Ok(())
+    }
+
+    /// Preview what installing packages will build and download
+    fn plan_install(&self, packages: &[PackageRef], allow: &[Refusal]) -> Result<InstallPlan, NixError> {
+        self.ensure_available()?;
+
+        let installables = self.installables(packages)?;
+        let (flags, env) = allow_flags(allow);
+        let args: Vec<&str> = ["build", "--dry-run", "--no-link"].into_iter()
+            .chain(flags)
+            .chain(installables.iter().map(String::as_str))
+            .collect();
+        let output = self.run_nix_with_env(&args, &env)?;
+
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        let names = packages.iter().map(ToString::to_string).collect();
+        Ok(InstallPlan::from_dry_run(names, installables, &output.stderr))
+    }
+
+    /// Preview what removing profile elements will free
+    fn plan_remove(&self, packages: &[&str]) -> Result<RemovePlan, NixError> {
+        let (removed, kept): (Vec<_>, Vec<_>) = self.list()?
+            .into_iter()
+            .partition(|p| packages.contains(&p.name.as_str()));
+        if let Some(missing) = packages.iter().find(|name| !removed.iter().any(|p| &p.name == *name)) {
+            return Err(NixError::NotInstalled(missing.to_string()));
+        }
+
+        let store_paths: Vec<String> = removed.into_iter().flat_map(|p| p.store_paths).collect();
+        let kept_paths: Vec<String> = kept.into_iter().flat_map(|p| p.store_paths).collect();
+
+        let closure = self.closure(&store_paths)?;
+        let still_used: std::collections::HashSet<String> = self.closure(&kept_paths)?
+            .into_iter()
+            .map(|info| info.path)
+            .collect();
+
+        let closure_size = closure.iter().map(|info| info.nar_size).sum();
+        let reclaimable_size: u64 = closure.iter()
+            .filter(|info| !still_used.contains(&info.path))
+            .map(|info| info.nar_size)
+            .sum();
+
+        Ok(RemovePlan {
+            packages: packages.iter().map(ToString::to_string).collect(),
+            store_paths,
+            closure_size,
+            reclaimable_size,
+            disk_delta: -(reclaimable_size as i64),
+        })
+    }
+
+    /// All store paths the given paths depend on, with their sizes
+    fn closure(&self, store_paths: &[String]) -> Result<Vec<PathInfo>, NixError> {
+        if store_paths.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let args: Vec<&str> = ["path-info", "--json", "--recursive"].into_iter()
+            .chain(store_paths.iter().map(String::as_str))
+            .collect();
+        let output = self.run_nix(&args)?;
+
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        parse_path_info(&output.stdout)
+    }
+
+    /// Evaluate name, version and description of a package
+    ///
+    /// Works for any flake, so it also covers packages that are not in the
+    /// local package cache.
+    fn describe(&self, package: &PackageRef) -> Result<Package, NixError> {
+        self.ensure_available()?;
+
+        let target = match &package.flake {
+            FlakeRef::Path(_) => {
+                let unqualified = PackageRef { outputs: Vec::new(), ..package.clone() };
+                self.installable(&unqualified)?
+            }
+            _ => package.eval_target(),
+        };
+        let output = self.run_nix(&["eval", "--json", &target, "--apply", DESCRIBE_EXPR])?;
+
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        let value: Value = serde_json::from_str(&output.stdout)?;
+        let field = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+        Ok(Package {
+            pname: field("name"),
+            version: field("version"),
+            description: field("description"),
+        })
+    }
+
+    /// Remove a package
+    fn remove(&self, package: &str) -> Result<(), NixError> {
+        self.remove_all(&[package])
+    }
+
+    /// Remove packages in one profile transaction
+    fn remove_all(&self, packages: &[&str]) -> Result<(), NixError> {
+        self.ensure_available()?;
+
+        let args: Vec<&str> = ["profile", "remove"].into_iter().chain(packages.iter().copied()).collect();
+        let output = self.run_nix(&args)?;
+        if !output.is_success() {
+            let not_installed = ["not found", "does not exist", "does not match any packages"];
+            if not_installed.iter().any(|s| output.stderr.contains(s)) {
+                return Err(NixError::NotInstalled(packages.join(", ")));
+            }
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        Ok(())
+    }
+
+    /// List installed packages
+    fn list(&self) -> Result<Vec<InstalledPackage>, NixError> {
+        parse_profile_list(&self.profile_list_json()?)
+    }
+
+    /// Get the raw `nix profile list --json` output
+    ///
+    /// Kept as-is so it can be stored as an offline snapshot of the profile.
+    fn profile_list_json(&self) -> Result<String, NixError> {
+        self.ensure_available()?;
+
+        let output = self.run_nix(&["profile", "list", "--json"])?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// The installable for `nix`, with Windows flake paths translated
+    fn installable(&self, package: &PackageRef) -> Result<String, NixError> {
+        package.installable_translated(|path| self.translate_path(path).map_err(Into::into))
+    }
+
+    /// The installables of packages that are resolved to a nixpkgs commit
+    fn installables(&self, packages: &[PackageRef]) -> Result<Vec<String>, NixError> {
+        packages.iter()
+            .map(|package| self.installable(package.require_resolved()?))
+            .collect()
+    }
+}
+
+/// Comma-separated package references for messages
+fn display_list(packages: &[PackageRef]) -> String {
+    packages.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Extra `nix` flags and environment overriding nixpkgs refusals
+fn allow_flags(allow: &[Refusal]) -> (Option<&'static str>, Vec<(&'static str, &'static str)>) {
+    let env = allow.iter().map(|refusal| (refusal.env_var(), "1")).collect();
+    let flags = (!allow.is_empty()).then_some("--impure");
+    (flags, env)
+}
+
+/// Parse `nix search --json` output
+fn parse_search_json(json_str: &str, limit: usize) -> Result<Vec<SearchResult>, NixError> {
+    if json_str.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let json: Value = serde_json::from_str(json_str)?;
+    let field = |info: &Value, key: &str| info.get(key).and_then(|v| v.as_str()).map(str::to_string);
+
+    let results = json.as_object()
+        .map(|obj| {
+            obj.iter()
+                .take(limit)
+                .map(|(attr, info)| SearchResult {
+                    pname: field(info, "pname").unwrap_or_else(|| attr.clone()),
+                    version: field(info, "version").unwrap_or_else(|| "unknown".to_string()),
+                    description: field(info, "description").unwrap_or_default(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(results)
+}
+
+/// Whether `nix` should be run directly instead of through WSL2
+///
+/// True on Linux, which includes running inside a WSL distribution.
+/// `NSFW_BACKEND=native|wsl` overrides the detection.
+pub fn runs_natively() -> bool {
+    runs_natively_with(std::env::var(BACKEND_ENV).ok().as_deref())
+}
+
+/// `runs_natively` for a given `NSFW_BACKEND` value
+fn runs_natively_with(backend: Option<&str>) -> bool {
+    match backend {
+        Some("native") => true,
+        Some("wsl") => false,
+        _ => cfg!(target_os = "linux"),
+    }
+}
+
+/// The backend for the host the CLI is running on
+pub fn default_backend() -> Box<dyn NixBackend> {
+    if runs_natively() {
+        Box::new(NixExecutor::new())
+    } else {
+        Box::new(BridgedNixExecutor::new(RealWSL2Bridge::new()))
+    }
+}
+
+/// The bridge for running Nix tools other than `nix` (e.g. `nix-env`)
+///
+/// Runs them directly whenever `default_backend` runs `nix` natively.
+pub fn default_bridge() -> Box<dyn WSL2Bridge> {
+    bridge_for(runs_natively())
+}
+
+fn bridge_for(native: bool) -> Box<dyn WSL2Bridge> {
+    if native {
+        Box::new(NativeBridge::new())
+    } else {
+        Box::new(RealWSL2Bridge::new())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Backend answering `nix` invocations from a table
+    ///
+    /// `nix` commands are keyed without `NIX_GLOBAL_ARGS`.
+    pub(crate) struct ScriptedBackend(HashMap<String, CommandOutput>);
+
+    impl ScriptedBackend {
+        pub(crate) fn new(responses: &[(&str, CommandOutput)]) -> Self {
+            Self(responses.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+        }
+    }
+
+    impl NixBackend for ScriptedBackend {
+        fn exec_nix(&self, args: &[&str], env: &[(&str, &str)]) -> Result<CommandOutput, NixError> {
+            // Responses are keyed without the global flags, which every call must pass
+            let args = args.strip_prefix(&NIX_GLOBAL_ARGS[..])
+                .ok_or_else(|| NixError::CommandFailed(format!("missing global flags: nix {}", args.join(" "))))?;
+            let command: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v))
+                .chain(args.iter().map(|a| a.to_string()))
+                .collect();
+            self.0.get(&command.join(" ")).cloned()
+                .ok_or_else(|| NixError::CommandFailed(format!("unexpected: nix {}", command.join(" "))))
+        }
+
+        fn run_tool(&self, program: &str, args: &[&str]) -> Result<CommandOutput, NixError> {
+            let command = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>().join(" ");
+            self.0.get(&command).cloned()
+                .ok_or_else(|| NixError::CommandFailed(format!("unexpected: {}", command)))
+        }
+
+        fn is_cache_built(&self) -> bool {
+            true
+        }
+    }
+
+    fn failure(stderr: &str) -> CommandOutput {
+        CommandOutput::new(String::new(), stderr.to_string(), 1)
+    }
+
+    #[test]
+    fn test_shared_error_classification() {
+        let backend = ScriptedBackend::new(&[
+            ("--version", failure("nix: command not found")),
+            ("search nixpkgs zzz --json", failure("error: no results for the given search term(s)!")),
+            ("profile install nixpkgs#nope", failure("error: flake 'flake:nixpkgs' does not provide attribute 'packages.x86_64-linux.nope'")),
+            ("profile remove vim", failure("warning: 'vim' does not match any packages")),
+            ("profile list --json", failure("error: unable to download 'https://cache.nixos.org': Could not resolve host")),
+        ]);
+
+        assert!(matches!(backend.check_nix_available(), Err(NixError::NixNotInstalled)));
+        assert!(backend.search("zzz", 10).unwrap().is_empty());
+        assert!(matches!(backend.install("nope"), Err(NixError::AttributeNotFound { .. })));
+        assert!(matches!(backend.remove("vim"), Err(NixError::NotInstalled(_))));
+        assert!(matches!(backend.list(), Err(NixError::NetworkError(_))));
+    }
+
+    #[test]
+    fn test_install_allowing_refusals() {
+        let refused = "error: Package ‘vscode-1.86.0’ in /nix/store/abc/vscode.nix:80 has an unfree license (‘unfree’), refusing to evaluate.";
+        let backend = ScriptedBackend::new(&[
+            ("profile install nixpkgs#vscode", failure(refused)),
+            ("NIXPKGS_ALLOW_UNFREE=1 profile install --impure nixpkgs#vscode", CommandOutput::new(String::new(), String::new(), 0)),
+        ]);
+
+        let vscode = PackageRef::parse("vscode").unwrap();
+        let err = backend.install_ref(&vscode).unwrap_err();
+        assert_eq!(err.refusal(), Some((Refusal::Unfree, "vscode-1.86.0")));
+        backend.install_allowing(&[vscode], &[Refusal::Unfree]).unwrap();
+    }
+
+    #[test]
+    fn test_transactions_pass_all_packages() {
+        let ok = CommandOutput::new(String::new(), String::new(), 0);
+        let backend = ScriptedBackend::new(&[
+            ("profile install nixpkgs#git nixpkgs#ripgrep nixpkgs#jq", ok.clone()),
+            ("profile remove git jq", ok),
+        ]);
+
+        let packages: Vec<PackageRef> = ["git", "ripgrep", "jq"].iter().map(|p| PackageRef::parse(p).unwrap()).collect();
+        backend.install_allowing(&packages, &[]).unwrap();
+        backend.remove_all(&["git", "jq"]).unwrap();
+    }
+
+    #[test]
+    fn test_plan_install() {
+        let dry_run = "these 2 paths will be fetched (1.00 MiB download, 4.00 MiB unpacked):\n  /nix/store/a-hello-2.12.1\n  /nix/store/b-glibc\n";
+        let backend = ScriptedBackend::new(&[
+            ("build --dry-run --no-link nixpkgs#hello", CommandOutput::new(String::new(), dry_run.to_string(), 0)),
+        ]);
+
+        let plan = backend.plan_install(&[PackageRef::parse("hello").unwrap()], &[]).unwrap();
+        assert_eq!(plan.installables, vec!["nixpkgs#hello"]);
+        assert_eq!(plan.to_fetch.len(), 2);
+        assert_eq!(plan.disk_delta, 4 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_plan_remove_counts_unshared_paths() {
+        let profile = r#"{"version":3,"elements":{
+            "jq":{"storePaths":["/nix/store/a-jq-1.7.1"]},
+            "hello":{"storePaths":["/nix/store/b-hello-2.12.1"]}
+        }}"#;
+        let ok = |stdout: &str| CommandOutput::new(stdout.to_string(), String::new(), 0);
+        let backend = ScriptedBackend::new(&[
+            ("profile list --json", ok(profile)),
+            ("path-info --json --recursive /nix/store/a-jq-1.7.1",
+             ok(r#"{"/nix/store/a-jq-1.7.1":{"narSize":300},"/nix/store/c-glibc":{"narSize":1000}}"#)),
+            ("path-info --json --recursive /nix/store/b-hello-2.12.1",
+             ok(r#"{"/nix/store/b-hello-2.12.1":{"narSize":50},"/nix/store/c-glibc":{"narSize":1000}}"#)),
+        ]);
+
+        let plan = backend.plan_remove(&["jq"]).unwrap();
+        assert_eq!(plan.closure_size, 1300);
+        assert_eq!(plan.reclaimable_size, 300);
+        assert_eq!(plan.disk_delta, -300);
+        assert!(matches!(backend.plan_remove(&["jq", "vim"]), Err(NixError::NotInstalled(name)) if name == "vim"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_native_bridge_runs_commands_directly() {
+        // Through wsl.exe this would run `wsl sh -c ...`, which does not exist here
+        let output = bridge_for(true).execute("sh", &["-c", "echo $0"]).unwrap();
+        assert_eq!(output.stdout.trim(), "sh");
+    }
+
+    #[test]
+    fn test_backend_override() {
+        assert!(!runs_natively_with(Some("wsl")));
+        assert!(runs_natively_with(Some("native")));
+        assert_eq!(runs_natively_with(None), cfg!(target_os = "linux"));
+        assert_eq!(runs_natively_with(Some("other")), cfg!(target_os = "linux"));
+    }
+
+    #[test]
+    fn test_parse_search_json() {
+        let json = r#"{
+            "legacyPackages.x86_64-linux.jq": {"pname":"jq","version":"1.7.1","description":"JSON processor"},
+            "legacyPackages.x86_64-linux.jo": {"version":"1.9"}
+        }"#;
+
+        let results = parse_search_json(json, 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].pname, "legacyPackages.x86_64-linux.jo");
+        assert_eq!(results[0].description, "");
+        assert_eq!(results[1].pname, "jq");
+
+        assert_eq!(parse_search_json(json, 1).unwrap().len(), 1);
+        assert!(parse_search_json("", 10).unwrap().is_empty());
+    }
+}
@@ -2,16 +2,14 @@
 ///
 /// This executor routes all Nix commands through a WSL2Bridge implementation,
 /// enabling Windows CLI to execute Nix operations in WSL2.
-use anyhow::Result;
-use serde_json::Value;
-
+use super::backend::NixBackend;
 use super::errors::NixError;
-use super::types::{SearchResult, InstalledPackage};
-use crate::wsl2::WSL2Bridge;
+use crate::wsl2::{CommandOutput, WSL2Bridge};
 
 /// Nix executor that uses WSL2Bridge for all operations
 ///
 /// Generic over the bridge type to allow both real and mock bridges.
+/// See `NixBackend` for the operations.
 pub struct BridgedNixExecutor<B: WSL2Bridge> {
     bridge: B,
 }
@@ -33,226 +31,59 @@ impl<B: WSL2Bridge> BridgedNixExecutor<B> {
     pub fn new(bridge: B) -> Self {
         Self { bridge }
     }
+}
 
-    /// Check if the Nix evaluation cache exists
-    pub fn is_cache_built(&self) -> bool {
-        // Check if the eval cache directory exists in WSL2
-        let check_cmd = "test -d ~/.cache/nix/eval-cache-v5 && echo 'exists' || echo 'missing'";
-        match self.bridge.execute("sh", &["-c", check_cmd]) {
-            Ok(output) if output.stdout.trim() == "exists" => true,
-            _ => false,
-        }
-    }
-
-    /// Check if Nix is available in WSL2
-    pub fn check_nix_available(&self) -> Result<String, NixError> {
-        // First check if WSL2 is available
-        if !self.bridge.is_available() {
-            return Err(NixError::WSL2NotAvailable);
-        }
-
-        // Try to get Nix version
-        match self.bridge.execute("nix", &["--version"]) {
-            Ok(output) if output.is_success() => {
-                Ok(output.stdout.trim().to_string())
-            }
-            Ok(output) => {
-                Err(NixError::CommandFailed(format!(
-                    "Nix not found in WSL2: {}",
-                    output.stderr
-                )))
-            }
-            Err(e) => {
-                Err(NixError::CommandFailed(format!(
-                    "Failed to check Nix: {}",
-                    e
-                )))
-            }
-        }
+impl<B: WSL2Bridge> NixBackend for BridgedNixExecutor<B> {
+    fn exec_nix(&self, args: &[&str], env: &[(&str, &str)]) -> Result<CommandOutput, NixError> {
+        let result = if env.is_empty() {
+            self.bridge.execute("nix", args)
+        } else {
+            // Windows environment variables do not reach WSL2; set them with env(1)
+            let assignments: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let env_args: Vec<&str> = assignments.iter().map(String::as_str)
+                .chain(std::iter::once("nix"))
+                .chain(args.iter().copied())
+                .collect();
+            self.bridge.execute("env", &env_args)
+        };
+
+        result.map_err(|e| NixError::CommandFailed(e.to_string()))
     }
 
-    /// Search for packages
-    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, NixError> {
-        // Check WSL2 available
-        if !self.bridge.is_available() {
-            return Err(NixError::WSL2NotAvailable);
-        }
-
-        // Execute search via bridge
-        let output = self.bridge
-            .execute("nix", &["search", "nixpkgs", query, "--json"])
-            .map_err(|e| NixError::CommandFailed(e.to_string()))?;
-
-        if !output.is_success() {
-            return Err(NixError::CommandFailed(output.stderr));
-        }
-
-        // Parse JSON results
-        self.parse_search_json(&output.stdout, limit)
+    fn run_tool(&self, program: &str, args: &[&str]) -> Result<CommandOutput, NixError> {
+        self.bridge
+            .execute(program, args)
+            .map_err(|e| NixError::CommandFailed(e.to_string()))
     }
 
-    /// Install a package
-    pub fn install(&self, package: &str) -> Result<(), NixError> {
-        // Check WSL2 available
+    fn ensure_available(&self) -> Result<(), NixError> {
         if !self.bridge.is_available() {
             return Err(NixError::WSL2NotAvailable);
         }
-
-        let pkg_attr = format!("nixpkgs#{}", package);
-
-        // Execute install via bridge
-        let output = self.bridge
-            .execute("nix", &["profile", "install", &pkg_attr])
-            .map_err(|e| NixError::CommandFailed(e.to_string()))?;
-
-        if !output.is_success() {
-            // Check if already installed
-            if output.stderr.contains("already installed") {
-                return Err(NixError::AlreadyInstalled(package.to_string()));
-            }
-            return Err(NixError::CommandFailed(output.stderr));
-        }
-
         Ok(())
     }
 
-    /// Remove a package
-    pub fn remove(&self, package: &str) -> Result<(), NixError> {
-        // Check WSL2 available
-        if !self.bridge.is_available() {
-            return Err(NixError::WSL2NotAvailable);
-        }
-
-        // Execute remove via bridge
-        let output = self.bridge
-            .execute("nix", &["profile", "remove", package])
-            .map_err(|e| NixError::CommandFailed(e.to_string()))?;
-
-        if !output.is_success() {
-            // Check if not installed
-            if output.stderr.contains("not found") || output.stderr.contains("does not exist") {
-                return Err(NixError::NotInstalled(package.to_string()));
-            }
-            return Err(NixError::CommandFailed(output.stderr));
-        }
-
-        Ok(())
+    fn translate_path(&self, path: &str) -> Result<String, NixError> {
+        self.bridge
+            .translate_path_to_wsl(path)
+            .map_err(|e| NixError::CommandFailed(e.to_string()))
     }
 
-    /// List installed packages
-    pub fn list(&self) -> Result<Vec<InstalledPackage>, NixError> {
-        // Check WSL2 available
-        if !self.bridge.is_available() {
-            return Err(NixError::WSL2NotAvailable);
-        }
-
-        // Execute list via bridge
-        let output = self.bridge
-            .execute("nix", &["profile", "list", "--json"])
-            .map_err(|e| NixError::CommandFailed(e.to_string()))?;
-
-        if !output.is_success() {
-            return Err(NixError::CommandFailed(output.stderr));
-        }
-
-        // Parse JSON results
-        self.parse_list_json(&output.stdout)
-    }
-
-    /// Parse search results from JSON
-    fn parse_search_json(&self, json_str: &str, limit: usize) -> Result<Vec<SearchResult>, NixError> {
-        let json: Value = serde_json::from_str(json_str)?;
-
-        let mut results = Vec::new();
-
-        if let Some(obj) = json.as_object() {
-            for (attr, pkg_info) in obj.iter().take(limit) {
-                let pname = pkg_info
-                    .get("pname")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or(attr)
-                    .to_string();
-
-                let version = pkg_info
-                    .get("version")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("unknown")
-                    .to_string();
-
-                let description = pkg_info
-                    .get("description")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string();
-
-                results.push(SearchResult {
-                    pname,
-                    version,
-                    description,
-                });
-            }
-        }
-
-        Ok(results)
-    }
-
-    /// Parse installed packages from JSON
-    fn parse_list_json(&self, json_str: &str) -> Result<Vec<InstalledPackage>, NixError> {
-        let json: Value = serde_json::from_str(json_str)?;
-
-        let mut packages = Vec::new();
-
-        if let Some(elements) = json.get("elements").and_then(|v| v.as_object()) {
-            for (name, element) in elements {
-                let store_path = element
-                    .get("storePaths")
-                    .and_then(|v| v.as_array())
-                    .and_then(|arr| arr.first())
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string();
-
-                let version = self.extract_version(&store_path, name);
-
-                packages.push(InstalledPackage {
-                    name: name.clone(),
-                    version,
-                    store_path,
-                });
-            }
-        }
-
-        Ok(packages)
-    }
-
-    /// Extract version from store path
-    fn extract_version(&self, store_path: &str, name: &str) -> String {
-        // Store path format: /nix/store/hash-name-version/...
-        let parts: Vec<&str> = store_path.split('/').collect();
-
-        if parts.len() >= 4 {
-            let package_part = parts[3]; // hash-name-version
-
-            // Try to extract version after name
-            if let Some(pos) = package_part.find(&format!("{}-", name)) {
-                let after_name = &package_part[pos + name.len() + 1..];
-
-                // Take until next dash (which would be architecture or hash continuation)
-                if let Some(dash_pos) = after_name.find('-') {
-                    return after_name[..dash_pos].to_string();
-                } else {
-                    return after_name.to_string();
-                }
-            }
-        }
-
-        "unknown".to_string()
+    fn is_cache_built(&self) -> bool {
+        // Check if the eval cache directory exists in WSL2
+        let check_cmd = "test -d ~/.cache/nix/eval-cache-v5 && echo 'exists' || echo 'missing'";
+        matches!(
+            self.bridge.execute("sh", &["-c", check_cmd]),
+            Ok(output) if output.stdout.trim() == "exists"
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nix_ops::backend::DESCRIBE_EXPR;
+    use crate::nix_ops::PackageRef;
     use crate::wsl2::MockWSL2Bridge;
 
     fn create_mock_executor() -> BridgedNixExecutor<MockWSL2Bridge> {
@@ -266,7 +97,6 @@ mod tests {
         let bridge = MockWSL2Bridge::new();
         let _executor = BridgedNixExecutor::new(bridge);
         // Just verify construction
-        assert!(true);
     }
 
     #[test]
@@ -276,6 +106,25 @@ mod tests {
         assert!(version.contains("nix"));
     }
 
+    #[test]
+    fn test_global_flags_are_sent() {
+        // An exact response only matches if the flags reach `nix` in WSL2
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix --extra-experimental-features nix-command flakes --version".to_string(),
+            crate::wsl2::CommandOutput::new("nix (Nix) 2.24.10".to_string(), String::new(), 0),
+        );
+        bridge.set_response(
+            "env NIXPKGS_ALLOW_UNFREE=1 nix --extra-experimental-features nix-command flakes profile install --impure nixpkgs#vscode".to_string(),
+            crate::wsl2::CommandOutput::new(String::new(), String::new(), 0),
+        );
+        let executor = BridgedNixExecutor::new(bridge);
+
+        assert_eq!(executor.check_nix_available().unwrap(), "nix (Nix) 2.24.10");
+        let package = PackageRef::parse("vscode").unwrap();
+        executor.install_allowing(&[package], &[crate::nix_ops::Refusal::Unfree]).unwrap();
+    }
+
     #[test]
     fn test_check_nix_available_wsl2_unavailable() {
         let mut bridge = MockWSL2Bridge::new();
@@ -307,6 +156,23 @@ mod tests {
         assert!(matches!(result.unwrap_err(), NixError::WSL2NotAvailable));
     }
 
+    #[test]
+    fn test_search_network_failure() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix search nixpkgs test --json".to_string(),
+            crate::wsl2::CommandOutput::new(
+                String::new(),
+                "error: unable to download 'https://github.com/NixOS/nixpkgs/archive/master.tar.gz': Couldn't resolve host name".to_string(),
+                1,
+            ),
+        );
+        let executor = BridgedNixExecutor::new(bridge);
+
+        let result = executor.search("test", 10);
+        assert!(matches!(result.unwrap_err(), NixError::NetworkError(_)));
+    }
+
     #[test]
     fn test_list_success() {
         let executor = create_mock_executor();
@@ -328,34 +194,61 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_version() {
-        let bridge = MockWSL2Bridge::new();
+    fn test_install_wsl2_unavailable() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_available(false);
         let executor = BridgedNixExecutor::new(bridge);
 
-        // Test version extraction
-        let version = executor.extract_version(
-            "/nix/store/abc123-firefox-130.0/bin/firefox",
-            "firefox"
+        let result = executor.install("firefox");
+        assert!(result.is_err());
+        assert!(matches!(result.unwrap_err(), NixError::WSL2NotAvailable));
+    }
+
+    #[test]
+    fn test_install_package_ref() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix profile install nixpkgs#python3Packages.requests^dist".to_string(),
+            crate::wsl2::CommandOutput::new(String::new(), String::new(), 0),
         );
-        assert_eq!(version, "130.0");
+        let executor = BridgedNixExecutor::new(bridge);
+
+        executor.install("python3Packages.requests^dist").unwrap();
 
-        // Test with no version
-        let version = executor.extract_version(
-            "/nix/store/abc123-vim",
-            "vim"
+        let result = executor.install("python3Packages..requests");
+        assert!(matches!(result.unwrap_err(), NixError::InvalidPackageRef { .. }));
+    }
+
+    #[test]
+    fn test_install_allowing_sets_env() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "env NIXPKGS_ALLOW_INSECURE=1 nix profile install --impure nixpkgs#openssl_1_1".to_string(),
+            crate::wsl2::CommandOutput::new(String::new(), String::new(), 0),
         );
-        assert_eq!(version, "unknown");
+        let executor = BridgedNixExecutor::new(bridge);
+
+        let package = PackageRef::parse("openssl_1_1").unwrap();
+        executor.install_allowing(&[package], &[crate::nix_ops::Refusal::Insecure]).unwrap();
     }
 
     #[test]
-    fn test_install_wsl2_unavailable() {
+    fn test_describe() {
         let mut bridge = MockWSL2Bridge::new();
-        bridge.set_available(false);
+        bridge.set_response(
+            format!("nix eval --json github:owner/tool#default --apply {}", DESCRIBE_EXPR),
+            crate::wsl2::CommandOutput::new(
+                r#"{"name":"tool","version":"1.2.0","description":"A tool"}"#.to_string(),
+                String::new(),
+                0,
+            ),
+        );
         let executor = BridgedNixExecutor::new(bridge);
 
-        let result = executor.install("firefox");
-        assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), NixError::WSL2NotAvailable));
+        let package = executor.describe(&PackageRef::parse("github:owner/tool").unwrap()).unwrap();
+        assert_eq!(package.pname, "tool");
+        assert_eq!(package.version, "1.2.0");
+        assert_eq!(package.description, "A tool");
     }
 
     #[test]
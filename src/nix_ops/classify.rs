@@ -0,0 +1,321 @@
+/// Classification of Nix failures
+///
+/// Turns the stderr of a failed Nix command into a specific `NixError`,
+/// extracting the details (attribute, hashes, file location, ...) needed to
+/// tell the user what went wrong and how to fix it. Patterns are checked in
+/// order; the first match wins.
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::errors::{is_network_failure, NixError};
+
+fn regex(pattern: &str) -> Regex {
+    Regex::new(pattern).expect("invalid classifier pattern")
+}
+
+/// `cannot connect to socket at '/nix/var/nix/daemon-socket/socket': Connection refused`
+static DAEMON_SOCKET: Lazy<Regex> = Lazy::new(|| {
+    regex(r"cannot connect to socket at '([^']+)'")
+});
+
+/// `No space left on device` or `... out of disk space`, optionally after a path
+static OUT_OF_DISK: Lazy<Regex> = Lazy::new(|| {
+    regex(r"(?i)(?:(?:writing to file|creating directory|opening file) '?([^':]*)'?: )?No space left on device")
+});
+
+/// `hash mismatch in fixed-output derivation '/nix/store/...drv':\n specified: sha256-...\n got: sha256-...`
+static HASH_MISMATCH: Lazy<Regex> = Lazy::new(|| {
+    regex(r"(?s)hash mismatch in fixed-output derivation '([^']+)':\s*specified:\s*(\S+)\s*got:\s*(\S+)")
+});
+
+/// `Package ‘vscode-1.86.0’ in /nix/store/.../default.nix:80 has an unfree license (‘unfree’), refusing to evaluate.`
+static UNFREE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"Package [‘']([^’']+)[’'] in \S+ has an unfree license \([‘']([^’']+)[’']\), refusing to evaluate")
+});
+
+/// `Package ‘openssl-1.1.1w’ in /nix/store/.../default.nix:211 is marked as insecure, refusing to evaluate.`
+static MARKED: Lazy<Regex> = Lazy::new(|| {
+    regex(r"Package [‘']([^’']+)[’'] in \S+ is marked as (insecure|broken), refusing to evaluate")
+});
+
+/// Entries of the `Known issues:` list following an insecure refusal
+static KNOWN_ISSUE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"(?m)^\s*- (.+)$")
+});
+
+/// `flake 'flake:nixpkgs' does not provide attribute 'packages.x86_64-linux.foo', ... or 'foo'`
+static FLAKE_ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"flake '([^']+)' does not provide attribute (?:[^\n]* or )?'([^']+)'")
+});
+
+/// `attribute 'foo' missing`
+static MISSING_ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"attribute '([^']+)' missing")
+});
+
+/// `error: unable to download 'https://cache.nixos.org/nix-cache-info': ...`
+///
+/// Only `error:` lines count: Nix warns about unreachable substituters and
+/// goes on building from source, so a warning is not why a command failed.
+static SUBSTITUTER: Lazy<Regex> = Lazy::new(|| {
+    regex(r"(?m)^error:[^\n]*unable to download '(https?://[^/']+)/(?:nix-cache-info|[a-z0-9]{32}\.narinfo|nar/[^']*)'")
+});
+
+/// An `error:` line; Nix 2.16+ indents the innermost one below the trace
+static ERROR_LINE: Lazy<Regex> = Lazy::new(|| {
+    regex(r"(?m)^[ \t]*error:[ \t]*(.*)$")
+});
+
+/// Source location of an evaluation error, on its own line
+/// (`at /nix/store/...-source/flake.nix:12:5:` or `at «string»:1:16:`)
+static ERROR_LOCATION: Lazy<Regex> = Lazy::new(|| {
+    regex(r"^[ \t]*at (/nix/store/[^\s:]+|«[^»]*»[^\s:]*):(\d+):(\d+)")
+});
+
+/// Location appended to the message by Nix before 2.13
+static INLINE_LOCATION: Lazy<Regex> = Lazy::new(|| {
+    regex(r"[ \t]at (/nix/store/[^\s:]+|«[^»]*»[^\s:]*):(\d+):(\d+)")
+});
+
+/// Parse an evaluation error from the last `error:` block of stderr
+///
+/// Trace frames (`… while evaluating ...`) are skipped to find the message,
+/// and the location is the `at` line that follows it, so the error points
+/// at the failing expression rather than the outermost frame.
+fn evaluation_error(stderr: &str) -> Option<NixError> {
+    let last = ERROR_LINE.captures_iter(stderr).last()?;
+    let rest = &stderr[last.get(0)?.end()..];
+
+    let mut lines = std::iter::once(&last[1])
+        .chain(rest.lines())
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with('…'));
+    let message = lines.next()?;
+
+    if let Some(caps) = INLINE_LOCATION.captures(message) {
+        let at = caps.get(0)?.start();
+        return Some(evaluation_error_at(&message[..at], &caps));
+    }
+
+    let location = lines.find(|line| !line.is_empty())?;
+    ERROR_LOCATION.captures(location).map(|caps| evaluation_error_at(message, &caps))
+}
+
+fn evaluation_error_at(message: &str, location: &regex::Captures) -> NixError {
+    NixError::EvaluationError {
+        message: message.trim().trim_end_matches(':').to_string(),
+        file: location[1].to_string(),
+        line: location[2].parse().unwrap_or(0),
+        column: location[3].parse().unwrap_or(0),
+    }
+}
+
+/// Classify the stderr of a failed Nix command
+///
+/// Unrecognized failures become `NetworkError` when they look like a
+/// network problem and `CommandFailed` otherwise.
+pub fn classify(stderr: &str) -> NixError {
+    if let Some(caps) = DAEMON_SOCKET.captures(stderr) {
+        return NixError::DaemonNotRunning { socket: caps[1].to_string() };
+    }
+
+    if let Some(caps) = OUT_OF_DISK.captures(stderr) {
+        let path = caps.get(1).map(|m| m.as_str().to_string()).filter(|p| !p.is_empty());
+        return NixError::OutOfDiskSpace { path };
+    }
+
+    if let Some(caps) = HASH_MISMATCH.captures(stderr) {
+        return NixError::HashMismatch {
+            derivation: caps[1].to_string(),
+            specified: caps[2].to_string(),
+            got: caps[3].to_string(),
+        };
+    }
+
+    if let Some(caps) = UNFREE.captures(stderr) {
+        return NixError::UnfreeRefused {
+            package: caps[1].to_string(),
+            license: caps[2].to_string(),
+        };
+    }
+
+    if let Some(caps) = MARKED.captures(stderr) {
+        let package = caps[1].to_string();
+        if &caps[2] == "broken" {
+            return NixError::BrokenRefused { package };
+        }
+        let issues = stderr.split_once("Known issues:")
+            .map(|(_, rest)| rest.split("\n\n").next().unwrap_or_default())
+            .map(|list| KNOWN_ISSUE.captures_iter(list).map(|c| c[1].trim().to_string()).collect())
+            .unwrap_or_default();
+        return NixError::InsecureRefused { package, issues };
+    }
+
+    if let Some(caps) = FLAKE_ATTRIBUTE.captures(stderr) {
+        return NixError::AttributeNotFound {
+            attribute: caps[2].to_string(),
+            flake: Some(caps[1].to_string()),
+        };
+    }
+
+    if let Some(caps) = MISSING_ATTRIBUTE.captures(stderr) {
+        return NixError::AttributeNotFound {
+            attribute: caps[1].to_string(),
+            flake: None,
+        };
+    }
+
+    // Evaluation errors come first: substituter warnings may precede them
+    if let Some(err) = evaluation_error(stderr) {
+        return err;
+    }
+
+    if let Some(caps) = SUBSTITUTER.captures(stderr) {
+        return NixError::SubstituterUnreachable { url: caps[1].to_string() };
+    }
+
+    if is_network_failure(stderr) {
+        return NixError::NetworkError(stderr.to_string());
+    }
+
+    NixError::CommandFailed(stderr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_not_found() {
+        let err = classify(
+            "error: flake 'flake:nixpkgs' does not provide attribute 'packages.x86_64-linux.nope', 'legacyPackages.x86_64-linux.nope' or 'nope'"
+        );
+        assert!(matches!(&err, NixError::AttributeNotFound { attribute, flake: Some(flake) }
+            if attribute == "nope" && flake == "flake:nixpkgs"));
+        assert!(err.suggestion().unwrap().contains("nsfw search nope"));
+
+        let err = classify("error: attribute 'nosuchpkg' missing");
+        assert!(matches!(err, NixError::AttributeNotFound { ref attribute, flake: None } if attribute == "nosuchpkg"));
+    }
+
+    #[test]
+    fn test_hash_mismatch() {
+        let err = classify(
+            "error: hash mismatch in fixed-output derivation '/nix/store/abc-source.drv':\n\
+             \x20        specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=\n\
+             \x20           got:    sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB="
+        );
+        match err {
+            NixError::HashMismatch { derivation, specified, got } => {
+                assert_eq!(derivation, "/nix/store/abc-source.drv");
+                assert!(specified.starts_with("sha256-AAA"));
+                assert!(got.starts_with("sha256-BBB"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unfree_refused() {
+        let err = classify(
+            "error: Package ‘vscode-1.86.0’ in /nix/store/abc-source/pkgs/applications/editors/vscode/vscode.nix:80 \
+             has an unfree license (‘unfree’), refusing to evaluate."
+        );
+        assert!(matches!(err, NixError::UnfreeRefused { ref package, ref license }
+            if package == "vscode-1.86.0" && license == "unfree"));
+    }
+
+    #[test]
+    fn test_insecure_and_broken_refused() {
+        let err = classify(
+            "error: Package ‘openssl-1.1.1w’ in /nix/store/abc-source/pkgs/development/libraries/openssl/default.nix:211 \
+             is marked as insecure, refusing to evaluate.\n\n\
+             \x20      Known issues:\n\
+             \x20       - OpenSSL 1.1 is reaching its end of life on 2023/09/11\n\n\
+             \x20      You can install it anyway by allowing this package, using the\n\
+             \x20      following methods:\n"
+        );
+        match err {
+            NixError::InsecureRefused { ref package, ref issues } => {
+                assert_eq!(package, "openssl-1.1.1w");
+                assert_eq!(issues, &["OpenSSL 1.1 is reaching its end of life on 2023/09/11"]);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(err.refusal(), Some((crate::nix_ops::Refusal::Insecure, "openssl-1.1.1w")));
+
+        let err = classify(
+            "error: Package ‘python3.12-foo-1.0’ in /nix/store/abc-source/pkgs/foo.nix:42 is marked as broken, refusing to evaluate."
+        );
+        assert!(matches!(err, NixError::BrokenRefused { ref package } if package == "python3.12-foo-1.0"));
+    }
+
+    #[test]
+    fn test_out_of_disk() {
+        let err = classify("error: writing to file '/nix/store/abc-firefox.drv': No space left on device");
+        assert!(matches!(err, NixError::OutOfDiskSpace { path: Some(ref p) } if p == "/nix/store/abc-firefox.drv"));
+
+        let err = classify("error: No space left on device");
+        assert!(matches!(err, NixError::OutOfDiskSpace { path: None }));
+    }
+
+    #[test]
+    fn test_substituter_unreachable() {
+        let err = classify(
+            "error: unable to download 'https://cache.nixos.org/nix-cache-info': Couldn't resolve host name (6)"
+        );
+        assert!(matches!(err, NixError::SubstituterUnreachable { ref url } if url == "https://cache.nixos.org"));
+        assert!(err.is_connectivity());
+
+        // Other downloads are plain network errors
+        let err = classify("error: unable to download 'https://github.com/NixOS/nixpkgs/archive/master.tar.gz': Timeout was reached");
+        assert!(matches!(err, NixError::NetworkError(_)));
+
+        // Nix only warns when it can fall back to building from source
+        let err = classify(
+            "warning: unable to download 'https://cache.nixos.org/abcdefghijklmnopqrstuvwxyz012345.narinfo': Couldn't resolve host name (6)\n\
+             error: undefined variable 'pkgz'\n\n       at /nix/store/abc-source/flake.nix:12:5:"
+        );
+        assert!(matches!(err, NixError::EvaluationError { line: 12, .. }), "{:?}", err);
+
+        let err = classify(
+            "warning: unable to download 'https://cache.nixos.org/nix-cache-info': Couldn't resolve host name (6)\n\
+             error: builder for '/nix/store/abc-hello.drv' failed with exit code 2"
+        );
+        assert!(!matches!(err, NixError::SubstituterUnreachable { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_evaluation_error_location() {
+        let err = classify(
+            "error: undefined variable 'pkgz'\n\n       at /nix/store/abc-source/flake.nix:12:5:\n\n           11|   {\n           12|     pkgz.hello;\n"
+        );
+        match err {
+            NixError::EvaluationError { message, file, line, column } => {
+                assert_eq!(message, "undefined variable 'pkgz'");
+                assert_eq!(file, "/nix/store/abc-source/flake.nix");
+                assert_eq!((line, column), (12, 5));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Nix before 2.13 puts the location on the message line
+        let err = classify("error: syntax error, unexpected '}' at /nix/store/abc-source/flake.nix:3:1");
+        assert!(matches!(err, NixError::EvaluationError { ref message, line: 3, .. }
+            if message == "syntax error, unexpected '}'"));
+    }
+
+    #[test]
+    fn test_daemon_not_running() {
+        let err = classify(
+            "error: cannot connect to socket at '/nix/var/nix/daemon-socket/socket': Connection refused"
+        );
+        assert!(matches!(err, NixError::DaemonNotRunning { ref socket } if socket.ends_with("socket")));
+        assert!(err.suggestion().unwrap().contains("nix-daemon"));
+    }
+
+    #[test]
+    fn test_unrecognized_failure() {
+        assert!(matches!(classify("error: something unexpected"), NixError::CommandFailed(_)));
+    }
+}
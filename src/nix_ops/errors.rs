@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use super::classify::classify;
+
 #[derive(Error, Debug)]
 pub enum NixError {
     #[error("Package not found: {0}")]
@@ -23,8 +25,38 @@ pub enum NixError {
     #[error("Package not installed: {0}")]
     NotInstalled(String),
 
-    #[error("Invalid package name: {0}")]
-    InvalidPackageName(String),
+    #[error("Invalid package reference '{input}': {reason}")]
+    InvalidPackageRef { input: String, reason: String },
+
+    #[error("No nixpkgs revision with {package} {version} is known")]
+    VersionNotFound { package: String, version: String },
+
+    #[error("Attribute '{attribute}' not found{}", flake.as_deref().map(|f| format!(" in {}", f)).unwrap_or_default())]
+    AttributeNotFound { attribute: String, flake: Option<String> },
+
+    #[error("Hash mismatch in {derivation}: expected {specified}, got {got}")]
+    HashMismatch { derivation: String, specified: String, got: String },
+
+    #[error("Refusing to install {package}: it has an unfree license ({license})")]
+    UnfreeRefused { package: String, license: String },
+
+    #[error("Refusing to install {package}: it is marked as insecure")]
+    InsecureRefused { package: String, issues: Vec<String> },
+
+    #[error("Refusing to install {package}: it is marked as broken")]
+    BrokenRefused { package: String },
+
+    #[error("Out of disk space{}", path.as_deref().map(|p| format!(" while writing {}", p)).unwrap_or_default())]
+    OutOfDiskSpace { path: Option<String> },
+
+    #[error("Binary cache {url} is unreachable")]
+    SubstituterUnreachable { url: String },
+
+    #[error("Evaluation error at {file}:{line}:{column}: {message}")]
+    EvaluationError { message: String, file: String, line: u32, column: u32 },
+
+    #[error("Cannot connect to the Nix daemon at {socket}")]
+    DaemonNotRunning { socket: String },
 
     #[error("Nix not found. Please install Nix: https://nixos.org/download.html")]
     NixNotInstalled,
@@ -33,4 +65,209 @@ pub enum NixError {
     WSL2NotAvailable,
 }
 
-pub type Result<T> = std::result::Result<T, NixError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, NixError>;
+
+/// Why nixpkgs refused to evaluate a package
+///
+/// Each refusal can be overridden for a single Nix invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Refusal {
+    Unfree,
+    Insecure,
+    Broken,
+}
+
+impl Refusal {
+    pub const ALL: [Refusal; 3] = [Refusal::Unfree, Refusal::Insecure, Refusal::Broken];
+
+    /// Environment variable making nixpkgs accept the package anyway
+    ///
+    /// Flakes only see it when evaluated with `--impure`.
+    pub fn env_var(self) -> &'static str {
+        match self {
+            Refusal::Unfree => "NIXPKGS_ALLOW_UNFREE",
+            Refusal::Insecure => "NIXPKGS_ALLOW_INSECURE",
+            Refusal::Broken => "NIXPKGS_ALLOW_BROKEN",
+        }
+    }
+
+    /// Command-line flag of `nsfw install` that allows it up front
+    pub fn flag(self) -> &'static str {
+        match self {
+            Refusal::Unfree => "--allow-unfree",
+            Refusal::Insecure => "--allow-insecure",
+            Refusal::Broken => "--allow-broken",
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Refusal::Unfree => "unfree",
+            Refusal::Insecure => "insecure",
+            Refusal::Broken => "broken",
+        })
+    }
+}
+
+/// Fragments of Nix/curl stderr output that indicate a network failure
+const NETWORK_FAILURE_PATTERNS: &[&str] = &[
+    "unable to download",
+    "could not resolve host",
+    "couldn't resolve host",
+    "could not resolve proxy",
+    "failed to connect",
+    "connection refused",
+    "connection timed out",
+    "timeout was reached",
+    "network is unreachable",
+    "temporary failure in name resolution",
+];
+
+/// Check if a failed command's stderr describes a network failure
+pub fn is_network_failure(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    NETWORK_FAILURE_PATTERNS.iter().any(|pattern| stderr.contains(pattern))
+}
+
+impl NixError {
+    /// Build the error for a failed Nix command from its stderr
+    ///
+    /// See `classify` for the recognized failures.
+    pub fn from_stderr(stderr: impl Into<String>) -> Self {
+        classify(&stderr.into())
+    }
+
+    /// Check if the error means Nix could not be reached at all
+    ///
+    /// Read-only commands fall back to local state on these errors.
+    pub fn is_connectivity(&self) -> bool {
+        matches!(
+            self,
+            NixError::NetworkError(_)
+                | NixError::WSL2NotAvailable
+                | NixError::SubstituterUnreachable { .. }
+                | NixError::DaemonNotRunning { .. }
+        )
+    }
+
+    /// Stable identifier of the error kind, e.g. `NSFW-E012`
+    ///
+    /// Codes are part of the CLI's interface: never renumber or reuse them.
+    pub fn code(&self) -> &'static str {
+        match self {
+            NixError::PackageNotFound(_) => "NSFW-E001",
+            NixError::NetworkError(_) => "NSFW-E002",
+            NixError::CommandFailed(_) => "NSFW-E003",
+            NixError::ParseError(_) => "NSFW-E004",
+            NixError::IoError(_) => "NSFW-E005",
+            NixError::AlreadyInstalled(_) => "NSFW-E006",
+            NixError::NotInstalled(_) => "NSFW-E007",
+            NixError::InvalidPackageRef { .. } => "NSFW-E008",
+            NixError::VersionNotFound { .. } => "NSFW-E009",
+            NixError::NixNotInstalled => "NSFW-E010",
+            NixError::WSL2NotAvailable => "NSFW-E011",
+            NixError::AttributeNotFound { .. } => "NSFW-E012",
+            NixError::HashMismatch { .. } => "NSFW-E013",
+            NixError::UnfreeRefused { .. } => "NSFW-E014",
+            NixError::OutOfDiskSpace { .. } => "NSFW-E015",
+            NixError::SubstituterUnreachable { .. } => "NSFW-E016",
+            NixError::EvaluationError { .. } => "NSFW-E017",
+            NixError::DaemonNotRunning { .. } => "NSFW-E018",
+            NixError::InsecureRefused { .. } => "NSFW-E019",
+            NixError::BrokenRefused { .. } => "NSFW-E020",
+        }
+    }
+
+    /// Process exit code for the error
+    ///
+    /// `NSFW-E0nn` exits with `10 + nn`, keeping 1 for errors outside Nix
+    /// operations and 2 for invalid command-line usage.
+    pub fn exit_code(&self) -> i32 {
+        let number: i32 = self.code()["NSFW-E".len()..].parse().expect("malformed error code");
+        10 + number
+    }
+
+    /// The refusal and the refused package (`name-version`), if nixpkgs
+    /// refused to evaluate a package
+    pub fn refusal(&self) -> Option<(Refusal, &str)> {
+        match self {
+            NixError::UnfreeRefused { package, .. } => Some((Refusal::Unfree, package)),
+            NixError::InsecureRefused { package, .. } => Some((Refusal::Insecure, package)),
+            NixError::BrokenRefused { package } => Some((Refusal::Broken, package)),
+            _ => None,
+        }
+    }
+
+    /// What the user can do about the error, if there is a specific fix
+    pub fn suggestion(&self) -> Option<String> {
+        let suggestion = match self {
+            NixError::PackageNotFound(name) | NixError::AttributeNotFound { attribute: name, .. } => {
+                let name = name.rsplit('.').next().unwrap_or(name);
+                format!("Search for the correct name: nsfw search {}", name)
+            }
+            NixError::HashMismatch { .. } => {
+                "The upstream source changed since the package was defined. \
+                 Report it at https://github.com/NixOS/nixpkgs/issues".to_string()
+            }
+            NixError::UnfreeRefused { .. }
+            | NixError::InsecureRefused { .. }
+            | NixError::BrokenRefused { .. } => {
+                let (refusal, _) = self.refusal().expect("refusal variant");
+                format!("Approve the package when asked, or re-run the install with {}", refusal.flag())
+            }
+            NixError::OutOfDiskSpace { .. } => {
+                "Free up space by removing old generations: nix-collect-garbage -d".to_string()
+            }
+            NixError::SubstituterUnreachable { url } => {
+                format!("Check your internet connection and proxy settings, or whether {} is down", url)
+            }
+            NixError::NetworkError(_) => "Check your internet connection and proxy settings".to_string(),
+            NixError::EvaluationError { file, line, .. } => {
+                format!("Fix the Nix expression in {} around line {}", file, line)
+            }
+            NixError::DaemonNotRunning { .. } => {
+                "Start the Nix daemon: sudo systemctl start nix-daemon".to_string()
+            }
+            NixError::AlreadyInstalled(_) => "List installed packages: nsfw list".to_string(),
+            NixError::NotInstalled(_) => "List installed packages: nsfw list".to_string(),
+            NixError::VersionNotFound { package, .. } => {
+                format!("List the known versions: nsfw info {}", package)
+            }
+            NixError::NixNotInstalled => "Run the setup: nsfw setup".to_string(),
+            NixError::WSL2NotAvailable => "Enable WSL2: wsl --install".to_string(),
+            NixError::CommandFailed(_)
+            | NixError::ParseError(_)
+            | NixError::IoError(_)
+            | NixError::InvalidPackageRef { .. } => return None,
+        };
+        Some(suggestion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_stderr_detects_network_failures() {
+        let err = NixError::from_stderr(
+            "error: unable to download 'https://channels.nixos.org/nixpkgs-unstable': Couldn't resolve host name (6)"
+        );
+        assert!(matches!(err, NixError::NetworkError(_)));
+        assert!(err.is_connectivity());
+
+        let err = NixError::from_stderr("error: attribute 'nosuchpkg' missing");
+        assert!(matches!(err, NixError::AttributeNotFound { .. }));
+        assert!(!err.is_connectivity());
+    }
+
+    #[test]
+    fn test_error_codes_are_stable() {
+        assert_eq!(NixError::PackageNotFound("x".into()).code(), "NSFW-E001");
+        assert_eq!(NixError::PackageNotFound("x".into()).exit_code(), 11);
+        assert_eq!(NixError::DaemonNotRunning { socket: "s".into() }.code(), "NSFW-E018");
+        assert_eq!(NixError::DaemonNotRunning { socket: "s".into() }.exit_code(), 28);
+    }
+}
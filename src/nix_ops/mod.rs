@@ -1,9 +1,19 @@
 pub mod errors;
+pub mod classify;
 pub mod types;
 pub mod executor;
 pub mod bridged_executor;
+pub mod backend;
+pub mod package_ref;
+pub mod profile;
+pub mod plan;
+pub mod store;
 
-pub use errors::{NixError, Result};
+pub use errors::{NixError, Refusal, Result};
 pub use types::{Package, SearchResult, InstalledPackage};
 pub use executor::NixExecutor;
 pub use bridged_executor::BridgedNixExecutor;
+pub use backend::{NixBackend, default_backend, default_bridge, runs_natively};
+pub use plan::{InstallPlan, RemovePlan};
+pub use store::StoreMaintenance;
+pub use package_ref::{FlakeRef, PackageRef, nixpkgs_at, pinned_nixpkgs_revision};
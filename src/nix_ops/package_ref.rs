@@ -0,0 +1,480 @@
+/// Parsed package references
+///
+/// A `PackageRef` names what to install: a flake, an attribute path inside
+/// it and optionally the outputs to use. It is parsed and validated before
+/// any Nix command runs, so typos are reported up front instead of as
+/// opaque Nix errors.
+///
+/// Accepted forms:
+/// - `firefox`, `python3Packages.requests` (attribute path in nixpkgs)
+/// - `openssl^dev,man` (specific outputs, `^*` for all)
+/// - `terraform@1.5.7`, `nodejs@18` (a version from historical nixpkgs)
+/// - `nixpkgs#hello`, `github:owner/repo#pkg`, `git+https://host/repo#pkg`
+/// - `./my-flake#app`, `/home/me/flake`, `C:\src\flake#app` (local flakes)
+use super::errors::NixError;
+use std::fmt;
+use std::str::FromStr;
+
+/// Flake registry name used for bare attribute paths
+pub const DEFAULT_FLAKE: &str = "nixpkgs";
+
+/// URL schemes of flake references (see `nix help flake`)
+const FLAKE_URL_SCHEMES: &[&str] = &[
+    "github:", "gitlab:", "sourcehut:", "git+", "hg+", "path:", "tarball+",
+    "file+", "http://", "https://", "flake:",
+];
+
+/// Where a package comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakeRef {
+    /// Flake from the registry, e.g. `nixpkgs`
+    Indirect(String),
+
+    /// Flake URL, e.g. `github:owner/repo` or `git+https://host/repo`
+    Url(String),
+
+    /// Flake in a local directory (Windows or Linux path)
+    Path(String),
+}
+
+impl FlakeRef {
+    /// Check if this is the default nixpkgs flake
+    pub fn is_nixpkgs(&self) -> bool {
+        matches!(self, FlakeRef::Indirect(name) if name == DEFAULT_FLAKE)
+    }
+
+    fn parse(input: &str, original: &str) -> Result<Self, NixError> {
+        if input.chars().any(char::is_whitespace) {
+            return Err(invalid(original, "flake reference must not contain spaces"));
+        }
+
+        if is_local_path(input) {
+            return Ok(FlakeRef::Path(input.to_string()));
+        }
+
+        if let Some(scheme) = FLAKE_URL_SCHEMES.iter().find(|s| input.starts_with(*s)) {
+            let rest = &input[scheme.len()..];
+            if rest.is_empty() {
+                return Err(invalid(original, &format!("'{}' needs a location", scheme)));
+            }
+            if matches!(*scheme, "github:" | "gitlab:" | "sourcehut:") {
+                let mut parts = rest.split('/');
+                let owner = parts.next().unwrap_or("");
+                let repo = parts.next().unwrap_or("");
+                if owner.is_empty() || repo.is_empty() {
+                    return Err(invalid(original, &format!("expected '{}owner/repo'", scheme)));
+                }
+            }
+            return Ok(FlakeRef::Url(input.to_string()));
+        }
+
+        if input.is_empty() || !input.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c)) {
+            return Err(invalid(original, "unknown flake reference"));
+        }
+        Ok(FlakeRef::Indirect(input.to_string()))
+    }
+}
+
+impl fmt::Display for FlakeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlakeRef::Indirect(s) | FlakeRef::Url(s) | FlakeRef::Path(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Reference to a package: flake, attribute path and outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageRef {
+    /// Flake providing the package
+    pub flake: FlakeRef,
+
+    /// Attribute path inside the flake (`None` = the flake's default package)
+    pub attr_path: Option<String>,
+
+    /// Requested outputs (empty = the package's default outputs)
+    pub outputs: Vec<String>,
+
+    /// Requested version, before it is resolved to a nixpkgs revision
+    pub version: Option<String>,
+}
+
+impl PackageRef {
+    /// Parse and validate a package reference
+    pub fn parse(input: &str) -> Result<Self, NixError> {
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            return Err(invalid(input, "package name is empty"));
+        }
+
+        let (base, outputs) = split_outputs(trimmed, input)?;
+
+        let (flake, attr_path) = match base.split_once('#') {
+            Some((flake, attr)) => {
+                let flake = if flake.is_empty() {
+                    return Err(invalid(input, "missing flake before '#'"));
+                } else {
+                    FlakeRef::parse(flake, input)?
+                };
+                let attr = if attr.is_empty() { None } else { Some(attr.to_string()) };
+                (flake, attr)
+            }
+            None if is_local_path(base) || FLAKE_URL_SCHEMES.iter().any(|s| base.starts_with(s)) => {
+                (FlakeRef::parse(base, input)?, None)
+            }
+            None => {
+                // nix-env style "nixpkgs.hello" means the same as "hello"
+                let attr = base.strip_prefix("nixpkgs.")
+                    .or_else(|| base.strip_prefix("nixos."))
+                    .unwrap_or(base);
+                (FlakeRef::Indirect(DEFAULT_FLAKE.to_string()), Some(attr.to_string()))
+            }
+        };
+
+        let (attr_path, version) = match attr_path.as_deref().and_then(|a| a.split_once('@')) {
+            Some((attr, version)) => (Some(attr.to_string()), Some(version.to_string())),
+            None => (attr_path, None),
+        };
+
+        if let Some(attr) = &attr_path {
+            validate_attr_path(attr, input)?;
+        }
+        if let Some(version) = &version {
+            validate_version(version, input)?;
+            if !flake.is_nixpkgs() {
+                return Err(invalid(input, "versions can only be requested for nixpkgs packages"));
+            }
+        }
+
+        Ok(Self { flake, attr_path, outputs, version })
+    }
+
+    /// Pin the package to a nixpkgs revision
+    ///
+    /// Replaces the flake with `nixpkgs_at(revision)`; the requested version
+    /// is considered resolved.
+    pub fn pinned(&self, revision: &str) -> Self {
+        Self {
+            flake: nixpkgs_at(revision),
+            attr_path: self.attr_path.clone(),
+            outputs: self.outputs.clone(),
+            version: None,
+        }
+    }
+
+    /// Fail if a requested version was not resolved with `pinned` yet
+    ///
+    /// Installing it as-is would silently install the current version.
+    pub fn require_resolved(&self) -> Result<&Self, NixError> {
+        match &self.version {
+            Some(_) => Err(invalid(
+                &self.to_string(),
+                "the version must be resolved to a nixpkgs revision first",
+            )),
+            None => Ok(self),
+        }
+    }
+
+    /// Short name for display and wrapper files
+    ///
+    /// The last attribute path segment (`requests` for
+    /// `python3Packages.requests`), else the repository or directory name.
+    pub fn name(&self) -> String {
+        if let Some(attr) = &self.attr_path {
+            return attr.rsplit('.').next().unwrap_or(attr).to_string();
+        }
+
+        let flake = self.flake.to_string();
+        flake
+            .trim_end_matches(['/', '\\'])
+            .rsplit(['/', '\\', ':'])
+            .next()
+            .filter(|s| !s.is_empty())
+            .unwrap_or("default")
+            .to_string()
+    }
+
+    /// Check if the package comes from the default nixpkgs flake
+    pub fn is_nixpkgs(&self) -> bool {
+        self.flake.is_nixpkgs()
+    }
+
+    /// The installable to pass to `nix`, e.g. `nixpkgs#openssl^dev,man`
+    pub fn installable(&self) -> String {
+        self.installable_with_flake(&self.flake.to_string())
+    }
+
+    /// The installable without outputs, for `nix eval`
+    pub fn eval_target(&self) -> String {
+        let mut target = self.flake.to_string();
+        target.push('#');
+        target.push_str(self.attr_path.as_deref().unwrap_or("default"));
+        target
+    }
+
+    /// The installable, with Windows flake paths rewritten by `translate`
+    ///
+    /// Used to turn `C:\src\flake` into a path WSL2 can read.
+    pub fn installable_translated<F>(&self, translate: F) -> Result<String, NixError>
+    where
+        F: FnOnce(&str) -> anyhow::Result<String>,
+    {
+        match &self.flake {
+            FlakeRef::Path(path) if is_windows_path(path) => {
+                let translated = translate(path)
+                    .map_err(|e| invalid(path, &format!("cannot translate path: {}", e)))?;
+                Ok(self.installable_with_flake(&translated))
+            }
+            _ => Ok(self.installable()),
+        }
+    }
+
+    fn installable_with_flake(&self, flake: &str) -> String {
+        let mut installable = flake.to_string();
+        if let Some(attr) = &self.attr_path {
+            installable.push('#');
+            installable.push_str(attr);
+        }
+        if !self.outputs.is_empty() {
+            installable.push('^');
+            installable.push_str(&self.outputs.join(","));
+        }
+        installable
+    }
+}
+
+impl FromStr for PackageRef {
+    type Err = NixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PackageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_nixpkgs() {
+            // Bare attribute paths are the common case; keep them short
+            let mut short = self.attr_path.clone().unwrap_or_default();
+            if let Some(version) = &self.version {
+                short.push('@');
+                short.push_str(version);
+            }
+            if !self.outputs.is_empty() {
+                short.push('^');
+                short.push_str(&self.outputs.join(","));
+            }
+            f.write_str(&short)
+        } else {
+            f.write_str(&self.installable())
+        }
+    }
+}
+
+/// Split a trailing `^out1,out2` from the reference
+fn split_outputs<'a>(input: &'a str, original: &str) -> Result<(&'a str, Vec<String>), NixError> {
+    let Some((base, outputs)) = input.rsplit_once('^') else {
+        return Ok((input, Vec::new()));
+    };
+
+    // A '^' inside the flake part (e.g. a URL query) is not an output spec
+    if base.contains('#') && outputs.contains('#') {
+        return Ok((input, Vec::new()));
+    }
+
+    let outputs: Vec<String> = outputs.split(',').map(|o| o.trim().to_string()).collect();
+    for output in &outputs {
+        let valid = output == "*"
+            || (!output.is_empty() && output.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c)));
+        if !valid {
+            return Err(invalid(original, &format!("invalid output name '{}'", output)));
+        }
+    }
+
+    Ok((base, outputs))
+}
+
+/// Check a requested version such as `1.5.7` or `18`
+fn validate_version(version: &str, original: &str) -> Result<(), NixError> {
+    let valid = version.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && version.chars().all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c));
+    if !valid {
+        return Err(invalid(original, &format!("invalid version '{}'", version)));
+    }
+    Ok(())
+}
+
+/// Check each segment of an attribute path
+fn validate_attr_path(attr_path: &str, original: &str) -> Result<(), NixError> {
+    for segment in attr_path.split('.') {
+        if segment.is_empty() {
+            return Err(invalid(original, "attribute path has an empty segment"));
+        }
+        if let Some(c) = segment.chars().find(|c| !(c.is_ascii_alphanumeric() || "_-'+".contains(*c))) {
+            return Err(invalid(original, &format!("unexpected character '{}' in attribute path", c)));
+        }
+    }
+    Ok(())
+}
+
+/// Flake reference for nixpkgs at a specific commit
+///
+/// Full commit hashes use the `github:` scheme. Channel revisions only carry
+/// an abbreviated hash, which GitHub resolves for archive downloads.
+pub fn nixpkgs_at(revision: &str) -> FlakeRef {
+    if revision.len() == 40 {
+        FlakeRef::Url(format!("github:NixOS/nixpkgs/{}", revision))
+    } else {
+        FlakeRef::Url(format!("https://github.com/NixOS/nixpkgs/archive/{}.tar.gz", revision))
+    }
+}
+
+/// The nixpkgs commit a flake URL is pinned to, if any
+///
+/// Recognizes the URLs created by `nixpkgs_at`, as recorded in the
+/// `originalUrl` of profile elements.
+pub fn pinned_nixpkgs_revision(url: &str) -> Option<String> {
+    let url = url.split('?').next().unwrap_or(url);
+    let revision = url.strip_prefix("github:NixOS/nixpkgs/")
+        .or_else(|| {
+            url.strip_prefix("https://github.com/NixOS/nixpkgs/archive/")
+                .and_then(|rest| rest.strip_suffix(".tar.gz"))
+        })?;
+
+    let is_commit = (7..=40).contains(&revision.len())
+        && revision.chars().all(|c| c.is_ascii_hexdigit());
+    is_commit.then(|| revision.to_string())
+}
+
+/// Check if a path uses Windows syntax (drive letter or backslashes)
+fn is_windows_path(input: &str) -> bool {
+    let bytes = input.as_bytes();
+    let has_drive = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/');
+
+    has_drive || input.contains('\\')
+}
+
+/// Check if a flake reference is a local directory
+fn is_local_path(input: &str) -> bool {
+    is_windows_path(input)
+        || input.starts_with('/')
+        || input.starts_with("./")
+        || input.starts_with("../")
+        || input.starts_with("~/")
+        || input == "."
+}
+
+fn invalid(input: &str, reason: &str) -> NixError {
+    NixError::InvalidPackageRef {
+        input: input.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_attr_paths() {
+        let pkg = PackageRef::parse("firefox").unwrap();
+        assert!(pkg.is_nixpkgs());
+        assert_eq!(pkg.installable(), "nixpkgs#firefox");
+
+        let pkg = PackageRef::parse("python3Packages.requests").unwrap();
+        assert_eq!(pkg.installable(), "nixpkgs#python3Packages.requests");
+        assert_eq!(pkg.name(), "requests");
+
+        // nix-env attribute paths from the package cache
+        let pkg = PackageRef::parse("nixpkgs.hello").unwrap();
+        assert_eq!(pkg.attr_path.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn test_outputs() {
+        let pkg = PackageRef::parse("openssl^dev,man").unwrap();
+        assert_eq!(pkg.outputs, vec!["dev".to_string(), "man".to_string()]);
+        assert_eq!(pkg.installable(), "nixpkgs#openssl^dev,man");
+        assert_eq!(pkg.to_string(), "openssl^dev,man");
+        assert_eq!(pkg.eval_target(), "nixpkgs#openssl");
+
+        assert_eq!(PackageRef::parse("nixpkgs#glibc^*").unwrap().outputs, vec!["*".to_string()]);
+        assert!(PackageRef::parse("openssl^").is_err());
+        assert!(PackageRef::parse("openssl^dev,,man").is_err());
+    }
+
+    #[test]
+    fn test_versions() {
+        let pkg = PackageRef::parse("terraform@1.5.7").unwrap();
+        assert_eq!(pkg.attr_path.as_deref(), Some("terraform"));
+        assert_eq!(pkg.version.as_deref(), Some("1.5.7"));
+        assert_eq!(pkg.to_string(), "terraform@1.5.7");
+
+        let pkg = PackageRef::parse("nixpkgs#openssl@3.0^dev").unwrap();
+        assert_eq!(pkg.version.as_deref(), Some("3.0"));
+        assert_eq!(pkg.outputs, vec!["dev".to_string()]);
+
+        let pinned = pkg.pinned("abcdef123456");
+        assert_eq!(pinned.version, None);
+        assert_eq!(pinned.installable(), "https://github.com/NixOS/nixpkgs/archive/abcdef123456.tar.gz#openssl^dev");
+
+        assert!(PackageRef::parse("terraform@").is_err());
+        assert!(PackageRef::parse("github:owner/repo#tool@1.0").is_err());
+    }
+
+    #[test]
+    fn test_pinned_nixpkgs_revision() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let pinned = PackageRef::parse("hello").unwrap().pinned(commit);
+        assert_eq!(pinned.flake.to_string(), format!("github:NixOS/nixpkgs/{}", commit));
+        assert_eq!(pinned_nixpkgs_revision(&pinned.flake.to_string()).as_deref(), Some(commit));
+
+        let archive = "https://github.com/NixOS/nixpkgs/archive/abcdef123456.tar.gz?narHash=sha256-x";
+        assert_eq!(pinned_nixpkgs_revision(archive).as_deref(), Some("abcdef123456"));
+        assert_eq!(pinned_nixpkgs_revision("flake:nixpkgs"), None);
+        assert_eq!(pinned_nixpkgs_revision("github:NixOS/nixpkgs/nixos-unstable"), None);
+    }
+
+    #[test]
+    fn test_flake_refs() {
+        let pkg = PackageRef::parse("github:nix-community/home-manager#home-manager").unwrap();
+        assert_eq!(pkg.flake, FlakeRef::Url("github:nix-community/home-manager".to_string()));
+        assert_eq!(pkg.attr_path.as_deref(), Some("home-manager"));
+        assert_eq!(pkg.to_string(), "github:nix-community/home-manager#home-manager");
+
+        let pkg = PackageRef::parse("github:owner/tool").unwrap();
+        assert_eq!(pkg.attr_path, None);
+        assert_eq!(pkg.name(), "tool");
+        assert_eq!(pkg.eval_target(), "github:owner/tool#default");
+
+        assert!(PackageRef::parse("github:owner").is_err());
+        assert!(PackageRef::parse("#hello").is_err());
+    }
+
+    #[test]
+    fn test_local_paths() {
+        let pkg = PackageRef::parse("./my-flake#app").unwrap();
+        assert_eq!(pkg.flake, FlakeRef::Path("./my-flake".to_string()));
+
+        let pkg = PackageRef::parse(r"C:\src\flake#app").unwrap();
+        assert_eq!(pkg.flake, FlakeRef::Path(r"C:\src\flake".to_string()));
+        let installable = pkg.installable_translated(|_| Ok("/mnt/c/src/flake".to_string())).unwrap();
+        assert_eq!(installable, "/mnt/c/src/flake#app");
+
+        let pkg = PackageRef::parse("/home/me/tools").unwrap();
+        assert_eq!(pkg.name(), "tools");
+        let installable = pkg.installable_translated(|_| panic!("Linux paths are not translated")).unwrap();
+        assert_eq!(installable, "/home/me/tools");
+    }
+
+    #[test]
+    fn test_rejects_invalid_names() {
+        for input in ["", "  ", "fire fox", "python3Packages..requests", "hello;rm", "pkg$"] {
+            let err = PackageRef::parse(input).unwrap_err();
+            assert!(matches!(err, NixError::InvalidPackageRef { .. }), "{input:?} -> {err}");
+        }
+    }
+}
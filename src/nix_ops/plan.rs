@@ -0,0 +1,193 @@
+/// Previews of what installing or removing packages will do
+///
+/// Install plans come from `nix build --dry-run`, which reports the store
+/// paths to build and to fetch from a binary cache with their sizes. Remove
+/// plans come from `nix path-info`, sizing the closure that becomes garbage.
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::errors::NixError;
+
+/// What `nsfw install` will do
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallPlan {
+    /// Packages as requested
+    pub packages: Vec<String>,
+
+    /// Installables passed to Nix
+    pub installables: Vec<String>,
+
+    /// Derivations that will be built locally
+    pub to_build: Vec<String>,
+
+    /// Store paths that will be downloaded from a binary cache
+    pub to_fetch: Vec<String>,
+
+    /// Bytes to download
+    pub download_size: u64,
+
+    /// Bytes the fetched paths take up in the store
+    pub unpacked_size: u64,
+
+    /// Growth of the store in bytes
+    ///
+    /// Only counts fetched paths; the size of build outputs is not known
+    /// before they are built.
+    pub disk_delta: i64,
+}
+
+impl InstallPlan {
+    /// Plan from the stderr of `nix build --dry-run`
+    pub fn from_dry_run(packages: Vec<String>, installables: Vec<String>, stderr: &str) -> Self {
+        let mut plan = InstallPlan {
+            packages,
+            installables,
+            ..Default::default()
+        };
+
+        let mut section: Option<&mut Vec<String>> = None;
+        for line in stderr.lines() {
+            let trimmed = line.trim();
+            if trimmed.ends_with("will be built:") {
+                section = Some(&mut plan.to_build);
+            } else if trimmed.contains("will be fetched") {
+                if let Some(caps) = FETCH_SIZES.captures(trimmed) {
+                    plan.download_size = mib_to_bytes(&caps[1]);
+                    plan.unpacked_size = mib_to_bytes(&caps[2]);
+                }
+                section = Some(&mut plan.to_fetch);
+            } else if trimmed.starts_with("/nix/store/") {
+                if let Some(paths) = section.as_deref_mut() {
+                    paths.push(trimmed.to_string());
+                }
+            } else {
+                section = None;
+            }
+        }
+
+        plan.disk_delta = plan.unpacked_size as i64;
+        plan
+    }
+
+    /// Check if everything is already in the store
+    pub fn is_noop(&self) -> bool {
+        self.to_build.is_empty() && self.to_fetch.is_empty()
+    }
+}
+
+/// What `nsfw remove` will do
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemovePlan {
+    /// Profile elements to remove
+    pub packages: Vec<String>,
+
+    /// Store paths of the elements
+    pub store_paths: Vec<String>,
+
+    /// Bytes taken up by the elements' closure
+    pub closure_size: u64,
+
+    /// Bytes of the closure no remaining profile element uses
+    pub reclaimable_size: u64,
+
+    /// Change of the store in bytes after the next garbage collection
+    ///
+    /// Removing a package frees nothing by itself; other generations and
+    /// garbage collector roots may keep paths alive longer.
+    pub disk_delta: i64,
+}
+
+/// `these 12 paths will be fetched (45.67 MiB download, 180.23 MiB unpacked):`
+static FETCH_SIZES: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\(([\d.]+) MiB download, ([\d.]+) MiB unpacked\)").expect("invalid pattern")
+});
+
+fn mib_to_bytes(mib: &str) -> u64 {
+    (mib.parse::<f64>().unwrap_or(0.0) * 1024.0 * 1024.0).round() as u64
+}
+
+/// A store path as reported by `nix path-info --json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathInfo {
+    pub path: String,
+
+    /// Bytes of the path itself
+    pub nar_size: u64,
+
+    /// Bytes of the path and everything it depends on (with `--closure-size`)
+    pub closure_size: Option<u64>,
+}
+
+/// Parse `nix path-info --json` output
+///
+/// Nix 2.19 changed the output from an array of objects with a `path` field
+/// to an object keyed by path. Paths that are not valid are skipped.
+pub fn parse_path_info(json: &str) -> Result<Vec<PathInfo>, NixError> {
+    let info = |path: String, info: &Value| PathInfo {
+        path,
+        nar_size: info.get("narSize").and_then(Value::as_u64).unwrap_or(0),
+        closure_size: info.get("closureSize").and_then(Value::as_u64),
+    };
+
+    let value: Value = serde_json::from_str(json)?;
+    let paths = match value {
+        Value::Object(paths) => paths.into_iter()
+            .filter(|(_, value)| value.is_object())
+            .map(|(path, value)| info(path, &value))
+            .collect(),
+        Value::Array(paths) => paths.iter()
+            .filter(|value| value.get("valid").and_then(Value::as_bool) != Some(false))
+            .filter_map(|value| Some(info(value.get("path")?.as_str()?.to_string(), value)))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_plan_from_dry_run() {
+        let stderr = "\
+these 2 derivations will be built:
+  /nix/store/aaa-hello-wrapper.drv
+  /nix/store/bbb-hello-env.drv
+these 3 paths will be fetched (12.50 MiB download, 50.00 MiB unpacked):
+  /nix/store/ccc-hello-2.12.1
+  /nix/store/ddd-glibc-2.39-52
+  /nix/store/eee-libidn2-2.3.7
+";
+        let plan = InstallPlan::from_dry_run(vec!["hello".into()], vec!["nixpkgs#hello".into()], stderr);
+        assert_eq!(plan.to_build.len(), 2);
+        assert_eq!(plan.to_fetch.len(), 3);
+        assert_eq!(plan.download_size, 12 * 1024 * 1024 + 512 * 1024);
+        assert_eq!(plan.disk_delta, 50 * 1024 * 1024);
+        assert!(!plan.is_noop());
+    }
+
+    #[test]
+    fn test_install_plan_single_path_and_noop() {
+        let stderr = "this path will be fetched (0.05 MiB download, 0.21 MiB unpacked):\n  /nix/store/abc-jq-1.7.1\n";
+        let plan = InstallPlan::from_dry_run(vec!["jq".into()], vec!["nixpkgs#jq".into()], stderr);
+        assert_eq!(plan.to_fetch, vec!["/nix/store/abc-jq-1.7.1"]);
+        assert!(plan.to_build.is_empty());
+
+        assert!(InstallPlan::default().is_noop());
+    }
+
+    #[test]
+    fn test_parse_path_info_both_shapes() {
+        let jq = PathInfo { path: "/nix/store/a-jq".to_string(), nar_size: 100, closure_size: Some(900) };
+
+        let old = r#"[{"path":"/nix/store/a-jq","narSize":100,"closureSize":900,"valid":true},{"path":"/nix/store/b-x","valid":false}]"#;
+        assert_eq!(parse_path_info(old).unwrap(), vec![jq.clone()]);
+
+        let new = r#"{"/nix/store/a-jq":{"narSize":100,"closureSize":900},"/nix/store/b-x":null}"#;
+        assert_eq!(parse_path_info(new).unwrap(), vec![jq]);
+    }
+}
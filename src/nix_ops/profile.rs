@@ -0,0 +1,262 @@
+/// Parsing of `nix profile list --json`
+///
+/// The `--json` flag exists since Nix 2.17, the oldest release NSFW
+/// supports; there is no fallback to the plain-text listing. The output
+/// format changed across Nix releases:
+/// - Manifest versions 1 and 2 (Nix < 2.20) list elements in an array.
+///   Elements have no name; version 1 calls the URLs `originalUri`/`uri`.
+/// - Manifest version 3 (Nix >= 2.20) keys elements by name.
+///
+/// Both shapes are parsed into the same `InstalledPackage` records.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::errors::NixError;
+use super::package_ref::{pinned_nixpkgs_revision, PackageRef};
+use super::types::InstalledPackage;
+
+/// Newest manifest version this parser was written against
+pub const SUPPORTED_MANIFEST_VERSION: u32 = 3;
+
+/// Top-level `nix profile list --json` output
+#[derive(Debug, Deserialize)]
+struct ProfileList {
+    /// Missing in some pre-release builds; the shape of `elements` decides then
+    #[serde(default)]
+    version: Option<u32>,
+
+    #[serde(default)]
+    elements: Elements,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Elements {
+    /// Manifest version 3 and later
+    Named(BTreeMap<String, ProfileElement>),
+    /// Manifest versions 1 and 2
+    Listed(Vec<ProfileElement>),
+}
+
+impl Default for Elements {
+    fn default() -> Self {
+        Elements::Listed(Vec::new())
+    }
+}
+
+/// A profile element, in any manifest version
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileElement {
+    #[serde(default = "default_active")]
+    active: bool,
+
+    #[serde(default)]
+    attr_path: Option<String>,
+
+    #[serde(default, alias = "originalUri")]
+    original_url: Option<String>,
+
+    #[serde(default, alias = "uri")]
+    url: Option<String>,
+
+    /// Requested outputs; `null` means the package's default outputs
+    #[serde(default)]
+    outputs: Option<Vec<String>>,
+
+    #[serde(default)]
+    priority: Option<i64>,
+
+    #[serde(default)]
+    store_paths: Vec<String>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Parse `nix profile list --json` output of any supported Nix version
+pub fn parse_profile_list(json: &str) -> Result<Vec<InstalledPackage>, NixError> {
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let list: ProfileList = serde_json::from_str(json)?;
+    if let Some(version) = list.version.filter(|v| *v > SUPPORTED_MANIFEST_VERSION) {
+        log::debug!("Parsing profile manifest version {} as version {}", version, SUPPORTED_MANIFEST_VERSION);
+    }
+
+    let packages = match list.elements {
+        Elements::Named(elements) => elements
+            .into_iter()
+            .map(|(name, element)| installed_package(name, element))
+            .collect(),
+        Elements::Listed(elements) => elements
+            .into_iter()
+            .map(|element| {
+                let name = element_name(&element);
+                installed_package(name, element)
+            })
+            .collect(),
+    };
+
+    Ok(packages)
+}
+
+/// Name of an unnamed (manifest version 1/2) element
+///
+/// Nix 2.20 names migrated elements after the last attribute path segment;
+/// elements installed without a flake fall back to the store path name.
+fn element_name(element: &ProfileElement) -> String {
+    if let Some(attr) = element.attr_path.as_deref().filter(|a| !a.is_empty()) {
+        return attr.rsplit('.').next().unwrap_or(attr).to_string();
+    }
+
+    element.store_paths.first()
+        .and_then(|path| store_path_name(path))
+        .map(|(pname, _)| pname)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn installed_package(name: String, element: ProfileElement) -> InstalledPackage {
+    let store_path = element.store_paths.first().cloned().unwrap_or_default();
+    let version = version_from_store_path(&store_path).unwrap_or_else(|| "unknown".to_string());
+    let pinned_revision = element.original_url.as_deref().and_then(pinned_nixpkgs_revision);
+
+    InstalledPackage {
+        name,
+        version,
+        store_path,
+        pinned_revision,
+        attr_path: element.attr_path,
+        original_url: element.original_url,
+        url: element.url,
+        outputs: element.outputs.unwrap_or_default(),
+        store_paths: element.store_paths,
+        priority: element.priority,
+        active: element.active,
+    }
+}
+
+/// Split the name of a store path into package name and version
+///
+/// Follows Nix's own rule: the version starts at the first dash-separated
+/// component that begins with a digit. Trailing output, wrapper or platform
+/// suffixes (`-dev`, `-env`, `-x86_64`) are dropped, so `python3-3.12.1-env`
+/// is `python3` version `3.12.1`.
+fn store_path_name(store_path: &str) -> Option<(String, Option<String>)> {
+    let base = store_path.strip_prefix("/nix/store/")?.split('/').next()?;
+    let (_hash, name) = base.split_once('-')?;
+
+    let components: Vec<&str> = name.split('-').collect();
+    let Some(start) = components.iter().position(|c| c.starts_with(|c: char| c.is_ascii_digit())) else {
+        return Some((name.to_string(), None));
+    };
+
+    let end = components[start..].iter()
+        .position(|c| !is_version_component(c))
+        .map_or(components.len(), |n| start + n);
+
+    let pname = components[..start].join("-");
+    let version = components[start..end].join("-");
+    Some((pname, Some(version)))
+}
+
+/// Check if a component continues a version (`1.0-2`, `1.0-rc1`)
+fn is_version_component(component: &str) -> bool {
+    let prerelease = ["rc", "alpha", "beta", "pre"].iter().any(|tag| {
+        component.strip_prefix(tag).is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+    });
+    prerelease || component.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Extract the package version from a store path
+///
+/// E.g. `/nix/store/<hash>-firefox-130.0/bin/firefox` is `130.0`.
+pub fn version_from_store_path(store_path: &str) -> Option<String> {
+    store_path_name(store_path).and_then(|(_, version)| version)
+}
+
+/// Find the profile element a package reference was installed as
+///
+/// nixpkgs packages match on the attribute path they were installed from,
+/// so `python3Packages.requests` finds the element named `requests`.
+/// Elements without an attribute path match on their name.
+pub fn find_installed<'a>(installed: &'a [InstalledPackage], package: &PackageRef) -> Option<&'a InstalledPackage> {
+    installed.iter().find(|p| match (&p.attr_path, &package.attr_path) {
+        (Some(installed_attr), Some(attr)) if package.is_nixpkgs() => {
+            installed_attr == attr || installed_attr.ends_with(&format!(".{}", attr))
+        }
+        _ => p.name == package.name(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_from_store_path() {
+        let version = |path: &str| version_from_store_path(path);
+
+        assert_eq!(version("/nix/store/abc123-firefox-130.0/bin/firefox").as_deref(), Some("130.0"));
+        assert_eq!(version("/nix/store/abc123-python3-3.12.1-env").as_deref(), Some("3.12.1"));
+        assert_eq!(version("/nix/store/abc123-openssl-3.0.13-dev").as_deref(), Some("3.0.13"));
+        assert_eq!(version("/nix/store/abc123-nodejs-slim-20.11.1").as_deref(), Some("20.11.1"));
+        assert_eq!(version("/nix/store/abc123-foo-1.0-rc1").as_deref(), Some("1.0-rc1"));
+        assert_eq!(version("/nix/store/abc123-firefox-130.0-x86_64/bin").as_deref(), Some("130.0"));
+        assert_eq!(version("/nix/store/abc123-vim"), None);
+        assert_eq!(version("not-a-store-path-1.0"), None);
+    }
+
+    #[test]
+    fn test_element_name_fallbacks() {
+        let json = r#"{"version":1,"elements":[
+            {"attrPath":"legacyPackages.x86_64-linux.python3Packages.requests",
+             "storePaths":["/nix/store/abc-python3.12-requests-2.31.0"]},
+            {"storePaths":["/nix/store/def-my-tool-0.3.1"]},
+            {"storePaths":[]}
+        ]}"#;
+
+        let names: Vec<String> = parse_profile_list(json).unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["requests", "my-tool", "unknown"]);
+    }
+
+    #[test]
+    fn test_pinned_revision() {
+        let json = r#"{"version":3,"elements":{
+            "terraform":{"originalUrl":"https://github.com/NixOS/nixpkgs/archive/abcdef123456.tar.gz",
+                         "storePaths":["/nix/store/abc-terraform-1.5.7"]},
+            "hello":{"originalUrl":"flake:nixpkgs","storePaths":["/nix/store/def-hello-2.12.1"]}
+        }}"#;
+
+        let packages = parse_profile_list(json).unwrap();
+        let terraform = packages.iter().find(|p| p.name == "terraform").unwrap();
+        assert_eq!(terraform.version, "1.5.7");
+        assert_eq!(terraform.pinned_revision.as_deref(), Some("abcdef123456"));
+        let hello = packages.iter().find(|p| p.name == "hello").unwrap();
+        assert_eq!(hello.pinned_revision, None);
+    }
+
+    #[test]
+    fn test_find_installed_by_attr_path() {
+        let json = r#"{"version":3,"elements":{
+            "requests":{"attrPath":"legacyPackages.x86_64-linux.python3Packages.requests",
+                        "originalUrl":"flake:nixpkgs","storePaths":["/nix/store/abc-python3.12-requests-2.31.0"]},
+            "my-tool":{"originalUrl":"path:/home/me/my-tool","storePaths":["/nix/store/def-my-tool-0.3.1"]}
+        }}"#;
+        let installed = parse_profile_list(json).unwrap();
+        let find = |input: &str| find_installed(&installed, &PackageRef::parse(input).unwrap()).map(|p| p.name.as_str());
+
+        assert_eq!(find("python3Packages.requests"), Some("requests"));
+        assert_eq!(find("python311Packages.requests"), None);
+        assert_eq!(find("my-tool"), Some("my-tool"));
+        assert_eq!(find("hello"), None);
+    }
+
+    #[test]
+    fn test_rejects_unknown_shape() {
+        assert!(matches!(parse_profile_list(r#"{"elements":"nope"}"#), Err(NixError::ParseError(_))));
+        assert!(parse_profile_list("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,463 @@
+/// Nix store disk usage and garbage collection
+///
+/// Inside WSL2 the store lives in a virtual disk that grows but never
+/// shrinks on its own, so users need to see where the space goes and get it
+/// back. Sizes come from `nix path-info`, dead paths from
+/// `nix-store --gc --print-dead`, garbage collector roots from
+/// `nix-store --gc --print-roots`, and profile generations from
+/// `nix-env --list-generations`; collection runs `nix-collect-garbage`.
+use chrono::NaiveDateTime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::backend::NixBackend;
+use super::errors::NixError;
+use super::plan::parse_path_info;
+
+/// A generation of the user's profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generation {
+    pub number: u32,
+
+    /// Creation time as printed by Nix (local time, `YYYY-MM-DD HH:MM:SS`)
+    pub created: String,
+
+    /// Whether this is the active generation
+    pub current: bool,
+}
+
+impl Generation {
+    /// Whole days between creation and `now`
+    pub fn age_days(&self, now: NaiveDateTime) -> Option<i64> {
+        let created = NaiveDateTime::parse_from_str(&self.created, "%Y-%m-%d %H:%M:%S").ok()?;
+        Some((now - created).num_days())
+    }
+}
+
+/// Closure size of an installed package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClosureUsage {
+    pub package: String,
+    pub closure_size: u64,
+}
+
+/// Disk usage of the Nix store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreUsage {
+    /// Bytes of all valid store paths
+    pub store_size: u64,
+
+    pub path_count: usize,
+
+    /// Bytes a garbage collection would free right now
+    pub reclaimable_size: u64,
+
+    pub dead_path_count: usize,
+
+    /// Profile generations, oldest first
+    pub generations: Vec<Generation>,
+
+    /// Installed packages with the largest closures, largest first
+    pub largest_closures: Vec<ClosureUsage>,
+}
+
+/// What `nsfw gc` would do
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcPreview {
+    /// Generations that would be deleted
+    pub generations: Vec<u32>,
+
+    pub dead_path_count: usize,
+
+    /// Live paths that only the deleted generations keep alive
+    pub released_path_count: usize,
+
+    /// Bytes of the dead and the released paths
+    pub reclaimable_size: u64,
+}
+
+/// What `nix-collect-garbage` did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    pub generations_removed: Vec<u32>,
+    pub paths_deleted: u64,
+    pub bytes_freed: u64,
+}
+
+/// `   5   2024-03-01 08:00:00   (current)`
+static GENERATION_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(\d+)\s+(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2})\s*(\(current\))?").expect("invalid pattern")
+});
+
+/// `removing profile version 3`
+static REMOVED_GENERATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^removing profile version (\d+)").expect("invalid pattern")
+});
+
+/// `/home/me/.local/state/nix/profiles/profile-5-link -> /nix/store/...-profile`
+static GC_ROOT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(.+) -> (/nix/store/\S+)$").expect("invalid pattern")
+});
+
+/// Generation link of the default profile (`profile-5-link`)
+static GENERATION_LINK: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|/)profile-(\d+)-link$").expect("invalid pattern")
+});
+
+/// `1234 store paths deleted, 567.89 MiB freed`
+static GC_SUMMARY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(\d+) store paths? deleted, ([\d.]+) (bytes|KiB|MiB|GiB|TiB) freed").expect("invalid pattern")
+});
+
+/// Parse a generation age such as `30d` into days
+///
+/// Days are the only unit `nix-collect-garbage --delete-older-than` takes.
+pub fn parse_age(age: &str) -> Option<u32> {
+    age.strip_suffix('d')?.parse().ok()
+}
+
+/// Parse `nix-env --list-generations` output
+pub fn parse_generations(output: &str) -> Vec<Generation> {
+    output.lines()
+        .filter_map(|line| GENERATION_LINE.captures(line))
+        .filter_map(|caps| Some(Generation {
+            number: caps[1].parse().ok()?,
+            created: caps[2].to_string(),
+            current: caps.get(3).is_some(),
+        }))
+        .collect()
+}
+
+/// Parse `nix-collect-garbage` output (stdout and stderr combined)
+pub fn parse_gc_output(output: &str) -> GcReport {
+    let generations_removed = REMOVED_GENERATION.captures_iter(output)
+        .filter_map(|caps| caps[1].parse().ok())
+        .collect();
+
+    let (paths_deleted, bytes_freed) = GC_SUMMARY.captures(output)
+        .map(|caps| {
+            let unit = match &caps[3] {
+                "KiB" => 1u64 << 10,
+                "MiB" => 1 << 20,
+                "GiB" => 1 << 30,
+                "TiB" => 1 << 40,
+                _ => 1,
+            };
+            let amount: f64 = caps[2].parse().unwrap_or(0.0);
+            (caps[1].parse().unwrap_or(0), (amount * unit as f64).round() as u64)
+        })
+        .unwrap_or_default();
+
+    GcReport { generations_removed, paths_deleted, bytes_freed }
+}
+
+/// Parse `nix-store --gc --print-roots` output into (root, store path) pairs
+pub fn parse_gc_roots(output: &str) -> Vec<(String, String)> {
+    output.lines()
+        .filter_map(|line| GC_ROOT.captures(line.trim()))
+        .map(|caps| (caps[1].to_string(), caps[2].to_string()))
+        .collect()
+}
+
+/// Generation number of a default profile generation link
+fn generation_of(root: &str) -> Option<u32> {
+    GENERATION_LINK.captures(root)?[1].parse().ok()
+}
+
+/// Generations `--delete-older-than` would delete
+///
+/// Mirrors Nix: the current generation is always kept.
+pub fn expired_generations(generations: &[Generation], older_than_days: u32, now: NaiveDateTime) -> Vec<u32> {
+    generations.iter()
+        .filter(|g| !g.current)
+        .filter(|g| g.age_days(now).is_some_and(|age| age >= i64::from(older_than_days)))
+        .map(|g| g.number)
+        .collect()
+}
+
+/// Disk usage and garbage collection on top of any `NixBackend`
+pub trait StoreMaintenance: NixBackend {
+    /// Profile generations, oldest first
+    fn generations(&self) -> Result<Vec<Generation>, NixError> {
+        let output = self.run_tool("nix-env", &["--list-generations"])?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+        Ok(parse_generations(&output.stdout))
+    }
+
+    /// Store paths no garbage collector root keeps alive
+    fn dead_paths(&self) -> Result<Vec<String>, NixError> {
+        let output = self.run_tool("nix-store", &["--gc", "--print-dead"])?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+        Ok(output.stdout.lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("/nix/store/"))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Garbage collector roots and the store paths they keep alive
+    fn gc_roots(&self) -> Result<Vec<(String, String)>, NixError> {
+        let output = self.run_tool("nix-store", &["--gc", "--print-roots"])?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+        Ok(parse_gc_roots(&output.stdout))
+    }
+
+    /// Live paths only the given generations keep alive, with their sizes
+    fn released_by(&self, generations: &[u32]) -> Result<Vec<(String, u64)>, NixError> {
+        if generations.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut expired = Vec::new();
+        let mut kept = Vec::new();
+        for (root, path) in self.gc_roots()? {
+            if generation_of(&root).is_some_and(|number| generations.contains(&number)) {
+                expired.push(path);
+            } else {
+                kept.push(path);
+            }
+        }
+        for paths in [&mut expired, &mut kept] {
+            paths.sort();
+            paths.dedup();
+        }
+
+        let still_used: HashSet<String> = self.closure(&kept)?.into_iter().map(|info| info.path).collect();
+        Ok(self.closure(&expired)?
+            .into_iter()
+            .filter(|info| !still_used.contains(&info.path))
+            .map(|info| (info.path, info.nar_size))
+            .collect())
+    }
+
+    /// Sizes of all valid store paths
+    fn store_paths(&self) -> Result<HashMap<String, u64>, NixError> {
+        let output = self.run_nix(&["path-info", "--json", "--all"])?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+        Ok(parse_path_info(&output.stdout)?
+            .into_iter()
+            .map(|info| (info.path, info.nar_size))
+            .collect())
+    }
+
+    /// Disk usage of the store, with the `top` largest installed closures
+    fn store_usage(&self, top: usize) -> Result<StoreUsage, NixError> {
+        self.ensure_available()?;
+
+        let sizes = self.store_paths()?;
+        let dead = self.dead_paths()?;
+        let reclaimable_size = dead.iter().filter_map(|path| sizes.get(path)).sum();
+
+        // One `nix path-info` call for the outputs of all installed packages
+        let packages: Vec<_> = self.list()?
+            .into_iter()
+            .filter(|package| !package.store_paths.is_empty())
+            .collect();
+        let mut paths: Vec<&str> = packages.iter()
+            .flat_map(|package| package.store_paths.iter().map(String::as_str))
+            .collect();
+        paths.sort_unstable();
+        paths.dedup();
+
+        let mut closure_sizes = HashMap::new();
+        if !paths.is_empty() {
+            let args: Vec<&str> = ["path-info", "--json", "--closure-size"].into_iter()
+                .chain(paths)
+                .collect();
+            let output = self.run_nix(&args)?;
+            if !output.is_success() {
+                return Err(NixError::from_stderr(output.stderr));
+            }
+            closure_sizes = parse_path_info(&output.stdout)?
+                .into_iter()
+                .filter_map(|info| Some((info.path, info.closure_size?)))
+                .collect();
+        }
+
+        let mut largest_closures: Vec<ClosureUsage> = packages.into_iter()
+            .map(|package| ClosureUsage {
+                // Outputs share most of their closure; the largest one is a good approximation
+                closure_size: package.store_paths.iter()
+                    .filter_map(|path| closure_sizes.get(path).copied())
+                    .max()
+                    .unwrap_or(0),
+                package: package.name,
+            })
+            .collect();
+        largest_closures.sort_by_key(|c| std::cmp::Reverse(c.closure_size));
+        largest_closures.truncate(top);
+
+        Ok(StoreUsage {
+            store_size: sizes.values().sum(),
+            path_count: sizes.len(),
+            reclaimable_size,
+            dead_path_count: dead.len(),
+            generations: self.generations()?,
+            largest_closures,
+        })
+    }
+
+    /// Preview a garbage collection without deleting anything
+    fn gc_preview(&self, older_than_days: Option<u32>, now: NaiveDateTime) -> Result<GcPreview, NixError> {
+        self.ensure_available()?;
+
+        let generations = match older_than_days {
+            Some(days) => expired_generations(&self.generations()?, days, now),
+            None => Vec::new(),
+        };
+
+        let sizes = self.store_paths()?;
+        let dead = self.dead_paths()?;
+        let released = self.released_by(&generations)?;
+
+        let dead_size: u64 = dead.iter().filter_map(|path| sizes.get(path)).sum();
+        let released_size: u64 = released.iter().map(|(_, size)| size).sum();
+
+        Ok(GcPreview {
+            generations,
+            dead_path_count: dead.len(),
+            released_path_count: released.len(),
+            reclaimable_size: dead_size + released_size,
+        })
+    }
+
+    /// Delete generations older than the given age, then collect garbage
+    fn collect_garbage(&self, older_than_days: Option<u32>) -> Result<GcReport, NixError> {
+        self.ensure_available()?;
+
+        let age = older_than_days.map(|days| format!("{}d", days));
+        let mut args = Vec::new();
+        if let Some(age) = &age {
+            args.extend(["--delete-older-than", age.as_str()]);
+        }
+
+        let output = self.run_tool("nix-collect-garbage", &args)?;
+        if !output.is_success() {
+            return Err(NixError::from_stderr(output.stderr));
+        }
+
+        Ok(parse_gc_output(&format!("{}\n{}", output.stdout, output.stderr)))
+    }
+}
+
+impl<B: NixBackend + ?Sized> StoreMaintenance for B {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_parse_generations() {
+        let output = "   1   2024-01-05 10:12:33   \n   2   2024-02-20 09:00:00   \n   3   2024-03-01 08:00:00   (current)\n";
+        let generations = parse_generations(output);
+        assert_eq!(generations.len(), 3);
+        assert!(generations[2].current);
+        assert_eq!(generations[0].age_days(at("2024-01-15 10:12:33")), Some(10));
+
+        let now = at("2024-03-10 00:00:00");
+        assert_eq!(expired_generations(&generations, 30, now), vec![1]);
+        assert_eq!(expired_generations(&generations, 0, now), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_parse_gc_output() {
+        let output = "removing old generations of profile /home/me/.local/state/nix/profiles/profile\n\
+                      removing profile version 1\n\
+                      removing profile version 2\n\
+                      finding garbage collector roots...\n\
+                      deleting garbage...\n\
+                      deleting '/nix/store/abc-hello-2.12.1'\n\
+                      deleting unused links...\n\
+                      note: currently hard linking saves 12.34 MiB\n\
+                      1234 store paths deleted, 512.00 MiB freed\n";
+        let report = parse_gc_output(output);
+        assert_eq!(report.generations_removed, vec![1, 2]);
+        assert_eq!(report.paths_deleted, 1234);
+        assert_eq!(report.bytes_freed, 512 * 1024 * 1024);
+
+        assert_eq!(parse_gc_output("0 store paths deleted, 0.00 MiB freed"), GcReport::default());
+    }
+
+    #[test]
+    fn test_store_usage() {
+        use crate::nix_ops::backend::tests::ScriptedBackend;
+        use crate::wsl2::CommandOutput;
+
+        let ok = |stdout: &str| CommandOutput::new(stdout.to_string(), String::new(), 0);
+        let backend = ScriptedBackend::new(&[
+            ("path-info --json --all", ok(r#"{"/nix/store/a-jq":{"narSize":100},"/nix/store/b-glibc":{"narSize":1000},"/nix/store/c-old":{"narSize":50}}"#)),
+            ("nix-store --gc --print-dead", ok("/nix/store/c-old\n")),
+            ("profile list --json", ok(r#"{"version":3,"elements":{"jq":{"storePaths":["/nix/store/a-jq","/nix/store/a-jq-man"]},"glibc":{"storePaths":["/nix/store/b-glibc"]}}}"#)),
+            // A single call covers every installed output
+            ("path-info --json --closure-size /nix/store/a-jq /nix/store/a-jq-man /nix/store/b-glibc", ok(
+                r#"{"/nix/store/a-jq":{"narSize":100,"closureSize":1100},"/nix/store/a-jq-man":{"narSize":10,"closureSize":10},"/nix/store/b-glibc":{"narSize":1000,"closureSize":1000}}"#
+            )),
+            ("nix-env --list-generations", ok("   1   2024-01-05 10:12:33   (current)\n")),
+        ]);
+
+        let usage = backend.store_usage(5).unwrap();
+        assert_eq!(usage.store_size, 1150);
+        assert_eq!(usage.path_count, 3);
+        assert_eq!(usage.reclaimable_size, 50);
+        assert_eq!(usage.largest_closures, vec![
+            ClosureUsage { package: "jq".to_string(), closure_size: 1100 },
+            ClosureUsage { package: "glibc".to_string(), closure_size: 1000 },
+        ]);
+        assert_eq!(usage.generations.len(), 1);
+    }
+
+    #[test]
+    fn test_gc_preview_counts_expired_generations() {
+        use crate::nix_ops::backend::tests::ScriptedBackend;
+        use crate::wsl2::CommandOutput;
+
+        let ok = |stdout: &str| CommandOutput::new(stdout.to_string(), String::new(), 0);
+        let backend = ScriptedBackend::new(&[
+            ("nix-env --list-generations", ok("   1   2024-01-05 10:12:33   \n   2   2024-03-01 08:00:00   (current)\n")),
+            ("path-info --json --all", ok(
+                r#"{"/nix/store/g1-profile":{"narSize":1},"/nix/store/g2-profile":{"narSize":1},"/nix/store/a-old-jq":{"narSize":100},"/nix/store/b-glibc":{"narSize":1000},"/nix/store/c-dead":{"narSize":50}}"#
+            )),
+            ("nix-store --gc --print-dead", ok("/nix/store/c-dead\n")),
+            ("nix-store --gc --print-roots", ok(
+                "/home/me/.local/state/nix/profiles/profile-1-link -> /nix/store/g1-profile\n\
+                 /home/me/.local/state/nix/profiles/profile-2-link -> /nix/store/g2-profile\n\
+                 /proc/42/maps -> /nix/store/b-glibc\n\
+                 {censored} -> /nix/store/b-glibc\n"
+            )),
+            ("path-info --json --recursive /nix/store/b-glibc /nix/store/g2-profile", ok(
+                r#"{"/nix/store/g2-profile":{"narSize":1},"/nix/store/b-glibc":{"narSize":1000}}"#
+            )),
+            ("path-info --json --recursive /nix/store/g1-profile", ok(
+                r#"{"/nix/store/g1-profile":{"narSize":1},"/nix/store/a-old-jq":{"narSize":100},"/nix/store/b-glibc":{"narSize":1000}}"#
+            )),
+        ]);
+
+        let preview = backend.gc_preview(Some(30), at("2024-03-10 00:00:00")).unwrap();
+        assert_eq!(preview.generations, vec![1]);
+        assert_eq!(preview.dead_path_count, 1);
+        // Generation 1 alone keeps its profile and the old jq alive; glibc stays
+        assert_eq!(preview.released_path_count, 2);
+        assert_eq!(preview.reclaimable_size, 50 + 1 + 100);
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d"), Some(30));
+        assert_eq!(parse_age("30"), None);
+        assert_eq!(parse_age("2w"), None);
+    }
+}
@@ -29,12 +29,67 @@ pub struct SearchResult {
 /// Installed package information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstalledPackage {
-    /// Package name
+    /// Package name (profile element name)
     pub name: String,
 
     /// Package version
     pub version: String,
 
-    /// Store path
+    /// Store path (the first one if the package has several outputs)
     pub store_path: String,
-}
\ No newline at end of file
+
+    /// nixpkgs commit the package was pinned to (e.g. by `nsfw install pkg@1.2`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_revision: Option<String>,
+
+    /// Attribute path inside the flake (e.g. "legacyPackages.x86_64-linux.hello")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attr_path: Option<String>,
+
+    /// Flake reference as given at install time (e.g. "flake:nixpkgs")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
+
+    /// Locked flake reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Requested outputs (empty = the package's default outputs)
+    #[serde(default)]
+    pub outputs: Vec<String>,
+
+    /// Store paths of all installed outputs
+    #[serde(default)]
+    pub store_paths: Vec<String>,
+
+    /// Priority for resolving file conflicts between packages (lower wins)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+
+    /// Whether the package is linked into the profile
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl Default for InstalledPackage {
+    /// Matches a minimal deserialized record, which is active
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            version: String::new(),
+            store_path: String::new(),
+            pinned_revision: None,
+            attr_path: None,
+            original_url: None,
+            url: None,
+            outputs: Vec::new(),
+            store_paths: Vec::new(),
+            priority: None,
+            active: default_active(),
+        }
+    }
+}
@@ -2,23 +2,66 @@
 ///
 /// This module handles the async building of the package cache
 /// without blocking the main thread or user operations.
-use super::{PackageCache, CachedPackage};
+use super::{PackageCache, CachedPackage, CacheDelta, CacheProvenance, NixpkgsRevision};
+use super::delta::DeltaTracker;
+use super::worker::BuildState;
+use super::stream;
+use super::categories::categorize;
+use crate::nix_ops::NixError;
 use crate::wsl2::WSL2Bridge;
 use anyhow::{Result, Context};
 use log::{info, debug};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::io::Read;
+
+/// Number of changed packages written per transaction
+const UPSERT_BATCH_SIZE: usize = 1000;
+
+/// Rough size of nixpkgs, used for progress until a previous build is known
+const EXPECTED_PACKAGE_COUNT: usize = 120_000;
+
+/// Number of packages restored by `build_popular_packages`
+const POPULAR_PACKAGE_COUNT: usize = 100;
+
+/// Largest share of the cached packages a single update may delete
+///
+/// nix-env output that ends early but still exits successfully would
+/// otherwise look like most of nixpkgs was removed.
+const MAX_REMOVED_FRACTION: f64 = 0.5;
+
+/// An update refused because it would delete most of the cache
+#[derive(Debug, thiserror::Error)]
+#[error("nix-env listed {listed} packages but {missing} of the {cached} cached ones are missing; \
+         keeping them in case the output was truncated")]
+pub struct TruncatedPackageSet {
+    pub listed: usize,
+    pub missing: usize,
+    pub cached: usize,
+}
 
 /// Background cache builder
 pub struct CacheBuilder<B: WSL2Bridge> {
     cache: PackageCache,
     bridge: B,
+
+    /// Apply deletions even past `MAX_REMOVED_FRACTION`
+    force: bool,
 }
 
 impl<B: WSL2Bridge> CacheBuilder<B> {
     /// Create a new cache builder
     pub fn new(cache: PackageCache, bridge: B) -> Self {
-        Self { cache, bridge }
+        Self { cache, bridge, force: false }
+    }
+
+    /// Delete packages missing from nix-env output however many there are
+    ///
+    /// For when nixpkgs really shrank, e.g. after switching to a smaller
+    /// channel; see `update_incremental`.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
     }
 
     /// Build the cache from nix-env -qa
@@ -27,62 +70,109 @@ impl<B: WSL2Bridge> CacheBuilder<B> {
     /// 1. Uses older nix-env command (no flakes needed)
     /// 2. Doesn't require eval-cache download
     /// 3. Can be run in background
+    ///
+    /// Returns the number of cached packages. See `update_incremental`
+    /// for the changes applied.
     pub fn build_from_nix_env(&self) -> Result<usize> {
-        info!("Starting background cache build from nix-env");
+        self.update_incremental()?;
 
-        // Use nix-env -qaP --json for fast package list
-        let output = self.bridge.execute(
-            "nix-env",
-            &["-qaP", "--json"]
-        ).context("Failed to execute nix-env")?;
+        let stats = self.cache.stats()?;
+        Ok(stats.total_packages as usize)
+    }
 
-        if !output.is_success() {
-            return Err(anyhow::anyhow!("nix-env failed: {}", output.stderr));
+    /// Update the cache from nix-env -qa, writing only what changed
+    ///
+    /// New packages are inserted, changed ones updated, and packages that
+    /// no longer exist in nixpkgs are deleted. Returns the delta so callers
+    /// can display a changelog.
+    ///
+    /// If more than half of the cached packages would be deleted, the
+    /// update fails with `TruncatedPackageSet` instead and the existing
+    /// packages are kept, unless `force` is set.
+    ///
+    /// The build state and progress are recorded in the cache so other
+    /// processes can report them.
+    pub fn update_incremental(&self) -> Result<CacheDelta> {
+        self.cache.set_build_state(&BuildState::Building {
+            percent: 0,
+            started_at: chrono::Utc::now().timestamp(),
+        })?;
+
+        match self.run_update() {
+            Ok(delta) => {
+                self.cache.set_build_state(&BuildState::Idle)?;
+                Ok(delta)
+            }
+            Err(e) => {
+                self.cache.set_build_state(&BuildState::Failed { error: format!("{:#}", e) })?;
+                Err(e)
+            }
         }
+    }
 
-        // Parse JSON output
-        let packages_json: HashMap<String, Value> = serde_json::from_str(&output.stdout)
-            .context("Failed to parse nix-env JSON output")?;
+    /// Load nix-env output and apply the delta (see `update_incremental`)
+    fn run_update(&self) -> Result<CacheDelta> {
+        info!("Starting cache update from nix-env");
+
+        // Detect the revision up front so provenance matches what nix-env evaluates
+        let revision = NixpkgsRevision::detect(&self.bridge);
+        if let Err(e) = &revision {
+            debug!("Building cache without provenance: {}", e);
+        }
 
-        // Convert to CachedPackage objects
-        let mut packages = Vec::new();
+        let fingerprints = self.cache.fingerprints()?;
+        let previous_total = fingerprints.len();
+        let expected_total = if fingerprints.is_empty() {
+            EXPECTED_PACKAGE_COUNT
+        } else {
+            fingerprints.len()
+        };
+
+        let mut tracker = DeltaTracker::new(fingerprints);
+        let mut packages = Vec::with_capacity(UPSERT_BATCH_SIZE);
+        let mut processed = 0usize;
         let now = chrono::Utc::now().timestamp();
 
-        for (attr_path, package_info) in packages_json.iter() {
-            // Extract package information
-            let name = package_info.get("pname")
-                .or_else(|| package_info.get("name"))
-                .and_then(|v| v.as_str())
-                .unwrap_or(attr_path)
-                .to_string();
-
-            let version = package_info.get("version")
-                .and_then(|v| v.as_str())
-                .unwrap_or("unknown")
-                .to_string();
-
-            let description = package_info.get("meta")
-                .and_then(|m| m.get("description"))
-                .and_then(|d| d.as_str())
-                .unwrap_or("")
-                .to_string();
-
-            packages.push(CachedPackage {
-                name,
-                version,
-                description,
-                attr_path: attr_path.clone(),
-                last_updated: now,
-                search_count: 0,
-            });
+        // Stream nix-env output and upsert changed packages in batches as they
+        // are parsed, so the full package set is never held in memory
+        let mut consume = |stdout: &mut dyn Read| -> Result<()> {
+            stream::for_each_package(stdout, |attr_path, package_info| {
+                processed += 1;
+                if processed.is_multiple_of(UPSERT_BATCH_SIZE) {
+                    // The total is only an estimate, so never claim completion early
+                    let percent = (processed * 100 / expected_total).min(99) as u8;
+                    self.cache.set_build_progress(percent)?;
+                }
 
-            // Batch upsert every 1000 packages
-            if packages.len() >= 1000 {
-                debug!("Upserting batch of 1000 packages");
-                self.cache.upsert_packages(&packages)
-                    .context("Failed to upsert package batch")?;
-                packages.clear();
-            }
+                let package = package_from_json(&attr_path, &package_info, &attr_path, now);
+                if !tracker.observe(&package) {
+                    return Ok(());
+                }
+                packages.push(package);
+
+                if packages.len() >= UPSERT_BATCH_SIZE {
+                    debug!("Upserting batch of {} packages", packages.len());
+                    self.cache.upsert_packages(&packages)
+                        .context("Failed to upsert package batch")?;
+                    packages.clear();
+                }
+                Ok(())
+            })?;
+            Ok(())
+        };
+
+        // Use nix-env -qaP --json for fast package list (--meta for license/platform data)
+        let output = self.bridge.execute_streamed(
+            "nix-env",
+            &["-qaP", "--json", "--meta"],
+            &mut consume,
+        ).context("Failed to execute nix-env")?;
+
+        if !output.is_success() {
+            return Err(match NixError::from_stderr(output.stderr) {
+                NixError::CommandFailed(stderr) => anyhow::anyhow!("nix-env failed: {}", stderr),
+                network => network.into(),
+            });
         }
 
         // Upsert remaining packages
@@ -91,61 +181,77 @@ impl<B: WSL2Bridge> CacheBuilder<B> {
                 .context("Failed to upsert final package batch")?;
         }
 
+        // Anything not seen in the new set was removed from nixpkgs
+        let (delta, removed) = tracker.finish();
+        if !self.force && removed.len() as f64 > previous_total as f64 * MAX_REMOVED_FRACTION {
+            return Err(TruncatedPackageSet {
+                listed: processed,
+                missing: removed.len(),
+                cached: previous_total,
+            }.into());
+        }
+        self.cache.delete_packages(&removed)
+            .context("Failed to delete removed packages")?;
+
+        self.finish_update(revision, &delta)?;
+        Ok(delta)
+    }
+
+    /// Record provenance and the delta summary after a successful update
+    fn finish_update(&self, revision: Result<NixpkgsRevision>, delta: &CacheDelta) -> Result<()> {
         let stats = self.cache.stats()?;
-        info!("Cache build complete: {} packages cached", stats.total_packages);
+        info!(
+            "Cache update complete: {} packages cached ({})",
+            stats.total_packages,
+            delta.summary()
+        );
+
+        if let Ok(revision) = revision {
+            // Remember which versions this commit has, for `nsfw install pkg@version`
+            if let Some(commit) = revision.commit() {
+                let indexed = self.cache.index_versions(commit)?;
+                debug!("Indexed {} new package versions at {}", indexed, commit);
+            }
 
-        Ok(stats.total_packages as usize)
+            self.cache.set_provenance(&CacheProvenance {
+                revision,
+                built_at: chrono::Utc::now().timestamp(),
+                package_count: stats.total_packages as usize,
+            })?;
+        }
+
+        // Only the counts are kept; the full changelog is returned to the caller
+        let summary = CacheDelta {
+            changes: Vec::new(),
+            ..delta.clone()
+        };
+        self.cache.set_metadata("last_delta", &serde_json::to_string(&summary)?)?;
+
+        Ok(())
     }
 
-    /// Quick build - just cache popular packages
+    /// Quick build - just cache the locally most popular packages
     ///
-    /// This provides instant results for common searches
-    /// while the full cache builds in background
+    /// Popularity comes from usage signals recorded on this machine (see
+    /// `UsageSignal`), so this restores the packages the user actually
+    /// works with, e.g. after the cache was cleared. Does nothing before
+    /// any usage has been recorded.
     pub fn build_popular_packages(&self) -> Result<usize> {
         info!("Quick-building cache with popular packages");
 
-        let popular_packages = vec![
-            // Programming languages
-            "python3", "python310", "python311", "python312",
-            "nodejs", "nodejs_20", "nodejs_22",
-            "go", "rustc", "cargo",
-            "ruby", "ruby_3_2", "ruby_3_3",
-            "php", "php83",
-            "java", "openjdk", "jdk17", "jdk21",
-
-            // Development tools
-            "git", "gh", "vim", "neovim", "emacs",
-            "vscode", "code", "jetbrains",
-            "docker", "docker-compose", "kubectl",
-            "terraform", "ansible",
-
-            // Databases
-            "postgresql", "mysql", "redis", "mongodb",
-            "sqlite",
-
-            // Build tools
-            "cmake", "make", "ninja", "meson",
-            "gcc", "clang", "llvm",
-
-            // Common utilities
-            "curl", "wget", "jq", "ripgrep", "fd",
-            "bat", "exa", "htop", "tmux", "zsh",
-        ];
-
         let mut count = 0;
         let now = chrono::Utc::now().timestamp();
 
-        for package_name in popular_packages {
+        for attr_path in self.cache.popular_attr_paths(POPULAR_PACKAGE_COUNT)? {
             // Try to get package info from nix-env
-            match self.get_package_info(package_name) {
+            match self.get_package_info(&attr_path) {
                 Ok(Some(mut pkg)) => {
                     pkg.last_updated = now;
-                    pkg.search_count = 100; // Give popular packages high initial count
                     self.cache.upsert_packages(&[pkg])?;
                     count += 1;
                 }
-                Ok(None) => debug!("Popular package '{}' not found", package_name),
-                Err(e) => debug!("Failed to get info for '{}': {}", package_name, e),
+                Ok(None) => debug!("Popular package '{}' not found", attr_path),
+                Err(e) => debug!("Failed to get info for '{}': {}", attr_path, e),
             }
         }
 
@@ -153,11 +259,11 @@ impl<B: WSL2Bridge> CacheBuilder<B> {
         Ok(count)
     }
 
-    /// Get package info for a single package
-    fn get_package_info(&self, package_name: &str) -> Result<Option<CachedPackage>> {
+    /// Get package info for a single package by attribute path
+    fn get_package_info(&self, attr_path: &str) -> Result<Option<CachedPackage>> {
         let output = self.bridge.execute(
             "nix-env",
-            &["-qaP", "--json", package_name]
+            &["-qaP", "--json", "--meta", "-A", attr_path]
         )?;
 
         if !output.is_success() {
@@ -168,38 +274,24 @@ impl<B: WSL2Bridge> CacheBuilder<B> {
             .context("Failed to parse package info")?;
 
         // Get the first package (usually the one we want)
+        let fallback_name = attr_path.rsplit('.').next().unwrap_or(attr_path);
         if let Some((attr_path, package_info)) = packages_json.iter().next() {
-            let name = package_info.get("pname")
-                .or_else(|| package_info.get("name"))
-                .and_then(|v| v.as_str())
-                .unwrap_or(package_name)
-                .to_string();
-
-            let version = package_info.get("version")
-                .and_then(|v| v.as_str())
-                .unwrap_or("unknown")
-                .to_string();
-
-            let description = package_info.get("meta")
-                .and_then(|m| m.get("description"))
-                .and_then(|d| d.as_str())
-                .unwrap_or("")
-                .to_string();
-
-            Ok(Some(CachedPackage {
-                name,
-                version,
-                description,
-                attr_path: attr_path.clone(),
-                last_updated: chrono::Utc::now().timestamp(),
-                search_count: 0,
-            }))
+            Ok(Some(package_from_json(
+                attr_path,
+                package_info,
+                fallback_name,
+                chrono::Utc::now().timestamp(),
+            )))
         } else {
             Ok(None)
         }
     }
 
-    /// Check if cache needs update (>24 hours old)
+    /// Check if cache needs update
+    ///
+    /// The cache is stale when the nixpkgs revision differs from the one it
+    /// was built from. If the revision can't be determined (e.g. WSL2 is
+    /// stopped), falls back to a 24-hour age check.
     pub fn needs_update(&self) -> Result<bool> {
         let stats = self.cache.stats()?;
 
@@ -207,19 +299,123 @@ impl<B: WSL2Bridge> CacheBuilder<B> {
             return Ok(true); // Empty cache needs building
         }
 
-        if let Some(last_updated) = stats.last_updated {
-            let age_hours = (chrono::Utc::now().timestamp() - last_updated) / 3600;
-            Ok(age_hours > 24)
-        } else {
-            Ok(true) // No timestamp means needs update
+        let current = match NixpkgsRevision::detect(&self.bridge) {
+            Ok(revision) => revision,
+            Err(e) => {
+                debug!("Falling back to age-based staleness: {}", e);
+                let built_at = self.cache.provenance()?
+                    .map(|p| p.built_at)
+                    .or(stats.last_updated);
+                return Ok(Self::is_older_than_a_day(built_at));
+            }
+        };
+
+        match self.cache.provenance()? {
+            Some(provenance) => {
+                let stale = provenance.revision != current;
+                if stale {
+                    info!("nixpkgs changed: {} -> {}", provenance.revision, current);
+                }
+                Ok(stale)
+            }
+            None => Ok(true), // Built before provenance was tracked
+        }
+    }
+
+    /// Age-based staleness check (>24 hours old)
+    fn is_older_than_a_day(built_at: Option<i64>) -> bool {
+        match built_at {
+            Some(built_at) => {
+                let age_hours = (chrono::Utc::now().timestamp() - built_at) / 3600;
+                age_hours > 24
+            }
+            None => true, // No timestamp means needs update
         }
     }
 }
 
+/// Convert a `nix-env --json --meta` entry into a `CachedPackage`
+fn package_from_json(attr_path: &str, package_info: &Value, fallback_name: &str, now: i64) -> CachedPackage {
+    let name = package_info.get("pname")
+        .or_else(|| package_info.get("name"))
+        .and_then(|v| v.as_str())
+        .unwrap_or(fallback_name)
+        .to_string();
+
+    let version = package_info.get("version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+
+    let meta = package_info.get("meta");
+
+    let description = meta
+        .and_then(|m| m.get("description"))
+        .and_then(|d| d.as_str())
+        .unwrap_or("")
+        .to_string();
+
+    // meta.license is a license attrset, a list of them, or a plain string
+    let licenses: Vec<&Value> = match meta.and_then(|m| m.get("license")) {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+
+    let license = licenses.iter()
+        .filter_map(|l| match l {
+            Value::String(s) => Some(s.as_str()),
+            _ => l.get("spdxId")
+                .or_else(|| l.get("shortName"))
+                .and_then(|v| v.as_str()),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // Prefer meta.unfree (newer nixpkgs), otherwise derive it from license.free
+    let unfree = meta
+        .and_then(|m| m.get("unfree"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or_else(|| {
+            licenses.iter().any(|l| l.get("free").and_then(|v| v.as_bool()) == Some(false))
+        });
+
+    let broken = meta
+        .and_then(|m| m.get("broken"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let platforms = meta
+        .and_then(|m| m.get("platforms"))
+        .and_then(|p| p.as_array())
+        .map(|items| {
+            items.iter()
+                .filter_map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+
+    CachedPackage {
+        name,
+        version,
+        description,
+        attr_path: attr_path.to_string(),
+        last_updated: now,
+        search_count: 0,
+        license,
+        platforms,
+        unfree,
+        broken,
+        category: categorize(attr_path, meta),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::wsl2::MockWSL2Bridge;
+    use crate::package_cache::temp_cache;
+    use crate::wsl2::{MockWSL2Bridge, CommandOutput};
 
     #[test]
     fn test_cache_builder_creation() {
@@ -230,6 +426,228 @@ mod tests {
         let _builder = CacheBuilder::new(cache, bridge);
 
         // Just verify construction works
-        assert!(true);
+    }
+
+    fn bridge_with_revision(revision: &str, packages_json: &str) -> MockWSL2Bridge {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix-instantiate --eval --json -E (import <nixpkgs> {}).lib.version".to_string(),
+            CommandOutput::new(format!("\"{}\"", revision), "".to_string(), 0),
+        );
+        bridge.set_response(
+            "nix-env -qaP --json --meta".to_string(),
+            CommandOutput::new(packages_json.to_string(), "".to_string(), 0),
+        );
+        bridge
+    }
+
+    #[test]
+    fn test_needs_update_follows_revision() {
+        let packages = r#"{"nixpkgs.hello":{"pname":"hello","version":"2.12","meta":{}}}"#;
+
+        let (dir, cache) = temp_cache();
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.1", packages));
+        assert!(builder.needs_update().unwrap());
+        assert_eq!(builder.build_from_nix_env().unwrap(), 1);
+        assert!(!builder.needs_update().unwrap());
+
+        let provenance = builder.cache.provenance().unwrap().unwrap();
+        assert_eq!(provenance.revision.revision, "24.05.1");
+        assert_eq!(builder.cache.build_state().unwrap(), BuildState::Idle);
+        assert_eq!(provenance.package_count, 1);
+
+        // Same database, newer channel
+        let cache = PackageCache::with_path(dir.path().join("packages.db")).unwrap();
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.2", packages));
+        assert!(builder.needs_update().unwrap());
+    }
+
+    #[test]
+    fn test_update_incremental_applies_delta() {
+        let (dir, cache) = temp_cache();
+        let db_path = dir.path().join("packages.db");
+
+        let first = r#"{
+            "nixpkgs.hello": {"pname":"hello","version":"2.12","meta":{}},
+            "nixpkgs.jq": {"pname":"jq","version":"1.7","meta":{}},
+            "nixpkgs.oldtool": {"pname":"oldtool","version":"0.1","meta":{}}
+        }"#;
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.1", first));
+        let delta = builder.update_incremental().unwrap();
+        assert_eq!(delta.added, 3);
+        assert!(delta.changes.is_empty());
+
+        let second = r#"{
+            "nixpkgs.hello": {"pname":"hello","version":"2.12","meta":{}},
+            "nixpkgs.jq": {"pname":"jq","version":"1.7.1","meta":{}},
+            "nixpkgs.ripgrep": {"pname":"ripgrep","version":"14.1.0","meta":{}}
+        }"#;
+        let cache = PackageCache::with_path(db_path.clone()).unwrap();
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.2", second));
+        let delta = builder.update_incremental().unwrap();
+        assert_eq!((delta.added, delta.updated, delta.removed, delta.unchanged), (1, 1, 1, 1));
+        assert_eq!(delta.changes.len(), 3);
+
+        let cache = PackageCache::with_path(db_path).unwrap();
+        assert_eq!(cache.stats().unwrap().total_packages, 3);
+        assert!(cache.search("oldtool", 10).unwrap().is_empty());
+        assert_eq!(cache.search("jq", 10).unwrap()[0].version, "1.7.1");
+        assert_eq!(cache.last_delta().unwrap().unwrap().removed, 1);
+    }
+
+    #[test]
+    fn test_update_refuses_to_delete_most_packages() {
+        let (dir, cache) = temp_cache();
+        let db_path = dir.path().join("packages.db");
+
+        let full = r#"{
+            "nixpkgs.hello": {"pname":"hello","version":"2.12","meta":{}},
+            "nixpkgs.jq": {"pname":"jq","version":"1.7","meta":{}},
+            "nixpkgs.ripgrep": {"pname":"ripgrep","version":"14.1.0","meta":{}}
+        }"#;
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.1", full));
+        builder.update_incremental().unwrap();
+
+        // Output cut short after the first package, with a zero exit code
+        let truncated = r#"{"nixpkgs.hello": {"pname":"hello","version":"2.12","meta":{}}}"#;
+        let cache = PackageCache::with_path(db_path.clone()).unwrap();
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.2", truncated));
+        let err = builder.update_incremental().unwrap_err();
+        assert!(err.to_string().contains("2 of the 3 cached ones are missing"), "{}", err);
+        assert!(err.downcast_ref::<TruncatedPackageSet>().is_some());
+
+        let cache = PackageCache::with_path(db_path.clone()).unwrap();
+        assert_eq!(cache.stats().unwrap().total_packages, 3);
+        assert_eq!(cache.provenance().unwrap().unwrap().revision.revision, "24.05.1");
+        assert!(matches!(cache.build_state().unwrap(), BuildState::Failed { .. }));
+
+        // Forced, the smaller package set is taken as it is
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.2", truncated)).force(true);
+        assert_eq!(builder.update_incremental().unwrap().removed, 2);
+
+        let cache = PackageCache::with_path(db_path).unwrap();
+        assert_eq!(cache.stats().unwrap().total_packages, 1);
+    }
+
+    #[test]
+    fn test_update_indexes_versions() {
+        use crate::package_cache::VersionResolver;
+
+        let (dir, cache) = temp_cache();
+        let db_path = dir.path().join("packages.db");
+
+        let first = r#"{"nixpkgs.terraform":{"pname":"terraform","version":"1.5.7","meta":{}}}"#;
+        let builder = CacheBuilder::new(cache, bridge_with_revision("23.11pre500000.aaaaaaaaaaaa", first));
+        builder.update_incremental().unwrap();
+
+        let second = r#"{"nixpkgs.terraform":{"pname":"terraform","version":"1.6.2","meta":{}}}"#;
+        let cache = PackageCache::with_path(db_path.clone()).unwrap();
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05pre600000.bbbbbbbbbbbb", second));
+        builder.update_incremental().unwrap();
+
+        let cache = PackageCache::with_path(db_path).unwrap();
+        assert_eq!(cache.resolve("terraform", "1.5.7").unwrap().unwrap().revision, "aaaaaaaaaaaa");
+        assert_eq!(cache.resolve("terraform", "1.6").unwrap().unwrap().revision, "bbbbbbbbbbbb");
+    }
+
+    #[test]
+    fn test_failed_update_records_build_state() {
+        let (_dir, cache) = temp_cache();
+        let builder = CacheBuilder::new(cache, MockWSL2Bridge::new());
+
+        assert!(builder.update_incremental().is_err());
+        assert!(matches!(builder.cache.build_state().unwrap(), BuildState::Failed { .. }));
+    }
+
+    #[test]
+    fn test_builder_accepts_boxed_bridge() {
+        // The CLI picks the bridge at runtime (see `default_bridge`)
+        let packages = r#"{"nixpkgs.hello":{"pname":"hello","version":"2.12","meta":{}}}"#;
+        let (_dir, cache) = temp_cache();
+        let bridge: Box<dyn WSL2Bridge> = Box::new(bridge_with_revision("24.05.1", packages));
+        let builder = CacheBuilder::new(cache, bridge);
+
+        assert_eq!(builder.build_from_nix_env().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_builder_and_searches_run_concurrently() {
+        let (dir, cache) = temp_cache();
+        let db_path = dir.path().join("packages.db");
+
+        let packages_json = format!(
+            "{{{}}}",
+            (0..5000)
+                .map(|i| format!(
+                    r#""nixpkgs.pkg{i}":{{"pname":"pkg{i}","version":"1.{i}","meta":{{"description":"package {i}"}}}}"#
+                ))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let builder = CacheBuilder::new(cache, bridge_with_revision("24.05.1", &packages_json));
+
+        let build = std::thread::spawn(move || builder.update_incremental());
+
+        // Foreground searches on a separate connection while the builder writes
+        let reader = PackageCache::with_path(db_path).unwrap();
+        for _ in 0..200 {
+            reader.search("pkg1", 20).unwrap();
+            reader.stats().unwrap();
+        }
+
+        let delta = build.join().unwrap().unwrap();
+        assert_eq!(delta.added, 5000);
+        assert_eq!(reader.stats().unwrap().total_packages, 5000);
+        assert!(!reader.search("pkg4999", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_package_from_json_metadata() {
+        let info: Value = serde_json::from_str(r#"{
+            "pname": "vscode",
+            "version": "1.93.0",
+            "meta": {
+                "description": "Code editor",
+                "license": {"shortName": "unfree", "free": false},
+                "platforms": ["x86_64-linux", "aarch64-linux"]
+            }
+        }"#).unwrap();
+
+        let pkg = package_from_json("nixpkgs.vscode", &info, "vscode", 0);
+        assert_eq!(pkg.name, "vscode");
+        assert_eq!(pkg.description, "Code editor");
+        assert_eq!(pkg.license, "unfree");
+        assert_eq!(pkg.platforms, "x86_64-linux, aarch64-linux");
+        assert!(pkg.unfree);
+        assert!(!pkg.broken);
+        assert_eq!(pkg.category, "");
+    }
+
+    #[test]
+    fn test_package_from_json_category() {
+        let info: Value = serde_json::from_str(r#"{
+            "pname": "neovim",
+            "meta": {"position": "/nix/store/x-source/pkgs/applications/editors/neovim/default.nix:1"}
+        }"#).unwrap();
+
+        let pkg = package_from_json("nixpkgs.neovim", &info, "neovim", 0);
+        assert_eq!(pkg.category, "editors");
+    }
+
+    #[test]
+    fn test_package_from_json_license_list() {
+        let info: Value = serde_json::from_str(r#"{
+            "name": "ripgrep-14.1.0",
+            "meta": {
+                "license": [{"spdxId": "MIT", "free": true}, {"spdxId": "Unlicense", "free": true}],
+                "broken": true
+            }
+        }"#).unwrap();
+
+        let pkg = package_from_json("nixpkgs.ripgrep", &info, "ripgrep", 0);
+        assert_eq!(pkg.license, "MIT, Unlicense");
+        assert_eq!(pkg.version, "unknown");
+        assert!(!pkg.unfree);
+        assert!(pkg.broken);
     }
 }
@@ -0,0 +1,228 @@
+/// Package categories for browsing
+///
+/// nixpkgs has no category field, so categories are derived from two
+/// sources: the package set in the attribute path (`python3Packages.requests`
+/// is a "python" package) and the location of the package in the nixpkgs
+/// tree from `meta.position` (`pkgs/applications/editors/...` is an
+/// "editors" package). Package sets take precedence.
+use super::popularity::RANK_SQL;
+use super::{CachedPackage, PackageCache, PACKAGE_COLUMNS};
+use anyhow::{Context, Result};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Friendly names for well-known package sets
+const PACKAGE_SETS: &[(&str, &str)] = &[
+    ("python3Packages", "python"),
+    ("python311Packages", "python"),
+    ("python312Packages", "python"),
+    ("python313Packages", "python"),
+    ("nodePackages", "nodejs"),
+    ("nodePackages_latest", "nodejs"),
+    ("haskellPackages", "haskell"),
+    ("perlPackages", "perl"),
+    ("rubyPackages", "ruby"),
+    ("luaPackages", "lua"),
+    ("lua51Packages", "lua"),
+    ("ocamlPackages", "ocaml"),
+    ("rPackages", "r"),
+    ("rubyPackages_3_3", "ruby"),
+    ("phpPackages", "php"),
+    ("php83Packages", "php"),
+    ("vimPlugins", "vim-plugins"),
+    ("emacsPackages", "emacs-packages"),
+    ("gnomeExtensions", "gnome-extensions"),
+    ("kdePackages", "kde"),
+    ("texlivePackages", "texlive"),
+    ("vscode-extensions", "vscode-extensions"),
+    ("coqPackages", "coq"),
+    ("idrisPackages", "idris"),
+    ("linuxPackages", "linux-kernel"),
+];
+
+/// Top-level nixpkgs directories whose subdirectory names a category
+const CATEGORIZED_TREES: &[&str] = &[
+    "applications",
+    "development",
+    "tools",
+    "servers",
+    "desktops",
+    "data",
+    "os-specific",
+];
+
+/// A category with its size and most popular packages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategorySummary {
+    pub name: String,
+    pub package_count: usize,
+    pub top_packages: Vec<String>,
+}
+
+/// Derive the category of a package from its attribute path and `meta`
+///
+/// Returns an empty string if no category can be derived.
+pub fn categorize(attr_path: &str, meta: Option<&Value>) -> String {
+    if let Some(category) = category_from_attr_path(attr_path) {
+        return category;
+    }
+
+    meta.and_then(|m| m.get("position"))
+        .and_then(|p| p.as_str())
+        .and_then(category_from_position)
+        .unwrap_or_default()
+}
+
+/// Category from a package set prefix, e.g. `nixpkgs.python3Packages.requests`
+fn category_from_attr_path(attr_path: &str) -> Option<String> {
+    let segments: Vec<&str> = attr_path.split('.').collect();
+
+    // Top-level packages ("hello", "nixpkgs.hello") are not in a set
+    let set = match segments.as_slice() {
+        [channel, set, _, ..] if matches!(*channel, "nixpkgs" | "nixos") => *set,
+        [set, _, ..] if !matches!(*set, "nixpkgs" | "nixos") => *set,
+        _ => return None,
+    };
+
+    if let Some((_, name)) = PACKAGE_SETS.iter().find(|(prefix, _)| *prefix == set) {
+        return Some(name.to_string());
+    }
+
+    // Unknown sets still make a useful category ("fooPackages" -> "foo")
+    set.strip_suffix("Packages")
+        .filter(|name| !name.is_empty())
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '_').to_lowercase())
+}
+
+/// Category from `meta.position`, e.g. `.../pkgs/applications/editors/vim/default.nix:12`
+fn category_from_position(position: &str) -> Option<String> {
+    let (_, tree_path) = position.split_once("/pkgs/")?;
+    let mut parts = tree_path.split('/');
+
+    match (parts.next()?, parts.next()?) {
+        ("games", _) => Some("games".to_string()),
+        (tree, category) if CATEGORIZED_TREES.contains(&tree) && !category.contains('.') => {
+            Some(category.to_string())
+        }
+        _ => None,
+    }
+}
+
+impl PackageCache {
+    /// List categories by size, with their most popular packages
+    pub fn categories(&self, top_packages: usize) -> Result<Vec<CategorySummary>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn.prepare_cached(
+            "SELECT category, COUNT(*) FROM packages
+             WHERE category != ''
+             GROUP BY category
+             ORDER BY COUNT(*) DESC, category ASC"
+        )?;
+        let counts = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to list categories")?;
+        drop(stmt);
+
+        let mut top_stmt = conn.prepare_cached(&format!(
+            "SELECT name FROM packages WHERE category = ?1 ORDER BY {} DESC, name ASC LIMIT ?2",
+            RANK_SQL
+        ))?;
+
+        let mut summaries = Vec::with_capacity(counts.len());
+        for (name, count) in counts {
+            let top = top_stmt.query_map(params![name, top_packages as i64], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            summaries.push(CategorySummary {
+                name,
+                package_count: count as usize,
+                top_packages: top,
+            });
+        }
+
+        Ok(summaries)
+    }
+
+    /// Most popular packages of a category
+    pub fn browse(&self, category: &str, limit: usize) -> Result<Vec<CachedPackage>> {
+        let conn = self.conn()?;
+
+        let sql = format!(
+            "SELECT {} FROM packages
+             WHERE category = ?1
+             ORDER BY {} DESC, name ASC
+             LIMIT ?2",
+            PACKAGE_COLUMNS, RANK_SQL
+        );
+
+        let packages = conn.prepare_cached(&sql)?
+            .query_map(params![category.to_lowercase(), limit as i64], Self::row_to_package)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to browse category")?;
+
+        Ok(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_cache::temp_cache;
+
+    #[test]
+    fn test_category_from_attr_path() {
+        assert_eq!(categorize("nixpkgs.python3Packages.requests", None), "python");
+        assert_eq!(categorize("nodePackages.typescript", None), "nodejs");
+        assert_eq!(categorize("nixos.haskellPackages.pandoc", None), "haskell");
+        assert_eq!(categorize("nixpkgs.elmPackages.elm", None), "elm");
+        assert_eq!(categorize("nixpkgs.hello", None), "");
+    }
+
+    #[test]
+    fn test_category_from_meta_position() {
+        let meta: Value = serde_json::json!({
+            "position": "/nix/store/abc-source/pkgs/applications/editors/vim/default.nix:42"
+        });
+        assert_eq!(categorize("nixpkgs.vim", Some(&meta)), "editors");
+
+        let meta: Value = serde_json::json!({
+            "position": "/nix/store/abc-source/pkgs/games/doom/default.nix:1"
+        });
+        assert_eq!(categorize("nixpkgs.doom", Some(&meta)), "games");
+
+        let meta: Value = serde_json::json!({
+            "position": "/nix/store/abc-source/pkgs/by-name/he/hello/package.nix:10"
+        });
+        assert_eq!(categorize("nixpkgs.hello", Some(&meta)), "");
+    }
+
+    #[test]
+    fn test_categories_and_browse() {
+        let (_dir, cache) = temp_cache();
+
+        let package = |attr_path: &str, category: &str| CachedPackage {
+            name: attr_path.rsplit('.').next().unwrap().to_string(),
+            attr_path: attr_path.to_string(),
+            category: category.to_string(),
+            ..Default::default()
+        };
+        cache.upsert_packages(&[
+            package("python3Packages.requests", "python"),
+            package("python3Packages.numpy", "python"),
+            package("vim", "editors"),
+            package("hello", ""),
+        ]).unwrap();
+        cache.record_usage("python3Packages.requests", super::super::UsageSignal::Installed).unwrap();
+
+        let categories = cache.categories(1).unwrap();
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories[0].name, "python");
+        assert_eq!(categories[0].package_count, 2);
+        assert_eq!(categories[0].top_packages, vec!["requests".to_string()]);
+
+        let editors = cache.browse("Editors", 10).unwrap();
+        assert_eq!(editors.len(), 1);
+        assert_eq!(editors[0].name, "vim");
+    }
+}
@@ -0,0 +1,213 @@
+/// Incremental cache updates
+///
+/// Compares a freshly loaded nixpkgs package set against the cached one so
+/// only changed rows are written and packages removed from nixpkgs are
+/// deleted. The resulting `CacheDelta` doubles as a changelog.
+use super::CachedPackage;
+use serde::{Serialize, Deserialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Kind of change to a cached package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// A single changelog entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageChange {
+    pub attr_path: String,
+    pub kind: ChangeKind,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+/// Difference between two package sets
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheDelta {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+
+    /// Per-package changes (left empty for the initial build)
+    pub changes: Vec<PackageChange>,
+}
+
+impl CacheDelta {
+    /// Check if the update changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.updated == 0 && self.removed == 0
+    }
+
+    /// One-line summary, e.g. "+12 added, ~340 updated, -3 removed"
+    pub fn summary(&self) -> String {
+        format!(
+            "+{} added, ~{} updated, -{} removed",
+            self.added, self.updated, self.removed
+        )
+    }
+}
+
+/// Version and content hash of a cached package row
+#[derive(Debug, Clone)]
+pub(crate) struct PackageFingerprint {
+    pub version: String,
+    pub hash: u64,
+}
+
+impl PackageFingerprint {
+    /// Fingerprint the fields that come from nixpkgs (not local popularity data)
+    pub fn of(package: &CachedPackage) -> Self {
+        let mut hasher = DefaultHasher::new();
+        package.name.hash(&mut hasher);
+        package.version.hash(&mut hasher);
+        package.description.hash(&mut hasher);
+        package.license.hash(&mut hasher);
+        package.platforms.hash(&mut hasher);
+        package.unfree.hash(&mut hasher);
+        package.broken.hash(&mut hasher);
+        package.category.hash(&mut hasher);
+
+        Self {
+            version: package.version.clone(),
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// Classifies packages of a new set one at a time against the old set
+///
+/// Works on a stream of packages, so the new set never has to be held in
+/// memory; only the old fingerprints are.
+pub(crate) struct DeltaTracker {
+    old: HashMap<String, PackageFingerprint>,
+    record_changes: bool,
+    delta: CacheDelta,
+}
+
+impl DeltaTracker {
+    /// Start tracking against the fingerprints of the current cache
+    pub fn new(old: HashMap<String, PackageFingerprint>) -> Self {
+        // A changelog of the initial build would just list every package
+        let record_changes = !old.is_empty();
+
+        Self {
+            old,
+            record_changes,
+            delta: CacheDelta::default(),
+        }
+    }
+
+    /// Classify a package from the new set
+    ///
+    /// Returns `true` if the package must be written to the cache.
+    pub fn observe(&mut self, package: &CachedPackage) -> bool {
+        let new = PackageFingerprint::of(package);
+
+        match self.old.remove(&package.attr_path) {
+            None => {
+                self.delta.added += 1;
+                self.record(package.attr_path.clone(), ChangeKind::Added, None, Some(new.version));
+                true
+            }
+            Some(old) if old.hash != new.hash => {
+                self.delta.updated += 1;
+                self.record(package.attr_path.clone(), ChangeKind::Updated, Some(old.version), Some(new.version));
+                true
+            }
+            Some(_) => {
+                self.delta.unchanged += 1;
+                false
+            }
+        }
+    }
+
+    /// Finish tracking
+    ///
+    /// Returns the delta and the attribute paths that no longer exist in
+    /// the new set and must be deleted.
+    pub fn finish(mut self) -> (CacheDelta, Vec<String>) {
+        let mut removed: Vec<(String, PackageFingerprint)> = std::mem::take(&mut self.old).into_iter().collect();
+        removed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.delta.removed = removed.len();
+        let mut attr_paths = Vec::with_capacity(removed.len());
+        for (attr_path, old) in removed {
+            self.record(attr_path.clone(), ChangeKind::Removed, Some(old.version), None);
+            attr_paths.push(attr_path);
+        }
+
+        (self.delta, attr_paths)
+    }
+
+    fn record(
+        &mut self,
+        attr_path: String,
+        kind: ChangeKind,
+        old_version: Option<String>,
+        new_version: Option<String>,
+    ) {
+        if self.record_changes {
+            self.delta.changes.push(PackageChange {
+                attr_path,
+                kind,
+                old_version,
+                new_version,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(attr_path: &str, version: &str) -> CachedPackage {
+        CachedPackage {
+            name: attr_path.to_string(),
+            version: version.to_string(),
+            attr_path: attr_path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_delta_tracker() {
+        let mut old = HashMap::new();
+        for pkg in [package("hello", "2.12"), package("jq", "1.7"), package("oldtool", "0.1")] {
+            old.insert(pkg.attr_path.clone(), PackageFingerprint::of(&pkg));
+        }
+
+        let mut tracker = DeltaTracker::new(old);
+        assert!(!tracker.observe(&package("hello", "2.12")));
+        assert!(tracker.observe(&package("jq", "1.7.1")));
+        assert!(tracker.observe(&package("ripgrep", "14.1.0")));
+
+        let (delta, removed) = tracker.finish();
+        assert_eq!((delta.added, delta.updated, delta.removed, delta.unchanged), (1, 1, 1, 1));
+        assert_eq!(removed, vec!["oldtool".to_string()]);
+        assert_eq!(delta.changes.len(), 3);
+        assert_eq!(delta.summary(), "+1 added, ~1 updated, -1 removed");
+
+        let update = delta.changes.iter().find(|c| c.kind == ChangeKind::Updated).unwrap();
+        assert_eq!(update.old_version.as_deref(), Some("1.7"));
+        assert_eq!(update.new_version.as_deref(), Some("1.7.1"));
+    }
+
+    #[test]
+    fn test_initial_build_skips_changelog() {
+        let mut tracker = DeltaTracker::new(HashMap::new());
+        assert!(tracker.observe(&package("hello", "2.12")));
+
+        let (delta, removed) = tracker.finish();
+        assert_eq!(delta.added, 1);
+        assert!(delta.changes.is_empty());
+        assert!(removed.is_empty());
+    }
+}
@@ -0,0 +1,466 @@
+/// Portable package cache snapshots
+///
+/// `nsfw cache export` writes the package database to a single gzip file so
+/// a prebuilt cache can be copied to machines behind slow proxies. The file
+/// starts with a one-line JSON manifest (format version, provenance, size and
+/// SHA-256) followed by the database itself. The checksum covers the manifest
+/// as well as the database, and imports verify all of it before the live
+/// database is replaced.
+use super::{CacheProvenance, PackageCache};
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, info};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies a file as an NSFW cache snapshot
+const SNAPSHOT_FORMAT: &str = "nsfw-cache-snapshot";
+
+/// Current snapshot layout; bumped on incompatible changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Upper bound for the manifest line, to reject garbage input early
+const MAX_MANIFEST_LEN: u64 = 64 * 1024;
+
+/// Metadata that describes this machine rather than the package set
+///
+/// Stripped from exports and kept across imports.
+const LOCAL_METADATA_KEYS: &[&str] = &[
+    "build_state",
+    "build_progress",
+    "build_started_at",
+    "build_error",
+    "last_refresh_check",
+    "profile_snapshot",
+    "profile_snapshot_at",
+    "search_cache_hits",
+    "search_cache_misses",
+];
+
+/// Header of a cache snapshot file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Always `nsfw-cache-snapshot`
+    pub format: String,
+
+    /// Snapshot layout version
+    pub version: u32,
+
+    /// nsfw version that wrote the snapshot
+    pub created_by: String,
+
+    /// When the snapshot was written (Unix timestamp)
+    pub created_at: i64,
+
+    /// Revision the cache was built from, if known
+    pub provenance: Option<CacheProvenance>,
+
+    /// Number of packages in the snapshot
+    pub package_count: usize,
+
+    /// Size of the uncompressed database in bytes
+    pub db_size: u64,
+
+    /// SHA-256 (hex) of this manifest, with this field empty, followed by
+    /// the uncompressed database
+    pub sha256: String,
+}
+
+impl PackageCache {
+    /// Write the cache to a compressed snapshot file
+    ///
+    /// The file is written next to `path` and renamed into place, so a
+    /// failed export never leaves a truncated snapshot behind.
+    pub fn export_snapshot(&self, path: &Path) -> Result<SnapshotManifest> {
+        let copy = TempPath::next_to(self.db_path(), "export");
+
+        // VACUUM INTO gives a compact, consistent copy including WAL contents
+        self.conn()?
+            .execute("VACUUM INTO ?1", [copy.path().to_string_lossy()])
+            .context("Failed to copy package cache for export")?;
+
+        let package_count: i64 = {
+            let conn = Connection::open(copy.path())
+                .context("Failed to open exported cache copy")?;
+            // Search history and popularity stay on this machine
+            conn.execute("DROP TABLE IF EXISTS search_cache", [])?;
+            conn.execute("DROP TABLE IF EXISTS popularity", [])?;
+            conn.execute("UPDATE packages SET search_count = 0", [])?;
+            for key in LOCAL_METADATA_KEYS {
+                conn.execute("DELETE FROM metadata WHERE key = ?1", [key])?;
+            }
+            conn.execute("VACUUM", [])?;
+            // Counted in the copy, which may differ from the live cache by now
+            conn.query_row("SELECT COUNT(*) FROM packages", [], |row| row.get(0))?
+        };
+
+        let mut manifest = SnapshotManifest {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            created_by: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            provenance: self.provenance()?,
+            package_count: package_count.max(0) as usize,
+            db_size: std::fs::metadata(copy.path())?.len(),
+            sha256: String::new(),
+        };
+        let mut hasher = manifest_hasher(&manifest)?;
+        std::io::copy(&mut File::open(copy.path())?, &mut hasher)?;
+        manifest.sha256 = hex(&hasher.finalize());
+
+        let partial = TempPath::new(path.with_extension("partial"));
+        {
+            let file = File::create(partial.path())
+                .with_context(|| format!("Failed to create {}", partial.path().display()))?;
+            let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+
+            serde_json::to_writer(&mut encoder, &manifest)?;
+            encoder.write_all(b"\n")?;
+            std::io::copy(&mut File::open(copy.path())?, &mut encoder)
+                .context("Failed to write snapshot")?;
+
+            encoder.finish()?
+                .into_inner()
+                .map_err(|e| anyhow!("Failed to flush snapshot: {}", e.error()))?
+                .sync_all()?;
+        }
+        partial.persist(path)?;
+
+        info!("Exported {} packages to {}", manifest.package_count, path.display());
+        Ok(manifest)
+    }
+
+    /// Replace the cache with the contents of a snapshot file
+    ///
+    /// The snapshot is fully decompressed and checked (format version,
+    /// size, SHA-256, SQLite integrity and package count) before the live
+    /// database is touched. The replacement itself runs through SQLite's
+    /// backup API, so concurrent readers see either the old or the new cache.
+    pub fn import_snapshot(&self, path: &Path) -> Result<SnapshotManifest> {
+        let staged = TempPath::next_to(self.db_path(), "import");
+        let manifest = unpack_snapshot(path, staged.path())?;
+        verify_database(staged.path(), &manifest)?;
+
+        // Keep what describes this machine rather than the package set
+        let mut local = Vec::new();
+        for key in LOCAL_METADATA_KEYS {
+            if let Some(value) = self.get_metadata(key)? {
+                local.push((*key, value));
+            }
+        }
+        let popularity = self.popularity_rows()?;
+
+        self.conn()?
+            .restore(DatabaseName::Main, staged.path(), None::<fn(rusqlite::backup::Progress)>)
+            .context("Failed to replace package cache database")?;
+
+        // Older snapshots may predate newer columns
+        self.initialize()?;
+        for (key, value) in local {
+            self.set_metadata(key, &value)?;
+        }
+        self.restore_popularity_rows(&popularity)?;
+
+        info!("Imported {} packages from {}", manifest.package_count, path.display());
+        Ok(manifest)
+    }
+}
+
+/// Raw popularity row: attribute path and scaled shown/selected/installed
+type PopularityRow = (String, f64, f64, f64);
+
+impl PackageCache {
+    fn popularity_rows(&self) -> Result<Vec<PopularityRow>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT attr_path, shown, selected, installed FROM popularity")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    fn restore_popularity_rows(&self, rows: &[PopularityRow]) -> Result<()> {
+        let conn = self.conn()?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO popularity (attr_path, shown, selected, installed)
+                 VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            for (attr_path, shown, selected, installed) in rows {
+                stmt.execute(rusqlite::params![attr_path, shown, selected, installed])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Read only the manifest of a snapshot file
+pub fn read_manifest(path: &Path) -> Result<SnapshotManifest> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+    let mut reader = BufReader::new(GzDecoder::new(BufReader::new(file)));
+    parse_manifest(&mut reader)
+}
+
+/// Decompress a snapshot into `target`, checking size and checksum
+fn unpack_snapshot(path: &Path, target: &Path) -> Result<SnapshotManifest> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+    let mut reader = BufReader::new(GzDecoder::new(BufReader::new(file)));
+    let manifest = parse_manifest(&mut reader)?;
+
+    let mut output = BufWriter::new(File::create(target)
+        .context("Failed to stage snapshot")?);
+    let mut hasher = manifest_hasher(&manifest)?;
+    let mut size = 0u64;
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buffer)
+            .context("Snapshot is corrupt or truncated")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+    output.flush()?;
+
+    if size != manifest.db_size {
+        bail!("Snapshot size mismatch: expected {} bytes, got {}", manifest.db_size, size);
+    }
+
+    let sha256 = hex(&hasher.finalize());
+    if sha256 != manifest.sha256 {
+        bail!("Snapshot checksum mismatch: expected {}, got {}", manifest.sha256, sha256);
+    }
+
+    debug!("Snapshot {} unpacked and checksum verified", path.display());
+    Ok(manifest)
+}
+
+/// Read and validate the manifest line
+fn parse_manifest<R: BufRead>(reader: &mut R) -> Result<SnapshotManifest> {
+    let mut line = Vec::new();
+    reader.by_ref()
+        .take(MAX_MANIFEST_LEN)
+        .read_until(b'\n', &mut line)
+        .context("Not a valid cache snapshot (failed to decompress)")?;
+
+    let manifest: SnapshotManifest = serde_json::from_slice(&line)
+        .map_err(|_| anyhow!("Not a valid cache snapshot (missing manifest)"))?;
+
+    if manifest.format != SNAPSHOT_FORMAT {
+        bail!("Not a valid cache snapshot (format '{}')", manifest.format);
+    }
+    if manifest.version > SNAPSHOT_VERSION {
+        bail!(
+            "Snapshot version {} is newer than supported ({}); upgrade nsfw to import it",
+            manifest.version, SNAPSHOT_VERSION
+        );
+    }
+
+    Ok(manifest)
+}
+
+/// Check that a staged database is intact and matches its manifest
+fn verify_database(path: &Path, manifest: &SnapshotManifest) -> Result<()> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("Snapshot does not contain a valid database")?;
+
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .context("Snapshot does not contain a valid database")?;
+    if integrity != "ok" {
+        bail!("Snapshot database is corrupt: {}", integrity);
+    }
+
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM packages", [], |row| row.get(0))
+        .context("Snapshot database has no package table")?;
+    if count as usize != manifest.package_count {
+        bail!(
+            "Snapshot package count mismatch: manifest says {}, database has {}",
+            manifest.package_count, count
+        );
+    }
+
+    Ok(())
+}
+
+/// Hasher for a snapshot's checksum, fed with the manifest
+///
+/// The manifest is hashed without its checksum; the database follows.
+fn manifest_hasher(manifest: &SnapshotManifest) -> Result<Sha256> {
+    let unsigned = SnapshotManifest {
+        sha256: String::new(),
+        ..manifest.clone()
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(&unsigned)?);
+    hasher.update(b"\n");
+    Ok(hasher)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A scratch file removed on drop unless persisted
+struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    fn new(path: PathBuf) -> Self {
+        let _ = std::fs::remove_file(&path);
+        Self { path }
+    }
+
+    /// Scratch file in the same directory as `db_path`
+    fn next_to(db_path: &Path, purpose: &str) -> Self {
+        Self::new(db_path.with_extension(format!("{}-{}", purpose, std::process::id())))
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Atomically move the file to its final location
+    fn persist(self, target: &Path) -> Result<()> {
+        std::fs::rename(&self.path, target)
+            .with_context(|| format!("Failed to write {}", target.display()))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_cache::{temp_cache, CachedPackage, NixpkgsRevision, RevisionSource, UsageSignal};
+
+    fn populated_cache() -> (tempfile::TempDir, PackageCache) {
+        let (dir, cache) = temp_cache();
+        cache.upsert_packages(&[
+            CachedPackage {
+                name: "hello".to_string(),
+                version: "2.12".to_string(),
+                attr_path: "nixpkgs.hello".to_string(),
+                ..Default::default()
+            },
+            CachedPackage {
+                name: "jq".to_string(),
+                version: "1.7.1".to_string(),
+                attr_path: "nixpkgs.jq".to_string(),
+                ..Default::default()
+            },
+        ]).unwrap();
+        cache.set_provenance(&CacheProvenance {
+            revision: NixpkgsRevision::new(RevisionSource::Channel, "24.05.1234.abcdef"),
+            built_at: 1_700_000_000,
+            package_count: 2,
+        }).unwrap();
+        (dir, cache)
+    }
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let (dir, source) = populated_cache();
+        source.save_profile_snapshot("{\"elements\":{}}").unwrap();
+        source.record_usage("nixpkgs.hello", UsageSignal::Installed).unwrap();
+
+        let file = dir.path().join("cache.nsfw");
+        let exported = source.export_snapshot(&file).unwrap();
+        assert_eq!(exported.package_count, 2);
+        assert_eq!(read_manifest(&file).unwrap().sha256, exported.sha256);
+
+        let (_target_dir, target) = temp_cache();
+        target.save_profile_snapshot("{\"elements\":{\"local\":{}}}").unwrap();
+        target.record_usage("nixpkgs.jq", UsageSignal::Selected).unwrap();
+        let imported = target.import_snapshot(&file).unwrap();
+
+        assert_eq!(imported.package_count, 2);
+        assert_eq!(target.search("jq", 10).unwrap().len(), 1);
+        assert_eq!(target.provenance().unwrap().unwrap().revision.revision, "24.05.1234.abcdef");
+        // Machine-local state is neither exported nor overwritten
+        assert!(target.profile_snapshot().unwrap().unwrap().json.contains("local"));
+        assert_eq!(target.popular_attr_paths(10).unwrap(), vec!["nixpkgs.jq".to_string()]);
+    }
+
+    #[test]
+    fn test_import_rejects_corrupt_snapshot() {
+        let (dir, source) = populated_cache();
+        let file = dir.path().join("cache.nsfw");
+        source.export_snapshot(&file).unwrap();
+
+        // Flip a byte inside the compressed payload
+        let mut bytes = std::fs::read(&file).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        std::fs::write(&file, bytes).unwrap();
+
+        let (_target_dir, target) = temp_cache();
+        assert!(target.import_snapshot(&file).is_err());
+        assert!(target.is_empty());
+    }
+
+    #[test]
+    fn test_import_rejects_edited_manifest() {
+        let (dir, source) = populated_cache();
+        let file = dir.path().join("cache.nsfw");
+        source.export_snapshot(&file).unwrap();
+
+        // Claim another revision while keeping the database and checksum
+        let mut contents = Vec::new();
+        GzDecoder::new(File::open(&file).unwrap()).read_to_end(&mut contents).unwrap();
+        let newline = contents.iter().position(|&b| b == b'\n').unwrap();
+        let mut manifest: SnapshotManifest = serde_json::from_slice(&contents[..newline]).unwrap();
+        manifest.provenance.as_mut().unwrap().revision = NixpkgsRevision::new(RevisionSource::Channel, "99.99");
+
+        let mut encoder = GzEncoder::new(File::create(&file).unwrap(), Compression::default());
+        serde_json::to_writer(&mut encoder, &manifest).unwrap();
+        encoder.write_all(&contents[newline..]).unwrap();
+        encoder.finish().unwrap();
+
+        let (_target_dir, target) = temp_cache();
+        let err = target.import_snapshot(&file).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        assert!(target.is_empty());
+    }
+
+    #[test]
+    fn test_import_rejects_newer_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("future.nsfw");
+
+        let mut encoder = GzEncoder::new(File::create(&file).unwrap(), Compression::default());
+        writeln!(
+            encoder,
+            r#"{{"format":"nsfw-cache-snapshot","version":99,"created_by":"9.0.0","created_at":0,"provenance":null,"package_count":0,"db_size":0,"sha256":""}}"#
+        ).unwrap();
+        encoder.finish().unwrap();
+
+        let err = read_manifest(&file).unwrap_err();
+        assert!(err.to_string().contains("newer than supported"));
+    }
+
+    #[test]
+    fn test_import_rejects_non_snapshot() {
+        let (dir, target) = temp_cache();
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, "hello").unwrap();
+
+        assert!(target.import_snapshot(&file).is_err());
+    }
+}
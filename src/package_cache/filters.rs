@@ -0,0 +1,179 @@
+//! Search filters for the package cache
+//!
+//! Filters are translated into SQL clauses against the enriched
+//! `packages` table (license, platforms, unfree, broken) so that
+//! `LIMIT` is applied after filtering.
+
+use rusqlite::types::Value as SqlValue;
+
+/// Filters applied to a package cache search
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    /// Exclude packages with an unfree license
+    pub free_only: bool,
+
+    /// Exclude packages marked as broken
+    pub exclude_broken: bool,
+
+    /// Only include packages with this license (SPDX id or short name)
+    pub license: Option<String>,
+
+    /// Only include packages available on this platform (e.g., "x86_64-linux")
+    pub platform: Option<String>,
+
+    /// Only include packages whose attribute path starts with this prefix
+    pub attr_prefix: Option<String>,
+
+    /// Restrict results by install state (`Some(true)` = installed only)
+    pub installed: Option<bool>,
+
+    /// Names of installed packages, used when `installed` is set
+    pub installed_names: Vec<String>,
+}
+
+impl SearchFilters {
+    /// Create an empty filter set (matches everything)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if any filter is active
+    pub fn is_active(&self) -> bool {
+        self.free_only
+            || self.exclude_broken
+            || self.license.is_some()
+            || self.platform.is_some()
+            || self.attr_prefix.is_some()
+            || self.installed.is_some()
+    }
+
+    /// Check if the installed-package list is needed to apply these filters
+    pub fn needs_installed_names(&self) -> bool {
+        self.installed.is_some()
+    }
+
+    /// Set the names of currently installed packages
+    pub fn with_installed_names(mut self, names: Vec<String>) -> Self {
+        self.installed_names = names;
+        self
+    }
+
+    /// Build the SQL `WHERE` clauses and their parameters
+    ///
+    /// Clauses are meant to be joined with `AND`. Parameters are positional
+    /// and must be appended after any parameters already bound by the caller.
+    pub(crate) fn to_sql(&self) -> (Vec<String>, Vec<SqlValue>) {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+
+        if self.free_only {
+            clauses.push("unfree = 0".to_string());
+        }
+
+        if self.exclude_broken {
+            clauses.push("broken = 0".to_string());
+        }
+
+        if let Some(license) = &self.license {
+            clauses.push(token_clause("license"));
+            params.push(SqlValue::Text(escape_like(&normalize_token(license))));
+        }
+
+        if let Some(platform) = &self.platform {
+            clauses.push(token_clause("platforms"));
+            params.push(SqlValue::Text(escape_like(&normalize_token(platform))));
+        }
+
+        if let Some(prefix) = &self.attr_prefix {
+            // Match both "python3Packages.x" and "nixpkgs.python3Packages.x"
+            let prefix = prefix.trim_end_matches('.');
+            clauses.push("(attr_path LIKE ? ESCAPE '\\' OR attr_path LIKE ? ESCAPE '\\')".to_string());
+            params.push(SqlValue::Text(format!("{}.%", escape_like(prefix))));
+            params.push(SqlValue::Text(format!("nixpkgs.{}.%", escape_like(prefix))));
+        }
+
+        if let Some(installed) = self.installed {
+            let (clause, mut installed_params) = self.installed_clause();
+            if installed {
+                clauses.push(clause);
+            } else {
+                clauses.push(format!("NOT {}", clause));
+            }
+            params.append(&mut installed_params);
+        }
+
+        (clauses, params)
+    }
+
+    /// Build a clause matching packages whose name or attribute tail is installed
+    fn installed_clause(&self) -> (String, Vec<SqlValue>) {
+        if self.installed_names.is_empty() {
+            return ("0".to_string(), Vec::new());
+        }
+
+        let mut parts = Vec::new();
+        let mut params = Vec::new();
+
+        for name in &self.installed_names {
+            parts.push("name = ? OR attr_path = ? OR attr_path LIKE ? ESCAPE '\\'".to_string());
+            params.push(SqlValue::Text(name.clone()));
+            params.push(SqlValue::Text(name.clone()));
+            params.push(SqlValue::Text(format!("%.{}", escape_like(name))));
+        }
+
+        (format!("({})", parts.join(" OR ")), params)
+    }
+}
+
+/// Clause matching one entry of a comma-separated column (case-insensitive)
+///
+/// The bound token must already be escaped with `escape_like`.
+fn token_clause(column: &str) -> String {
+    format!(
+        "(',' || REPLACE(LOWER({}), ' ', '') || ',') LIKE '%,' || ? || ',%' ESCAPE '\\'",
+        column
+    )
+}
+
+/// Normalize a user-supplied token for comparison with a comma-separated column
+fn normalize_token(token: &str) -> String {
+    token.trim().to_lowercase().replace(' ', "")
+}
+
+/// Escape LIKE wildcards so user input is matched literally
+pub(crate) fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_filters_inactive() {
+        let filters = SearchFilters::new();
+        assert!(!filters.is_active());
+
+        let (clauses, params) = filters.to_sql();
+        assert!(clauses.is_empty());
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn test_installed_filter_needs_names() {
+        let filters = SearchFilters {
+            installed: Some(false),
+            ..Default::default()
+        };
+        assert!(filters.is_active());
+        assert!(filters.needs_installed_names());
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("python3_12%"), "python3\\_12\\%");
+    }
+}
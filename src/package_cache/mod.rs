@@ -5,33 +5,81 @@
 ///
 /// Features:
 /// - Fast local searches (< 10ms)
-/// - Automatic background updates
+/// - Automatic background updates (detached worker process)
 /// - Smart prioritization (popular packages first)
-/// - Learning from user searches
+/// - Learning from local usage (shown, selected, installed)
 mod builder;
-
-pub use builder::CacheBuilder;
+mod categories;
+mod delta;
+mod export;
+mod filters;
+mod popularity;
+mod provenance;
+mod snapshot;
+pub mod stream;
+mod versions;
+mod worker;
+
+pub use builder::{CacheBuilder, TruncatedPackageSet};
+pub use categories::{CategorySummary, categorize};
+pub use delta::{CacheDelta, ChangeKind, PackageChange};
+pub use export::{SnapshotManifest, SNAPSHOT_VERSION, read_manifest};
+pub use filters::SearchFilters;
+pub use popularity::{PopularityStats, UsageSignal};
+pub use provenance::{CacheProvenance, NixpkgsRevision, RevisionSource};
+pub use snapshot::ProfileSnapshot;
+pub use versions::{ResolvedVersion, VersionResolver, compare_versions};
+pub use worker::{BuildLock, BuildState, spawn_refresh_worker};
 
 use rusqlite::{Connection, params, Result as SqlResult};
+use rusqlite::types::Value as SqlValue;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use anyhow::{Result, Context};
-use log::{debug, info};
+use log::{debug, info, warn};
 
 /// Cached package information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CachedPackage {
     pub name: String,
     pub version: String,
     pub description: String,
     pub attr_path: String,
     pub last_updated: i64,  // Unix timestamp
-    pub search_count: i32,  // Popularity tracking
+    pub search_count: i32,  // Legacy popularity counter, superseded by `popularity`
+    #[serde(default)]
+    pub license: String,    // Comma-separated SPDX ids / short names
+    #[serde(default)]
+    pub platforms: String,  // Comma-separated Nix system doubles
+    #[serde(default)]
+    pub unfree: bool,
+    #[serde(default)]
+    pub broken: bool,
+    #[serde(default)]
+    pub category: String,   // Derived from the package set or nixpkgs location
 }
 
+/// Columns selected for a `CachedPackage`, in `row_to_package` order
+const PACKAGE_COLUMNS: &str =
+    "name, version, description, attr_path, last_updated, search_count, license, platforms, unfree, broken, category";
+
+/// Number of prepared statements kept per connection
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+
+/// How long to wait for another writer before failing with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Package cache manager
+///
+/// Holds one connection for its lifetime. The database runs in WAL mode so
+/// a background builder (with its own `PackageCache`) can write while
+/// searches read.
 pub struct PackageCache {
     db_path: PathBuf,
+    conn: Mutex<Connection>,
 }
 
 impl PackageCache {
@@ -41,10 +89,55 @@ impl PackageCache {
         std::fs::create_dir_all(&cache_dir)
             .context("Failed to create cache directory")?;
 
-        let db_path = cache_dir.join("packages.db");
+        let db_path = Self::default_db_path()?;
         debug!("Package cache database: {}", db_path.display());
 
-        Ok(Self { db_path })
+        Self::open(db_path)
+    }
+
+    /// Location of the database used by `new`
+    pub fn default_db_path() -> Result<PathBuf> {
+        Ok(Self::cache_directory()?.join("packages.db"))
+    }
+
+    /// Create a package cache backed by a specific database file
+    pub fn with_path(db_path: PathBuf) -> Result<Self> {
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create cache directory")?;
+        }
+
+        Self::open(db_path)
+    }
+
+    /// Open the managed connection and configure it for concurrent use
+    fn open(db_path: PathBuf) -> Result<Self> {
+        let conn = Connection::open(&db_path)
+            .context("Failed to open database")?;
+
+        conn.busy_timeout(BUSY_TIMEOUT)
+            .context("Failed to set busy timeout")?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+
+        // WAL lets readers proceed while a writer holds the database
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))
+            .context("Failed to enable WAL journaling")?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            warn!("Package cache is using '{}' journaling instead of WAL", journal_mode);
+        }
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .context("Failed to set synchronous mode")?;
+
+        Ok(Self {
+            db_path,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Lock the managed connection
+    pub(crate) fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn.lock()
+            .map_err(|_| anyhow::anyhow!("Package cache connection lock poisoned"))
     }
 
     /// Get the cache directory path
@@ -65,8 +158,7 @@ impl PackageCache {
 
     /// Initialize the database schema
     pub fn initialize(&self) -> Result<()> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
+        let conn = self.conn()?;
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS packages (
@@ -75,11 +167,23 @@ impl PackageCache {
                 description TEXT NOT NULL,
                 attr_path TEXT PRIMARY KEY,
                 last_updated INTEGER NOT NULL,
-                search_count INTEGER DEFAULT 0
+                search_count INTEGER DEFAULT 0,
+                license TEXT NOT NULL DEFAULT '',
+                platforms TEXT NOT NULL DEFAULT '',
+                unfree INTEGER NOT NULL DEFAULT 0,
+                broken INTEGER NOT NULL DEFAULT 0,
+                category TEXT NOT NULL DEFAULT ''
             )",
             [],
         ).context("Failed to create packages table")?;
 
+        // Databases created before metadata enrichment lack these columns
+        Self::ensure_column(&conn, "packages", "license", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(&conn, "packages", "platforms", "TEXT NOT NULL DEFAULT ''")?;
+        Self::ensure_column(&conn, "packages", "unfree", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "packages", "broken", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "packages", "category", "TEXT NOT NULL DEFAULT ''")?;
+
         // Create indexes for fast searches
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_name ON packages(name)",
@@ -91,6 +195,11 @@ impl PackageCache {
             [],
         ).context("Failed to create popularity index")?;
 
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_category ON packages(category)",
+            [],
+        ).context("Failed to create category index")?;
+
         // Metadata table for cache state
         conn.execute(
             "CREATE TABLE IF NOT EXISTS metadata (
@@ -100,93 +209,169 @@ impl PackageCache {
             [],
         ).context("Failed to create metadata table")?;
 
+        Self::create_popularity_table(&conn)?;
+        Self::create_versions_table(&conn)?;
+
         info!("Package cache database initialized");
         Ok(())
     }
 
+    /// Add a column to an existing table if it is missing
+    fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))
+            .context("Failed to read table info")?;
+
+        let exists = stmt.query_map([], |row| row.get::<_, String>(1))
+            .context("Failed to read table columns")?
+            .filter_map(|c| c.ok())
+            .any(|c| c == column);
+
+        if !exists {
+            debug!("Adding missing column {}.{}", table, column);
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            ).with_context(|| format!("Failed to add column {}", column))?;
+        }
+
+        Ok(())
+    }
+
     /// Search packages in the cache
     pub fn search(&self, query: &str, limit: usize) -> Result<Vec<CachedPackage>> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
+        self.search_filtered(query, limit, &SearchFilters::default())
+    }
+
+    /// Search packages in the cache, applying metadata filters
+    pub fn search_filtered(
+        &self,
+        query: &str,
+        limit: usize,
+        filters: &SearchFilters,
+    ) -> Result<Vec<CachedPackage>> {
+        let conn = self.conn()?;
 
         let search_pattern = format!("%{}%", query.to_lowercase());
 
-        let mut stmt = conn.prepare(
-            "SELECT name, version, description, attr_path, last_updated, search_count
+        let (filter_clauses, filter_params) = filters.to_sql();
+        let mut where_clause = "(LOWER(name) LIKE ? OR LOWER(description) LIKE ?)".to_string();
+        for clause in &filter_clauses {
+            where_clause.push_str(" AND ");
+            where_clause.push_str(clause);
+        }
+
+        let sql = format!(
+            "SELECT {}
              FROM packages
-             WHERE LOWER(name) LIKE ?1 OR LOWER(description) LIKE ?1
-             ORDER BY search_count DESC, name ASC
-             LIMIT ?2"
-        ).context("Failed to prepare search query")?;
-
-        let packages = stmt.query_map(params![search_pattern, limit as i32], |row| {
-            Ok(CachedPackage {
-                name: row.get(0)?,
-                version: row.get(1)?,
-                description: row.get(2)?,
-                attr_path: row.get(3)?,
-                last_updated: row.get(4)?,
-                search_count: row.get(5)?,
-            })
-        }).context("Failed to execute search")?
-          .collect::<SqlResult<Vec<_>>>()
-          .context("Failed to collect results")?;
+             WHERE {}
+             ORDER BY {} DESC, name ASC
+             LIMIT ?",
+            PACKAGE_COLUMNS, where_clause, popularity::RANK_SQL
+        );
+
+        let mut stmt = conn.prepare_cached(&sql)
+            .context("Failed to prepare search query")?;
+
+        let mut query_params = vec![
+            SqlValue::Text(search_pattern.clone()),
+            SqlValue::Text(search_pattern),
+        ];
+        query_params.extend(filter_params);
+        query_params.push(SqlValue::Integer(limit as i64));
+
+        let packages = stmt.query_map(rusqlite::params_from_iter(query_params), Self::row_to_package)
+            .context("Failed to execute search")?
+            .collect::<SqlResult<Vec<_>>>()
+            .context("Failed to collect results")?;
+
+        drop(stmt);
 
         debug!("Cache search for '{}': found {} results", query, packages.len());
 
-        // Increment search count for found packages
-        if !packages.is_empty() {
-            self.increment_search_counts(&packages)?;
-        }
+        // Learn from what was shown (on the same connection)
+        let attr_paths: Vec<&str> = packages.iter().map(|p| p.attr_path.as_str()).collect();
+        Self::record_shown(&conn, &attr_paths)?;
 
         Ok(packages)
     }
 
-    /// Increment search counts for packages (popularity tracking)
-    fn increment_search_counts(&self, packages: &[CachedPackage]) -> Result<()> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
-
-        let attr_paths: Vec<&str> = packages.iter().map(|p| p.attr_path.as_str()).collect();
-        let placeholders = vec!["?"; attr_paths.len()].join(",");
+    /// Look up a single package by attribute path or name
+    ///
+    /// `hello` matches the attribute paths `hello` and `nixpkgs.hello` as
+    /// well as a package named `hello`; attribute path matches win.
+    pub fn find_package(&self, name: &str) -> Result<Option<CachedPackage>> {
+        let conn = self.conn()?;
 
-        let query = format!(
-            "UPDATE packages SET search_count = search_count + 1 WHERE attr_path IN ({})",
-            placeholders
+        let sql = format!(
+            "SELECT {}
+             FROM packages
+             WHERE attr_path = ?1 OR attr_path LIKE ?2 ESCAPE '\\' OR name = ?1
+             ORDER BY (attr_path = ?1 OR attr_path LIKE ?2 ESCAPE '\\') DESC, {} DESC
+             LIMIT 1",
+            PACKAGE_COLUMNS, popularity::RANK_SQL
         );
 
-        let mut stmt = conn.prepare(&query)
-            .context("Failed to prepare update query")?;
+        let suffix = format!("%.{}", filters::escape_like(name));
+        let package = conn.prepare_cached(&sql)
+            .context("Failed to prepare package lookup")?
+            .query_row(params![name, suffix], Self::row_to_package);
 
-        stmt.execute(rusqlite::params_from_iter(attr_paths))
-            .context("Failed to update search counts")?;
+        match package {
+            Ok(package) => Ok(Some(package)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to look up package"),
+        }
+    }
 
-        Ok(())
+    /// Map a row selected with `PACKAGE_COLUMNS` to a `CachedPackage`
+    fn row_to_package(row: &rusqlite::Row) -> SqlResult<CachedPackage> {
+        Ok(CachedPackage {
+            name: row.get(0)?,
+            version: row.get(1)?,
+            description: row.get(2)?,
+            attr_path: row.get(3)?,
+            last_updated: row.get(4)?,
+            search_count: row.get(5)?,
+            license: row.get(6)?,
+            platforms: row.get(7)?,
+            unfree: row.get(8)?,
+            broken: row.get(9)?,
+            category: row.get(10)?,
+        })
     }
 
     /// Add or update packages in the cache
     pub fn upsert_packages(&self, packages: &[CachedPackage]) -> Result<()> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
+        let conn = self.conn()?;
 
         let tx = conn.unchecked_transaction()
             .context("Failed to start transaction")?;
 
-        for package in packages {
-            tx.execute(
+        {
+            let mut stmt = tx.prepare_cached(
                 "INSERT OR REPLACE INTO packages
-                 (name, version, description, attr_path, last_updated, search_count)
+                 (name, version, description, attr_path, last_updated, search_count,
+                  license, platforms, unfree, broken, category)
                  VALUES (?1, ?2, ?3, ?4, ?5,
-                    COALESCE((SELECT search_count FROM packages WHERE attr_path = ?4), 0)
+                    COALESCE((SELECT search_count FROM packages WHERE attr_path = ?4), 0),
+                    ?6, ?7, ?8, ?9, ?10
                  )",
-                params![
+            ).context("Failed to prepare insert query")?;
+
+            for package in packages {
+                stmt.execute(params![
                     package.name,
                     package.version,
                     package.description,
                     package.attr_path,
                     package.last_updated,
-                ],
-            ).context("Failed to insert package")?;
+                    package.license,
+                    package.platforms,
+                    package.unfree,
+                    package.broken,
+                    package.category,
+                ]).context("Failed to insert package")?;
+            }
         }
 
         tx.commit().context("Failed to commit transaction")?;
@@ -195,10 +380,61 @@ impl PackageCache {
         Ok(())
     }
 
+    /// Fingerprint every cached package, keyed by attribute path
+    pub(crate) fn fingerprints(&self) -> Result<HashMap<String, delta::PackageFingerprint>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM packages", PACKAGE_COLUMNS))
+            .context("Failed to prepare fingerprint query")?;
+
+        let mut fingerprints = HashMap::new();
+        let rows = stmt.query_map([], Self::row_to_package)
+            .context("Failed to read cached packages")?;
+
+        for row in rows {
+            let package = row.context("Failed to read cached package")?;
+            fingerprints.insert(package.attr_path.clone(), delta::PackageFingerprint::of(&package));
+        }
+
+        Ok(fingerprints)
+    }
+
+    /// Delete packages from the cache by attribute path
+    pub fn delete_packages(&self, attr_paths: &[String]) -> Result<()> {
+        if attr_paths.is_empty() {
+            return Ok(());
+        }
+
+        let conn = self.conn()?;
+
+        let tx = conn.unchecked_transaction()
+            .context("Failed to start transaction")?;
+
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM packages WHERE attr_path = ?1")
+                .context("Failed to prepare delete query")?;
+
+            for attr_path in attr_paths {
+                stmt.execute(params![attr_path])
+                    .context("Failed to delete package")?;
+            }
+        }
+
+        tx.commit().context("Failed to commit transaction")?;
+
+        info!("Deleted {} packages from cache", attr_paths.len());
+        Ok(())
+    }
+
+    /// Get the summary of the last incremental update, if any
+    pub fn last_delta(&self) -> Result<Option<CacheDelta>> {
+        Ok(self.get_metadata("last_delta")?
+            .and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
     /// Get cache statistics
     pub fn stats(&self) -> Result<CacheStats> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
+        let conn = self.conn()?;
 
         let total_packages: i32 = conn.query_row(
             "SELECT COUNT(*) FROM packages",
@@ -212,12 +448,98 @@ impl PackageCache {
             |row| row.get(0)
         ).ok();
 
+        // Recent writes may still live in the WAL file
+        let wal_path = PathBuf::from(format!("{}-wal", self.db_path.display()));
+        let db_size = [&self.db_path, &wal_path].iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum();
+
         Ok(CacheStats {
             total_packages,
             last_updated,
+            db_size,
         })
     }
 
+    /// Get the path of the cache database
+    pub fn db_path(&self) -> &std::path::Path {
+        &self.db_path
+    }
+
+    /// Read a value from the metadata table
+    pub fn get_metadata(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn()?;
+
+        let value = conn.prepare_cached("SELECT value FROM metadata WHERE key = ?1")
+            .context("Failed to prepare metadata query")?
+            .query_row(params![key], |row| row.get(0));
+
+        match value {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Failed to read cache metadata"),
+        }
+    }
+
+    /// Write a value to the metadata table
+    pub fn set_metadata(&self, key: &str, value: &str) -> Result<()> {
+        let conn = self.conn()?;
+
+        conn.prepare_cached("INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)")
+            .context("Failed to prepare metadata update")?
+            .execute(params![key, value])
+            .context("Failed to write cache metadata")?;
+
+        Ok(())
+    }
+
+    /// Get the provenance of the last completed cache build
+    pub fn provenance(&self) -> Result<Option<CacheProvenance>> {
+        let source = self.get_metadata("nixpkgs_source")?
+            .and_then(|s| RevisionSource::parse(&s));
+        let revision = self.get_metadata("nixpkgs_revision")?;
+
+        let (Some(source), Some(revision)) = (source, revision) else {
+            return Ok(None);
+        };
+
+        let built_at = self.get_metadata("built_at")?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let package_count = self.get_metadata("package_count")?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        Ok(Some(CacheProvenance {
+            revision: NixpkgsRevision::new(source, revision),
+            built_at,
+            package_count,
+        }))
+    }
+
+    /// Record the provenance of a completed cache build
+    pub fn set_provenance(&self, provenance: &CacheProvenance) -> Result<()> {
+        self.set_metadata("nixpkgs_source", provenance.revision.source.as_str())?;
+        self.set_metadata("nixpkgs_revision", &provenance.revision.revision)?;
+        self.set_metadata("built_at", &provenance.built_at.to_string())?;
+        self.set_metadata("package_count", &provenance.package_count.to_string())?;
+        Ok(())
+    }
+
+    /// Check the database file for corruption
+    ///
+    /// Returns the problems SQLite found, or nothing if the database is sound.
+    pub fn integrity_check(&self) -> Result<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let problems = stmt.query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to check database integrity")?;
+
+        Ok(problems.into_iter().filter(|p| p != "ok").collect())
+    }
+
     /// Check if cache is empty
     pub fn is_empty(&self) -> bool {
         self.stats().map(|s| s.total_packages == 0).unwrap_or(true)
@@ -225,8 +547,7 @@ impl PackageCache {
 
     /// Clear all cached packages
     pub fn clear(&self) -> Result<()> {
-        let conn = Connection::open(&self.db_path)
-            .context("Failed to open database")?;
+        let conn = self.conn()?;
 
         conn.execute("DELETE FROM packages", [])
             .context("Failed to clear cache")?;
@@ -247,6 +568,16 @@ impl Default for PackageCache {
 pub struct CacheStats {
     pub total_packages: i32,
     pub last_updated: Option<i64>,
+    pub db_size: u64,  // Database file size in bytes
+}
+
+/// Initialized cache in a fresh temporary directory, for unit tests
+#[cfg(test)]
+pub(crate) fn temp_cache() -> (tempfile::TempDir, PackageCache) {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = PackageCache::with_path(dir.path().join("packages.db")).unwrap();
+    cache.initialize().unwrap();
+    (dir, cache)
 }
 
 #[cfg(test)]
@@ -265,6 +596,18 @@ mod tests {
         assert!(cache.initialize().is_ok());
     }
 
+    #[test]
+    fn test_integrity_check() {
+        let (dir, cache) = temp_cache();
+        assert!(cache.integrity_check().unwrap().is_empty());
+
+        // Garbage is not a database at all
+        let garbage = dir.path().join("garbage.db");
+        std::fs::write(&garbage, vec![0x42; 8192]).unwrap();
+        let opened = PackageCache::with_path(garbage).and_then(|cache| cache.integrity_check());
+        assert!(opened.is_err());
+    }
+
     #[test]
     fn test_search_empty_cache() {
         let cache = PackageCache::new().unwrap();
@@ -288,6 +631,7 @@ mod tests {
                 attr_path: "nixpkgs.hello".to_string(),
                 last_updated: chrono::Utc::now().timestamp(),
                 search_count: 0,
+                ..Default::default()
             },
         ];
 
@@ -297,4 +641,138 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].name, "hello");
     }
+
+    fn package(attr_path: &str, license: &str, unfree: bool, broken: bool) -> CachedPackage {
+        CachedPackage {
+            name: attr_path.rsplit('.').next().unwrap().to_string(),
+            version: "1.0".to_string(),
+            description: "test tool".to_string(),
+            attr_path: attr_path.to_string(),
+            last_updated: 0,
+            search_count: 0,
+            license: license.to_string(),
+            platforms: "x86_64-linux, aarch64-linux".to_string(),
+            unfree,
+            broken,
+            category: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_search_filtered() {
+        let (_dir, cache) = temp_cache();
+        cache.upsert_packages(&[
+            package("ripgrep", "MIT, Unlicense", false, false),
+            package("vscode", "unfree", true, false),
+            package("python3Packages.requests", "Apache-2.0", false, false),
+            package("oldtool", "MIT", false, true),
+        ]).unwrap();
+
+        let free = SearchFilters { free_only: true, ..Default::default() };
+        let results = cache.search_filtered("", 10, &free).unwrap();
+        assert!(results.iter().all(|p| !p.unfree));
+        assert_eq!(results.len(), 3);
+
+        let mit = SearchFilters {
+            license: Some("mit".to_string()),
+            exclude_broken: true,
+            ..Default::default()
+        };
+        let results = cache.search_filtered("", 10, &mit).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].attr_path, "ripgrep");
+
+        let prefix = SearchFilters {
+            attr_prefix: Some("python3Packages".to_string()),
+            ..Default::default()
+        };
+        let results = cache.search_filtered("", 10, &prefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "requests");
+
+        let platform = SearchFilters {
+            platform: Some("aarch64-darwin".to_string()),
+            ..Default::default()
+        };
+        assert!(cache.search_filtered("", 10, &platform).unwrap().is_empty());
+
+        // Wildcards in a filter value are matched literally
+        let wildcard = SearchFilters {
+            license: Some("%".to_string()),
+            ..Default::default()
+        };
+        assert!(cache.search_filtered("", 10, &wildcard).unwrap().is_empty());
+
+        let literal = SearchFilters {
+            platform: Some("x86_64-linux".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cache.search_filtered("", 10, &literal).unwrap().len(), 4);
+
+        let wildcard = SearchFilters {
+            platform: Some("x86_64-lin_x".to_string()),
+            ..Default::default()
+        };
+        assert!(cache.search_filtered("", 10, &wildcard).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_filtered_installed() {
+        let (_dir, cache) = temp_cache();
+        cache.upsert_packages(&[
+            package("ripgrep", "MIT", false, false),
+            package("jq", "MIT", false, false),
+        ]).unwrap();
+
+        let installed = SearchFilters {
+            installed: Some(true),
+            ..Default::default()
+        }.with_installed_names(vec!["jq".to_string()]);
+        let results = cache.search_filtered("", 10, &installed).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "jq");
+
+        let not_installed = SearchFilters {
+            installed: Some(false),
+            ..Default::default()
+        }.with_installed_names(vec!["jq".to_string()]);
+        let results = cache.search_filtered("", 10, &not_installed).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "ripgrep");
+    }
+
+    #[test]
+    fn test_find_package() {
+        let (_dir, cache) = temp_cache();
+        cache.upsert_packages(&[
+            package("nixpkgs.ripgrep", "MIT", false, false),
+            package("python3Packages.requests", "Apache-2.0", false, false),
+        ]).unwrap();
+
+        let found = cache.find_package("ripgrep").unwrap().unwrap();
+        assert_eq!(found.attr_path, "nixpkgs.ripgrep");
+
+        let found = cache.find_package("python3Packages.requests").unwrap().unwrap();
+        assert_eq!(found.name, "requests");
+
+        assert!(cache.find_package("rip").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_provenance_roundtrip() {
+        let (_dir, cache) = temp_cache();
+        assert!(cache.provenance().unwrap().is_none());
+
+        let provenance = CacheProvenance {
+            revision: NixpkgsRevision::new(RevisionSource::Channel, "24.11pre712345.abcdef123456"),
+            built_at: 1_700_000_000,
+            package_count: 42,
+        };
+        cache.set_provenance(&provenance).unwrap();
+
+        let stored = cache.provenance().unwrap().unwrap();
+        assert_eq!(stored.revision, provenance.revision);
+        assert_eq!(stored.built_at, 1_700_000_000);
+        assert_eq!(stored.package_count, 42);
+    }
 }
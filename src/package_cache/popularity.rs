@@ -0,0 +1,230 @@
+/// Local popularity learning
+///
+/// Ranking is learned from three separate signals: a package was shown in
+/// search results, explicitly selected (e.g. `nsfw info`), or installed.
+/// Each search spreads a single "shown" unit across its results, so broad
+/// queries no longer inflate hundreds of packages.
+///
+/// Counters decay with a half-life. Instead of rewriting every row over
+/// time, each event is stored scaled by `2^((t - EPOCH) / HALF_LIFE)`; all
+/// rows share the same decay factor at any instant, so ordering by the
+/// stored values equals ordering by decayed values. Nothing but attribute
+/// paths and counters is stored, the data never leaves the machine (it is
+/// excluded from cache exports), and `nsfw cache reset-stats` clears it.
+use super::PackageCache;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Time for a popularity signal to lose half of its weight
+pub const HALF_LIFE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Reference time for stored scores (keeps the scaled values small)
+const EPOCH: i64 = 1_700_000_000;
+
+/// Ranking expression for a `packages` row, higher is more popular
+pub(crate) const RANK_SQL: &str =
+    "COALESCE((SELECT installed * 10.0 + selected * 3.0 + shown
+               FROM popularity WHERE popularity.attr_path = packages.attr_path), 0)";
+
+/// A usage signal for a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageSignal {
+    /// Appeared in search results
+    Shown,
+    /// Explicitly picked by the user (e.g. `nsfw info`)
+    Selected,
+    /// Installed into the profile
+    Installed,
+}
+
+impl UsageSignal {
+    fn column(&self) -> &'static str {
+        match self {
+            UsageSignal::Shown => "shown",
+            UsageSignal::Selected => "selected",
+            UsageSignal::Installed => "installed",
+        }
+    }
+}
+
+/// Decayed popularity counters of a package, as of now
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PopularityStats {
+    pub shown: f64,
+    pub selected: f64,
+    pub installed: f64,
+}
+
+/// Scale factor for an event happening at `timestamp`
+fn event_weight(timestamp: i64) -> f64 {
+    2f64.powf((timestamp - EPOCH) as f64 / HALF_LIFE.as_secs() as f64)
+}
+
+impl PackageCache {
+    /// Create the popularity table
+    pub(crate) fn create_popularity_table(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS popularity (
+                attr_path TEXT PRIMARY KEY,
+                shown REAL NOT NULL DEFAULT 0,
+                selected REAL NOT NULL DEFAULT 0,
+                installed REAL NOT NULL DEFAULT 0
+            )",
+            [],
+        ).context("Failed to create popularity table")?;
+        Ok(())
+    }
+
+    /// Record that packages were shown together in one set of search results
+    pub(crate) fn record_shown(conn: &Connection, attr_paths: &[&str]) -> Result<()> {
+        if attr_paths.is_empty() {
+            return Ok(());
+        }
+
+        let amount = 1.0 / attr_paths.len() as f64;
+        for attr_path in attr_paths {
+            Self::bump(conn, attr_path, UsageSignal::Shown, amount)?;
+        }
+        Ok(())
+    }
+
+    /// Record a usage signal for a package
+    pub fn record_usage(&self, attr_path: &str, signal: UsageSignal) -> Result<()> {
+        let conn = self.conn()?;
+        Self::bump(&conn, attr_path, signal, 1.0)
+    }
+
+    /// Record a usage signal for a package given by name or attribute path
+    ///
+    /// Returns `false` if the package is not in the cache.
+    pub fn record_usage_by_name(&self, name: &str, signal: UsageSignal) -> Result<bool> {
+        match self.find_package(name)? {
+            Some(package) => {
+                self.record_usage(&package.attr_path, signal)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn bump(conn: &Connection, attr_path: &str, signal: UsageSignal, amount: f64) -> Result<()> {
+        let column = signal.column();
+        let sql = format!(
+            "INSERT INTO popularity (attr_path, {column}) VALUES (?1, ?2)
+             ON CONFLICT(attr_path) DO UPDATE SET {column} = {column} + ?2"
+        );
+
+        conn.prepare_cached(&sql)
+            .context("Failed to prepare popularity update")?
+            .execute(params![attr_path, amount * event_weight(chrono::Utc::now().timestamp())])
+            .context("Failed to update popularity")?;
+        Ok(())
+    }
+
+    /// Get the decayed popularity counters of a package
+    pub fn popularity(&self, attr_path: &str) -> Result<PopularityStats> {
+        let conn = self.conn()?;
+
+        let stored: Option<(f64, f64, f64)> = conn.prepare_cached(
+            "SELECT shown, selected, installed FROM popularity WHERE attr_path = ?1"
+        )?
+        .query_row(params![attr_path], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .optional()
+        .context("Failed to read popularity")?;
+
+        let decay = 1.0 / event_weight(chrono::Utc::now().timestamp());
+        Ok(stored
+            .map(|(shown, selected, installed)| PopularityStats {
+                shown: shown * decay,
+                selected: selected * decay,
+                installed: installed * decay,
+            })
+            .unwrap_or_default())
+    }
+
+    /// Attribute paths with the strongest popularity signals, most popular first
+    pub fn popular_attr_paths(&self, limit: usize) -> Result<Vec<String>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn.prepare_cached(
+            "SELECT attr_path FROM popularity
+             ORDER BY installed * 10.0 + selected * 3.0 + shown DESC
+             LIMIT ?1"
+        )?;
+        let attr_paths = stmt.query_map(params![limit as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .context("Failed to read popular packages")?;
+
+        Ok(attr_paths)
+    }
+
+    /// Forget all popularity data
+    pub fn reset_popularity(&self) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM popularity", [])
+            .context("Failed to clear popularity data")?;
+        // Counter from before popularity signals were split
+        conn.execute("UPDATE packages SET search_count = 0", [])
+            .context("Failed to clear search counts")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_cache::{temp_cache, CachedPackage};
+
+    fn seeded_cache() -> (tempfile::TempDir, PackageCache) {
+        let (dir, cache) = temp_cache();
+        cache.upsert_packages(&["jq", "jo", "jless"].map(|name| CachedPackage {
+            name: name.to_string(),
+            description: "json tool".to_string(),
+            attr_path: format!("nixpkgs.{}", name),
+            ..Default::default()
+        })).unwrap();
+        (dir, cache)
+    }
+
+    #[test]
+    fn test_shown_is_split_across_results() {
+        let (_dir, cache) = seeded_cache();
+        cache.search("json", 10).unwrap();
+
+        let stats = cache.popularity("nixpkgs.jq").unwrap();
+        assert!((stats.shown - 1.0 / 3.0).abs() < 0.01);
+        assert_eq!(stats.selected, 0.0);
+    }
+
+    #[test]
+    fn test_signals_drive_ranking() {
+        let (_dir, cache) = seeded_cache();
+        cache.record_usage("nixpkgs.jo", UsageSignal::Selected).unwrap();
+        assert!(cache.record_usage_by_name("jless", UsageSignal::Installed).unwrap());
+        assert!(!cache.record_usage_by_name("missing", UsageSignal::Installed).unwrap());
+
+        let results = cache.search("json", 10).unwrap();
+        let names: Vec<&str> = results.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["jless", "jo", "jq"]);
+        assert_eq!(cache.popular_attr_paths(1).unwrap(), vec!["nixpkgs.jless".to_string()]);
+    }
+
+    #[test]
+    fn test_decay_halves_after_half_life() {
+        let now = chrono::Utc::now().timestamp();
+        let ratio = event_weight(now) / event_weight(now - HALF_LIFE.as_secs() as i64);
+        assert!((ratio - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reset_popularity() {
+        let (_dir, cache) = seeded_cache();
+        cache.record_usage("nixpkgs.jq", UsageSignal::Installed).unwrap();
+        cache.reset_popularity().unwrap();
+
+        assert_eq!(cache.popularity("nixpkgs.jq").unwrap(), PopularityStats::default());
+        assert!(cache.popular_attr_paths(10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+/// Cache provenance tracking
+///
+/// Records which nixpkgs revision the package cache was built from, so the
+/// cache is rebuilt when nixpkgs changes rather than on a wall-clock timer.
+use crate::nix_ops::backend::NIX_GLOBAL_ARGS;
+use crate::wsl2::WSL2Bridge;
+use anyhow::{Result, Context, anyhow};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::fmt;
+
+/// Where the nixpkgs revision was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionSource {
+    /// `<nixpkgs>` from the user's channels (what `nix-env` evaluates)
+    Channel,
+    /// The `nixpkgs` flake from the registry / lock file
+    Flake,
+}
+
+impl RevisionSource {
+    /// Stable identifier stored in the metadata table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Channel => "channel",
+            Self::Flake => "flake",
+        }
+    }
+
+    /// Parse a stored identifier
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "channel" => Some(Self::Channel),
+            "flake" => Some(Self::Flake),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RevisionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A specific nixpkgs revision
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NixpkgsRevision {
+    pub source: RevisionSource,
+    pub revision: String,
+}
+
+impl NixpkgsRevision {
+    /// Create a new revision record
+    pub fn new(source: RevisionSource, revision: impl Into<String>) -> Self {
+        Self {
+            source,
+            revision: revision.into(),
+        }
+    }
+
+    /// The git commit of this revision, if it names one
+    ///
+    /// Flake revisions are full hashes; channel versions end in an
+    /// abbreviated one (e.g. "24.11pre712345.abcdef123456").
+    pub fn commit(&self) -> Option<&str> {
+        let commit = match self.source {
+            RevisionSource::Flake => self.revision.as_str(),
+            RevisionSource::Channel => self.revision.rsplit('.').next()?,
+        };
+
+        let is_hash = commit.len() >= 7 && commit.chars().all(|c| c.is_ascii_hexdigit());
+        is_hash.then_some(commit)
+    }
+
+    /// Detect the nixpkgs revision `nix-env` will evaluate
+    ///
+    /// Tries the channel first (that is what `nix-env -qa` reads), then falls
+    /// back to the flake registry entry for `nixpkgs`.
+    pub fn detect<B: WSL2Bridge>(bridge: &B) -> Result<Self> {
+        Self::detect_channel(bridge)
+            .or_else(|channel_err| {
+                log::debug!("Channel revision unavailable: {}", channel_err);
+                Self::detect_flake(bridge)
+            })
+            .context("Could not determine nixpkgs revision")
+    }
+
+    /// Read `lib.version` from `<nixpkgs>` (e.g. "24.11pre712345.abcdef123456")
+    fn detect_channel<B: WSL2Bridge>(bridge: &B) -> Result<Self> {
+        let output = bridge.execute(
+            "nix-instantiate",
+            &["--eval", "--json", "-E", "(import <nixpkgs> {}).lib.version"],
+        )?;
+
+        if !output.is_success() {
+            return Err(anyhow!("nix-instantiate failed: {}", output.stderr.trim()));
+        }
+
+        let version: String = serde_json::from_str(output.stdout.trim())
+            .context("Failed to parse nixpkgs version")?;
+
+        Ok(Self::new(RevisionSource::Channel, version))
+    }
+
+    /// Read the locked revision of the `nixpkgs` flake
+    fn detect_flake<B: WSL2Bridge>(bridge: &B) -> Result<Self> {
+        let args: Vec<&str> = NIX_GLOBAL_ARGS.into_iter()
+            .chain(["flake", "metadata", "nixpkgs", "--json"])
+            .collect();
+        let output = bridge.execute("nix", &args)?;
+
+        if !output.is_success() {
+            return Err(anyhow!("nix flake metadata failed: {}", output.stderr.trim()));
+        }
+
+        let json: Value = serde_json::from_str(&output.stdout)
+            .context("Failed to parse flake metadata")?;
+
+        let revision = json.get("revision")
+            .or_else(|| json.get("locked").and_then(|l| l.get("rev")))
+            .or_else(|| json.get("locked").and_then(|l| l.get("narHash")))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Flake metadata has no revision"))?;
+
+        Ok(Self::new(RevisionSource::Flake, revision))
+    }
+}
+
+impl fmt::Display for NixpkgsRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.revision, self.source)
+    }
+}
+
+/// Provenance of a completed cache build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheProvenance {
+    /// nixpkgs revision the cache was built from
+    pub revision: NixpkgsRevision,
+
+    /// When the build finished (Unix timestamp)
+    pub built_at: i64,
+
+    /// Number of packages in the cache after the build
+    pub package_count: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wsl2::{MockWSL2Bridge, CommandOutput};
+
+    #[test]
+    fn test_detect_channel_revision() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix-instantiate --eval --json -E (import <nixpkgs> {}).lib.version".to_string(),
+            CommandOutput::new("\"24.11pre712345.abcdef123456\"\n".to_string(), "".to_string(), 0),
+        );
+
+        let revision = NixpkgsRevision::detect(&bridge).unwrap();
+        assert_eq!(revision.source, RevisionSource::Channel);
+        assert_eq!(revision.revision, "24.11pre712345.abcdef123456");
+        assert_eq!(revision.commit(), Some("abcdef123456"));
+    }
+
+    #[test]
+    fn test_detect_falls_back_to_flake() {
+        let mut bridge = MockWSL2Bridge::new();
+        bridge.set_response(
+            "nix flake metadata nixpkgs --json".to_string(),
+            CommandOutput::new(r#"{"revision":"0123abcd","locked":{"rev":"0123abcd"}}"#.to_string(), "".to_string(), 0),
+        );
+
+        let revision = NixpkgsRevision::detect(&bridge).unwrap();
+        assert_eq!(revision.source, RevisionSource::Flake);
+        assert_eq!(revision.revision, "0123abcd");
+        assert_eq!(revision.commit(), Some("0123abcd"));
+        assert_eq!(NixpkgsRevision::new(RevisionSource::Channel, "24.05.1").commit(), None);
+    }
+
+    #[test]
+    fn test_detect_fails_without_nix() {
+        let bridge = MockWSL2Bridge::new();
+        assert!(NixpkgsRevision::detect(&bridge).is_err());
+    }
+}
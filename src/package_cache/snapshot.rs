@@ -0,0 +1,54 @@
+/// Offline snapshot of the installed profile
+///
+/// The raw output of the last successful `nix profile list --json` is kept
+/// in the cache database, so `nsfw list` can still answer when WSL2 is
+/// stopped or the network is down.
+use super::PackageCache;
+use anyhow::Result;
+
+/// Last known `nix profile list --json` output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileSnapshot {
+    /// Raw JSON as printed by Nix
+    pub json: String,
+
+    /// When the snapshot was taken (Unix timestamp)
+    pub taken_at: i64,
+}
+
+impl PackageCache {
+    /// Get the last stored profile snapshot
+    pub fn profile_snapshot(&self) -> Result<Option<ProfileSnapshot>> {
+        let Some(json) = self.get_metadata("profile_snapshot")? else {
+            return Ok(None);
+        };
+
+        let taken_at = self.get_metadata("profile_snapshot_at")?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        Ok(Some(ProfileSnapshot { json, taken_at }))
+    }
+
+    /// Replace the profile snapshot with fresh `nix profile list --json` output
+    pub fn save_profile_snapshot(&self, json: &str) -> Result<()> {
+        self.set_metadata("profile_snapshot", json)?;
+        self.set_metadata("profile_snapshot_at", &chrono::Utc::now().timestamp().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::package_cache::temp_cache;
+
+    #[test]
+    fn test_profile_snapshot_roundtrip() {
+        let (_dir, cache) = temp_cache();
+        assert!(cache.profile_snapshot().unwrap().is_none());
+
+        cache.save_profile_snapshot(r#"{"elements":{}}"#).unwrap();
+        let snapshot = cache.profile_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.json, r#"{"elements":{}}"#);
+        assert!(snapshot.taken_at > 0);
+    }
+}
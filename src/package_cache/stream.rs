@@ -0,0 +1,116 @@
+/// Streaming parser for `nix-env -qaP --json` output
+///
+/// The full nixpkgs package set is several hundred MB of JSON. Instead of
+/// reading it into a `String` and a `HashMap`, entries are decoded one at a
+/// time from a reader and handed to a callback, so memory stays bounded by
+/// the size of a single entry.
+use anyhow::{Result, anyhow};
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+use std::io::{BufReader, Read};
+
+/// Call `on_entry` for each `attr_path => package` entry of a JSON object
+///
+/// Returns the number of entries processed. Stops at the first error
+/// returned by `on_entry`.
+pub fn for_each_package<R, F>(reader: R, on_entry: F) -> Result<usize>
+where
+    R: Read,
+    F: FnMut(String, Value) -> Result<()>,
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+
+    let mut visitor = EntryVisitor {
+        on_entry,
+        count: 0,
+        callback_error: None,
+    };
+
+    let result = (&mut deserializer).deserialize_map(&mut visitor);
+
+    // A callback failure surfaces as a serde error; report the original instead
+    if let Some(e) = visitor.callback_error.take() {
+        return Err(e);
+    }
+    result.map_err(|e| anyhow!("Failed to parse nix-env JSON output: {}", e))?;
+
+    deserializer.end()
+        .map_err(|e| anyhow!("Trailing data after nix-env JSON output: {}", e))?;
+
+    Ok(visitor.count)
+}
+
+/// Visitor that forwards map entries to a callback as they are decoded
+struct EntryVisitor<F> {
+    on_entry: F,
+    count: usize,
+    callback_error: Option<anyhow::Error>,
+}
+
+impl<'de, F> Visitor<'de> for &mut EntryVisitor<F>
+where
+    F: FnMut(String, Value) -> Result<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object of packages keyed by attribute path")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while let Some((attr_path, package)) = map.next_entry::<String, Value>()? {
+            if let Err(e) = (self.on_entry)(attr_path, package) {
+                self.callback_error = Some(e);
+                return Err(serde::de::Error::custom("package callback failed"));
+            }
+            self.count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_package() {
+        let json = r#"{"nixpkgs.hello":{"pname":"hello"},"nixpkgs.jq":{"pname":"jq"}}"#;
+
+        let mut seen = Vec::new();
+        let count = for_each_package(json.as_bytes(), |attr_path, package| {
+            seen.push((attr_path, package["pname"].as_str().unwrap().to_string()));
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(seen[0], ("nixpkgs.hello".to_string(), "hello".to_string()));
+        assert_eq!(seen[1], ("nixpkgs.jq".to_string(), "jq".to_string()));
+    }
+
+    #[test]
+    fn test_for_each_package_callback_error() {
+        let json = r#"{"a":{},"b":{}}"#;
+
+        let err = for_each_package(json.as_bytes(), |attr_path, _| {
+            if attr_path == "b" {
+                Err(anyhow!("stop at b"))
+            } else {
+                Ok(())
+            }
+        }).unwrap_err();
+
+        assert_eq!(err.to_string(), "stop at b");
+    }
+
+    #[test]
+    fn test_for_each_package_rejects_array() {
+        let result = for_each_package("[1, 2]".as_bytes(), |_, _| Ok(()));
+        assert!(result.is_err());
+    }
+}
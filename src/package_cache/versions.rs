@@ -0,0 +1,192 @@
+/// Version index for installing historical package versions
+///
+/// Every cache build records which version of each package the indexed
+/// nixpkgs commit contains. Over time this accumulates the versions seen on
+/// this machine, so `nsfw install terraform@1.5.7` can be pinned to a commit
+/// that actually has that version. Other sources of version history can
+/// plug in through `VersionResolver`.
+use super::PackageCache;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// A package version found in a specific nixpkgs commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedVersion {
+    /// Attribute path without channel prefix (e.g. "python3Packages.requests")
+    pub attr_path: String,
+
+    /// Exact version in that commit
+    pub version: String,
+
+    /// nixpkgs git commit (possibly abbreviated)
+    pub revision: String,
+}
+
+/// Resolves a requested package version to a nixpkgs commit
+pub trait VersionResolver {
+    /// Find a commit with the version, or a version starting with it
+    ///
+    /// `1.5` matches `1.5` and `1.5.7` (the newest wins), not `1.50`.
+    fn resolve(&self, attr_path: &str, version: &str) -> Result<Option<ResolvedVersion>>;
+
+    /// All versions of a package the resolver knows about, newest first
+    fn known_versions(&self, attr_path: &str) -> Result<Vec<String>>;
+}
+
+/// Check if `version` is `requested` or one of its point releases
+fn matches_version(version: &str, requested: &str) -> bool {
+    version == requested
+        || version.strip_prefix(requested).is_some_and(|rest| rest.starts_with(['.', '-']))
+}
+
+/// Compare versions component by component, numerically where possible
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| v.split(['.', '-']).map(str::to_string).collect::<Vec<_>>();
+    let (a, b) = (split(a), split(b));
+
+    for (x, y) in a.iter().zip(&b) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl PackageCache {
+    /// Create the version index table
+    pub(crate) fn create_versions_table(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS package_versions (
+                attr_path TEXT NOT NULL,
+                version TEXT NOT NULL,
+                revision TEXT NOT NULL,
+                indexed_at INTEGER NOT NULL,
+                PRIMARY KEY (attr_path, version)
+            )",
+            [],
+        ).context("Failed to create version index table")?;
+        Ok(())
+    }
+
+    /// Record the versions of all cached packages as found in `revision`
+    ///
+    /// Versions that are already indexed keep their earlier commit, which
+    /// contains them just as well.
+    pub fn index_versions(&self, revision: &str) -> Result<usize> {
+        let conn = self.conn()?;
+
+        let indexed = conn.execute(
+            "INSERT OR IGNORE INTO package_versions (attr_path, version, revision, indexed_at)
+             SELECT CASE
+                        WHEN attr_path LIKE 'nixpkgs.%' THEN substr(attr_path, 9)
+                        WHEN attr_path LIKE 'nixos.%' THEN substr(attr_path, 7)
+                        ELSE attr_path
+                    END,
+                    version, ?1, ?2
+             FROM packages
+             WHERE version != ''",
+            params![revision, chrono::Utc::now().timestamp()],
+        ).context("Failed to index package versions")?;
+
+        Ok(indexed)
+    }
+
+    fn indexed_versions(&self, attr_path: &str) -> Result<Vec<ResolvedVersion>> {
+        let conn = self.conn()?;
+
+        let mut stmt = conn.prepare_cached(
+            "SELECT version, revision FROM package_versions WHERE attr_path = ?1"
+        )?;
+        let mut versions = stmt.query_map(params![attr_path], |row| {
+            Ok(ResolvedVersion {
+                attr_path: attr_path.to_string(),
+                version: row.get(0)?,
+                revision: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("Failed to read version index")?;
+
+        versions.sort_by(|a, b| compare_versions(&b.version, &a.version));
+        Ok(versions)
+    }
+}
+
+impl VersionResolver for PackageCache {
+    fn resolve(&self, attr_path: &str, version: &str) -> Result<Option<ResolvedVersion>> {
+        Ok(self.indexed_versions(attr_path)?
+            .into_iter()
+            .find(|v| matches_version(&v.version, version)))
+    }
+
+    fn known_versions(&self, attr_path: &str) -> Result<Vec<String>> {
+        Ok(self.indexed_versions(attr_path)?
+            .into_iter()
+            .map(|v| v.version)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_cache::{temp_cache, CachedPackage};
+
+    fn cache_with(versions: &[(&str, &str)]) -> (tempfile::TempDir, PackageCache) {
+        let (dir, cache) = temp_cache();
+        cache.upsert_packages(&versions.iter().map(|(attr_path, version)| CachedPackage {
+            name: attr_path.rsplit('.').next().unwrap().to_string(),
+            version: version.to_string(),
+            attr_path: attr_path.to_string(),
+            ..Default::default()
+        }).collect::<Vec<_>>()).unwrap();
+        (dir, cache)
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.10.0", "1.9.2"), Ordering::Greater);
+        assert_eq!(compare_versions("1.5", "1.5.7"), Ordering::Less);
+        assert_eq!(compare_versions("2.0", "2.0"), Ordering::Equal);
+        assert!(matches_version("1.5.7", "1.5"));
+        assert!(!matches_version("1.50.0", "1.5"));
+    }
+
+    #[test]
+    fn test_resolve_from_index() {
+        let (_dir, cache) = cache_with(&[("nixpkgs.terraform", "1.5.7")]);
+        assert_eq!(cache.index_versions("aaaaaaaaaaaa").unwrap(), 1);
+
+        // A later build of a newer nixpkgs
+        cache.upsert_packages(&[CachedPackage {
+            name: "terraform".to_string(),
+            version: "1.6.2".to_string(),
+            attr_path: "nixpkgs.terraform".to_string(),
+            ..Default::default()
+        }]).unwrap();
+        cache.index_versions("bbbbbbbbbbbb").unwrap();
+
+        let resolved = cache.resolve("terraform", "1.5").unwrap().unwrap();
+        assert_eq!(resolved.version, "1.5.7");
+        assert_eq!(resolved.revision, "aaaaaaaaaaaa");
+        assert_eq!(cache.resolve("terraform", "1").unwrap().unwrap().revision, "bbbbbbbbbbbb");
+        assert!(cache.resolve("terraform", "1.4").unwrap().is_none());
+        assert_eq!(cache.known_versions("terraform").unwrap(), vec!["1.6.2", "1.5.7"]);
+    }
+
+    #[test]
+    fn test_reindexing_keeps_first_revision() {
+        let (_dir, cache) = cache_with(&[("nixpkgs.python3Packages.requests", "2.31.0")]);
+        cache.index_versions("aaaaaaaaaaaa").unwrap();
+        assert_eq!(cache.index_versions("bbbbbbbbbbbb").unwrap(), 0);
+
+        let resolved = cache.resolve("python3Packages.requests", "2.31.0").unwrap().unwrap();
+        assert_eq!(resolved.revision, "aaaaaaaaaaaa");
+    }
+}
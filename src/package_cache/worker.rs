@@ -0,0 +1,294 @@
+/// Detached background cache refresh
+///
+/// A refresh runs in a separate `nsfw cache refresh-worker` process so it
+/// outlives the CLI invocation that started it. An OS file lock on a file
+/// next to the database prevents duplicate builds; the OS releases it when
+/// the worker exits, even if it crashes. Build progress is written to the
+/// database so `nsfw cache status` can report it.
+use super::PackageCache;
+use anyhow::{Result, Context};
+use log::{debug, info};
+use serde::{Serialize, Deserialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Minimum time between background staleness checks
+pub const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// State of the most recent cache build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum BuildState {
+    /// No build running
+    Idle,
+    /// A build is in progress
+    Building { percent: u8, started_at: i64 },
+    /// The last build failed
+    Failed { error: String },
+}
+
+impl PackageCache {
+    /// Get the state of the most recent cache build
+    pub fn build_state(&self) -> Result<BuildState> {
+        let state = match self.get_metadata("build_state")?.as_deref() {
+            Some("building") => BuildState::Building {
+                percent: self.get_metadata("build_progress")?
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+                started_at: self.get_metadata("build_started_at")?
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+            },
+            Some("failed") => BuildState::Failed {
+                error: self.get_metadata("build_error")?.unwrap_or_default(),
+            },
+            _ => BuildState::Idle,
+        };
+
+        Ok(state)
+    }
+
+    /// Record the state of the current cache build
+    pub fn set_build_state(&self, state: &BuildState) -> Result<()> {
+        match state {
+            BuildState::Idle => {
+                self.set_metadata("build_state", "idle")?;
+            }
+            BuildState::Building { percent, started_at } => {
+                self.set_metadata("build_state", "building")?;
+                self.set_metadata("build_progress", &percent.to_string())?;
+                self.set_metadata("build_started_at", &started_at.to_string())?;
+            }
+            BuildState::Failed { error } => {
+                self.set_metadata("build_state", "failed")?;
+                self.set_metadata("build_error", error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the progress of a running build
+    pub fn set_build_progress(&self, percent: u8) -> Result<()> {
+        self.set_metadata("build_progress", &percent.min(100).to_string())
+    }
+
+    /// Check if enough time has passed since the last background refresh check
+    pub fn refresh_check_due(&self) -> Result<bool> {
+        let last_check: Option<i64> = self.get_metadata("last_refresh_check")?
+            .and_then(|v| v.parse().ok());
+
+        Ok(match last_check {
+            Some(last_check) => {
+                chrono::Utc::now().timestamp() - last_check >= REFRESH_CHECK_INTERVAL.as_secs() as i64
+            }
+            None => true,
+        })
+    }
+
+    /// Record that a background refresh check just ran
+    pub fn mark_refresh_checked(&self) -> Result<()> {
+        self.set_metadata("last_refresh_check", &chrono::Utc::now().timestamp().to_string())
+    }
+}
+
+/// Exclusive lock on cache builds, released when dropped
+///
+/// Held as an OS advisory lock on the lock file, so it is released when
+/// the holding process exits, however it exits. The file itself stays in
+/// place and records the PID of the last holder for diagnostics.
+#[derive(Debug)]
+pub struct BuildLock {
+    file: File,
+}
+
+impl BuildLock {
+    /// Path of the lock file for a cache database
+    pub fn path_for(cache: &PackageCache) -> PathBuf {
+        Self::path_for_db(cache.db_path())
+    }
+
+    fn path_for_db(db_path: &Path) -> PathBuf {
+        db_path.with_extension("lock")
+    }
+
+    /// Try to take the build lock
+    ///
+    /// Returns `None` if another process holds it.
+    pub fn acquire(cache: &PackageCache) -> Result<Option<Self>> {
+        Self::acquire_at(&Self::path_for(cache))
+    }
+
+    /// Try to take the build lock of a database that may not open
+    pub fn acquire_for_db(db_path: &Path) -> Result<Option<Self>> {
+        Self::acquire_at(&Self::path_for_db(db_path))
+    }
+
+    fn acquire_at(path: &Path) -> Result<Option<Self>> {
+        let mut file = open_lock_file(path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => return Err(e).context("Failed to lock cache build lock"),
+        }
+
+        file.set_len(0).context("Failed to write cache build lock")?;
+        writeln!(file, "{}", std::process::id())
+            .context("Failed to write cache build lock")?;
+        debug!("Acquired cache build lock {}", path.display());
+        Ok(Some(Self { file }))
+    }
+
+    /// Check if another process currently holds the lock
+    pub fn is_held(cache: &PackageCache) -> bool {
+        Self::is_held_at(&Self::path_for(cache))
+    }
+
+    fn is_held_at(path: &Path) -> bool {
+        if !path.exists() {
+            return false;
+        }
+
+        // Taking the lock and releasing it right away tells if anyone has it
+        match open_lock_file(path).map(|file| file.try_lock()) {
+            Ok(Ok(())) => false,
+            Ok(Err(TryLockError::WouldBlock)) => true,
+            Ok(Err(TryLockError::Error(e))) | Err(e) => {
+                debug!("Could not check cache build lock: {}", e);
+                false
+            }
+        }
+    }
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock; unlock explicitly to not rely on it
+        if let Err(e) = self.file.unlock() {
+            debug!("Failed to release cache build lock: {}", e);
+        }
+    }
+}
+
+/// Open the lock file, creating it if needed, without truncating it
+fn open_lock_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+}
+
+/// Start a detached `nsfw cache refresh-worker` process
+///
+/// The worker keeps running after this process exits. Returns `false`
+/// without spawning if a build is already running.
+pub fn spawn_refresh_worker(cache: &PackageCache) -> Result<bool> {
+    if BuildLock::is_held(cache) {
+        debug!("Cache build already running, not spawning a worker");
+        return Ok(false);
+    }
+
+    let exe = std::env::current_exe()
+        .context("Failed to locate the nsfw executable")?;
+
+    let mut command = Command::new(exe);
+    command
+        .args(["cache", "refresh-worker"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    detach(&mut command);
+
+    command.spawn()
+        .context("Failed to start background cache worker")?;
+
+    info!("Started background cache refresh worker");
+    Ok(true)
+}
+
+/// Detach a child so it survives the parent console and Ctrl+C
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP | CREATE_NO_WINDOW);
+}
+
+/// Detach a child so it survives the parent console and Ctrl+C
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // Own process group: the terminal's SIGINT/SIGHUP no longer reach it
+    command.process_group(0);
+}
+
+#[cfg(not(any(windows, unix)))]
+fn detach(_command: &mut Command) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_cache::temp_cache;
+
+    #[test]
+    fn test_build_lock_is_exclusive() {
+        let (_dir, cache) = temp_cache();
+
+        let lock = BuildLock::acquire(&cache).unwrap();
+        assert!(lock.is_some());
+        assert!(BuildLock::is_held(&cache));
+        assert!(BuildLock::acquire(&cache).unwrap().is_none());
+
+        drop(lock);
+        assert!(!BuildLock::is_held(&cache));
+        assert!(BuildLock::acquire(&cache).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_build_lock_ignores_file_age() {
+        let (_dir, cache) = temp_cache();
+        let path = BuildLock::path_for(&cache);
+
+        // A lock file left behind by a crashed worker is not held
+        std::fs::write(&path, "999999\n").unwrap();
+        assert!(!BuildLock::is_held(&cache));
+
+        // A long build keeps its lock however old the file is
+        let lock = BuildLock::acquire(&cache).unwrap().unwrap();
+        let two_hours_ago = std::time::SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        File::options().write(true).open(&path).unwrap().set_modified(two_hours_ago).unwrap();
+        assert!(BuildLock::is_held(&cache));
+        assert!(BuildLock::acquire(&cache).unwrap().is_none());
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_build_state_roundtrip() {
+        let (_dir, cache) = temp_cache();
+        assert_eq!(cache.build_state().unwrap(), BuildState::Idle);
+
+        cache.set_build_state(&BuildState::Building { percent: 0, started_at: 100 }).unwrap();
+        cache.set_build_progress(42).unwrap();
+        assert_eq!(cache.build_state().unwrap(), BuildState::Building { percent: 42, started_at: 100 });
+
+        cache.set_build_state(&BuildState::Failed { error: "nix-env failed".to_string() }).unwrap();
+        assert_eq!(cache.build_state().unwrap(), BuildState::Failed { error: "nix-env failed".to_string() });
+    }
+
+    #[test]
+    fn test_refresh_check_interval() {
+        let (_dir, cache) = temp_cache();
+        assert!(cache.refresh_check_due().unwrap());
+
+        cache.mark_refresh_checked().unwrap();
+        assert!(!cache.refresh_check_due().unwrap());
+    }
+}
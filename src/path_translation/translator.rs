@@ -28,6 +28,7 @@ pub enum PathType {
 /// // Linux → Windows
 /// let win_path = translator.to_windows("/mnt/c/Users/John/file.txt")?;
 /// assert_eq!(win_path, "C:\\Users\\John\\file.txt");
+/// # Ok::<(), anyhow::Error>(())
 /// ```
 pub struct PathTranslator {
     /// Whether to preserve Nix store paths (default: true)
@@ -53,6 +54,8 @@ impl PathTranslator {
     /// # Examples
     ///
     /// ```
+    /// use nsfw::path_translation::{PathTranslator, PathType};
+    ///
     /// let translator = PathTranslator::new();
     /// assert_eq!(translator.detect_type("C:\\Users"), PathType::Windows);
     /// assert_eq!(translator.detect_type("/mnt/c/Users"), PathType::Linux);
@@ -1,10 +1,12 @@
 //! Linux distribution installer for WSL2
 
 use anyhow::{Result, Context, bail};
-use std::process::Command;
+use std::path::Path;
 use crate::ui::{OutputFormatter, MessageType};
+use super::runner::CommandRunner;
+use super::sources::LocalFile;
 
-pub fn install_distro(distro: &str) -> Result<()> {
+pub fn install_distro(runner: &dyn CommandRunner, distro: &str) -> Result<()> {
     eprintln!();
     eprintln!("{}", OutputFormatter::format_message(
         MessageType::Info,
@@ -12,14 +14,12 @@ pub fn install_distro(distro: &str) -> Result<()> {
     ));
 
     // Use 'wsl --install -d <distro>' to install specific distribution
-    let output = Command::new("wsl")
-        .args(&["--install", "-d", distro])
-        .output()
+    let output = runner.run("wsl", &["--install", "-d", distro])
         .context("Failed to install Linux distribution")?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        
+    if !output.is_success() {
+        let stderr = &output.stderr;
+
         // Check if distro is already installed
         if stderr.contains("already installed") {
             eprintln!("{}", OutputFormatter::format_message(
@@ -39,3 +39,36 @@ pub fn install_distro(distro: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Register a distribution from a local image with `wsl --import`
+///
+/// For machines without access to the Microsoft Store. The image is checked
+/// against its checksum first; its disk is stored in `install_dir`.
+pub fn import_distro(runner: &dyn CommandRunner, distro: &str, image: &LocalFile, install_dir: &Path) -> Result<()> {
+    eprintln!();
+    eprintln!("{}", OutputFormatter::format_message(
+        MessageType::Info,
+        &format!("📦 Importing {} from {}...", distro, image.path.display())
+    ));
+
+    image.verify()?;
+
+    std::fs::create_dir_all(install_dir)
+        .with_context(|| format!("Failed to create {}", install_dir.display()))?;
+
+    let install_dir = install_dir.to_string_lossy();
+    let image_path = image.path.to_string_lossy();
+    let output = runner.run("wsl", &["--import", distro, &install_dir, &image_path, "--version", "2"])
+        .context("Failed to import Linux distribution")?;
+
+    if !output.is_success() {
+        bail!("Failed to import {}: {}", distro, output.stderr.trim());
+    }
+
+    eprintln!("{}", OutputFormatter::format_message(
+        MessageType::Success,
+        &format!("✓ {} imported successfully", distro)
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,922 @@
+//! System health checks
+//!
+//! `nsfw doctor` runs the checks the setup wizard relies on, from WSL2 down
+//! to the package cache, and reports each as passed, warned or failed with a
+//! hint on how to fix it. Problems NSFW can fix itself come with a `Repair`.
+//!
+//! Checks inside Linux go through the `NixBackend`, so they run natively on
+//! Linux and through WSL2 on Windows alike.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::nix_ops::{NixBackend, NixError, StoreMaintenance};
+use crate::package_cache::{BuildLock, PackageCache};
+use super::runner::{CommandRunner, SystemRunner};
+use super::wsl_detector::{self, WslDistro};
+
+/// Oldest Nix with the commands NSFW uses, including `nix profile list --json`
+const MIN_NIX_VERSION: (u32, u32) = (2, 17);
+
+/// Free space on /nix below which installs are likely to fail
+const MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Free space on /nix below which a warning is shown
+const LOW_FREE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Clock difference between Windows and WSL2 tolerated without a warning
+const CLOCK_SKEW_WARN_SECS: i64 = 60;
+
+/// Clock difference that breaks TLS and binary cache signatures
+const CLOCK_SKEW_FAIL_SECS: i64 = 300;
+
+/// Experimental features NSFW needs
+const REQUIRED_FEATURES: [&str; 2] = ["nix-command", "flakes"];
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    /// The check does not apply or depends on one that failed
+    Skip,
+}
+
+/// An automated fix for a failed check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Repair {
+    /// Boot the WSL distribution with systemd
+    EnableSystemd,
+
+    /// Start the Nix daemon
+    StartDaemon { systemd: bool },
+
+    /// Add the user to the `nix-users` group
+    JoinNixUsers { user: String },
+
+    /// Enable experimental features in the user's `nix.conf`
+    EnableFeatures { missing: Vec<String> },
+
+    /// Add nixpkgs to the user's flake registry
+    AddRegistry,
+
+    /// Delete store paths nothing refers to
+    CollectGarbage,
+
+    /// Delete the package cache database so it is rebuilt
+    ///
+    /// Package popularity and the version index go with it; the index
+    /// only covers nixpkgs revisions the cache was built from, so older
+    /// versions cannot be indexed again.
+    ResetPackageCache,
+
+    /// Set the WSL clock from the hardware clock
+    SyncClock,
+}
+
+impl Repair {
+    /// What the repair does, for confirmation prompts
+    pub fn description(&self) -> String {
+        match self {
+            Repair::EnableSystemd => "Enable systemd in /etc/wsl.conf".to_string(),
+            Repair::StartDaemon { .. } => "Start nix-daemon".to_string(),
+            Repair::JoinNixUsers { user } => format!("Add {} to the nix-users group", user),
+            Repair::EnableFeatures { missing } => format!("Enable {} in ~/.config/nix/nix.conf", missing.join(" and ")),
+            Repair::AddRegistry => "Add nixpkgs-unstable to the flake registry".to_string(),
+            Repair::CollectGarbage => "Delete unreferenced store paths".to_string(),
+            Repair::ResetPackageCache => {
+                "Delete the package cache so it is rebuilt, losing learned package popularity \
+                 and the older versions 'nsfw install pkg@version' can find".to_string()
+            }
+            Repair::SyncClock => "Set the WSL clock from the hardware clock".to_string(),
+        }
+    }
+
+    /// What the user still has to do after the repair, if anything
+    pub fn follow_up(&self) -> Option<&'static str> {
+        match self {
+            Repair::EnableSystemd => Some("Run 'wsl --shutdown' for the change to take effect"),
+            Repair::JoinNixUsers { .. } => Some("Group membership applies to new shells; run 'wsl --shutdown' to restart WSL"),
+            Repair::ResetPackageCache => Some("Run 'nsfw update' to rebuild the package cache"),
+            _ => None,
+        }
+    }
+
+    /// Whether the repair runs as root through `sudo`
+    pub fn needs_sudo(&self) -> bool {
+        self.command().is_some_and(|(program, _)| program == "sudo")
+    }
+
+    /// The repair as a command line to run by hand, where sudo may prompt
+    pub fn manual_command(&self) -> Option<String> {
+        let (program, args) = self.command()?;
+        let args = args.iter().filter(|arg| program != "sudo" || *arg != "-n");
+        Some(std::iter::once(program.to_string())
+            .chain(args.map(|arg| shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    /// The command running the repair inside Linux
+    ///
+    /// `sudo` never prompts, since there is no terminal to prompt on; when
+    /// it needs a password, the user runs `manual_command` instead (see
+    /// `Doctor::can_sudo`).
+    fn command(&self) -> Option<(&'static str, Vec<String>)> {
+        let sudo = |args: &[&str]| Some(("sudo", ["-n"].iter().chain(args).map(|a| a.to_string()).collect()));
+
+        match self {
+            Repair::EnableSystemd => sudo(&["sh", "-c", ENABLE_SYSTEMD_SCRIPT]),
+            Repair::StartDaemon { systemd: true } => sudo(&["systemctl", "start", "nix-daemon"]),
+            Repair::StartDaemon { systemd: false } => sudo(&["sh", "-c", START_DAEMON_SCRIPT]),
+            Repair::JoinNixUsers { user } => sudo(&["usermod", "-a", "-G", "nix-users", user]),
+            Repair::EnableFeatures { missing } => Some(("sh", vec![
+                "-c".to_string(),
+                format!(
+                    "mkdir -p ~/.config/nix && echo 'extra-experimental-features = {}' >> ~/.config/nix/nix.conf",
+                    missing.join(" ")
+                ),
+            ])),
+            Repair::SyncClock => sudo(&["hwclock", "-s"]),
+            Repair::AddRegistry | Repair::CollectGarbage | Repair::ResetPackageCache => None,
+        }
+    }
+}
+
+/// Quote an argument for a POSIX shell, unless it needs none
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:~".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Replaces any `systemd=` setting in /etc/wsl.conf with `systemd=true` under `[boot]`
+const ENABLE_SYSTEMD_SCRIPT: &str = r#"touch /etc/wsl.conf && sed -i '/^systemd *=/d' /etc/wsl.conf && if grep -q '^\[boot\]' /etc/wsl.conf; then sed -i '/^\[boot\]/a systemd=true' /etc/wsl.conf; else printf '\n[boot]\nsystemd=true\n' >> /etc/wsl.conf; fi"#;
+
+/// Starts the daemon of a multi-user install without systemd
+const START_DAEMON_SCRIPT: &str =
+    "nohup /nix/var/nix/profiles/default/bin/nix-daemon >/dev/null 2>&1 &";
+
+/// Result of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    /// Stable identifier (e.g. `daemon`)
+    pub id: &'static str,
+
+    /// Human-readable name
+    pub name: &'static str,
+
+    pub status: CheckStatus,
+
+    /// What was found
+    pub message: String,
+
+    /// How to fix it by hand
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+
+    /// How NSFW can fix it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repair: Option<Repair>,
+}
+
+impl CheckResult {
+    fn new(id: &'static str, name: &'static str, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self { id, name, status, message: message.into(), fix: None, repair: None }
+    }
+
+    fn pass(id: &'static str, name: &'static str, message: impl Into<String>) -> Self {
+        Self::new(id, name, CheckStatus::Pass, message)
+    }
+
+    fn warn(id: &'static str, name: &'static str, message: impl Into<String>) -> Self {
+        Self::new(id, name, CheckStatus::Warn, message)
+    }
+
+    fn fail(id: &'static str, name: &'static str, message: impl Into<String>) -> Self {
+        Self::new(id, name, CheckStatus::Fail, message)
+    }
+
+    fn skip(id: &'static str, name: &'static str, message: impl Into<String>) -> Self {
+        Self::new(id, name, CheckStatus::Skip, message)
+    }
+
+    fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    fn with_repair(mut self, repair: Repair) -> Self {
+        self.repair = Some(repair);
+        self
+    }
+
+    /// Check if the check found a problem
+    pub fn is_problem(&self) -> bool {
+        matches!(self.status, CheckStatus::Warn | CheckStatus::Fail)
+    }
+}
+
+/// Runs the health checks
+pub struct Doctor<'a> {
+    backend: &'a dyn NixBackend,
+
+    /// Runs `wsl` on Windows
+    runner: Box<dyn CommandRunner>,
+
+    /// Whether Nix runs on this host rather than in WSL2
+    native: bool,
+
+    /// Package cache database to check
+    cache_db: PathBuf,
+
+    /// Configured wrapper directory
+    wrapper_dir: Option<PathBuf>,
+}
+
+impl<'a> Doctor<'a> {
+    pub fn new(backend: &'a dyn NixBackend, native: bool, cache_db: PathBuf, wrapper_dir: Option<PathBuf>) -> Self {
+        Self { backend, runner: Box::new(SystemRunner), native, cache_db, wrapper_dir }
+    }
+
+    /// Run Windows commands with another runner
+    pub fn with_runner(mut self, runner: Box<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Run every check, in order
+    ///
+    /// Checks that depend on a failed one are skipped.
+    pub fn run(&self) -> Vec<CheckResult> {
+        let mut results = Vec::new();
+
+        let linux_ok = if self.native {
+            results.push(CheckResult::skip("wsl", "WSL", "Running natively on Linux"));
+            results.push(CheckResult::skip("distro", "Linux distribution", "Running natively on Linux"));
+            true
+        } else {
+            let wsl = self.check_wsl();
+            let distro = if wsl.status == CheckStatus::Fail {
+                CheckResult::skip("distro", "Linux distribution", "WSL is not installed")
+            } else {
+                self.check_distro()
+            };
+            let ok = wsl.status != CheckStatus::Fail && distro.status != CheckStatus::Fail;
+            results.push(wsl);
+            results.push(distro);
+            ok
+        };
+
+        if !linux_ok {
+            for (id, name) in LINUX_CHECKS {
+                results.push(CheckResult::skip(id, name, "No usable Linux distribution"));
+            }
+        } else {
+            let (systemd_check, systemd) = self.check_systemd();
+            results.push(systemd_check);
+
+            let nix = self.check_nix();
+            let nix_ok = nix.status != CheckStatus::Fail;
+            results.push(nix);
+
+            if nix_ok {
+                results.push(self.check_daemon(systemd));
+                results.push(self.check_groups());
+                results.push(self.check_features());
+                results.push(self.check_nixpkgs());
+            } else {
+                for (id, name) in NIX_CHECKS {
+                    results.push(CheckResult::skip(id, name, "Nix is not installed"));
+                }
+            }
+
+            results.push(self.check_disk());
+        }
+
+        results.push(self.check_cache());
+
+        if self.native {
+            results.push(CheckResult::skip("wrappers", "Wrapper directory", "Wrappers are only used on Windows"));
+            results.push(CheckResult::skip("clock", "Clock", "Running natively on Linux"));
+        } else {
+            results.push(self.check_wrappers());
+            results.push(if linux_ok {
+                self.check_clock()
+            } else {
+                CheckResult::skip("clock", "Clock", "No usable Linux distribution")
+            });
+        }
+
+        results
+    }
+
+    /// Whether repairs can run `sudo` without a password
+    pub fn can_sudo(&self) -> bool {
+        self.probe("sudo", &["-n", "true"]).is_some()
+    }
+
+    /// Apply a repair
+    pub fn repair(&self, repair: &Repair) -> Result<()> {
+        match repair {
+            Repair::AddRegistry => {
+                let output = self.backend.run_nix(&["registry", "add", "nixpkgs", "github:NixOS/nixpkgs/nixpkgs-unstable"])?;
+                if !output.is_success() {
+                    return Err(NixError::from_stderr(output.stderr).into());
+                }
+            }
+            Repair::CollectGarbage => {
+                self.backend.collect_garbage(None)?;
+            }
+            Repair::ResetPackageCache => {
+                // A detached refresh worker may be writing to the database
+                let Some(_lock) = BuildLock::acquire_for_db(&self.cache_db)? else {
+                    bail!("A package cache refresh is running; try again once it has finished");
+                };
+
+                // SQLite keeps recent writes next to the database in WAL mode
+                for suffix in ["", "-wal", "-shm"] {
+                    let mut path = self.cache_db.clone().into_os_string();
+                    path.push(suffix);
+                    match std::fs::remove_file(&path) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {
+                let Some((program, args)) = repair.command() else {
+                    bail!("No command for repair '{}'", repair.description());
+                };
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                let output = self.backend.run_tool(program, &args)?;
+                if !output.is_success() {
+                    bail!("{}", output.stderr.trim());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a Linux command, returning its trimmed stdout if it succeeded
+    fn probe(&self, program: &str, args: &[&str]) -> Option<String> {
+        match self.backend.run_tool(program, args) {
+            Ok(output) if output.is_success() => Some(output.stdout.trim().to_string()),
+            Ok(output) => {
+                log::debug!("{} {:?} failed: {}", program, args, output.stderr.trim());
+                None
+            }
+            Err(e) => {
+                log::debug!("{} {:?} failed: {}", program, args, e);
+                None
+            }
+        }
+    }
+
+    fn check_wsl(&self) -> CheckResult {
+        let status = wsl_detector::check_wsl2(self.runner.as_ref()).unwrap_or_default();
+        if status.is_installed {
+            CheckResult::pass("wsl", "WSL", format!("WSL {}", status.version.as_deref().unwrap_or("2")))
+        } else {
+            CheckResult::fail("wsl", "WSL", "WSL is not installed")
+                .with_fix("Run 'nsfw setup' or 'wsl --install', then restart Windows")
+        }
+    }
+
+    fn check_distro(&self) -> CheckResult {
+        match wsl_detector::list_distros(self.runner.as_ref()) {
+            Ok(distros) => evaluate_distros(&distros),
+            Err(e) => CheckResult::fail("distro", "Linux distribution", format!("Could not list distributions: {}", e))
+                .with_fix("Run 'wsl --list --verbose' to see what is wrong"),
+        }
+    }
+
+    /// Check whether systemd is PID 1, which starts nix-daemon on boot
+    fn check_systemd(&self) -> (CheckResult, bool) {
+        let Some(init) = self.probe("ps", &["-p", "1", "-o", "comm="]) else {
+            return (CheckResult::warn("systemd", "systemd", "Could not determine the init process"), false);
+        };
+
+        if init == "systemd" {
+            return (CheckResult::pass("systemd", "systemd", "systemd is running"), true);
+        }
+
+        // How a native Linux system boots is not NSFW's business
+        if self.native {
+            return (CheckResult::skip("systemd", "systemd", format!("PID 1 is {}", init)), false);
+        }
+
+        let result = CheckResult::warn(
+            "systemd",
+            "systemd",
+            format!("PID 1 is {}; nix-daemon does not start automatically", init)
+        )
+            .with_fix("Add 'systemd=true' to the [boot] section of /etc/wsl.conf, then run 'wsl --shutdown'")
+            .with_repair(Repair::EnableSystemd);
+        (result, false)
+    }
+
+    fn check_nix(&self) -> CheckResult {
+        match self.backend.check_nix_available() {
+            Ok(version) => evaluate_nix_version(&version),
+            Err(NixError::NixNotInstalled) => CheckResult::fail("nix", "Nix", "Nix is not installed")
+                .with_fix("Run 'nsfw setup' to install Nix"),
+            Err(e) => {
+                let result = CheckResult::fail("nix", "Nix", e.to_string());
+                match e.suggestion() {
+                    Some(suggestion) => result.with_fix(suggestion),
+                    None => result,
+                }
+            }
+        }
+    }
+
+    fn check_daemon(&self, systemd: bool) -> CheckResult {
+        if self.probe("pgrep", &["-x", "nix-daemon"]).is_some() {
+            return CheckResult::pass("daemon", "Nix daemon", "nix-daemon is running");
+        }
+
+        // Single-user installs talk to the store directly
+        if self.probe("test", &["-e", "/nix/var/nix/daemon-socket"]).is_none() {
+            return CheckResult::pass("daemon", "Nix daemon", "Single-user install, no daemon needed");
+        }
+
+        let fix = if systemd {
+            "Run 'sudo systemctl enable --now nix-daemon'"
+        } else {
+            "Run 'sudo nix-daemon &', or enable systemd so it starts automatically"
+        };
+        CheckResult::fail("daemon", "Nix daemon", "nix-daemon is not running")
+            .with_fix(fix)
+            .with_repair(Repair::StartDaemon { systemd })
+    }
+
+    fn check_groups(&self) -> CheckResult {
+        let user = self.probe("id", &["-un"]).unwrap_or_default();
+        let groups = self.probe("id", &["-nG"]).unwrap_or_default();
+        let group_exists = self.probe("getent", &["group", "nix-users"]).is_some();
+        evaluate_groups(&user, &groups, group_exists)
+    }
+
+    fn check_features(&self) -> CheckResult {
+        let config = self.probe("sh", &["-c", "cat /etc/nix/nix.conf ~/.config/nix/nix.conf 2>/dev/null; true"])
+            .unwrap_or_default();
+        evaluate_features(&config)
+    }
+
+    fn check_nixpkgs(&self) -> CheckResult {
+        let registry = self.backend.run_nix(&["registry", "list"])
+            .ok()
+            .filter(|output| output.is_success())
+            .map(|output| output.stdout)
+            .unwrap_or_default();
+        let channels = self.probe("nix-channel", &["--list"]).unwrap_or_default();
+        evaluate_nixpkgs(&registry, &channels)
+    }
+
+    fn check_disk(&self) -> CheckResult {
+        let df = self.probe("sh", &["-c", "df -Pk /nix 2>/dev/null || df -Pk /"]);
+        match df.as_deref().and_then(parse_df_available) {
+            Some(available) => evaluate_disk(available),
+            None => CheckResult::warn("disk", "Disk space", "Could not determine free space on /nix"),
+        }
+    }
+
+    fn check_cache(&self) -> CheckResult {
+        evaluate_cache(&self.cache_db)
+    }
+
+    fn check_wrappers(&self) -> CheckResult {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        evaluate_wrapper_dir(self.wrapper_dir.as_deref(), &path)
+    }
+
+    fn check_clock(&self) -> CheckResult {
+        let linux = self.probe("date", &["+%s"]).and_then(|s| s.parse::<i64>().ok());
+        let host = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).ok();
+        match (linux, host) {
+            (Some(linux), Some(host)) => evaluate_clock_skew(linux - host),
+            _ => CheckResult::warn("clock", "Clock", "Could not read the WSL clock"),
+        }
+    }
+}
+
+/// Checks that need a Linux system, after `systemd`
+const LINUX_CHECKS: [(&str, &str); 7] = [
+    ("systemd", "systemd"),
+    ("nix", "Nix"),
+    ("daemon", "Nix daemon"),
+    ("groups", "Groups"),
+    ("features", "Experimental features"),
+    ("nixpkgs", "nixpkgs"),
+    ("disk", "Disk space"),
+];
+
+/// Checks that need Nix
+const NIX_CHECKS: [(&str, &str); 4] = [
+    ("daemon", "Nix daemon"),
+    ("groups", "Groups"),
+    ("features", "Experimental features"),
+    ("nixpkgs", "nixpkgs"),
+];
+
+/// Check the distribution `wsl` starts by default
+fn evaluate_distros(distros: &[WslDistro]) -> CheckResult {
+    let name = "Linux distribution";
+    let Some(distro) = distros.iter().find(|d| d.is_default).or(distros.first()) else {
+        return CheckResult::fail("distro", name, "No Linux distribution is installed")
+            .with_fix("Run 'nsfw setup' to install Ubuntu");
+    };
+
+    if distro.version != 2 {
+        return CheckResult::fail("distro", name, format!("{} runs under WSL {}; Nix needs WSL 2", distro.name, distro.version))
+            .with_fix(format!("Run 'wsl --set-version {} 2'", distro.name));
+    }
+
+    let message = format!("{} ({}, WSL 2)", distro.name, distro.state);
+    match distro.state.as_str() {
+        "Running" | "Stopped" => CheckResult::pass("distro", name, message),
+        _ => CheckResult::warn("distro", name, message)
+            .with_fix(format!("Wait for {} to finish, or run 'wsl -d {}' to see what it is doing", distro.state.to_lowercase(), distro.name)),
+    }
+}
+
+/// Parse the version out of `nix --version` (e.g. `nix (Nix) 2.18.1`)
+fn parse_nix_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.split_whitespace().last()?;
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??))
+}
+
+fn evaluate_nix_version(output: &str) -> CheckResult {
+    match parse_nix_version(output) {
+        Some(version) if version < MIN_NIX_VERSION => {
+            CheckResult::fail("nix", "Nix", format!("{} is too old; NSFW needs Nix {}.{} or newer", output, MIN_NIX_VERSION.0, MIN_NIX_VERSION.1))
+                .with_fix("Upgrade Nix with 'sudo nix upgrade-nix'")
+        }
+        Some(_) => CheckResult::pass("nix", "Nix", output),
+        None => CheckResult::warn("nix", "Nix", format!("Unrecognized version '{}'", output)),
+    }
+}
+
+fn evaluate_groups(user: &str, groups: &str, group_exists: bool) -> CheckResult {
+    let name = "Groups";
+    if user == "root" {
+        return CheckResult::pass("groups", name, "Running as root");
+    }
+    if !group_exists {
+        return CheckResult::pass("groups", name, "No nix-users group; the daemon accepts all users");
+    }
+    if groups.split_whitespace().any(|g| g == "nix-users") {
+        return CheckResult::pass("groups", name, format!("{} is in nix-users", user));
+    }
+
+    CheckResult::warn("groups", name, format!("{} is not in the nix-users group", user))
+        .with_fix(format!("Run 'sudo usermod -a -G nix-users {}', then restart WSL", user))
+        .with_repair(Repair::JoinNixUsers { user: user.to_string() })
+}
+
+/// Experimental features enabled by `nix.conf` contents
+fn enabled_features(config: &str) -> Vec<&str> {
+    config.lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(key, _)| matches!(key.trim(), "experimental-features" | "extra-experimental-features"))
+        .flat_map(|(_, value)| value.split_whitespace())
+        .collect()
+}
+
+fn evaluate_features(config: &str) -> CheckResult {
+    let name = "Experimental features";
+    let enabled = enabled_features(config);
+    let missing: Vec<String> = REQUIRED_FEATURES.iter()
+        .filter(|feature| !enabled.contains(feature))
+        .map(|feature| feature.to_string())
+        .collect();
+
+    if missing.is_empty() {
+        return CheckResult::pass("features", name, "nix-command and flakes are enabled");
+    }
+
+    // NSFW enables them per invocation; only plain `nix` is affected
+    CheckResult::warn("features", name, format!(
+        "{} not enabled in nix.conf; 'nix' commands outside NSFW will fail",
+        missing.join(" and ")
+    ))
+        .with_fix(format!("Add 'extra-experimental-features = {}' to ~/.config/nix/nix.conf", missing.join(" ")))
+        .with_repair(Repair::EnableFeatures { missing })
+}
+
+/// Check that `nixpkgs#...` installables resolve
+fn evaluate_nixpkgs(registry: &str, channels: &str) -> CheckResult {
+    let name = "nixpkgs";
+    let in_registry = registry.lines()
+        .any(|line| line.split_whitespace().nth(1) == Some("flake:nixpkgs"));
+    let channel = channels.lines()
+        .find_map(|line| line.strip_prefix("nixpkgs "))
+        .map(str::trim);
+
+    match (in_registry, channel) {
+        (true, Some(url)) => CheckResult::pass("nixpkgs", name, format!("In the flake registry; channel {}", url)),
+        (true, None) => CheckResult::pass("nixpkgs", name, "In the flake registry"),
+        (false, channel) => {
+            let message = match channel {
+                Some(url) => format!("Not in the flake registry (only channel {})", url),
+                None => "Not in the flake registry and no nixpkgs channel".to_string(),
+            };
+            CheckResult::fail("nixpkgs", name, message)
+                .with_fix("Run 'nix registry add nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable'")
+                .with_repair(Repair::AddRegistry)
+        }
+    }
+}
+
+/// Parse the bytes available from `df -Pk` output
+fn parse_df_available(output: &str) -> Option<u64> {
+    let line = output.lines().last()?;
+    let kib: u64 = line.split_whitespace().nth(3)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+fn evaluate_disk(available: u64) -> CheckResult {
+    let name = "Disk space";
+    let message = format!("{} free on /nix", crate::ui::OutputFormatter::format_size(available));
+    let result = if available < MIN_FREE_BYTES {
+        CheckResult::fail("disk", name, message)
+    } else if available < LOW_FREE_BYTES {
+        CheckResult::warn("disk", name, message)
+    } else {
+        return CheckResult::pass("disk", name, message);
+    };
+
+    result
+        .with_fix("Run 'nsfw du' to see what uses space and 'nsfw gc --older-than 30d' to free it")
+        .with_repair(Repair::CollectGarbage)
+}
+
+fn evaluate_cache(db_path: &Path) -> CheckResult {
+    let name = "Package cache";
+    if !db_path.exists() {
+        return CheckResult::warn("cache", name, "The package cache has not been built")
+            .with_fix("Run 'nsfw update'");
+    }
+
+    let broken = |message: String| CheckResult::fail("cache", name, message)
+        .with_fix(format!("Delete {} and run 'nsfw update'", db_path.display()))
+        .with_repair(Repair::ResetPackageCache);
+
+    let cache = match PackageCache::with_path(db_path.to_path_buf()) {
+        Ok(cache) => cache,
+        Err(e) => return broken(format!("Cannot open the package cache: {:#}", e)),
+    };
+
+    match cache.integrity_check() {
+        Ok(problems) if problems.is_empty() => {}
+        Ok(problems) => return broken(format!("The package cache is corrupt: {}", problems[0])),
+        Err(e) => return broken(format!("Cannot check the package cache: {:#}", e)),
+    }
+
+    match cache.stats() {
+        Ok(stats) if stats.total_packages > 0 => {
+            CheckResult::pass("cache", name, format!("{} packages", stats.total_packages))
+        }
+        _ => CheckResult::warn("cache", name, "The package cache is empty").with_fix("Run 'nsfw update'"),
+    }
+}
+
+/// Compare directories as Windows does, ignoring case and trailing separators
+fn same_dir(a: &Path, b: &Path) -> bool {
+    let normalize = |p: &Path| p.to_string_lossy().trim_end_matches(['\\', '/']).to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+fn evaluate_wrapper_dir(dir: Option<&Path>, path_var: &std::ffi::OsStr) -> CheckResult {
+    let name = "Wrapper directory";
+    let Some(dir) = dir else {
+        return CheckResult::skip("wrappers", name, "No wrapper directory configured")
+            .with_fix("Set 'wrapper_dir' in the NSFW config.json to a directory on PATH");
+    };
+
+    if !dir.is_dir() {
+        return CheckResult::warn("wrappers", name, format!("{} does not exist", dir.display()))
+            .with_fix(format!("Create {} or change 'wrapper_dir' in config.json", dir.display()));
+    }
+
+    if std::env::split_paths(path_var).any(|entry| same_dir(&entry, dir)) {
+        CheckResult::pass("wrappers", name, format!("{} is on PATH", dir.display()))
+    } else {
+        CheckResult::warn("wrappers", name, format!("{} is not on PATH", dir.display()))
+            .with_fix(format!("Add {} to your user PATH in the Environment Variables settings", dir.display()))
+    }
+}
+
+/// Check the difference of the WSL clock to Windows in seconds
+fn evaluate_clock_skew(skew: i64) -> CheckResult {
+    let name = "Clock";
+    if skew.abs() <= CLOCK_SKEW_WARN_SECS {
+        return CheckResult::pass("clock", name, "WSL and Windows clocks agree");
+    }
+
+    let message = format!("WSL clock is {}s {} Windows", skew.abs(), if skew > 0 { "ahead of" } else { "behind" });
+    let result = if skew.abs() > CLOCK_SKEW_FAIL_SECS {
+        CheckResult::fail("clock", name, format!("{}; downloads may fail TLS and signature checks", message))
+    } else {
+        CheckResult::warn("clock", name, message)
+    };
+    result
+        .with_fix("Run 'sudo hwclock -s' in WSL, or 'wsl --shutdown' to restart it")
+        .with_repair(Repair::SyncClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix_ops::backend::tests::ScriptedBackend;
+    use crate::wsl2::CommandOutput;
+
+    fn ok(stdout: &str) -> CommandOutput {
+        CommandOutput::new(stdout.to_string(), String::new(), 0)
+    }
+
+    fn failed() -> CommandOutput {
+        CommandOutput::new(String::new(), String::new(), 1)
+    }
+
+    fn status_of<'r>(results: &'r [CheckResult], id: &str) -> &'r CheckResult {
+        results.iter().find(|r| r.id == id).unwrap()
+    }
+
+    #[test]
+    fn test_native_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = ScriptedBackend::new(&[
+            ("ps -p 1 -o comm=", ok("init\n")),
+            ("--version", ok("nix (Nix) 2.24.10\n")),
+            ("pgrep -x nix-daemon", failed()),
+            ("test -e /nix/var/nix/daemon-socket", ok("")),
+            ("id -un", ok("alice\n")),
+            ("id -nG", ok("alice sudo\n")),
+            ("getent group nix-users", ok("nix-users:x:30000:\n")),
+            ("sh -c cat /etc/nix/nix.conf ~/.config/nix/nix.conf 2>/dev/null; true", ok("build-users-group = nixbld\nexperimental-features = nix-command\n")),
+            ("registry list", ok("global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable\n")),
+            ("nix-channel --list", ok("")),
+            ("sh -c df -Pk /nix 2>/dev/null || df -Pk /", ok("Filesystem 1024-blocks Used Available Capacity Mounted on\n/dev/sdc 263174212 52000000 198000000 21% /\n")),
+        ]);
+        let doctor = Doctor::new(&backend, true, dir.path().join("packages.db"), None);
+        let results = doctor.run();
+
+        assert_eq!(status_of(&results, "wsl").status, CheckStatus::Skip);
+        assert_eq!(status_of(&results, "systemd").status, CheckStatus::Skip);
+        assert_eq!(status_of(&results, "nix").status, CheckStatus::Pass);
+        assert_eq!(status_of(&results, "daemon").repair, Some(Repair::StartDaemon { systemd: false }));
+        assert_eq!(status_of(&results, "groups").repair, Some(Repair::JoinNixUsers { user: "alice".to_string() }));
+        assert_eq!(status_of(&results, "features").status, CheckStatus::Warn);
+        assert_eq!(status_of(&results, "features").repair, Some(Repair::EnableFeatures { missing: vec!["flakes".to_string()] }));
+        assert_eq!(status_of(&results, "nixpkgs").status, CheckStatus::Pass);
+        assert_eq!(status_of(&results, "disk").status, CheckStatus::Pass);
+        assert_eq!(status_of(&results, "cache").status, CheckStatus::Warn);
+        assert_eq!(status_of(&results, "clock").status, CheckStatus::Skip);
+    }
+
+    #[test]
+    fn test_missing_nix_skips_dependent_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = ScriptedBackend::new(&[
+            ("ps -p 1 -o comm=", ok("systemd\n")),
+            ("sh -c df -Pk /nix 2>/dev/null || df -Pk /", failed()),
+        ]);
+        let doctor = Doctor::new(&backend, true, dir.path().join("packages.db"), None);
+        let results = doctor.run();
+
+        assert_eq!(status_of(&results, "nix").status, CheckStatus::Fail);
+        for id in ["daemon", "groups", "features", "nixpkgs"] {
+            assert_eq!(status_of(&results, id).status, CheckStatus::Skip, "{}", id);
+        }
+        assert_eq!(status_of(&results, "disk").status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_repair_runs_without_prompting() {
+        let backend = ScriptedBackend::new(&[
+            ("sudo -n usermod -a -G nix-users alice", ok("")),
+            ("sudo -n hwclock -s", CommandOutput::new(String::new(), "sudo: a password is required\n".to_string(), 1)),
+        ]);
+        let doctor = Doctor::new(&backend, false, PathBuf::from("packages.db"), None);
+
+        doctor.repair(&Repair::JoinNixUsers { user: "alice".to_string() }).unwrap();
+        let error = doctor.repair(&Repair::SyncClock).unwrap_err();
+        assert_eq!(error.to_string(), "sudo: a password is required");
+    }
+
+    #[test]
+    fn test_sudo_repairs_fall_back_to_manual_commands() {
+        let backend = ScriptedBackend::new(&[
+            ("sudo -n true", CommandOutput::new(String::new(), "sudo: a password is required\n".to_string(), 1)),
+        ]);
+        let doctor = Doctor::new(&backend, false, PathBuf::from("packages.db"), None);
+        assert!(!doctor.can_sudo());
+
+        let join = Repair::JoinNixUsers { user: "alice".to_string() };
+        assert!(join.needs_sudo());
+        assert_eq!(join.manual_command().unwrap(), "sudo usermod -a -G nix-users alice");
+        assert_eq!(
+            Repair::StartDaemon { systemd: false }.manual_command().unwrap(),
+            "sudo sh -c 'nohup /nix/var/nix/profiles/default/bin/nix-daemon >/dev/null 2>&1 &'"
+        );
+        assert!(Repair::EnableSystemd.manual_command().unwrap().contains(r"'\''/^systemd *=/d'\''"));
+        assert!(!Repair::EnableFeatures { missing: vec!["flakes".to_string()] }.needs_sudo());
+    }
+
+    #[test]
+    fn test_evaluate_distros() {
+        let distro = |name: &str, state: &str, version, is_default| WslDistro {
+            name: name.to_string(), state: state.to_string(), version, is_default,
+        };
+
+        assert_eq!(evaluate_distros(&[]).status, CheckStatus::Fail);
+        assert_eq!(evaluate_distros(&[distro("Debian", "Running", 2, false), distro("Old", "Stopped", 1, true)]).status, CheckStatus::Fail);
+        assert_eq!(evaluate_distros(&[distro("Ubuntu", "Stopped", 2, true)]).status, CheckStatus::Pass);
+        assert_eq!(evaluate_distros(&[distro("Ubuntu", "Installing", 2, true)]).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_evaluate_nix_version() {
+        assert_eq!(parse_nix_version("nix (Nix) 2.18.1"), Some((2, 18)));
+        assert_eq!(parse_nix_version("nix (Determinate Nix 3.6.2) 2.29.0"), Some((2, 29)));
+        assert_eq!(evaluate_nix_version("nix (Nix) 2.3.16").status, CheckStatus::Fail);
+        assert_eq!(evaluate_nix_version("nix (Nix) 2.16.2").status, CheckStatus::Fail);
+        assert_eq!(evaluate_nix_version("nix (Nix) 2.24.10").status, CheckStatus::Pass);
+        assert_eq!(evaluate_nix_version("weird").status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_evaluate_features() {
+        let config = "experimental-features = nix-command\nextra-experimental-features = flakes ca-derivations\n";
+        assert_eq!(evaluate_features(config).status, CheckStatus::Pass);
+        assert_eq!(evaluate_features("").status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_evaluate_nixpkgs() {
+        let registry = "global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable\n";
+        assert_eq!(evaluate_nixpkgs(registry, "").status, CheckStatus::Pass);
+
+        let result = evaluate_nixpkgs("", "nixpkgs https://nixos.org/channels/nixpkgs-unstable\n");
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.message.contains("only channel"));
+        assert_eq!(result.repair, Some(Repair::AddRegistry));
+    }
+
+    #[test]
+    fn test_evaluate_disk_and_clock() {
+        assert_eq!(parse_df_available("Filesystem 1024-blocks Used Available Capacity Mounted on\n/dev/sdc 100 50 2048 50% /nix\n"), Some(2 * 1024 * 1024));
+        assert_eq!(evaluate_disk(512 * 1024 * 1024).status, CheckStatus::Fail);
+        assert_eq!(evaluate_disk(3 * 1024 * 1024 * 1024).status, CheckStatus::Warn);
+        assert_eq!(evaluate_disk(LOW_FREE_BYTES).status, CheckStatus::Pass);
+
+        assert_eq!(evaluate_clock_skew(-30).status, CheckStatus::Pass);
+        assert_eq!(evaluate_clock_skew(120).status, CheckStatus::Warn);
+        assert!(evaluate_clock_skew(-600).message.contains("600s behind"));
+    }
+
+    #[test]
+    fn test_evaluate_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("packages.db");
+        assert_eq!(evaluate_cache(&db).status, CheckStatus::Warn);
+
+        std::fs::write(&db, vec![0x42; 8192]).unwrap();
+        let result = evaluate_cache(&db);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(result.repair, Some(Repair::ResetPackageCache));
+
+        let backend = ScriptedBackend::new(&[]);
+        let doctor = Doctor::new(&backend, true, db.clone(), None);
+        let worker = BuildLock::acquire_for_db(&db).unwrap().unwrap();
+        assert!(doctor.repair(&Repair::ResetPackageCache).is_err());
+        assert!(db.exists());
+
+        drop(worker);
+        doctor.repair(&Repair::ResetPackageCache).unwrap();
+        assert!(!db.exists());
+    }
+
+    #[test]
+    fn test_evaluate_wrapper_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::env::join_paths([PathBuf::from("/usr/bin"), dir.path().to_path_buf()]).unwrap();
+
+        assert_eq!(evaluate_wrapper_dir(None, &path).status, CheckStatus::Skip);
+        assert_eq!(evaluate_wrapper_dir(Some(dir.path()), &path).status, CheckStatus::Pass);
+        assert_eq!(evaluate_wrapper_dir(Some(dir.path()), std::ffi::OsStr::new("/usr/bin")).status, CheckStatus::Warn);
+        assert_eq!(evaluate_wrapper_dir(Some(&dir.path().join("missing")), &path).status, CheckStatus::Warn);
+    }
+}
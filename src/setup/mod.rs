@@ -4,52 +4,141 @@
 //! - WSL2 detection and installation
 //! - Linux distro detection and installation
 //! - Nix installation and configuration
+//! - Health checks (`nsfw doctor`)
+//!
+//! Setup runs as a sequence of steps (see `steps`) whose progress is saved
+//! after each one (see `state`), so `nsfw setup` can be rerun at any time:
+//! it skips what is done, resumes after a restart of Windows and reports
+//! the step and phase a failure happened in.
+//!
+//! Machines without internet access install from local files instead (see
+//! `sources`).
 
 pub mod wsl_detector;
 pub mod distro_installer;
 pub mod nix_installer;
 pub mod wsl_command;
+pub mod runner;
+pub mod doctor;
+pub mod state;
+pub mod steps;
+pub mod sources;
+
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
 
-use anyhow::Result;
 use crate::ui::{OutputFormatter, MessageType};
+use state::{Phase, SetupState};
+
+pub use runner::{CommandRunner, MockCommandRunner, SystemRunner};
+pub use sources::{LocalFile, SetupSources};
+use steps::{SetupContext, SetupStep, StepOutcome};
 
 pub struct SetupWizard {
     auto_yes: bool,
     interactive: bool,
+
+    /// Where progress is saved (defaults to `SetupState::default_path`)
+    state_path: Option<PathBuf>,
+
+    /// Runs `wsl` and PowerShell
+    runner: Box<dyn CommandRunner>,
+
+    /// Local files to install from
+    sources: SetupSources,
+
+    steps: Vec<Box<dyn SetupStep>>,
 }
 
 impl SetupWizard {
     pub fn new(auto_yes: bool, interactive: bool) -> Self {
-        Self { auto_yes, interactive }
+        Self {
+            auto_yes,
+            interactive,
+            state_path: None,
+            runner: Box::new(SystemRunner),
+            sources: SetupSources::default(),
+            steps: steps::default_steps(),
+        }
+    }
+
+    /// Save progress to a specific file
+    pub fn with_state_path(mut self, path: PathBuf) -> Self {
+        self.state_path = Some(path);
+        self
+    }
+
+    /// Run commands with another runner
+    pub fn with_runner(mut self, runner: Box<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Install from local files instead of downloading
+    pub fn with_sources(mut self, sources: SetupSources) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Run other steps than the full setup
+    pub fn with_steps(mut self, steps: Vec<Box<dyn SetupStep>>) -> Self {
+        self.steps = steps;
+        self
     }
 
     pub fn run(&self) -> Result<()> {
         self.print_header();
 
-        // Step 1: Check WSL2
+        let state_path = match &self.state_path {
+            Some(path) => path.clone(),
+            None => SetupState::default_path()?,
+        };
+        let mut state = SetupState::load_from(&state_path)?;
+
+        if let Some(failure) = &state.last_failure {
+            eprintln!("{}", OutputFormatter::format_message(
+                MessageType::Warning,
+                &format!("The last setup run failed at '{}' ({} phase): {}", failure.step, failure.phase, failure.message)
+            ));
+        }
+
+        let mut ctx = SetupContext {
+            runner: self.runner.as_ref(),
+            distro: state.distro.clone().or_else(|| self.sources.distro.clone()),
+            auto_yes: self.auto_yes,
+            interactive: self.interactive,
+            sources: &self.sources,
+        };
+
+        // Check phase: find out what is already done
         eprintln!("{}", OutputFormatter::format_message(
             MessageType::Info,
             "Checking system requirements..."
         ));
         eprintln!();
 
-        let wsl_status = wsl_detector::check_wsl2()?;
-        let distro_status = wsl_detector::check_linux_distro()?;
-        let nix_status = if distro_status.is_installed {
-            nix_installer::check_nix(&distro_status.distro_name)?
-        } else {
-            nix_installer::NixStatus::default()
-        };
-
-        // Show status
-        self.print_status(&wsl_status, &distro_status, &nix_status);
+        let mut pending = Vec::new();
+        for step in &self.steps {
+            let done = match step.check(&mut ctx) {
+                Ok(done) => done,
+                Err(e) => return Err(self.fail(&mut state, &state_path, step.as_ref(), Phase::Check, e)),
+            };
 
-        // Determine what needs to be done
-        let needs_wsl = !wsl_status.is_installed;
-        let needs_distro = !distro_status.is_installed;
-        let needs_nix = !nix_status.is_installed;
+            eprintln!("  {} {}", if done { "✓" } else { "✗" }, step.description());
+            if done {
+                state.mark_completed(step.id());
+            } else {
+                state.completed.remove(&step.id());
+                pending.push(step.as_ref());
+            }
+        }
+        state.distro = ctx.distro.clone();
+        eprintln!();
 
-        if !needs_wsl && !needs_distro && !needs_nix {
+        if pending.is_empty() {
+            state.clear_restart();
+            state.last_failure = None;
+            state.save_to(&state_path)?;
             eprintln!("{}", OutputFormatter::format_message(
                 MessageType::Success,
                 "✓ Your system is already configured! NSFW is ready to use."
@@ -57,11 +146,23 @@ impl SetupWizard {
             return Ok(());
         }
 
+        // A step waiting for a restart is not done before Windows restarted
+        if let Some(step) = state.awaiting_restart {
+            if state.restart_pending(last_boot(ctx.runner).as_deref()) {
+                state.save_to(&state_path)?;
+                return Err(anyhow!(
+                    "Step '{}' needs a restart of Windows to finish. Restart, then run 'nsfw setup' again",
+                    step
+                ));
+            }
+            state.clear_restart();
+        }
+        state.save_to(&state_path)?;
+
         // Ask for confirmation
         if !self.auto_yes {
-            eprintln!();
-            self.print_installation_plan(needs_wsl, needs_distro, needs_nix, &distro_status);
-            
+            self.print_installation_plan(&pending);
+
             if !self.confirm("Continue with automatic setup?")? {
                 eprintln!("{}", OutputFormatter::format_message(
                     MessageType::Warning,
@@ -71,24 +172,49 @@ impl SetupWizard {
             }
         }
 
-        // Execute setup steps
-        if needs_wsl {
-            self.install_wsl2()?;
-        }
+        for step in pending {
+            // Earlier steps may have done this one too
+            match step.check(&mut ctx) {
+                Ok(true) => {
+                    state.mark_completed(step.id());
+                    state.save_to(&state_path)?;
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => return Err(self.fail(&mut state, &state_path, step, Phase::Check, e)),
+            }
 
-        if needs_distro {
-            let distro = if self.interactive {
-                self.choose_distro()?
-            } else {
-                "Ubuntu-24.04".to_string()
+            let outcome = match step.apply(&mut ctx) {
+                Ok(outcome) => outcome,
+                Err(e) => return Err(self.fail(&mut state, &state_path, step, Phase::Apply, e)),
             };
-            distro_installer::install_distro(&distro)?;
-        }
+            state.distro = ctx.distro.clone();
+
+            if outcome == StepOutcome::RestartRequired {
+                state.request_restart(step.id(), last_boot(ctx.runner));
+                state.save_to(&state_path)?;
+                eprintln!("{}", OutputFormatter::format_message(
+                    MessageType::Warning,
+                    "⚠️  Please restart your computer, then run 'nsfw setup' again to continue."
+                ));
+                return Ok(());
+            }
+
+            if let Err(e) = step.verify(&mut ctx) {
+                return Err(self.fail(&mut state, &state_path, step, Phase::Verify, e));
+            }
 
-        if needs_nix {
-            nix_installer::install_nix(&distro_status.distro_name, self.auto_yes)?;
+            state.mark_completed(step.id());
+            state.save_to(&state_path)?;
+            eprintln!("{}", OutputFormatter::format_message(
+                MessageType::Success,
+                &format!("✓ {}", step.description())
+            ));
         }
 
+        state.last_failure = None;
+        state.save_to(&state_path)?;
+
         // Final success message
         eprintln!();
         eprintln!("{}", OutputFormatter::format_message(
@@ -101,62 +227,32 @@ impl SetupWizard {
         Ok(())
     }
 
+    /// Record a failed step and describe where it failed
+    fn fail(
+        &self,
+        state: &mut SetupState,
+        state_path: &std::path::Path,
+        step: &dyn SetupStep,
+        phase: Phase,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        state.record_failure(step.id(), phase, format!("{:#}", error));
+        if let Err(e) = state.save_to(state_path) {
+            log::warn!("Failed to save setup progress: {}", e);
+        }
+
+        error.context(format!("Setup failed at step '{}' ({} phase)", step.id(), phase))
+    }
+
     fn print_header(&self) {
         eprintln!("{}", OutputFormatter::format_section("NSFW Setup Wizard"));
     }
 
-    fn print_status(
-        &self,
-        wsl: &wsl_detector::WSL2Status,
-        distro: &wsl_detector::DistroStatus,
-        nix: &nix_installer::NixStatus,
-    ) {
-        let wsl_icon = if wsl.is_installed { "✓" } else { "✗" };
-        let distro_icon = if distro.is_installed { "✓" } else { "✗" };
-        let nix_icon = if nix.is_installed { "✓" } else { "✗" };
-
-        eprintln!("  {} WSL2: {}", wsl_icon, if wsl.is_installed { 
-            format!("Enabled (version {})", wsl.version.as_deref().unwrap_or("unknown"))
-        } else { 
-            "Not installed".to_string() 
-        });
-
-        eprintln!("  {} Linux distribution: {}", distro_icon, if distro.is_installed {
-            log::debug!("Detected distro name: '{}'", distro.distro_name);
-            format!("{}", distro.distro_name)
-        } else {
-            "None found".to_string()
-        });
-
-        eprintln!("  {} Nix package manager: {}", nix_icon, if nix.is_installed {
-            format!("Installed ({})", nix.version.as_deref().unwrap_or("unknown"))
-        } else {
-            "Not installed".to_string()
-        });
-    }
-
-    fn print_installation_plan(
-        &self,
-        needs_wsl: bool,
-        needs_distro: bool,
-        needs_nix: bool,
-        _distro_status: &wsl_detector::DistroStatus,
-    ) {
+    fn print_installation_plan(&self, pending: &[&dyn SetupStep]) {
         eprintln!("{}", OutputFormatter::format_section("Installation Plan"));
-        
-        if needs_wsl {
-            eprintln!("  • Install WSL2 (Windows Subsystem for Linux)");
-        }
-        
-        if needs_distro {
-            eprintln!("  • Install Ubuntu 24.04 LTS");
-        }
-        
-        if needs_nix {
-            eprintln!("  • Install Nix package manager");
-            eprintln!("  • Configure Nix daemon");
-            eprintln!("  • Add user to nix-users group");
-            eprintln!("  • Enable experimental features");
+
+        for step in pending {
+            eprintln!("  • {}", step.description());
         }
 
         eprintln!();
@@ -175,49 +271,179 @@ impl SetupWizard {
         let answer = input.trim().to_lowercase();
         Ok(answer.is_empty() || answer == "y" || answer == "yes")
     }
+}
 
-    fn choose_distro(&self) -> Result<String> {
-        use std::io::{self, Write};
-        
-        eprintln!("{}", OutputFormatter::format_section("Choose Linux Distribution"));
-        eprintln!("  1. Ubuntu 24.04 LTS (recommended) ⭐");
-        eprintln!("  2. Debian 12");
-        eprintln!("  3. Ubuntu 22.04 LTS");
-        eprintln!();
-        eprint!("Select [1-3] (default: 1): ");
-        io::stderr().flush()?;
-        
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        
-        let choice = input.trim();
-        let distro = match choice {
-            "2" => "Debian",
-            "3" => "Ubuntu-22.04",
-            _ => "Ubuntu-24.04", // Default to Ubuntu 24.04
-        };
-        
-        Ok(distro.to_string())
+/// When Windows last booted, which tells one boot from the next
+///
+/// `None` if it cannot be found out.
+fn last_boot(runner: &dyn CommandRunner) -> Option<String> {
+    let command = "(Get-CimInstance Win32_OperatingSystem).LastBootUpTime.ToString('o')";
+    match runner.run("powershell.exe", &["-NoProfile", "-Command", command]) {
+        Ok(output) if output.is_success() && !output.stdout.trim().is_empty() => {
+            Some(output.stdout.trim().to_string())
+        }
+        Ok(output) => {
+            log::debug!("Could not get the last boot time: {}", output.stderr.trim());
+            None
+        }
+        Err(e) => {
+            log::debug!("Could not get the last boot time: {}", e);
+            None
+        }
     }
+}
 
-    fn install_wsl2(&self) -> Result<()> {
-        eprintln!();
-        eprintln!("{}", OutputFormatter::format_message(
-            MessageType::Info,
-            "📦 Installing WSL2..."
-        ));
-        
-        wsl_detector::install_wsl2()?;
-        
-        eprintln!("{}", OutputFormatter::format_message(
-            MessageType::Success,
-            "✓ WSL2 installed successfully"
-        ));
-        eprintln!("{}", OutputFormatter::format_message(
-            MessageType::Warning,
-            "⚠️  Please restart your computer, then run 'nsfw setup' again."
-        ));
-        
-        std::process::exit(0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::StepId;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Step acting on a shared fake system
+    struct FakeStep {
+        id: StepId,
+        system: Rc<RefCell<FakeSystem>>,
+    }
+
+    #[derive(Default)]
+    struct FakeSystem {
+        done: Vec<StepId>,
+        applied: Vec<StepId>,
+        needs_restart: Vec<StepId>,
+        broken: Vec<StepId>,
+
+        /// Last boot time of Windows
+        booted_at: String,
+    }
+
+    impl SetupStep for FakeStep {
+        fn id(&self) -> StepId {
+            self.id
+        }
+
+        fn description(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn check(&self, _ctx: &mut SetupContext<'_>) -> Result<bool> {
+            Ok(self.system.borrow().done.contains(&self.id))
+        }
+
+        fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome> {
+            let mut system = self.system.borrow_mut();
+            system.applied.push(self.id);
+            if self.id == StepId::InstallDistro {
+                ctx.distro = Some("Debian".to_string());
+            }
+            if system.needs_restart.contains(&self.id) {
+                return Ok(StepOutcome::RestartRequired);
+            }
+            if !system.broken.contains(&self.id) {
+                system.done.push(self.id);
+            }
+            Ok(StepOutcome::Done)
+        }
+    }
+
+    fn wizard(system: &Rc<RefCell<FakeSystem>>, state_path: PathBuf) -> SetupWizard {
+        let steps = [StepId::InstallWsl, StepId::InstallDistro, StepId::InstallNix, StepId::ConfigureNix]
+            .into_iter()
+            .map(|id| Box::new(FakeStep { id, system: system.clone() }) as Box<dyn SetupStep>)
+            .collect();
+
+        let runner = MockCommandRunner::new();
+        runner.set_response(
+            "powershell.exe -NoProfile -Command (Get-CimInstance Win32_OperatingSystem).LastBootUpTime.ToString('o')",
+            crate::wsl2::CommandOutput::new(system.borrow().booted_at.clone(), String::new(), 0),
+        );
+        SetupWizard::new(true, false)
+            .with_state_path(state_path)
+            .with_runner(Box::new(runner))
+            .with_steps(steps)
+    }
+
+    #[test]
+    fn test_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup-state.json");
+        let system = Rc::new(RefCell::new(FakeSystem {
+            needs_restart: vec![StepId::InstallWsl],
+            booted_at: "2026-10-18T08:00:00.0000000+02:00".to_string(),
+            ..Default::default()
+        }));
+
+        wizard(&system, path.clone()).run().unwrap();
+        assert_eq!(system.borrow().applied, vec![StepId::InstallWsl]);
+        assert_eq!(SetupState::load_from(&path).unwrap().awaiting_restart, Some(StepId::InstallWsl));
+
+        // Rerunning before the restart does not apply the step again, however often
+        assert!(wizard(&system, path.clone()).run().is_err());
+        assert!(wizard(&system, path.clone()).run().is_err());
+        assert_eq!(system.borrow().applied, vec![StepId::InstallWsl]);
+
+        // The restart finished installing WSL
+        system.borrow_mut().booted_at = "2026-10-18T09:30:00.0000000+02:00".to_string();
+        system.borrow_mut().done.push(StepId::InstallWsl);
+        wizard(&system, path.clone()).run().unwrap();
+        assert_eq!(system.borrow().applied, vec![
+            StepId::InstallWsl, StepId::InstallDistro, StepId::InstallNix, StepId::ConfigureNix,
+        ]);
+
+        let state = SetupState::load_from(&path).unwrap();
+        assert_eq!(state.completed.len(), 4);
+        assert_eq!(state.distro.as_deref(), Some("Debian"));
+        assert_eq!(state.awaiting_restart, None);
+    }
+
+    #[test]
+    fn test_step_is_applied_again_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup-state.json");
+        let system = Rc::new(RefCell::new(FakeSystem {
+            needs_restart: vec![StepId::InstallWsl],
+            booted_at: "2026-10-18T08:00:00.0000000+02:00".to_string(),
+            ..Default::default()
+        }));
+        wizard(&system, path.clone()).run().unwrap();
+
+        // Windows restarted, but WSL is still not installed
+        system.borrow_mut().booted_at = "2026-10-18T09:30:00.0000000+02:00".to_string();
+        system.borrow_mut().needs_restart.clear();
+        wizard(&system, path.clone()).run().unwrap();
+        assert_eq!(system.borrow().applied[..2], [StepId::InstallWsl, StepId::InstallWsl]);
+        assert_eq!(SetupState::load_from(&path).unwrap().completed.len(), 4);
+    }
+
+    #[test]
+    fn test_failure_records_step_and_phase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup-state.json");
+        let system = Rc::new(RefCell::new(FakeSystem {
+            done: vec![StepId::InstallWsl, StepId::InstallDistro],
+            broken: vec![StepId::InstallNix],
+            ..Default::default()
+        }));
+
+        let error = wizard(&system, path.clone()).run().unwrap_err();
+        assert_eq!(error.to_string(), "Setup failed at step 'install-nix' (verify phase)");
+
+        let state = SetupState::load_from(&path).unwrap();
+        let failure = state.last_failure.as_ref().unwrap();
+        assert_eq!((failure.step, failure.phase), (StepId::InstallNix, Phase::Verify));
+        assert!(state.is_completed(StepId::InstallDistro));
+        assert!(!state.is_completed(StepId::InstallNix));
+
+        // Once fixed, a rerun only does what is left
+        system.borrow_mut().broken.clear();
+        system.borrow_mut().applied.clear();
+        wizard(&system, path.clone()).run().unwrap();
+        assert_eq!(system.borrow().applied, vec![StepId::InstallNix, StepId::ConfigureNix]);
+        assert!(SetupState::load_from(&path).unwrap().last_failure.is_none());
+
+        // Nothing to do on a configured system
+        system.borrow_mut().applied.clear();
+        wizard(&system, path).run().unwrap();
+        assert!(system.borrow().applied.is_empty());
     }
 }
@@ -1,8 +1,9 @@
 //! Nix installation and configuration for WSL2
 
 use anyhow::{Result, Context, bail};
-use std::process::Command;
 use crate::ui::{OutputFormatter, MessageType, ProgressIndicator};
+use super::runner::CommandRunner;
+use super::sources::{shell_quote, InstallerKind, LocalFile, SetupSources};
 use super::wsl_command;
 
 #[derive(Debug, Default)]
@@ -14,15 +15,15 @@ pub struct NixStatus {
     pub experimental_enabled: bool,
 }
 
-pub fn check_nix(distro: &str) -> Result<NixStatus> {
+pub fn check_nix(runner: &dyn CommandRunner, distro: &str) -> Result<NixStatus> {
     // Check if 'nix' command exists in WSL
     // Use PowerShell wrapper to avoid UTF-16 encoding issues
     log::debug!("Checking for Nix in distro: '{}'", distro);
 
     let is_installed = if !distro.is_empty() {
-        wsl_command::wsl_command_exists(&["-d", distro, "--", "which", "nix"])
+        wsl_command::wsl_command_exists(runner, &["-d", distro, "--", "which", "nix"])
     } else {
-        wsl_command::wsl_command_exists(&["--", "which", "nix"])
+        wsl_command::wsl_command_exists(runner, &["--", "which", "nix"])
     };
 
     log::debug!("Nix installed: {}", is_installed);
@@ -32,16 +33,16 @@ pub fn check_nix(distro: &str) -> Result<NixStatus> {
     }
 
     // Get version
-    let version = get_nix_version(distro)?;
+    let version = get_nix_version(runner, distro)?;
 
     // Check daemon
-    let daemon_running = check_daemon_running(distro)?;
+    let daemon_running = check_daemon_running(runner, distro)?;
 
     // Check user group
-    let in_nix_users = check_nix_users_group(distro)?;
+    let in_nix_users = check_nix_users_group(runner, distro)?;
 
     // Check experimental features
-    let experimental_enabled = check_experimental_features(distro)?;
+    let experimental_enabled = check_experimental_features(runner, distro)?;
 
     Ok(NixStatus {
         is_installed: true,
@@ -52,7 +53,7 @@ pub fn check_nix(distro: &str) -> Result<NixStatus> {
     })
 }
 
-pub fn install_nix(distro: &str, auto_yes: bool) -> Result<()> {
+pub fn install_nix(runner: &dyn CommandRunner, distro: &str, auto_yes: bool) -> Result<()> {
     eprintln!();
     eprintln!("{}", OutputFormatter::format_message(
         MessageType::Info,
@@ -68,27 +69,61 @@ pub fn install_nix(distro: &str, auto_yes: bool) -> Result<()> {
     );
 
     progress.set_message("Installing Nix...");
+    run_installer(runner, distro, &install_cmd, progress)
+}
+
+/// Install Nix from a local installer, without network access
+///
+/// The installer is checked against its checksum first. A release tarball
+/// from nixos.org is unpacked and its `install` script run; anything else
+/// is run as a `nix-installer` binary.
+pub fn install_nix_from(runner: &dyn CommandRunner, distro: &str, installer: &LocalFile, auto_yes: bool) -> Result<()> {
+    eprintln!();
+    eprintln!("{}", OutputFormatter::format_message(
+        MessageType::Info,
+        &format!("📦 Installing Nix from {}...", installer.path.display())
+    ));
+
+    installer.verify()?;
+
+    let path = shell_quote(&installer.wsl_path()?);
+    let install_cmd = match InstallerKind::of(&installer.path) {
+        InstallerKind::Tarball => format!(
+            "set -e\ndir=$(mktemp -d)\ntrap 'rm -rf \"$dir\"' EXIT\ntar -xf {} -C \"$dir\"\n\"$dir\"/nix-*/install --daemon {}",
+            path,
+            if auto_yes { "--yes" } else { "" }
+        ),
+        InstallerKind::Binary => format!(
+            "set -e\ninstaller=$(mktemp)\ntrap 'rm -f \"$installer\"' EXIT\ncp {} \"$installer\"\nchmod +x \"$installer\"\n\"$installer\" install linux --init none {}",
+            path,
+            if auto_yes { "--no-confirm" } else { "" }
+        ),
+    };
+
+    let progress = ProgressIndicator::spinner("Installing Nix...");
+    run_installer(runner, distro, &install_cmd, progress)
+}
 
-    let output = Command::new("wsl")
-        .args(&["-d", distro, "--", "bash", "-c", &install_cmd])
-        .output()
+/// Run an installer script in the distribution, reporting failures
+fn run_installer(runner: &dyn CommandRunner, distro: &str, install_cmd: &str, progress: ProgressIndicator) -> Result<()> {
+    let output = runner.run("wsl", &["-d", distro, "--", "bash", "-c", install_cmd])
         .context("Failed to install Nix")?;
 
     progress.finish_and_clear();
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.is_success() {
+        let stderr = &output.stderr;
+        let stdout = &output.stdout;
 
         log::error!("Nix installation command failed");
         log::error!("Command: {}", install_cmd);
-        log::error!("Exit code: {:?}", output.status.code());
+        log::error!("Exit code: {}", output.exit_code);
         log::error!("Stdout: {}", stdout);
         log::error!("Stderr: {}", stderr);
 
         eprintln!("{}", OutputFormatter::format_message(
             MessageType::Error,
-            &format!("Nix installation failed. Run with --verbose for details.")
+            "Nix installation failed. Run with --verbose for details."
         ));
 
         if log::log_enabled!(log::Level::Debug) {
@@ -103,13 +138,14 @@ pub fn install_nix(distro: &str, auto_yes: bool) -> Result<()> {
         "✓ Nix installed successfully"
     ));
 
-    // Configure Nix
-    configure_nix(distro)?;
-
     Ok(())
 }
 
-fn configure_nix(distro: &str) -> Result<()> {
+/// Enable flakes, join the nix-users group and add the nixpkgs channel
+///
+/// Offline setups register the local nixpkgs from `sources`, if any,
+/// instead of the online channel.
+pub fn configure_nix(runner: &dyn CommandRunner, distro: &str, sources: &SetupSources) -> Result<()> {
     eprintln!("{}", OutputFormatter::format_message(
         MessageType::Info,
         "⚙️  Configuring Nix..."
@@ -123,9 +159,7 @@ if ! grep -q 'experimental-features' ~/.config/nix/nix.conf 2>/dev/null; then
 fi
 "#;
 
-    Command::new("wsl")
-        .args(&["-d", distro, "--", "bash", "-c", config_cmd])
-        .output()
+    runner.run("wsl", &["-d", distro, "--", "bash", "-c", config_cmd])
         .context("Failed to configure Nix")?;
 
     eprintln!("{}", OutputFormatter::format_message(
@@ -134,28 +168,32 @@ fi
     ));
 
     // Add user to nix-users group if not already
-    add_to_nix_users(distro)?;
+    add_to_nix_users(runner, distro)?;
 
     // Configure channels
-    configure_channels(distro)?;
+    configure_channels(runner, distro, sources)?;
 
     Ok(())
 }
 
-fn add_to_nix_users(distro: &str) -> Result<()> {
-    let groups = wsl_command::run_wsl_distro_command(distro, &["groups"])?;
+fn add_to_nix_users(runner: &dyn CommandRunner, distro: &str) -> Result<()> {
+    let groups = wsl_command::run_wsl_distro_command(runner, distro, &["groups"])?;
+
+    // Installers that open the daemon to all users create no nix-users group
+    let group_exists = wsl_command::wsl_command_exists(runner, &["-d", distro, "--", "getent", "group", "nix-users"]);
 
-    if !groups.contains("nix-users") {
+    if group_exists && !groups.contains("nix-users") {
         eprintln!("{}", OutputFormatter::format_message(
             MessageType::Info,
             "Adding user to nix-users group..."
         ));
 
-        let username = wsl_command::run_wsl_distro_command(distro, &["whoami"])?;
+        let username = wsl_command::run_wsl_distro_command(runner, distro, &["whoami"])?;
         let username = username.trim();
 
         // Use bash -c to run the sudo command
         wsl_command::run_wsl_distro_command(
+            runner,
             distro,
             &["bash", "-c", &format!("sudo usermod -a -G nix-users {}", username)]
         ).context("Failed to add user to nix-users group")?;
@@ -169,27 +207,44 @@ fn add_to_nix_users(distro: &str) -> Result<()> {
     Ok(())
 }
 
-fn configure_channels(distro: &str) -> Result<()> {
+fn configure_channels(runner: &dyn CommandRunner, distro: &str, sources: &SetupSources) -> Result<()> {
+    let url = match &sources.nixpkgs {
+        Some(nixpkgs) => {
+            nixpkgs.verify()?;
+            // nix-channel unpacks URLs ending in .tar.xz itself, file:// ones included
+            format!("file://{}", nixpkgs.wsl_path()?.replace(' ', "%20"))
+        }
+        None if sources.is_offline() => {
+            eprintln!("{}", OutputFormatter::format_message(
+                MessageType::Warning,
+                "Skipping Nix channels on an offline setup; pass --nixpkgs <nixexprs.tar.xz> to register a local nixpkgs"
+            ));
+            return Ok(());
+        }
+        None => "https://nixos.org/channels/nixpkgs-unstable".to_string(),
+    };
+
     eprintln!("{}", OutputFormatter::format_message(
         MessageType::Info,
         "Configuring Nix channels..."
     ));
 
-    // Add nixpkgs-unstable channel
-    Command::new("wsl")
-        .args(&["-d", distro, "--", "nix-channel", "--add", "https://nixos.org/channels/nixpkgs-unstable", "nixpkgs"])
-        .output()?;
+    // Add the nixpkgs channel
+    runner.run("wsl", &["-d", distro, "--", "nix-channel", "--add", &url, "nixpkgs"])?;
 
     // Update channels (this can take a while)
     let progress = ProgressIndicator::spinner("Updating channels...");
 
-    let update_result = Command::new("wsl")
-        .args(&["-d", distro, "--", "nix-channel", "--update"])
-        .output()?;
+    let update_result = runner.run("wsl", &["-d", distro, "--", "nix-channel", "--update"])?;
 
     progress.finish_and_clear();
 
-    if update_result.status.success() {
+    // Nothing needs the network here, so failures are real
+    if sources.nixpkgs.is_some() && !update_result.is_success() {
+        bail!("Failed to register the local nixpkgs: {}", update_result.stderr.trim());
+    }
+
+    if update_result.is_success() {
         eprintln!("{}", OutputFormatter::format_message(
             MessageType::Success,
             "✓ Channels configured and updated"
@@ -199,22 +254,22 @@ fn configure_channels(distro: &str) -> Result<()> {
     Ok(())
 }
 
-fn get_nix_version(distro: &str) -> Result<String> {
-    let version = wsl_command::run_wsl_distro_command(distro, &["nix", "--version"])?;
+fn get_nix_version(runner: &dyn CommandRunner, distro: &str) -> Result<String> {
+    let version = wsl_command::run_wsl_distro_command(runner, distro, &["nix", "--version"])?;
     Ok(version.trim().to_string())
 }
 
-fn check_daemon_running(distro: &str) -> Result<bool> {
-    Ok(wsl_command::wsl_command_exists(&["-d", distro, "--", "pgrep", "nix-daemon"]))
+fn check_daemon_running(runner: &dyn CommandRunner, distro: &str) -> Result<bool> {
+    Ok(wsl_command::wsl_command_exists(runner, &["-d", distro, "--", "pgrep", "nix-daemon"]))
 }
 
-fn check_nix_users_group(distro: &str) -> Result<bool> {
-    let groups = wsl_command::run_wsl_distro_command(distro, &["groups"])?;
+fn check_nix_users_group(runner: &dyn CommandRunner, distro: &str) -> Result<bool> {
+    let groups = wsl_command::run_wsl_distro_command(runner, distro, &["groups"])?;
     Ok(groups.contains("nix-users"))
 }
 
-fn check_experimental_features(distro: &str) -> Result<bool> {
-    match wsl_command::run_wsl_distro_command(distro, &["cat", "~/.config/nix/nix.conf"]) {
+fn check_experimental_features(runner: &dyn CommandRunner, distro: &str) -> Result<bool> {
+    match wsl_command::run_wsl_distro_command(runner, distro, &["cat", "~/.config/nix/nix.conf"]) {
         Ok(config) => Ok(config.contains("experimental-features")),
         Err(_) => Ok(false), // File doesn't exist or can't be read
     }
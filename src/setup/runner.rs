@@ -0,0 +1,221 @@
+/// Command execution for setup
+///
+/// Setup runs Windows programs (`wsl`, `powershell.exe`) before any Nix or
+/// WSL2 bridge exists. Going through `CommandRunner` instead of
+/// `std::process::Command` lets the whole wizard run against a
+/// `MockCommandRunner` on any platform.
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::process::Command;
+use std::rc::Rc;
+
+use crate::wsl2::CommandOutput;
+
+/// Runs programs on the host
+pub trait CommandRunner {
+    /// Run a program, capturing its output
+    ///
+    /// Fails only if the program could not be started; a non-zero exit code
+    /// is reported in the output.
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput>;
+
+    /// Run a program, returning whether it exited successfully
+    fn succeeds(&self, program: &str, args: &[&str]) -> bool {
+        match self.run(program, args) {
+            Ok(output) => output.is_success(),
+            Err(e) => {
+                log::debug!("{} {:?} could not run: {}", program, args, e);
+                false
+            }
+        }
+    }
+}
+
+impl<R: CommandRunner + ?Sized> CommandRunner for Rc<R> {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        (**self).run(program, args)
+    }
+}
+
+/// Runs programs with `std::process::Command`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        log::debug!("Running: {} {}", program, args.join(" "));
+
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|e| anyhow!("Failed to execute {}: {}", program, e))?;
+
+        Ok(CommandOutput::new(
+            decode_output(&output.stdout),
+            decode_output(&output.stderr),
+            output.status.code().unwrap_or(-1),
+        ))
+    }
+}
+
+/// Decode program output, which is UTF-16 LE for `wsl` and PowerShell
+///
+/// UTF-16 is recognized by its byte order mark or by the zero high bytes of
+/// ASCII characters; anything else is decoded as UTF-8.
+pub fn decode_output(bytes: &[u8]) -> String {
+    let is_utf16 = bytes.len().is_multiple_of(2)
+        && (bytes.starts_with(&[0xff, 0xfe]) || bytes.iter().skip(1).step_by(2).any(|&b| b == 0));
+
+    if !is_utf16 {
+        return String::from_utf8_lossy(bytes).to_string();
+    }
+
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+
+    String::from_utf16_lossy(&units).trim_start_matches('\u{feff}').to_string()
+}
+
+/// Scripted runner for testing setup without Windows
+///
+/// Commands are matched by their full command line (program and arguments
+/// joined with spaces); a line ending in ` *` matches any command line
+/// starting with what precedes the `*`. Exact matches win over wildcards.
+/// Unknown commands fail to start, like a missing program. Every command run
+/// is recorded.
+#[derive(Debug, Default)]
+pub struct MockCommandRunner {
+    responses: RefCell<HashMap<String, CommandOutput>>,
+
+    /// Responses that take effect once a command has run
+    effects: RefCell<HashMap<String, Vec<(String, CommandOutput)>>>,
+
+    calls: RefCell<Vec<String>>,
+}
+
+impl MockCommandRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the response to a command line
+    ///
+    /// # Example
+    /// ```
+    /// use nsfw::setup::MockCommandRunner;
+    /// use nsfw::wsl2::CommandOutput;
+    ///
+    /// let runner = MockCommandRunner::new();
+    /// runner.set_response("wsl --status", CommandOutput::new("Default Version: 2".to_string(), String::new(), 0));
+    /// ```
+    pub fn set_response(&self, command: &str, output: CommandOutput) {
+        self.responses.borrow_mut().insert(command.to_string(), output);
+    }
+
+    /// Change the response to `command` once `trigger` has run
+    ///
+    /// Models commands that change the system, e.g. `wsl --install` making
+    /// `wsl --status` succeed.
+    pub fn set_response_after(&self, trigger: &str, command: &str, output: CommandOutput) {
+        self.effects.borrow_mut()
+            .entry(trigger.to_string())
+            .or_default()
+            .push((command.to_string(), output));
+    }
+
+    /// Command lines run so far, in order
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+
+    /// Check if a command line has run
+    pub fn was_called(&self, command: &str) -> bool {
+        self.calls.borrow().iter().any(|call| call == command)
+    }
+}
+
+impl CommandRunner for MockCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        let command = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>().join(" ");
+        self.calls.borrow_mut().push(command.clone());
+
+        let output = lookup(&self.responses.borrow(), &command).cloned();
+
+        let trigger = lookup_key(&self.effects.borrow(), &command);
+        if let Some(effects) = trigger.and_then(|trigger| self.effects.borrow_mut().remove(&trigger)) {
+            self.responses.borrow_mut().extend(effects);
+        }
+
+        output.ok_or_else(|| anyhow!("No mock response configured for command: '{}'", command))
+    }
+}
+
+/// Key matching a command line, preferring an exact match over the longest wildcard
+fn lookup_key<V>(map: &HashMap<String, V>, command: &str) -> Option<String> {
+    if map.contains_key(command) {
+        return Some(command.to_string());
+    }
+
+    map.keys()
+        .filter(|key| key.strip_suffix(" *").is_some_and(|prefix| command.starts_with(prefix)))
+        .max_by_key(|key| key.len())
+        .cloned()
+}
+
+fn lookup<'m, V>(map: &'m HashMap<String, V>, command: &str) -> Option<&'m V> {
+    map.get(&lookup_key(map, command)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn test_decode_output() {
+        assert_eq!(decode_output(&utf16("Ubuntu  Running  2")), "Ubuntu  Running  2");
+
+        let mut with_bom = vec![0xff, 0xfe];
+        with_bom.extend(utf16("Débian"));
+        assert_eq!(decode_output(&with_bom), "Débian");
+
+        assert_eq!(decode_output("nix (Nix) 2.18.1\n".as_bytes()), "nix (Nix) 2.18.1\n");
+        assert_eq!(decode_output(b"odd"), "odd");
+        assert_eq!(decode_output(b""), "");
+    }
+
+    #[test]
+    fn test_mock_effects_and_calls() {
+        let runner = MockCommandRunner::new();
+        runner.set_response("wsl --status", CommandOutput::new(String::new(), String::new(), 1));
+        runner.set_response("wsl --install", CommandOutput::new(String::new(), String::new(), 0));
+        runner.set_response_after("wsl --install", "wsl --status", CommandOutput::new(String::new(), String::new(), 0));
+
+        assert!(!runner.succeeds("wsl", &["--status"]));
+        assert!(runner.succeeds("wsl", &["--install"]));
+        assert!(runner.succeeds("wsl", &["--status"]));
+        assert!(runner.run("wsl", &["--unregister", "Ubuntu"]).is_err());
+
+        assert_eq!(runner.calls(), vec!["wsl --status", "wsl --install", "wsl --status", "wsl --unregister Ubuntu"]);
+        assert!(runner.was_called("wsl --install"));
+    }
+
+    #[test]
+    fn test_mock_wildcards() {
+        let runner = MockCommandRunner::new();
+        runner.set_response("wsl -d Debian -- *", CommandOutput::new("any".to_string(), String::new(), 0));
+        runner.set_response("wsl -d Debian -- bash -c *", CommandOutput::new("script".to_string(), String::new(), 0));
+        runner.set_response("wsl -d Debian -- whoami", CommandOutput::new("alice".to_string(), String::new(), 0));
+
+        assert_eq!(runner.run("wsl", &["-d", "Debian", "--", "whoami"]).unwrap().stdout, "alice");
+        assert_eq!(runner.run("wsl", &["-d", "Debian", "--", "bash", "-c", "echo\nhi"]).unwrap().stdout, "script");
+        assert_eq!(runner.run("wsl", &["-d", "Debian", "--", "id"]).unwrap().stdout, "any");
+        assert!(runner.run("wsl", &["-d", "Ubuntu", "--", "id"]).is_err());
+    }
+}
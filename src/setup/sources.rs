@@ -0,0 +1,220 @@
+//! Local installation sources for offline setup
+//!
+//! By default setup downloads the Linux distribution from the Microsoft
+//! Store and pipes the Nix installer from the internet into `sh`. Locked-down
+//! and air-gapped machines can instead point setup at files on disk: a
+//! distribution image (`.wsl` or rootfs tarball) registered with
+//! `wsl --import`, a Nix installer tarball or `nix-installer` binary, and a
+//! nixpkgs channel tarball to register instead of the online channel. Files
+//! are checked against their SHA-256 before they are used.
+//!
+//! Sources come from the `setup` section of `config.json` and the
+//! `nsfw setup` flags, which take precedence.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::path_translation::PathTranslator;
+use crate::ui::{MessageType, OutputFormatter};
+
+/// A file on the Windows side, optionally pinned to a checksum
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalFile {
+    pub path: PathBuf,
+
+    /// Expected SHA-256 as hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl LocalFile {
+    pub fn new(path: PathBuf, sha256: Option<String>) -> Self {
+        Self { path, sha256 }
+    }
+
+    /// Check that the file exists and matches its checksum
+    ///
+    /// Without a pinned checksum the file is used as is and its checksum is
+    /// printed so it can be pinned.
+    pub fn verify(&self) -> Result<()> {
+        let actual = sha256_file(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+
+        match &self.sha256 {
+            Some(expected) if !expected.trim().eq_ignore_ascii_case(&actual) => bail!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                self.path.display(),
+                expected.trim(),
+                actual
+            ),
+            Some(_) => log::debug!("{} matches its SHA-256", self.path.display()),
+            None => eprintln!("{}", OutputFormatter::format_message(
+                MessageType::Warning,
+                &format!("No checksum given for {} (SHA-256 {})", self.path.display(), actual)
+            )),
+        }
+
+        Ok(())
+    }
+
+    /// The file's path as seen from inside WSL
+    pub fn wsl_path(&self) -> Result<String> {
+        to_wsl_path(&self.path)
+    }
+}
+
+/// Where setup installs the distribution and Nix from
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetupSources {
+    /// Name of the distribution to install or import
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro: Option<String>,
+
+    /// Distribution image to `wsl --import` instead of downloading one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro_image: Option<LocalFile>,
+
+    /// Directory the imported distribution's disk is stored in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro_install_dir: Option<PathBuf>,
+
+    /// Nix installer to run instead of downloading one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nix_installer: Option<LocalFile>,
+
+    /// nixpkgs channel tarball (`nixexprs.tar.xz`) to register instead of
+    /// the online channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixpkgs: Option<LocalFile>,
+}
+
+impl SetupSources {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check if setup installs from local files, so it must not download
+    pub fn is_offline(&self) -> bool {
+        self.distro_image.is_some() || self.nix_installer.is_some() || self.nixpkgs.is_some()
+    }
+
+    /// Directory an imported distribution is stored in
+    ///
+    /// Defaults to `%LOCALAPPDATA%\nsfw\distros\<name>`.
+    pub fn install_dir(&self, distro: &str) -> Result<PathBuf> {
+        match &self.distro_install_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => Ok(dirs::data_local_dir()
+                .context("Could not find local data directory")?
+                .join("nsfw")
+                .join("distros")
+                .join(distro)),
+        }
+    }
+}
+
+/// Kind of a local Nix installer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallerKind {
+    /// Release tarball from nixos.org with an `install` script inside
+    Tarball,
+
+    /// Self-contained `nix-installer` binary from Determinate Systems
+    Binary,
+}
+
+impl InstallerKind {
+    /// Tell the kind from the file name
+    pub fn of(path: &Path) -> Self {
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if [".tar.xz", ".tar.gz", ".tgz", ".tar"].iter().any(|ext| name.ends_with(ext)) {
+            InstallerKind::Tarball
+        } else {
+            InstallerKind::Binary
+        }
+    }
+}
+
+/// Translate a Windows path for use inside WSL
+///
+/// Paths that are already Linux paths are kept.
+pub fn to_wsl_path(path: &Path) -> Result<String> {
+    let path = path.to_str()
+        .ok_or_else(|| anyhow!("Path is not valid Unicode: {}", path.display()))?;
+
+    if path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+    PathTranslator::new().to_linux(path)
+}
+
+/// Quote a string for `bash -c`
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// SHA-256 of a file as hex
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NIX_SHA256: &str = "a0745f7ed88cfac6058d1d4cfb57bc71127a28ad7c6756004142a90aab5ee99f";
+
+    #[test]
+    fn test_verify_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("installer");
+        std::fs::write(&path, "nix").unwrap();
+        assert_eq!(sha256_file(&path).unwrap(), NIX_SHA256);
+
+        assert!(LocalFile::new(path.clone(), None).verify().is_ok());
+        assert!(LocalFile::new(path.clone(), Some(NIX_SHA256.to_uppercase())).verify().is_ok());
+
+        std::fs::write(&path, "tampered").unwrap();
+        let error = LocalFile::new(path.clone(), Some(NIX_SHA256.to_string())).verify().unwrap_err();
+        assert!(error.to_string().starts_with("Checksum mismatch"), "{}", error);
+
+        assert!(LocalFile::new(dir.path().join("missing"), None).verify().is_err());
+    }
+
+    #[test]
+    fn test_installer_kind() {
+        assert_eq!(InstallerKind::of(Path::new("nix-2.24.10-x86_64-linux.tar.xz")), InstallerKind::Tarball);
+        assert_eq!(InstallerKind::of(Path::new("NIX.TGZ")), InstallerKind::Tarball);
+        assert_eq!(InstallerKind::of(Path::new("nix-installer-x86_64-linux")), InstallerKind::Binary);
+    }
+
+    #[test]
+    fn test_wsl_paths_and_quoting() {
+        assert_eq!(to_wsl_path(Path::new(r"D:\Offline\nix.tar.xz")).unwrap(), "/mnt/d/Offline/nix.tar.xz");
+        assert_eq!(to_wsl_path(Path::new("/tmp/nix.tar.xz")).unwrap(), "/tmp/nix.tar.xz");
+        assert_eq!(shell_quote("/mnt/c/it's here"), r"'/mnt/c/it'\''s here'");
+    }
+
+    #[test]
+    fn test_sources_config_roundtrip() {
+        let sources = SetupSources {
+            nix_installer: Some(LocalFile::new(PathBuf::from("nix-installer"), Some("ab".to_string()))),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&sources).unwrap();
+        assert_eq!(json, r#"{"nix_installer":{"path":"nix-installer","sha256":"ab"}}"#);
+        assert_eq!(serde_json::from_str::<SetupSources>(&json).unwrap(), sources);
+        assert!(SetupSources::default().is_empty());
+        assert!(sources.is_offline());
+        assert!(!SetupSources { distro: Some("Debian".to_string()), ..Default::default() }.is_offline());
+    }
+}
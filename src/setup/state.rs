@@ -0,0 +1,217 @@
+//! Persisted progress of the setup wizard
+//!
+//! Stored as JSON next to the user configuration (`setup-state.json`), so a
+//! rerun of `nsfw setup`, for example after the restart WSL2 needs, knows
+//! which steps finished, which distribution was chosen and where the last
+//! run failed.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{load_json_file, save_json_file, Config};
+
+/// A step of the setup, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepId {
+    InstallWsl,
+    InstallDistro,
+    InstallNix,
+    ConfigureNix,
+}
+
+impl fmt::Display for StepId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = match self {
+            StepId::InstallWsl => "install-wsl",
+            StepId::InstallDistro => "install-distro",
+            StepId::InstallNix => "install-nix",
+            StepId::ConfigureNix => "configure-nix",
+        };
+        write!(f, "{}", id)
+    }
+}
+
+/// Phase of a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Finding out whether the step is already done
+    Check,
+    /// Doing the step
+    Apply,
+    /// Confirming the step took effect
+    Verify,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            Phase::Check => "check",
+            Phase::Apply => "apply",
+            Phase::Verify => "verify",
+        };
+        write!(f, "{}", phase)
+    }
+}
+
+/// Where a setup run failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepFailure {
+    pub step: StepId,
+    pub phase: Phase,
+    pub message: String,
+
+    /// Unix timestamp of the failure
+    pub failed_at: i64,
+}
+
+/// Setup progress across runs
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetupState {
+    /// Finished steps with the Unix timestamp they finished at
+    #[serde(default)]
+    pub completed: BTreeMap<StepId, i64>,
+
+    /// Distribution Nix is installed into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro: Option<String>,
+
+    /// Step that finishes once Windows has restarted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub awaiting_restart: Option<StepId>,
+
+    /// When Windows had last booted as the restart was asked for
+    ///
+    /// A different boot time on a later run means Windows has restarted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_requested_at_boot: Option<String>,
+
+    /// Failure of the last run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<StepFailure>,
+}
+
+impl SetupState {
+    /// Default location of the state file
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(Config::default_path()?.with_file_name("setup-state.json"))
+    }
+
+    /// Load the state from a file, starting fresh when it does not exist
+    pub fn load_from(path: &Path) -> Result<Self> {
+        load_json_file(path, "setup state file")
+    }
+
+    /// Save the state to a file
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        save_json_file(self, path)
+    }
+
+    /// Check if a step finished in an earlier run
+    pub fn is_completed(&self, step: StepId) -> bool {
+        self.completed.contains_key(&step)
+    }
+
+    /// Record that a step finished, keeping the time it first did
+    pub fn mark_completed(&mut self, step: StepId) {
+        self.completed.entry(step).or_insert_with(|| chrono::Utc::now().timestamp());
+        if self.awaiting_restart == Some(step) {
+            self.clear_restart();
+        }
+        if self.last_failure.as_ref().is_some_and(|f| f.step == step) {
+            self.last_failure = None;
+        }
+    }
+
+    /// Record that a step finishes only after a restart of Windows
+    pub fn request_restart(&mut self, step: StepId, last_boot: Option<String>) {
+        self.awaiting_restart = Some(step);
+        self.restart_requested_at_boot = last_boot;
+    }
+
+    /// Check if Windows is known not to have restarted since the restart was
+    /// asked for
+    ///
+    /// Without boot times to compare, a rerun is taken as after the restart.
+    pub fn restart_pending(&self, last_boot: Option<&str>) -> bool {
+        self.awaiting_restart.is_some()
+            && matches!((self.restart_requested_at_boot.as_deref(), last_boot), (Some(then), Some(now)) if then == now)
+    }
+
+    /// Forget about a requested restart
+    pub fn clear_restart(&mut self) {
+        self.awaiting_restart = None;
+        self.restart_requested_at_boot = None;
+    }
+
+    /// Record where a run failed
+    pub fn record_failure(&mut self, step: StepId, phase: Phase, message: String) {
+        self.completed.remove(&step);
+        self.last_failure = Some(StepFailure {
+            step,
+            phase,
+            message,
+            failed_at: chrono::Utc::now().timestamp(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nsfw").join("setup-state.json");
+        assert_eq!(SetupState::load_from(&path).unwrap(), SetupState::default());
+
+        let mut state = SetupState {
+            distro: Some("Ubuntu-24.04".to_string()),
+            awaiting_restart: Some(StepId::InstallWsl),
+            ..Default::default()
+        };
+        state.mark_completed(StepId::InstallWsl);
+        state.record_failure(StepId::InstallNix, Phase::Verify, "nix not found".to_string());
+        state.save_to(&path).unwrap();
+
+        let loaded = SetupState::load_from(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.is_completed(StepId::InstallWsl));
+        assert_eq!(loaded.awaiting_restart, None);
+        assert_eq!(loaded.last_failure.as_ref().unwrap().phase, Phase::Verify);
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"install_wsl\""));
+    }
+
+    #[test]
+    fn test_restart_pending_until_next_boot() {
+        let boot = "2026-10-18T08:00:00.0000000+02:00";
+        let mut state = SetupState::default();
+        assert!(!state.restart_pending(Some(boot)));
+
+        state.request_restart(StepId::InstallWsl, Some(boot.to_string()));
+        assert!(state.restart_pending(Some(boot)));
+        assert!(!state.restart_pending(Some("2026-10-18T09:30:00.0000000+02:00")));
+        assert!(!state.restart_pending(None));
+
+        state.mark_completed(StepId::InstallWsl);
+        assert_eq!((state.awaiting_restart, state.restart_requested_at_boot), (None, None));
+    }
+
+    #[test]
+    fn test_completing_failed_step_clears_failure() {
+        let mut state = SetupState::default();
+        state.record_failure(StepId::ConfigureNix, Phase::Apply, "sudo failed".to_string());
+        state.mark_completed(StepId::InstallNix);
+        assert!(state.last_failure.is_some());
+
+        state.mark_completed(StepId::ConfigureNix);
+        assert!(state.last_failure.is_none());
+    }
+}
@@ -0,0 +1,237 @@
+//! Steps of the setup wizard
+//!
+//! Each step can check whether it is already done, apply itself and verify
+//! that applying it worked. Checks make reruns of `nsfw setup` skip what is
+//! already in place, whether an earlier run or the user did it.
+
+use anyhow::{bail, Result};
+
+use super::state::StepId;
+use super::runner::CommandRunner;
+use super::sources::SetupSources;
+use super::{distro_installer, nix_installer, wsl_detector};
+
+/// Distribution installed when the user does not choose one
+pub const DEFAULT_DISTRO: &str = "Ubuntu-24.04";
+
+/// What the steps share during a run
+pub struct SetupContext<'a> {
+    /// Runs `wsl` and PowerShell
+    pub runner: &'a dyn CommandRunner,
+
+    /// Distribution Nix is installed into, once known
+    pub distro: Option<String>,
+
+    /// Skip confirmation prompts
+    pub auto_yes: bool,
+
+    /// Let the user choose options
+    pub interactive: bool,
+
+    /// Local files to install from instead of downloading
+    pub sources: &'a SetupSources,
+}
+
+impl SetupContext<'_> {
+    /// The distribution, which earlier steps must have determined
+    pub fn distro(&self) -> Result<&str> {
+        match self.distro.as_deref() {
+            Some(distro) => Ok(distro),
+            None => bail!("No Linux distribution has been chosen"),
+        }
+    }
+}
+
+/// Result of applying a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The step is done and can be verified
+    Done,
+
+    /// The step finishes once Windows has restarted
+    RestartRequired,
+}
+
+/// One step of the setup
+pub trait SetupStep {
+    fn id(&self) -> StepId;
+
+    /// What the step does, for the plan and progress output
+    fn description(&self) -> String;
+
+    /// Check if the step is already done
+    fn check(&self, ctx: &mut SetupContext<'_>) -> Result<bool>;
+
+    /// Do the step
+    fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome>;
+
+    /// Confirm the step took effect
+    fn verify(&self, ctx: &mut SetupContext<'_>) -> Result<()> {
+        if !self.check(ctx)? {
+            bail!("{} did not take effect", self.description());
+        }
+        Ok(())
+    }
+}
+
+/// The steps of a full setup, in order
+pub fn default_steps() -> Vec<Box<dyn SetupStep>> {
+    vec![
+        Box::new(InstallWsl),
+        Box::new(InstallDistro),
+        Box::new(InstallNix),
+        Box::new(ConfigureNix),
+    ]
+}
+
+/// Enable WSL2
+pub struct InstallWsl;
+
+impl SetupStep for InstallWsl {
+    fn id(&self) -> StepId {
+        StepId::InstallWsl
+    }
+
+    fn description(&self) -> String {
+        "Install WSL2 (Windows Subsystem for Linux)".to_string()
+    }
+
+    fn check(&self, ctx: &mut SetupContext<'_>) -> Result<bool> {
+        Ok(wsl_detector::check_wsl2(ctx.runner)?.is_installed)
+    }
+
+    fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome> {
+        wsl_detector::install_wsl2(ctx.runner)?;
+        Ok(StepOutcome::RestartRequired)
+    }
+}
+
+/// Install the Linux distribution Nix goes into
+pub struct InstallDistro;
+
+impl SetupStep for InstallDistro {
+    fn id(&self) -> StepId {
+        StepId::InstallDistro
+    }
+
+    fn description(&self) -> String {
+        "Install a Linux distribution".to_string()
+    }
+
+    fn check(&self, ctx: &mut SetupContext<'_>) -> Result<bool> {
+        let Ok(distros) = wsl_detector::list_distros(ctx.runner) else {
+            return Ok(false);
+        };
+
+        // A distribution chosen in an earlier run must be the one installed
+        if let Some(chosen) = ctx.distro.as_deref() {
+            return Ok(distros.iter().any(|d| d.name == chosen));
+        }
+
+        match distros.iter().find(|d| d.is_default).or(distros.first()) {
+            Some(distro) => {
+                ctx.distro = Some(distro.name.clone());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome> {
+        let distro = match ctx.distro.clone() {
+            Some(distro) => distro,
+            // An imported image is whatever distribution it contains
+            None if ctx.interactive && ctx.sources.distro_image.is_none() => choose_distro()?,
+            None => DEFAULT_DISTRO.to_string(),
+        };
+
+        match &ctx.sources.distro_image {
+            Some(image) => {
+                let install_dir = ctx.sources.install_dir(&distro)?;
+                distro_installer::import_distro(ctx.runner, &distro, image, &install_dir)?;
+            }
+            None => distro_installer::install_distro(ctx.runner, &distro)?,
+        }
+        ctx.distro = Some(distro);
+        Ok(StepOutcome::Done)
+    }
+}
+
+/// Install Nix into the distribution
+pub struct InstallNix;
+
+impl SetupStep for InstallNix {
+    fn id(&self) -> StepId {
+        StepId::InstallNix
+    }
+
+    fn description(&self) -> String {
+        "Install the Nix package manager".to_string()
+    }
+
+    fn check(&self, ctx: &mut SetupContext<'_>) -> Result<bool> {
+        let Some(distro) = ctx.distro.as_deref() else {
+            return Ok(false);
+        };
+        Ok(nix_installer::check_nix(ctx.runner, distro)?.is_installed)
+    }
+
+    fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome> {
+        match &ctx.sources.nix_installer {
+            Some(installer) => nix_installer::install_nix_from(ctx.runner, ctx.distro()?, installer, ctx.auto_yes)?,
+            None => nix_installer::install_nix(ctx.runner, ctx.distro()?, ctx.auto_yes)?,
+        }
+        Ok(StepOutcome::Done)
+    }
+}
+
+/// Enable flakes, join nix-users and add the nixpkgs channel
+pub struct ConfigureNix;
+
+impl SetupStep for ConfigureNix {
+    fn id(&self) -> StepId {
+        StepId::ConfigureNix
+    }
+
+    fn description(&self) -> String {
+        "Configure Nix (flakes, nix-users group, channels)".to_string()
+    }
+
+    fn check(&self, ctx: &mut SetupContext<'_>) -> Result<bool> {
+        let Some(distro) = ctx.distro.as_deref() else {
+            return Ok(false);
+        };
+        let status = nix_installer::check_nix(ctx.runner, distro)?;
+        Ok(status.is_installed && status.experimental_enabled)
+    }
+
+    fn apply(&self, ctx: &mut SetupContext<'_>) -> Result<StepOutcome> {
+        nix_installer::configure_nix(ctx.runner, ctx.distro()?, ctx.sources)?;
+        Ok(StepOutcome::Done)
+    }
+}
+
+/// Ask which distribution to install
+fn choose_distro() -> Result<String> {
+    use crate::ui::OutputFormatter;
+    use std::io::{self, Write};
+
+    eprintln!("{}", OutputFormatter::format_section("Choose Linux Distribution"));
+    eprintln!("  1. Ubuntu 24.04 LTS (recommended) ⭐");
+    eprintln!("  2. Debian 12");
+    eprintln!("  3. Ubuntu 22.04 LTS");
+    eprintln!();
+    eprint!("Select [1-3] (default: 1): ");
+    io::stderr().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    let distro = match input.trim() {
+        "2" => "Debian",
+        "3" => "Ubuntu-22.04",
+        _ => DEFAULT_DISTRO,
+    };
+
+    Ok(distro.to_string())
+}
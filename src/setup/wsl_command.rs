@@ -2,128 +2,83 @@
 //!
 //! Handles UTF-16 LE encoding issues when calling WSL from Windows
 
-use std::process::Command;
-use anyhow::{Result, Context};
+use anyhow::Result;
 
-/// Execute a WSL command and return output as UTF-8 String
+use super::runner::CommandRunner;
+
+/// Run a `wsl` command line through PowerShell
 ///
-/// PowerShell always outputs UTF-16 LE on Windows, so we need to decode it properly.
+/// PowerShell outputs UTF-16 LE on Windows; the runner decodes it.
+fn run_powershell_wsl(runner: &dyn CommandRunner, ps_command: &str) -> Result<crate::wsl2::CommandOutput> {
+    log::debug!("Running WSL command via PowerShell: {}", ps_command);
+    runner.run("powershell.exe", &["-NoProfile", "-Command", ps_command])
+}
+
+/// Execute a WSL command and return output as UTF-8 String
 ///
 /// # Arguments
+/// * `runner` - Runs the PowerShell process
 /// * `args` - WSL command arguments (e.g., ["--list", "--verbose"])
 ///
 /// # Returns
 /// * `Ok(String)` - Decoded stdout as UTF-8 String
 /// * `Err` - If command fails or cannot be decoded
-pub fn run_wsl_command(args: &[&str]) -> Result<String> {
-    let wsl_args = args.join(" ");
-    let ps_command = format!("wsl {}", wsl_args);
-
-    log::debug!("Running WSL command via PowerShell: {}", ps_command);
-
-    let output = Command::new("powershell.exe")
-        .args(&["-NoProfile", "-Command", &ps_command])
-        .output()
-        .context("Failed to execute PowerShell")?;
-
-    if !output.status.success() {
-        let stderr = decode_utf16_le(&output.stderr);
-        log::debug!("WSL command failed. Exit code: {:?}", output.status.code());
-        log::debug!("Stderr: {}", stderr);
-        anyhow::bail!("WSL command failed: {}", stderr);
-    }
-
-    // PowerShell always outputs UTF-16 LE - decode it properly
-    let stdout = decode_utf16_le(&output.stdout);
-    log::debug!("WSL command output: {}", stdout.trim());
-
-    Ok(stdout)
-}
-
-/// Decode UTF-16 LE bytes to a Rust String
-///
-/// PowerShell outputs UTF-16 LE (Little Endian) on Windows.
-/// Each character is 2 bytes in little-endian format.
-fn decode_utf16_le(bytes: &[u8]) -> String {
-    if bytes.is_empty() {
-        return String::new();
-    }
-
-    // If byte count is odd, we have a problem - fall back to UTF-8
-    if bytes.len() % 2 != 0 {
-        log::warn!("Odd number of bytes in UTF-16 output, falling back to UTF-8");
-        return String::from_utf8_lossy(bytes).to_string();
+pub fn run_wsl_command(runner: &dyn CommandRunner, args: &[&str]) -> Result<String> {
+    let ps_command = format!("wsl {}", args.join(" "));
+    let output = run_powershell_wsl(runner, &ps_command)?;
+
+    if !output.is_success() {
+        log::debug!("WSL command failed. Exit code: {}", output.exit_code);
+        log::debug!("Stderr: {}", output.stderr);
+        anyhow::bail!("WSL command failed: {}", output.stderr);
     }
 
-    // Convert bytes to u16 values (UTF-16 LE)
-    let output_u16: Vec<u16> = bytes
-        .chunks_exact(2)
-        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-        .collect();
+    log::debug!("WSL command output: {}", output.stdout.trim());
 
-    String::from_utf16_lossy(&output_u16)
+    Ok(output.stdout)
 }
 
 /// Execute a WSL command in a specific distro
 ///
 /// # Arguments
+/// * `runner` - Runs the PowerShell process
 /// * `distro` - Name of the WSL distro (e.g., "Ubuntu")
 /// * `command` - Command to run in the distro
 ///
 /// # Returns
 /// * `Ok(String)` - Decoded stdout as UTF-8 String
 /// * `Err` - If command fails
-pub fn run_wsl_distro_command(distro: &str, command: &[&str]) -> Result<String> {
-    let cmd = command.join(" ");
-
-    let ps_command = if distro.is_empty() {
-        format!("wsl -- {}", cmd)
-    } else {
-        format!("wsl -d {} -- {}", distro, cmd)
-    };
+pub fn run_wsl_distro_command(runner: &dyn CommandRunner, distro: &str, command: &[&str]) -> Result<String> {
+    let output = run_powershell_wsl(runner, &distro_command_line(distro, command))?;
 
-    log::debug!("Running WSL distro command: {}", ps_command);
-
-    let output = Command::new("powershell.exe")
-        .args(&["-NoProfile", "-Command", &ps_command])
-        .output()
-        .context("Failed to execute PowerShell")?;
-
-    if !output.status.success() {
-        let stderr = decode_utf16_le(&output.stderr);
-        log::debug!("WSL distro command failed. Exit code: {:?}", output.status.code());
-        log::debug!("Stderr: {}", stderr);
-        anyhow::bail!("Command failed: {}", stderr);
+    if !output.is_success() {
+        log::debug!("WSL distro command failed. Exit code: {}", output.exit_code);
+        log::debug!("Stderr: {}", output.stderr);
+        anyhow::bail!("Command failed: {}", output.stderr);
     }
 
-    let stdout = decode_utf16_le(&output.stdout);
-    log::debug!("Command output: {}", stdout.trim());
+    log::debug!("Command output: {}", output.stdout.trim());
 
-    Ok(stdout)
+    Ok(output.stdout)
 }
 
 /// Check if a WSL command succeeds (returns true) or fails (returns false)
 ///
 /// # Arguments
+/// * `runner` - Runs the PowerShell process
 /// * `args` - WSL command arguments
 ///
 /// # Returns
 /// * `true` - If command exits with status 0
 /// * `false` - If command fails
-pub fn wsl_command_exists(args: &[&str]) -> bool {
+pub fn wsl_command_exists(runner: &dyn CommandRunner, args: &[&str]) -> bool {
     let wsl_args = args.join(" ");
-    let ps_command = format!("wsl {}", wsl_args);
 
-    let output = Command::new("powershell.exe")
-        .args(&["-NoProfile", "-Command", &ps_command])
-        .output();
-
-    match output {
+    match run_powershell_wsl(runner, &format!("wsl {}", wsl_args)) {
         Ok(result) => {
-            let success = result.status.success();
+            let success = result.is_success();
             if !success {
-                let stderr = decode_utf16_le(&result.stderr);
-                log::debug!("Command '{}' failed: {}", wsl_args, stderr);
+                log::debug!("Command '{}' failed: {}", wsl_args, result.stderr);
             }
             log::debug!("Command '{}' exists: {}", wsl_args, success);
             success
@@ -135,13 +90,42 @@ pub fn wsl_command_exists(args: &[&str]) -> bool {
     }
 }
 
+/// PowerShell command line running a command in a distro (the default one if empty)
+fn distro_command_line(distro: &str, command: &[&str]) -> String {
+    let cmd = command.join(" ");
+    if distro.is_empty() {
+        format!("wsl -- {}", cmd)
+    } else {
+        format!("wsl -d {} -- {}", distro, cmd)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::setup::runner::MockCommandRunner;
+    use crate::wsl2::CommandOutput;
 
     #[test]
     fn test_wsl_command_construction() {
-        // Just test that the functions exist and compile
-        assert!(true);
+        assert_eq!(distro_command_line("Debian", &["nix", "--version"]), "wsl -d Debian -- nix --version");
+        assert_eq!(distro_command_line("", &["groups"]), "wsl -- groups");
+    }
+
+    #[test]
+    fn test_run_wsl_distro_command() {
+        let runner = MockCommandRunner::new();
+        runner.set_response(
+            "powershell.exe -NoProfile -Command wsl -d Debian -- whoami",
+            CommandOutput::new("alice\n".to_string(), String::new(), 0)
+        );
+        runner.set_response(
+            "powershell.exe -NoProfile -Command wsl -d Debian -- false",
+            CommandOutput::new(String::new(), "oops".to_string(), 1)
+        );
+
+        assert_eq!(run_wsl_distro_command(&runner, "Debian", &["whoami"]).unwrap(), "alice\n");
+        assert_eq!(run_wsl_distro_command(&runner, "Debian", &["false"]).unwrap_err().to_string(), "Command failed: oops");
+        assert!(!wsl_command_exists(&runner, &["-d", "Debian", "--", "which", "nix"]));
     }
 }
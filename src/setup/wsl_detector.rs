@@ -1,7 +1,7 @@
 //! WSL2 detection and installation
 
 use anyhow::{Result, Context, bail};
-use std::process::Command;
+use super::runner::CommandRunner;
 use super::wsl_command;
 
 #[derive(Debug, Default)]
@@ -16,49 +16,52 @@ pub struct DistroStatus {
     pub distro_name: String,
 }
 
-pub fn check_wsl2() -> Result<WSL2Status> {
-    // Try to run 'wsl --status'
-    let output = Command::new("wsl")
-        .args(&["--status"])
-        .output();
+/// A distribution as listed by `wsl --list --verbose`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WslDistro {
+    pub name: String,
+
+    /// `Running`, `Stopped`, `Installing`, ...
+    pub state: String,
+
+    /// WSL version the distribution runs under (1 or 2)
+    pub version: u8,
 
-    match output {
-        Ok(result) if result.status.success() => {
-            let stdout = String::from_utf8_lossy(&result.stdout);
+    /// Whether `wsl` without `-d` starts this distribution
+    pub is_default: bool,
+}
+
+pub fn check_wsl2(runner: &dyn CommandRunner) -> Result<WSL2Status> {
+    // Try to run 'wsl --status'
+    match runner.run("wsl", &["--status"]) {
+        Ok(result) if result.is_success() => {
             Ok(WSL2Status {
                 is_installed: true,
-                version: Some(extract_version(&stdout)),
+                version: Some(extract_version(&result.stdout)),
             })
         }
         _ => {
             // WSL might be installed but not configured
             // Try 'wsl --version' as fallback
-            let version_output = Command::new("wsl")
-                .args(&["--version"])
-                .output();
-
-            match version_output {
-                Ok(result) if result.status.success() => {
-                    Ok(WSL2Status {
-                        is_installed: true,
-                        version: Some("2.0+".to_string()),
-                    })
-                }
-                _ => {
-                    Ok(WSL2Status {
-                        is_installed: false,
-                        version: None,
-                    })
-                }
+            if runner.succeeds("wsl", &["--version"]) {
+                Ok(WSL2Status {
+                    is_installed: true,
+                    version: Some("2.0+".to_string()),
+                })
+            } else {
+                Ok(WSL2Status {
+                    is_installed: false,
+                    version: None,
+                })
             }
         }
     }
 }
 
-pub fn check_linux_distro() -> Result<DistroStatus> {
+pub fn check_linux_distro(runner: &dyn CommandRunner) -> Result<DistroStatus> {
     // Run 'wsl --list --verbose' to see installed distros
     // Use PowerShell wrapper to avoid UTF-16 LE encoding issues
-    match wsl_command::run_wsl_command(&["--list", "--verbose"]) {
+    match wsl_command::run_wsl_command(runner, &["--list", "--verbose"]) {
         Ok(stdout) => {
             log::debug!("WSL list output: {}", stdout);
             let distros = parse_distro_list(&stdout);
@@ -88,16 +91,19 @@ pub fn check_linux_distro() -> Result<DistroStatus> {
     }
 }
 
-pub fn install_wsl2() -> Result<()> {
+/// List the installed distributions with their state and WSL version
+pub fn list_distros(runner: &dyn CommandRunner) -> Result<Vec<WslDistro>> {
+    let stdout = wsl_command::run_wsl_command(runner, &["--list", "--verbose"])?;
+    Ok(parse_distros(&stdout))
+}
+
+pub fn install_wsl2(runner: &dyn CommandRunner) -> Result<()> {
     // Run 'wsl --install' which installs WSL2 and Ubuntu by default
-    let output = Command::new("wsl")
-        .args(&["--install"])
-        .output()
+    let output = runner.run("wsl", &["--install"])
         .context("Failed to run 'wsl --install'")?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("WSL installation failed: {}", stderr);
+    if !output.is_success() {
+        bail!("WSL installation failed: {}", output.stderr);
     }
 
     Ok(())
@@ -117,10 +123,14 @@ fn extract_version(wsl_status: &str) -> String {
 }
 
 fn parse_distro_list(output: &str) -> Vec<String> {
+    parse_distros(output).into_iter().map(|distro| distro.name).collect()
+}
+
+fn parse_distros(output: &str) -> Vec<WslDistro> {
     let mut distros = Vec::new();
 
     // WSL output may contain UTF-16 BOM and special characters
-    let cleaned = output.replace('\u{feff}', "").replace('\r', "");
+    let cleaned = output.replace(['\u{feff}', '\r', '\0'], "");
 
     for line in cleaned.lines().skip(1) { // Skip header
         let line = line.trim();
@@ -128,30 +138,24 @@ fn parse_distro_list(output: &str) -> Vec<String> {
             continue;
         }
 
-        // Split by whitespace and filter out empty parts
-        let parts: Vec<&str> = line.split_whitespace().collect();
+        // The default distribution is marked with "*", possibly glued to its name
+        let is_default = line.starts_with('*');
+        let parts: Vec<&str> = line.trim_start_matches('*').split_whitespace().collect();
 
-        if parts.is_empty() {
+        // Skip the header and lines without a state
+        let [name, state, rest @ ..] = parts.as_slice() else {
+            continue;
+        };
+        if *name == "NAME" {
             continue;
         }
 
-        // First part is either "*" or distro name, or "* distro"
-        let mut name = parts[0];
-        let mut idx = 0;
-
-        // If first part is just "*", the name is in the next part
-        if name == "*" && parts.len() > 1 {
-            name = parts[1];
-            idx = 1;
-        } else {
-            // Remove leading "*" if present
-            name = name.trim_start_matches('*').trim();
-        }
-
-        // Skip if it's the header or empty
-        if !name.is_empty() && name != "NAME" && parts.len() > idx + 1 {
-            distros.push(name.to_string());
-        }
+        distros.push(WslDistro {
+            name: name.to_string(),
+            state: state.to_string(),
+            version: rest.first().and_then(|v| v.parse().ok()).unwrap_or(2),
+            is_default,
+        });
     }
 
     distros
@@ -167,4 +171,14 @@ mod tests {
         let distros = parse_distro_list(output);
         assert_eq!(distros, vec!["Ubuntu", "Debian"]);
     }
+
+    #[test]
+    fn test_parse_distros_state_and_version() {
+        let output = "  NAME            STATE           VERSION\r\n*Ubuntu-24.04    Running         2\r\n  Legacy          Stopped         1\r\n";
+        let distros = parse_distros(output);
+        assert_eq!(distros, vec![
+            WslDistro { name: "Ubuntu-24.04".to_string(), state: "Running".to_string(), version: 2, is_default: true },
+            WslDistro { name: "Legacy".to_string(), state: "Stopped".to_string(), version: 1, is_default: false },
+        ]);
+    }
 }
@@ -72,7 +72,9 @@ impl WrapperGenerator {
     /// Path to the generated wrapper script
     ///
     /// # Example
-    /// ```
+    /// ```no_run
+    /// use nsfw::templates::{PackageInfo, WrapperGenerator, WrapperType};
+    ///
     /// let generator = WrapperGenerator::new("C:\\wrappers".into());
     /// let info = PackageInfo::new(
     ///     "firefox".to_string(),
@@ -80,6 +82,7 @@ impl WrapperGenerator {
     ///     WrapperType::Gui
     /// );
     /// let wrapper_path = generator.generate(&info)?;
+    /// # Ok::<(), anyhow::Error>(())
     /// ```
     pub fn generate(&self, package_info: &PackageInfo) -> Result<PathBuf> {
         // Create output directory if it doesn't exist
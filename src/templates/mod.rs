@@ -1,4 +1,5 @@
 pub mod generator;
+#[allow(clippy::module_inception)]
 pub mod templates;
 
 pub use generator::{WrapperGenerator, WrapperType, PackageInfo};
@@ -1,4 +1,4 @@
-/// Wrapper script templates for different package types
+//! Wrapper script templates for different package types
 
 /// Console application wrapper template
 /// Runs in visible command prompt window
@@ -73,14 +73,14 @@ mod tests {
     #[test]
     fn test_create_spinner() {
         let spinner = create_spinner("Testing...");
-        assert!(true); // Just verify it doesn't panic
+        // Just verify it doesn't panic
         spinner.finish_and_clear();
     }
 
     #[test]
     fn test_create_progress_bar() {
         let pb = create_progress_bar(100, "Processing");
-        assert!(true); // Just verify it doesn't panic
+        // Just verify it doesn't panic
         pb.finish_and_clear();
     }
 }
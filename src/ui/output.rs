@@ -1,6 +1,10 @@
 /// Output formatting utilities
 use colored::*;
 use crate::nix_ops::types::{SearchResult, InstalledPackage};
+use crate::nix_ops::{InstallPlan, RemovePlan};
+use crate::nix_ops::store::StoreUsage;
+use crate::setup::doctor::{CheckResult, CheckStatus};
+use crate::package_cache::{CacheDelta, CategorySummary, ChangeKind};
 
 /// Message type for colored output
 pub enum MessageType {
@@ -73,7 +77,31 @@ impl OutputFormatter {
             if detailed {
                 output.push('\n');
                 output.push_str(&format!("   {}: {}\n", "Version".bright_black(), pkg.version.yellow()));
-                output.push_str(&format!("   {}: {}\n", "Store path".bright_black(), pkg.store_path.bright_black()));
+                if let Some(attr_path) = &pkg.attr_path {
+                    output.push_str(&format!("   {}: {}\n", "Attribute".bright_black(), attr_path));
+                }
+                if let Some(url) = &pkg.original_url {
+                    output.push_str(&format!("   {}: {}\n", "Source".bright_black(), url));
+                }
+                if !pkg.outputs.is_empty() {
+                    output.push_str(&format!("   {}: {}\n", "Outputs".bright_black(), pkg.outputs.join(", ")));
+                }
+                if pkg.store_paths.len() > 1 {
+                    for path in &pkg.store_paths {
+                        output.push_str(&format!("   {}: {}\n", "Store path".bright_black(), path.bright_black()));
+                    }
+                } else {
+                    output.push_str(&format!("   {}: {}\n", "Store path".bright_black(), pkg.store_path.bright_black()));
+                }
+                if let Some(priority) = pkg.priority {
+                    output.push_str(&format!("   {}: {}\n", "Priority".bright_black(), priority));
+                }
+                if !pkg.active {
+                    output.push_str(&format!("   {}: {}\n", "Active".bright_black(), "no".red()));
+                }
+                if let Some(revision) = &pkg.pinned_revision {
+                    output.push_str(&format!("   {}: nixpkgs {}\n", "Pinned to".bright_black(), revision.cyan()));
+                }
             }
 
             output.push('\n');
@@ -82,6 +110,75 @@ impl OutputFormatter {
         output
     }
 
+    /// Format a cache update delta as a changelog
+    ///
+    /// Shows at most `max_entries` individual changes.
+    pub fn format_cache_delta(delta: &CacheDelta, max_entries: usize) -> String {
+        let mut output = String::new();
+
+        for change in delta.changes.iter().take(max_entries) {
+            let line = match change.kind {
+                ChangeKind::Added => format!(
+                    "   {} {} {}\n",
+                    "+".green().bold(),
+                    change.attr_path.bright_white(),
+                    change.new_version.as_deref().unwrap_or("").yellow()
+                ),
+                ChangeKind::Updated => format!(
+                    "   {} {} {} → {}\n",
+                    "~".yellow().bold(),
+                    change.attr_path.bright_white(),
+                    change.old_version.as_deref().unwrap_or("").bright_black(),
+                    change.new_version.as_deref().unwrap_or("").yellow()
+                ),
+                ChangeKind::Removed => format!(
+                    "   {} {}\n",
+                    "-".red().bold(),
+                    change.attr_path.bright_black()
+                ),
+            };
+            output.push_str(&line);
+        }
+
+        if delta.changes.len() > max_entries {
+            output.push_str(&format!(
+                "   {}\n",
+                format!("... and {} more", delta.changes.len() - max_entries).bright_black()
+            ));
+        }
+
+        output
+    }
+
+    /// Format package categories with their most popular packages
+    pub fn format_categories(categories: &[CategorySummary]) -> String {
+        if categories.is_empty() {
+            return Self::format_message(MessageType::Info, "No categories available");
+        }
+
+        let mut output = String::new();
+
+        for category in categories {
+            output.push_str(&format!(
+                "{} {}
+",
+                category.name.bright_green().bold(),
+                format!("({} packages)", category.package_count).bright_black()
+            ));
+
+            if !category.top_packages.is_empty() {
+                output.push_str(&format!(
+                    "   {}: {}
+",
+                    "Top".bright_black(),
+                    category.top_packages.join(", ").bright_white()
+                ));
+            }
+        }
+
+        output
+    }
+
     /// Wrap text to specified width
     fn wrap_text(text: &str, width: usize) -> String {
         let words: Vec<&str> = text.split_whitespace().collect();
@@ -89,7 +186,7 @@ impl OutputFormatter {
         let mut current_line = String::new();
 
         for word in words {
-            if current_line.len() + word.len() + 1 <= width {
+            if current_line.len() + word.len() < width {
                 if !current_line.is_empty() {
                     current_line.push(' ');
                 }
@@ -119,6 +216,136 @@ impl OutputFormatter {
         )
     }
 
+    /// Format a labelled field line (e.g. "   Version: 1.0")
+    pub fn format_field(label: &str, value: &str) -> String {
+        format!("   {}: {}\n", label.bright_black(), value.yellow())
+    }
+
+    /// Format a byte count as a human-readable size
+    pub fn format_size(bytes: u64) -> String {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            format!("{} {}", bytes, UNITS[0])
+        } else {
+            format!("{:.1} {}", size, UNITS[unit])
+        }
+    }
+
+    /// Format a signed byte count (e.g. "+12.5 MiB")
+    pub fn format_size_delta(bytes: i64) -> String {
+        let sign = if bytes < 0 { "-" } else { "+" };
+        format!("{}{}", sign, Self::format_size(bytes.unsigned_abs()))
+    }
+
+    /// Format what an installation will do
+    pub fn format_install_plan(plan: &InstallPlan) -> String {
+        if plan.is_noop() {
+            return Self::format_field("Plan", "everything is already in the Nix store");
+        }
+
+        let mut output = String::new();
+        if !plan.to_build.is_empty() {
+            output.push_str(&Self::format_field("Build", &format!("{} derivation(s)", plan.to_build.len())));
+        }
+        if !plan.to_fetch.is_empty() {
+            output.push_str(&Self::format_field("Fetch", &format!(
+                "{} path(s), {} download",
+                plan.to_fetch.len(),
+                Self::format_size(plan.download_size)
+            )));
+        }
+
+        let mut disk = Self::format_size_delta(plan.disk_delta);
+        if !plan.to_build.is_empty() {
+            disk.push_str(" plus build outputs");
+        }
+        output.push_str(&Self::format_field("Disk", &disk));
+        output
+    }
+
+    /// Format what a removal will do
+    pub fn format_remove_plan(plan: &RemovePlan) -> String {
+        let mut output = String::new();
+        output.push_str(&Self::format_field("Store paths", &plan.store_paths.len().to_string()));
+        output.push_str(&Self::format_field("Closure", &Self::format_size(plan.closure_size)));
+        output.push_str(&Self::format_field(
+            "Disk",
+            &format!("{} after the next garbage collection", Self::format_size_delta(plan.disk_delta))
+        ));
+        output
+    }
+
+    /// Format the disk usage of the Nix store
+    pub fn format_store_usage(usage: &StoreUsage) -> String {
+        let mut output = String::new();
+        output.push_str(&Self::format_field(
+            "Store size",
+            &format!("{} in {} paths", Self::format_size(usage.store_size), usage.path_count)
+        ));
+        output.push_str(&Self::format_field(
+            "Reclaimable now",
+            &format!("{} in {} dead paths", Self::format_size(usage.reclaimable_size), usage.dead_path_count)
+        ));
+
+        let old: Vec<_> = usage.generations.iter().filter(|g| !g.current).collect();
+        let generations = match old.first() {
+            Some(oldest) => format!("{} ({} old, oldest from {})", usage.generations.len(), old.len(), oldest.created),
+            None => usage.generations.len().to_string(),
+        };
+        output.push_str(&Self::format_field("Generations", &generations));
+
+        if !usage.largest_closures.is_empty() {
+            output.push_str(&format!("\n   {}\n", "Largest closures".bright_cyan()));
+            let width = usage.largest_closures.iter().map(|c| c.package.len()).max().unwrap_or(0);
+            for closure in &usage.largest_closures {
+                output.push_str(&format!(
+                    "   {:width$}  {}\n",
+                    closure.package.bright_white(),
+                    Self::format_size(closure.closure_size).yellow(),
+                    width = width
+                ));
+            }
+        }
+
+        output
+    }
+
+    /// Format health check results, one line each with a fix hint for problems
+    pub fn format_check_results(results: &[CheckResult]) -> String {
+        let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        let mut output = String::new();
+
+        for result in results {
+            let icon = match result.status {
+                CheckStatus::Pass => "✓".green().bold(),
+                CheckStatus::Warn => "⚠".yellow().bold(),
+                CheckStatus::Fail => "✗".red().bold(),
+                CheckStatus::Skip => "-".dimmed(),
+            };
+            output.push_str(&format!(
+                "  {} {:width$}  {}\n",
+                icon,
+                result.name.bright_white(),
+                result.message,
+                width = width
+            ));
+
+            if let Some(fix) = result.fix.as_deref().filter(|_| result.is_problem()) {
+                output.push_str(&format!("    {:width$}  {} {}\n", "", "→".cyan(), fix.dimmed(), width = width));
+            }
+        }
+
+        output
+    }
+
     /// Format a section header
     pub fn format_section(title: &str) -> String {
         format!("\n{}\n{}", title.bright_cyan().bold(), "─".repeat(title.len()).bright_black())
@@ -149,6 +376,45 @@ mod tests {
         assert!(output.contains("No results"));
     }
 
+    #[test]
+    fn test_format_categories() {
+        let categories = vec![CategorySummary {
+            name: "python".to_string(),
+            package_count: 2,
+            top_packages: vec!["requests".to_string(), "numpy".to_string()],
+        }];
+
+        let output = OutputFormatter::format_categories(&categories);
+        assert!(output.contains("python"));
+        assert!(output.contains("2 packages"));
+        assert!(output.contains("requests, numpy"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(OutputFormatter::format_size(512), "512 B");
+        assert_eq!(OutputFormatter::format_size(1536), "1.5 KiB");
+        assert_eq!(OutputFormatter::format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_format_install_plan() {
+        let plan = InstallPlan {
+            to_build: vec!["/nix/store/a-env.drv".to_string()],
+            to_fetch: vec!["/nix/store/b-jq".to_string(), "/nix/store/c-oniguruma".to_string()],
+            download_size: 1536,
+            unpacked_size: 4096,
+            disk_delta: 4096,
+            ..Default::default()
+        };
+
+        let output = OutputFormatter::format_install_plan(&plan);
+        assert!(output.contains("1 derivation(s)"));
+        assert!(output.contains("2 path(s), 1.5 KiB download"));
+        assert!(output.contains("+4.0 KiB plus build outputs"));
+        assert_eq!(OutputFormatter::format_size_delta(-512), "-512 B");
+    }
+
     #[test]
     fn test_format_installed_packages_empty() {
         let packages: Vec<InstalledPackage> = vec![];
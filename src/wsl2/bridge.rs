@@ -3,6 +3,7 @@
 /// This trait provides an abstraction over WSL2 command execution,
 /// allowing for both real WSL2 execution and mock testing.
 use anyhow::Result;
+use std::io::Read;
 
 /// Output from a command execution
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -53,11 +54,39 @@ pub trait WSL2Bridge {
     ///
     /// # Example
     /// ```
+    /// # use nsfw::wsl2::WSL2Bridge;
+    /// # fn example(bridge: &impl WSL2Bridge) -> anyhow::Result<()> {
     /// let output = bridge.execute("nix", &["--version"])?;
     /// println!("Nix version: {}", output.stdout);
+    /// # Ok(())
+    /// # }
     /// ```
     fn execute(&self, command: &str, args: &[&str]) -> Result<CommandOutput>;
 
+    /// Execute a command in WSL2, streaming stdout to `consume`
+    ///
+    /// Use this for commands with very large output (e.g. `nix-env -qa --json`)
+    /// so it never has to be buffered in memory. `consume` is only called
+    /// when stdout is available; the returned `CommandOutput` has an empty
+    /// `stdout` and carries stderr and the exit code.
+    ///
+    /// The default implementation buffers via `execute`, which is fine for
+    /// bridges whose output is already in memory (e.g. the mock bridge).
+    fn execute_streamed(
+        &self,
+        command: &str,
+        args: &[&str],
+        consume: &mut dyn FnMut(&mut dyn Read) -> Result<()>,
+    ) -> Result<CommandOutput> {
+        let output = self.execute(command, args)?;
+
+        if output.is_success() {
+            consume(&mut output.stdout.as_bytes())?;
+        }
+
+        Ok(CommandOutput::new(String::new(), output.stderr, output.exit_code))
+    }
+
     /// Check if WSL2 is available
     ///
     /// # Returns
@@ -71,8 +100,12 @@ pub trait WSL2Bridge {
     ///
     /// # Example
     /// ```
+    /// # use nsfw::wsl2::WSL2Bridge;
+    /// # fn example(bridge: &impl WSL2Bridge) -> anyhow::Result<()> {
     /// let version = bridge.version()?;
     /// println!("WSL2 version: {}", version);
+    /// # Ok(())
+    /// # }
     /// ```
     fn version(&self) -> Result<String>;
 
@@ -86,8 +119,12 @@ pub trait WSL2Bridge {
     ///
     /// # Example
     /// ```
+    /// # use nsfw::wsl2::WSL2Bridge;
+    /// # fn example(bridge: &impl WSL2Bridge) -> anyhow::Result<()> {
     /// let wsl_path = bridge.translate_path_to_wsl("C:\\Users\\John")?;
     /// assert_eq!(wsl_path, "/mnt/c/Users/John");
+    /// # Ok(())
+    /// # }
     /// ```
     fn translate_path_to_wsl(&self, windows_path: &str) -> Result<String>;
 
@@ -101,8 +138,12 @@ pub trait WSL2Bridge {
     ///
     /// # Example
     /// ```
+    /// # use nsfw::wsl2::WSL2Bridge;
+    /// # fn example(bridge: &impl WSL2Bridge) -> anyhow::Result<()> {
     /// let windows_path = bridge.translate_path_to_windows("/mnt/c/Users/John")?;
     /// assert_eq!(windows_path, "C:\\Users\\John");
+    /// # Ok(())
+    /// # }
     /// ```
     fn translate_path_to_windows(&self, wsl_path: &str) -> Result<String>;
 }
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use anyhow::{Result, anyhow};
 
 use super::bridge::{WSL2Bridge, CommandOutput};
+use crate::nix_ops::backend::NIX_GLOBAL_ARGS;
 use crate::path_translation::PathTranslator;
 
 /// Mock WSL2 bridge for testing without actual WSL2
@@ -42,6 +43,8 @@ impl MockWSL2Bridge {
     ///
     /// # Example
     /// ```
+    /// use nsfw::wsl2::{CommandOutput, MockWSL2Bridge};
+    ///
     /// let mut bridge = MockWSL2Bridge::new();
     /// bridge.set_response(
     ///     "nix --version".to_string(),
@@ -71,6 +74,21 @@ impl MockWSL2Bridge {
         }
     }
 
+    /// Response to a command line
+    ///
+    /// Responses to `nix` commands may leave out the global flags every
+    /// backend passes (`NIX_GLOBAL_ARGS`); a response that includes them
+    /// wins.
+    fn lookup(&self, full_command: &str) -> Option<CommandOutput> {
+        if let Some(output) = self.responses.get(full_command) {
+            return Some(output.clone());
+        }
+
+        let with_flags = format!("nix {} ", NIX_GLOBAL_ARGS.join(" "));
+        let without_flags = full_command.replacen(&with_flags, "nix ", 1);
+        self.responses.get(&without_flags).cloned()
+    }
+
     /// Add common Nix command responses
     ///
     /// This is a helper to set up common responses for testing:
@@ -114,9 +132,7 @@ impl WSL2Bridge for MockWSL2Bridge {
 
         let full_command = self.build_command_string(command, args);
 
-        self.responses
-            .get(&full_command)
-            .cloned()
+        self.lookup(&full_command)
             .ok_or_else(|| anyhow!("No mock response configured for command: '{}'", full_command))
     }
 
@@ -1,7 +1,9 @@
 pub mod bridge;
 pub mod mock;
+pub mod native;
 pub mod real;
 
 pub use bridge::{WSL2Bridge, CommandOutput};
 pub use mock::MockWSL2Bridge;
+pub use native::NativeBridge;
 pub use real::RealWSL2Bridge;
@@ -0,0 +1,128 @@
+/// Native bridge running commands directly on the host
+///
+/// Used on Linux, including inside a WSL distribution, where the commands
+/// `RealWSL2Bridge` would hand to `wsl.exe` can run as they are.
+use std::io::Read;
+use std::process::Command;
+use anyhow::{Result, anyhow};
+
+use super::bridge::{WSL2Bridge, CommandOutput};
+use super::real::stream_command;
+
+/// Bridge that executes commands on the host itself
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativeBridge;
+
+impl NativeBridge {
+    /// Create a new native bridge
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl WSL2Bridge for NativeBridge {
+    fn execute(&self, command: &str, args: &[&str]) -> Result<CommandOutput> {
+        let output = Command::new(command)
+            .args(args)
+            .output()
+            .map_err(|e| anyhow!("Failed to execute {}: {}", command, e))?;
+
+        Ok(CommandOutput::new(
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+            output.status.code().unwrap_or(-1),
+        ))
+    }
+
+    fn execute_streamed(
+        &self,
+        command: &str,
+        args: &[&str],
+        consume: &mut dyn FnMut(&mut dyn Read) -> Result<()>,
+    ) -> Result<CommandOutput> {
+        stream_command(Command::new(command).args(args), consume)
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn version(&self) -> Result<String> {
+        let output = Command::new("uname")
+            .arg("-r")
+            .output()
+            .map_err(|e| anyhow!("Failed to get kernel version: {}", e))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn translate_path_to_wsl(&self, path: &str) -> Result<String> {
+        // Paths on the host are already Linux paths
+        Ok(path.to_string())
+    }
+
+    fn translate_path_to_windows(&self, path: &str) -> Result<String> {
+        Ok(path.to_string())
+    }
+}
+
+impl<B: WSL2Bridge + ?Sized> WSL2Bridge for Box<B> {
+    fn execute(&self, command: &str, args: &[&str]) -> Result<CommandOutput> {
+        (**self).execute(command, args)
+    }
+
+    fn execute_streamed(
+        &self,
+        command: &str,
+        args: &[&str],
+        consume: &mut dyn FnMut(&mut dyn Read) -> Result<()>,
+    ) -> Result<CommandOutput> {
+        (**self).execute_streamed(command, args, consume)
+    }
+
+    fn is_available(&self) -> bool {
+        (**self).is_available()
+    }
+
+    fn version(&self) -> Result<String> {
+        (**self).version()
+    }
+
+    fn translate_path_to_wsl(&self, windows_path: &str) -> Result<String> {
+        (**self).translate_path_to_wsl(windows_path)
+    }
+
+    fn translate_path_to_windows(&self, wsl_path: &str) -> Result<String> {
+        (**self).translate_path_to_windows(wsl_path)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_runs_on_host() {
+        let bridge = NativeBridge::new();
+        let output = bridge.execute("sh", &["-c", "echo native; echo oops >&2; exit 3"]).unwrap();
+        assert_eq!(output.stdout, "native\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert_eq!(output.exit_code, 3);
+
+        assert!(bridge.execute("nsfw-no-such-program", &[]).is_err());
+    }
+
+    #[test]
+    fn test_execute_streamed() {
+        let bridge: Box<dyn WSL2Bridge> = Box::new(NativeBridge::new());
+        let mut streamed = String::new();
+        let output = bridge.execute_streamed("sh", &["-c", "printf '{}'"], &mut |stdout| {
+            stdout.read_to_string(&mut streamed)?;
+            Ok(())
+        }).unwrap();
+
+        assert!(output.is_success());
+        assert_eq!(streamed, "{}");
+        assert_eq!(bridge.translate_path_to_wsl("/home/alice").unwrap(), "/home/alice");
+    }
+}
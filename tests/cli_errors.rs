@@ -0,0 +1,110 @@
+//! Error reporting of the `nsfw` binary
+//!
+//! Only failures that happen before Nix is contacted are exercised here,
+//! along with offline output, which never contacts Nix.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn nsfw() -> Command {
+    Command::cargo_bin("nsfw").unwrap()
+}
+
+#[test]
+fn test_json_error_on_stderr() {
+    let output = nsfw()
+        .args(["info", "foo bar", "--format", "json"])
+        .assert()
+        .failure()
+        .code(18)
+        .get_output()
+        .stderr
+        .clone();
+
+    // The report is all that is written to stderr
+    let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(report["code"], "NSFW-E008");
+    assert_eq!(report["exit_code"], 18);
+    assert!(report["message"].as_str().unwrap().contains("foo bar"));
+    assert!(report["suggestion"].as_str().unwrap().starts_with("Use a name or attribute path"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_json_stderr_is_one_value_after_opening_cache() {
+    // Opening a fresh cache logs and starts a refresh worker before failing
+    let home = tempfile::tempdir().unwrap();
+    let output = nsfw()
+        .env("HOME", home.path())
+        .env_remove("RUST_LOG")
+        .args(["search", "x", "--free-only", "--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+
+    let mut values = serde_json::Deserializer::from_slice(&output).into_iter::<serde_json::Value>();
+    let report = values.next().unwrap().unwrap();
+    assert!(values.next().is_none(), "{}", String::from_utf8_lossy(&output));
+    assert_eq!(report["message"], "Package cache is empty");
+}
+
+#[test]
+fn test_text_error_includes_code() {
+    nsfw()
+        .args(["info", "foo bar"])
+        .assert()
+        .code(18)
+        .stderr(predicate::str::contains("[NSFW-E008]"));
+}
+
+#[test]
+fn test_all_packages_are_resolved_before_installing() {
+    // The invalid second reference stops the transaction before Nix runs
+    nsfw()
+        .args(["install", "git", "foo bar", "--dry-run"])
+        .assert()
+        .code(18)
+        .stderr(predicate::function(|stderr: &str| {
+            serde_json::from_str::<serde_json::Value>(stderr)
+                .is_ok_and(|report| report["code"] == "NSFW-E008")
+        }));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_offline_json_is_marked_stale() {
+    let home = tempfile::tempdir().unwrap();
+    let output = nsfw()
+        .env("HOME", home.path())
+        .args(["search", "firefox", "--offline", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stale"], true);
+    assert!(json["as_of"].is_null());
+    assert_eq!(json["results"], serde_json::json!([]));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_offline_list_without_snapshot_suggests_listing_online() {
+    let home = tempfile::tempdir().unwrap();
+    let output = nsfw()
+        .env("HOME", home.path())
+        .args(["list", "--offline", "--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+
+    let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert!(report["message"].as_str().unwrap().contains("No offline snapshot"));
+    assert!(report["suggestion"].as_str().unwrap().starts_with("Run 'nsfw list' once while Nix is reachable"));
+}
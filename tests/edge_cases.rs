@@ -3,7 +3,7 @@
 //! These tests verify behavior in unusual or boundary conditions.
 
 use nsfw::wsl2::{MockWSL2Bridge, CommandOutput};
-use nsfw::nix_ops::BridgedNixExecutor;
+use nsfw::nix_ops::{BridgedNixExecutor, NixBackend};
 use nsfw::path_translation::PathTranslator;
 
 #[test]
@@ -3,7 +3,7 @@
 //! These tests verify end-to-end workflows using the mock WSL2 bridge.
 
 use nsfw::wsl2::{MockWSL2Bridge, CommandOutput};
-use nsfw::nix_ops::{BridgedNixExecutor, NixError};
+use nsfw::nix_ops::{BridgedNixExecutor, NixBackend, NixError};
 
 /// Helper to create a mock executor with common responses
 fn create_test_executor() -> BridgedNixExecutor<MockWSL2Bridge> {
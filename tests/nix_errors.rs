@@ -0,0 +1,44 @@
+//! Classification of multi-line Nix stderr
+//!
+//! Each `tests/fixtures/nix_errors/<nix-version>-<case>.txt` is the stderr
+//! of a failing command, laid out the way that Nix release prints it.
+
+use nsfw::nix_ops::classify::classify;
+use nsfw::nix_ops::NixError;
+use std::fs;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nix_errors").join(name);
+    fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing {}", path.display()))
+}
+
+#[test]
+fn test_trace_points_at_failing_expression() {
+    match classify(&fixture("nix-2.18-undefined-variable-trace.txt")) {
+        NixError::EvaluationError { message, file, line, column } => {
+            assert_eq!(message, "undefined variable 'undefinedThing'");
+            assert_eq!(file, "/nix/store/rq1r6kkzhvzcyq5d3bb4mm1dg8adqkb1-source/flake.nix");
+            assert_eq!((line, column), (9, 50));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_trace_with_virtual_location() {
+    match classify(&fixture("nix-2.19-type-error-string.txt")) {
+        NixError::EvaluationError { message, file, line, column } => {
+            assert_eq!(message, "cannot add a string to an integer");
+            assert_eq!(file, "«string»");
+            assert_eq!((line, column), (1, 8));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_build_log_panic_is_not_evaluation_error() {
+    let err = classify(&fixture("nix-2.18-build-log-panic.txt"));
+    assert!(matches!(err, NixError::CommandFailed(_)), "{:?}", err);
+}
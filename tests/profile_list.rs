@@ -0,0 +1,77 @@
+//! Fixture corpus for `nix profile list --json` parsing
+//!
+//! `tests/fixtures/profile_list/nix-<version>-*.json` follow the
+//! `nix profile list --json` output of that Nix release (`--json` exists
+//! since Nix 2.17). `manifest-v1-on-disk.json` is a version 1 profile
+//! `manifest.json`, written by the first releases with `nix profile`; that
+//! command never printed it, but the parser still accepts it.
+//! `<name>.expected.json` holds the packages each must parse to.
+
+use nsfw::nix_ops::profile::parse_profile_list;
+use nsfw::nix_ops::InstalledPackage;
+use std::fs;
+use std::path::PathBuf;
+
+fn fixture_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/profile_list")
+}
+
+#[test]
+fn test_profile_list_fixtures() {
+    let mut checked = 0;
+
+    for entry in fs::read_dir(fixture_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if !name.ends_with(".json") || name.ends_with(".expected.json") {
+            continue;
+        }
+
+        let input = fs::read_to_string(&path).unwrap();
+        let parsed = parse_profile_list(&input)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+        let expected_path = path.with_extension("expected.json");
+        let expected: Vec<InstalledPackage> = serde_json::from_str(
+            &fs::read_to_string(&expected_path)
+                .unwrap_or_else(|_| panic!("{}: missing {}", name, expected_path.display())),
+        ).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&expected).unwrap(),
+            "{} parsed differently than expected",
+            name
+        );
+        checked += 1;
+    }
+
+    assert!(checked >= 5, "fixture corpus is incomplete ({} fixtures)", checked);
+}
+
+#[test]
+fn test_schemas_agree() {
+    // The same profile before and after the Nix 2.20 manifest migration
+    let v2 = r#"{"version":2,"elements":[{"active":true,"attrPath":"legacyPackages.x86_64-linux.hello",
+        "originalUrl":"flake:nixpkgs","outputs":null,"priority":5,
+        "storePaths":["/nix/store/4b2d0dcjz7kxf7a2wpq6yv0wmdx6kiv5-hello-2.12.1"],"url":"github:NixOS/nixpkgs/b3a2"}]}"#;
+    let v3 = r#"{"version":3,"elements":{"hello":{"active":true,"attrPath":"legacyPackages.x86_64-linux.hello",
+        "originalUrl":"flake:nixpkgs","outputs":null,"priority":5,
+        "storePaths":["/nix/store/4b2d0dcjz7kxf7a2wpq6yv0wmdx6kiv5-hello-2.12.1"],"url":"github:NixOS/nixpkgs/b3a2"}}}"#;
+
+    assert_eq!(
+        serde_json::to_value(parse_profile_list(v2).unwrap()).unwrap(),
+        serde_json::to_value(parse_profile_list(v3).unwrap()).unwrap(),
+    );
+}
+
+#[test]
+fn test_default_matches_minimal_record() {
+    let minimal: InstalledPackage =
+        serde_json::from_str(r#"{"name":"","version":"","store_path":""}"#).unwrap();
+
+    assert_eq!(
+        serde_json::to_value(InstalledPackage::default()).unwrap(),
+        serde_json::to_value(minimal).unwrap(),
+    );
+}
@@ -0,0 +1,281 @@
+//! Scripted runs of the setup wizard
+//!
+//! The wizard runs against a `MockCommandRunner` playing a Windows machine,
+//! so every step, including restarts and failures, is exercised on any
+//! platform.
+
+use std::path::Path;
+use std::rc::Rc;
+
+use nsfw::setup::state::{Phase, SetupState, StepId};
+use nsfw::setup::{LocalFile, MockCommandRunner, SetupSources, SetupWizard};
+use nsfw::wsl2::CommandOutput;
+
+const DISTRO: &str = "Ubuntu-24.04";
+
+/// SHA-256 of the fake installer and image contents, "nix"
+const CONTENT_SHA256: &str = "a0745f7ed88cfac6058d1d4cfb57bc71127a28ad7c6756004142a90aab5ee99f";
+
+fn ok(stdout: &str) -> CommandOutput {
+    CommandOutput::new(stdout.to_string(), String::new(), 0)
+}
+
+fn failed(stderr: &str) -> CommandOutput {
+    CommandOutput::new(String::new(), stderr.to_string(), 1)
+}
+
+/// PowerShell command reporting when Windows last booted
+const LAST_BOOT: &str = "(Get-CimInstance Win32_OperatingSystem).LastBootUpTime.ToString('o')";
+
+/// Command line of a `wsl` invocation through PowerShell
+fn ps(command: &str) -> String {
+    format!("powershell.exe -NoProfile -Command {}", command)
+}
+
+/// Command line of a command inside the distribution, through PowerShell
+fn in_distro(command: &str) -> String {
+    ps(&format!("wsl -d {} -- {}", DISTRO, command))
+}
+
+/// A Windows machine with WSL2 and Ubuntu but no Nix
+fn machine_without_nix() -> MockCommandRunner {
+    let runner = MockCommandRunner::new();
+    runner.set_response("wsl --status", ok("Default Version: 2\nWSL version: 2.3.26.0\n"));
+    runner.set_response(&ps("wsl --list --verbose"), ok(&format!("  NAME            STATE           VERSION\n* {}    Stopped         2\n", DISTRO)));
+    runner.set_response(&in_distro("which nix"), failed(""));
+    runner
+}
+
+/// Make the Nix installer succeed and the distribution look like it has Nix
+fn script_nix_install(runner: &MockCommandRunner) {
+    script_nix_install_with(runner, &format!("wsl -d {} -- bash -c curl *", DISTRO));
+}
+
+/// Make an installer command succeed and the distribution look like it has Nix
+fn script_nix_install_with(runner: &MockCommandRunner, installer: &str) {
+    runner.set_response(installer, ok("Nix was installed successfully!\n"));
+    for (command, output) in [
+        (in_distro("which nix"), ok("/nix/var/nix/profiles/default/bin/nix\n")),
+        (in_distro("nix --version"), ok("nix (Nix) 2.24.10\n")),
+        (in_distro("pgrep nix-daemon"), ok("123\n")),
+        (in_distro("groups"), ok("alice sudo\n")),
+        (in_distro("cat ~/.config/nix/nix.conf"), failed("No such file or directory")),
+    ] {
+        runner.set_response_after(installer, &command, output);
+    }
+}
+
+/// Make configuring Nix succeed
+fn script_nix_configure(runner: &MockCommandRunner) {
+    let configure = format!("wsl -d {} -- bash -c \nmkdir *", DISTRO);
+    runner.set_response(&configure, ok(""));
+    runner.set_response_after(&configure, &in_distro("cat ~/.config/nix/nix.conf"), ok("experimental-features = nix-command flakes\n"));
+    runner.set_response(&in_distro("getent group nix-users"), failed(""));
+    runner.set_response(&format!("wsl -d {} -- nix-channel *", DISTRO), ok(""));
+}
+
+fn wizard(runner: &Rc<MockCommandRunner>, state_path: &Path) -> SetupWizard {
+    SetupWizard::new(true, false)
+        .with_runner(Box::new(runner.clone()))
+        .with_state_path(state_path.to_path_buf())
+}
+
+#[test]
+fn test_fresh_machine_resumes_after_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+
+    let runner = MockCommandRunner::new();
+    runner.set_response("wsl --status", failed("WSL is not installed"));
+    runner.set_response("wsl --version", failed(""));
+    runner.set_response(&ps("wsl --list --verbose"), failed("no distributions"));
+    runner.set_response("wsl --install", ok(""));
+    runner.set_response(&ps(LAST_BOOT), ok("2026-10-18T08:00:00.0000000+02:00\r\n"));
+    let runner = Rc::new(runner);
+
+    // WSL2 needs a restart before anything else can happen
+    wizard(&runner, &state_path).run().unwrap();
+    assert!(runner.was_called("wsl --install"));
+    assert!(!runner.calls().iter().any(|c| c.starts_with("wsl --install -d")));
+    assert_eq!(SetupState::load_from(&state_path).unwrap().awaiting_restart, Some(StepId::InstallWsl));
+
+    // Without the restart, reruns keep waiting for it
+    assert!(wizard(&runner, &state_path).run().is_err());
+    assert!(wizard(&runner, &state_path).run().is_err());
+
+    // After the restart WSL works, but the distribution still has to be installed
+    runner.set_response(&ps(LAST_BOOT), ok("2026-10-18T09:30:00.0000000+02:00\r\n"));
+    runner.set_response("wsl --status", ok("Default Version: 2\n"));
+    runner.set_response(&format!("wsl --install -d {}", DISTRO), ok(""));
+    runner.set_response_after(
+        &format!("wsl --install -d {}", DISTRO),
+        &ps("wsl --list --verbose"),
+        ok(&format!("  NAME            STATE           VERSION\n* {}    Running         2\n", DISTRO)),
+    );
+    runner.set_response(&in_distro("which nix"), failed(""));
+    script_nix_install(&runner);
+    script_nix_configure(&runner);
+
+    wizard(&runner, &state_path).run().unwrap();
+
+    let state = SetupState::load_from(&state_path).unwrap();
+    assert_eq!(state.completed.len(), 4);
+    assert_eq!(state.distro.as_deref(), Some(DISTRO));
+    assert_eq!(state.awaiting_restart, None);
+    assert!(state.last_failure.is_none());
+    assert_eq!(runner.calls().iter().filter(|c| *c == "wsl --install").count(), 1);
+}
+
+#[test]
+fn test_failed_nix_install_is_reported_and_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+
+    let runner = machine_without_nix();
+    runner.set_response(&format!("wsl -d {} -- bash -c curl *", DISTRO), failed("curl: (6) Could not resolve host"));
+    let runner = Rc::new(runner);
+
+    let error = wizard(&runner, &state_path).run().unwrap_err();
+    assert_eq!(error.to_string(), "Setup failed at step 'install-nix' (apply phase)");
+    assert!(format!("{:#}", error).contains("Nix installation failed"));
+
+    let state = SetupState::load_from(&state_path).unwrap();
+    let failure = state.last_failure.as_ref().unwrap();
+    assert_eq!((failure.step, failure.phase), (StepId::InstallNix, Phase::Apply));
+    assert!(state.is_completed(StepId::InstallWsl));
+    assert!(state.is_completed(StepId::InstallDistro));
+
+    // With the network back, the rerun picks up at the failed step
+    script_nix_install(&runner);
+    script_nix_configure(&runner);
+    wizard(&runner, &state_path).run().unwrap();
+
+    let state = SetupState::load_from(&state_path).unwrap();
+    assert!(state.last_failure.is_none());
+    assert!(state.is_completed(StepId::ConfigureNix));
+    assert!(!runner.was_called("wsl --install"));
+}
+
+#[test]
+fn test_configuration_that_does_not_stick_fails_verification() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+
+    let runner = machine_without_nix();
+    script_nix_install(&runner);
+    // The configuration script runs but nix.conf stays empty
+    runner.set_response(&format!("wsl -d {} -- bash -c \nmkdir *", DISTRO), ok(""));
+    runner.set_response(&in_distro("getent group nix-users"), failed(""));
+    runner.set_response(&format!("wsl -d {} -- nix-channel *", DISTRO), ok(""));
+    let runner = Rc::new(runner);
+
+    let error = wizard(&runner, &state_path).run().unwrap_err();
+    assert_eq!(error.to_string(), "Setup failed at step 'configure-nix' (verify phase)");
+
+    let state = SetupState::load_from(&state_path).unwrap();
+    assert_eq!(state.last_failure.as_ref().unwrap().phase, Phase::Verify);
+    assert!(state.is_completed(StepId::InstallNix));
+}
+
+#[test]
+fn test_configured_machine_changes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+
+    let runner = machine_without_nix();
+    script_nix_install(&runner);
+    script_nix_configure(&runner);
+    let runner = Rc::new(runner);
+    wizard(&runner, &state_path).run().unwrap();
+
+    let before = runner.calls().len();
+    wizard(&runner, &state_path).run().unwrap();
+    let rerun = &runner.calls()[before..];
+    assert!(rerun.iter().all(|c| !c.contains("--install") && !c.contains("bash -c")), "{:?}", rerun);
+}
+
+#[test]
+fn test_offline_setup_uses_local_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+    let image = dir.path().join("ubuntu.wsl");
+    let installer = dir.path().join("nix-2.24.10-x86_64-linux.tar.xz");
+    let nixpkgs = dir.path().join("nixexprs.tar.xz");
+    std::fs::write(&image, "nix").unwrap();
+    std::fs::write(&installer, "nix").unwrap();
+    std::fs::write(&nixpkgs, "nix").unwrap();
+    let install_dir = dir.path().join("distros").join(DISTRO);
+
+    let runner = MockCommandRunner::new();
+    runner.set_response("wsl --status", ok("Default Version: 2\n"));
+    runner.set_response(&ps("wsl --list --verbose"), failed("no distributions"));
+    let import = format!("wsl --import {} {} {} --version 2", DISTRO, install_dir.display(), image.display());
+    runner.set_response(&import, ok(""));
+    runner.set_response_after(&import, &ps("wsl --list --verbose"), ok(&format!("  NAME            STATE           VERSION\n* {}    Stopped         2\n", DISTRO)));
+    runner.set_response(&in_distro("which nix"), failed(""));
+    script_nix_install_with(&runner, &format!("wsl -d {} -- bash -c set -e\ndir=$(mktemp -d)\ntrap *", DISTRO));
+    script_nix_configure(&runner);
+    let runner = Rc::new(runner);
+
+    let sources = SetupSources {
+        distro_image: Some(LocalFile::new(image, Some(CONTENT_SHA256.to_string()))),
+        distro_install_dir: Some(install_dir.clone()),
+        nix_installer: Some(LocalFile::new(installer.clone(), Some(CONTENT_SHA256.to_string()))),
+        nixpkgs: Some(LocalFile::new(nixpkgs.clone(), Some(CONTENT_SHA256.to_string()))),
+        ..Default::default()
+    };
+    wizard(&runner, &state_path).with_sources(sources).run().unwrap();
+
+    assert!(runner.was_called(&import));
+    assert!(install_dir.is_dir());
+    let calls = runner.calls();
+    assert!(calls.iter().any(|c| c.contains(&format!("tar -xf '{}'", installer.display()))), "{:?}", calls);
+    // The unpacked installer does not outlive the script
+    assert!(calls.iter().any(|c| c.contains("trap 'rm -rf \"$dir\"' EXIT")), "{:?}", calls);
+    assert!(runner.was_called(&format!("wsl -d {} -- nix-channel --add file://{} nixpkgs", DISTRO, nixpkgs.display())));
+    assert!(!calls.iter().any(|c| c.starts_with("wsl --install") || c.contains("curl") || c.contains("https://")), "{:?}", calls);
+    assert_eq!(SetupState::load_from(&state_path).unwrap().completed.len(), 4);
+}
+
+#[test]
+fn test_offline_setup_without_nixpkgs_skips_channels() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+    let installer = dir.path().join("nix-installer-x86_64-linux");
+    std::fs::write(&installer, "nix").unwrap();
+
+    let runner = machine_without_nix();
+    script_nix_install_with(&runner, &format!("wsl -d {} -- bash -c set -e\ninstaller=$(mktemp)\ntrap *", DISTRO));
+    script_nix_configure(&runner);
+    let runner = Rc::new(runner);
+
+    let sources = SetupSources {
+        nix_installer: Some(LocalFile::new(installer, Some(CONTENT_SHA256.to_string()))),
+        ..Default::default()
+    };
+    wizard(&runner, &state_path).with_sources(sources).run().unwrap();
+
+    let calls = runner.calls();
+    assert!(calls.iter().any(|c| c.contains("trap 'rm -f \"$installer\"' EXIT")), "{:?}", calls);
+    assert!(!calls.iter().any(|c| c.contains("nix-channel")), "{:?}", calls);
+    assert_eq!(SetupState::load_from(&state_path).unwrap().completed.len(), 4);
+}
+
+#[test]
+fn test_installer_with_wrong_checksum_is_not_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("setup-state.json");
+    let installer = dir.path().join("nix-installer-x86_64-linux");
+    std::fs::write(&installer, "tampered").unwrap();
+
+    let runner = Rc::new(machine_without_nix());
+    let sources = SetupSources {
+        nix_installer: Some(LocalFile::new(installer, Some(CONTENT_SHA256.to_string()))),
+        ..Default::default()
+    };
+
+    let error = wizard(&runner, &state_path).with_sources(sources).run().unwrap_err();
+    assert_eq!(error.to_string(), "Setup failed at step 'install-nix' (apply phase)");
+    assert!(format!("{:#}", error).contains("Checksum mismatch"));
+    assert!(!runner.calls().iter().any(|c| c.contains("bash -c")));
+}
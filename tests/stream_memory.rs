@@ -0,0 +1,25 @@
+//! Memory use of streamed nix-env parsing
+//!
+//! Kept in its own test binary: the tracking allocator counts every
+//! allocation in the process, so no other test may run alongside.
+
+mod support;
+
+use nsfw::package_cache::stream;
+use support::{measure_peak, SyntheticNixEnvOutput, TrackingAllocator};
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+#[test]
+fn test_streamed_parse_memory_is_flat() {
+    let small = measure_peak(|| {
+        stream::for_each_package(SyntheticNixEnvOutput::new(1_000), |_, _| Ok(())).unwrap();
+    });
+    let large = measure_peak(|| {
+        stream::for_each_package(SyntheticNixEnvOutput::new(50_000), |_, _| Ok(())).unwrap();
+    });
+
+    // 50x the input must not mean meaningfully more memory
+    assert!(large < small * 2 + 64 * 1024, "small: {}, large: {}", small, large);
+}
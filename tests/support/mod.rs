@@ -0,0 +1,93 @@
+//! Helpers shared by the memory tests and the benchmarks
+//!
+//! Included by `benches/performance.rs` through a `#[path]` attribute.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator wrapper that tracks current and peak heap usage
+pub struct TrackingAllocator;
+
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+/// Run `f` and return the peak heap growth (in bytes) it caused
+///
+/// Only meaningful with `TrackingAllocator` as the global allocator.
+pub fn measure_peak<F: FnOnce()>(f: F) -> usize {
+    let baseline = CURRENT_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(baseline, Ordering::Relaxed);
+    f();
+    PEAK_BYTES.load(Ordering::Relaxed).saturating_sub(baseline)
+}
+
+/// Reader that generates `nix-env -qaP --json --meta` style output on the fly
+///
+/// Stands in for the WSL2 pipe: the input is never held in memory as a whole.
+pub struct SyntheticNixEnvOutput {
+    total: usize,
+    next: usize,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl SyntheticNixEnvOutput {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            next: 0,
+            buffer: b"{".to_vec(),
+            position: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        self.buffer.clear();
+        self.position = 0;
+
+        if self.next < self.total {
+            let i = self.next;
+            let separator = if i + 1 < self.total { "," } else { "}" };
+            self.buffer.extend_from_slice(format!(
+                r#""nixpkgs.package-{i}":{{"name":"package-{i}-1.0.{i}","pname":"package-{i}","version":"1.0.{i}","meta":{{"description":"Synthetic package number {i} used to benchmark streaming parsing","license":{{"spdxId":"MIT","free":true}},"platforms":["x86_64-linux","aarch64-linux","x86_64-darwin","aarch64-darwin"]}}}}{separator}"#
+            ).as_bytes());
+            self.next += 1;
+        } else if self.total == 0 && self.next == 0 {
+            self.buffer.push(b'}');
+            self.next = 1;
+        }
+    }
+}
+
+impl Read for SyntheticNixEnvOutput {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            self.refill();
+            if self.buffer.is_empty() {
+                return Ok(0);
+            }
+        }
+
+        let n = out.len().min(self.buffer.len() - self.position);
+        out[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}